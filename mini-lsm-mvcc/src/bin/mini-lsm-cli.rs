@@ -1 +0,0 @@
-../../../mini-lsm-starter/src/bin/mini-lsm-cli.rs
\ No newline at end of file
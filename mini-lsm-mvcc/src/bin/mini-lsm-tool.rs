@@ -0,0 +1,103 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use mini_lsm_mvcc::checker::check_storage;
+use mini_lsm_mvcc::compact::{
+    CompactionOptions, LeveledCompactionOptions, SimpleLeveledCompactionOptions,
+    TieredCompactionOptions,
+};
+use mini_lsm_mvcc::lsm_storage::LsmStorageOptions;
+
+#[derive(Debug, Clone, ValueEnum)]
+enum CompactionStrategy {
+    Simple,
+    Leveled,
+    Tiered,
+    None,
+}
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Offline tools for a mini-lsm directory", long_about = None)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Checks the manifest, the SSTs and the WALs of a storage without opening it.
+    Check {
+        #[arg(long, default_value = "lsm.db")]
+        path: PathBuf,
+        /// The compaction strategy the storage is opened with.
+        #[arg(long, default_value = "leveled")]
+        compaction: CompactionStrategy,
+        #[arg(long)]
+        enable_wal: bool,
+    },
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    match args.command {
+        Command::Check {
+            path,
+            compaction,
+            enable_wal,
+        } => {
+            let options = LsmStorageOptions {
+                compaction_options: match compaction {
+                    CompactionStrategy::None => CompactionOptions::NoCompaction,
+                    CompactionStrategy::Simple => {
+                        CompactionOptions::Simple(SimpleLeveledCompactionOptions {
+                            size_ratio_percent: 200,
+                            level0_file_num_compaction_trigger: 2,
+                            max_levels: 4,
+                        })
+                    }
+                    CompactionStrategy::Tiered => {
+                        CompactionOptions::Tiered(TieredCompactionOptions {
+                            num_tiers: 3,
+                            max_size_amplification_percent: 200,
+                            size_ratio: 1,
+                            min_merge_width: 2,
+                        })
+                    }
+                    CompactionStrategy::Leveled => {
+                        CompactionOptions::Leveled(LeveledCompactionOptions {
+                            level0_file_num_compaction_trigger: 2,
+                            max_levels: 4,
+                            base_level_size_mb: 128,
+                            level_size_multiplier: 2,
+                        })
+                    }
+                },
+                enable_wal,
+                ..LsmStorageOptions::default_for_week1_test()
+            };
+            let report = check_storage(&path, &options)?;
+            println!(
+                "{} SSTs with {} entries and {} WALs checked",
+                report.num_sstables, report.num_entries, report.num_wals
+            );
+            for file in &report.missing_files {
+                println!("missing file: {}", file.display());
+            }
+            for file in &report.orphan_files {
+                println!("orphan file: {}", file.display());
+            }
+            for warning in &report.warnings {
+                println!("warning: {}", warning);
+            }
+            for error in &report.errors {
+                println!("error: {}", error);
+            }
+            if !report.is_ok() {
+                bail!("{} is inconsistent", path.display());
+            }
+            println!("{} is consistent", path.display());
+        }
+    }
+    Ok(())
+}
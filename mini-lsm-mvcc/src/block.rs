@@ -7,6 +7,10 @@ pub use iterator::BlockIterator;
 
 pub(crate) const SIZEOF_U16: usize = std::mem::size_of::<u16>();
 
+/// Set in the key overlap of an entry if the entry is a merge operand. The overlap is shorter than
+/// the key, which never reaches this length.
+pub(crate) const MERGE_OPERAND_FLAG: u16 = 1 << 15;
+
 /// A block is the smallest unit of read and caching in LSM tree. It is a collection of sorted
 /// key-value pairs.
 pub struct Block {
@@ -1,8 +1,9 @@
 use bytes::BufMut;
 
+use crate::iterators::RecordKind;
 use crate::key::{KeySlice, KeyVec};
 
-use super::{Block, SIZEOF_U16};
+use super::{Block, MERGE_OPERAND_FLAG, SIZEOF_U16};
 
 /// Builds a block.
 pub struct BlockBuilder {
@@ -49,6 +50,12 @@ impl BlockBuilder {
     /// Adds a key-value pair to the block. Returns false when the block is full.
     #[must_use]
     pub fn add(&mut self, key: KeySlice, value: &[u8]) -> bool {
+        self.add_with_kind(key, value, RecordKind::Value)
+    }
+
+    /// Adds a record of the given kind to the block. Returns false when the block is full.
+    #[must_use]
+    pub fn add_with_kind(&mut self, key: KeySlice, value: &[u8], kind: RecordKind) -> bool {
         assert!(!key.is_empty(), "key must not be empty");
         if self.estimated_size() + key.raw_len() + value.len() + SIZEOF_U16 * 3 /* key_len, value_len and offset */ > self.block_size
             && !self.is_empty()
@@ -58,8 +65,12 @@ impl BlockBuilder {
         // Add the offset of the data into the offset array.
         self.offsets.push(self.data.len() as u16);
         let overlap = compute_overlap(self.first_key.as_key_slice(), key);
-        // Encode key overlap.
-        self.data.put_u16(overlap as u16);
+        // Encode key overlap, and the record kind in its highest bit.
+        let kind_flag = match kind {
+            RecordKind::Value => 0,
+            RecordKind::MergeOperand => MERGE_OPERAND_FLAG,
+        };
+        self.data.put_u16(overlap as u16 | kind_flag);
         // Encode key length.
         self.data.put_u16((key.key_len() - overlap) as u16);
         // Encode key content.
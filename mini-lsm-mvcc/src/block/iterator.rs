@@ -3,7 +3,8 @@ use std::sync::Arc;
 use bytes::Buf;
 
 use crate::{
-    block::SIZEOF_U16,
+    block::{MERGE_OPERAND_FLAG, SIZEOF_U16},
+    iterators::RecordKind,
     key::{KeySlice, KeyVec},
 };
 
@@ -17,6 +18,8 @@ pub struct BlockIterator {
     key: KeyVec,
     /// the value range from the block
     value_range: (usize, usize),
+    /// the kind of the current record
+    kind: RecordKind,
     /// the current index at the iterator position
     idx: usize,
     /// the first key in the block
@@ -37,10 +40,16 @@ impl Block {
 impl BlockIterator {
     fn new(block: Arc<Block>) -> Self {
         Self {
-            first_key: block.get_first_key(),
+            // The block of an SST without data blocks is empty.
+            first_key: if block.offsets.is_empty() {
+                KeyVec::new()
+            } else {
+                block.get_first_key()
+            },
             block,
             key: KeyVec::new(),
             value_range: (0, 0),
+            kind: RecordKind::Value,
             idx: 0,
         }
     }
@@ -59,6 +68,13 @@ impl BlockIterator {
         iter
     }
 
+    /// Creates a block iterator and seek to the last entry.
+    pub fn create_and_seek_to_last(block: Arc<Block>) -> Self {
+        let mut iter = Self::new(block);
+        iter.seek_to_last();
+        iter
+    }
+
     /// Returns the key of the current entry.
     pub fn key(&self) -> KeySlice {
         debug_assert!(!self.key.is_empty(), "invalid iterator");
@@ -71,6 +87,12 @@ impl BlockIterator {
         &self.block.data[self.value_range.0..self.value_range.1]
     }
 
+    /// Returns the kind of the current entry.
+    pub fn record_kind(&self) -> RecordKind {
+        debug_assert!(!self.key.is_empty(), "invalid iterator");
+        self.kind
+    }
+
     /// Returns true if the iterator is valid.
     pub fn is_valid(&self) -> bool {
         !self.key.is_empty()
@@ -99,13 +121,38 @@ impl BlockIterator {
         self.seek_to(self.idx);
     }
 
+    /// Seeks to the last key in the block.
+    pub fn seek_to_last(&mut self) {
+        match self.block.offsets.len() {
+            0 => self.seek_to(0),
+            len => self.seek_to(len - 1),
+        }
+    }
+
+    /// Move to the previous key in the block.
+    pub fn prev(&mut self) {
+        if self.idx == 0 {
+            self.key.clear();
+            self.value_range = (0, 0);
+            return;
+        }
+        self.idx -= 1;
+        self.seek_to(self.idx);
+    }
+
     /// Seek to the specified position and update the current `key` and `value`
     /// Index update will be handled by caller
     fn seek_to_offset(&mut self, offset: usize) {
         let mut entry = &self.block.data[offset..];
         // Since `get_u16()` will automatically move the ptr 2 bytes ahead here,
         // we don't need to manually advance it
-        let overlap_len = entry.get_u16() as usize;
+        let overlap = entry.get_u16();
+        self.kind = if overlap & MERGE_OPERAND_FLAG != 0 {
+            RecordKind::MergeOperand
+        } else {
+            RecordKind::Value
+        };
+        let overlap_len = (overlap & !MERGE_OPERAND_FLAG) as usize;
         let key_len = entry.get_u16() as usize;
         let key = &entry[..key_len];
         self.key.clear();
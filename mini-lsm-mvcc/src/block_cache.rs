@@ -0,0 +1,437 @@
+mod clock_pro;
+mod lru;
+mod ring;
+mod secondary;
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::hash::{BuildHasher, BuildHasherDefault};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use anyhow::Result;
+use parking_lot::Mutex;
+
+use crate::block::Block;
+use crate::table::bloom::Bloom;
+use crate::table::BlockMeta;
+
+use self::clock_pro::ClockProShard;
+use self::lru::LruShard;
+pub use self::secondary::{CompressedSecondaryCache, FileSecondaryCache, SecondaryCache};
+
+/// An entry of the block cache: a data block, or a partition of the index or the filter. The
+/// entries are keyed by the SST id and their offset in the file.
+#[derive(Clone)]
+pub enum CachedBlock {
+    Data(Arc<Block>),
+    Index(Arc<Vec<BlockMeta>>),
+    Filter(Arc<Bloom>),
+}
+
+impl CachedBlock {
+    /// The number of bytes charged to the cache for the entry.
+    pub fn charge(&self) -> usize {
+        match self {
+            CachedBlock::Data(block) => block.data.len() + block.offsets.len() * 2,
+            CachedBlock::Index(block_meta) => block_meta
+                .iter()
+                .map(|x| 8 + x.first_key.raw_len() + x.last_key.raw_len())
+                .sum(),
+            CachedBlock::Filter(bloom) => bloom.filter.len() + 1,
+        }
+    }
+
+    fn stats_idx(&self) -> usize {
+        match self {
+            CachedBlock::Data(_) => 0,
+            CachedBlock::Index(_) => 1,
+            CachedBlock::Filter(_) => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Evicts the least recently used blocks.
+    Lru,
+    /// Evicts with CLOCK-Pro, which protects the blocks read repeatedly from large scans.
+    ClockPro,
+}
+
+#[derive(Debug, Clone)]
+pub struct BlockCacheOptions {
+    // Total capacity in bytes of the cached blocks
+    pub capacity: u64,
+    // The share of the capacity reserved for index and filter partitions, so that scans over many
+    // data blocks do not evict them
+    pub high_priority_ratio: f64,
+    // Keep the index and filter partitions read by an SST in the SST until it is dropped, so that
+    // they are never evicted
+    pub pin_index_and_filter_blocks: bool,
+    // How blocks are evicted when the cache is full
+    pub eviction_policy: EvictionPolicy,
+    // Each pool is split into `1 << num_shard_bits` shards with their own lock and capacity
+    pub num_shard_bits: usize,
+    // The tier keeping the blocks evicted from the cache, which is consulted before reading them
+    // from the SSTs
+    pub secondary_cache: Option<Arc<dyn SecondaryCache>>,
+}
+
+impl Default for BlockCacheOptions {
+    fn default() -> Self {
+        Self {
+            capacity: 1 << 30,
+            high_priority_ratio: 0.2,
+            pin_index_and_filter_blocks: false,
+            eviction_policy: EvictionPolicy::Lru,
+            num_shard_bits: 4,
+            secondary_cache: None,
+        }
+    }
+}
+
+/// A snapshot of the hit and miss counters and the memory usage of a block cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BlockCacheStats {
+    pub data_hits: u64,
+    pub data_misses: u64,
+    pub index_hits: u64,
+    pub index_misses: u64,
+    pub filter_hits: u64,
+    pub filter_misses: u64,
+    /// Bytes of the data blocks in the cache.
+    pub low_priority_usage: u64,
+    /// Bytes of the index and filter partitions in the cache.
+    pub high_priority_usage: u64,
+    /// Bytes of the index and filter partitions pinned by SSTs, which may also be in the cache.
+    pub pinned_usage: u64,
+    /// Reads missed by the cache and served by the secondary cache.
+    pub secondary_hits: u64,
+    /// Reads missed by both the cache and the secondary cache, which are not counted if there is
+    /// no secondary cache.
+    pub secondary_misses: u64,
+    /// Bytes of the blocks in the secondary cache.
+    pub secondary_usage: u64,
+}
+
+impl BlockCacheStats {
+    pub fn hits(&self) -> u64 {
+        self.data_hits + self.index_hits + self.filter_hits
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.data_misses + self.index_misses + self.filter_misses
+    }
+
+    pub fn usage(&self) -> u64 {
+        self.low_priority_usage + self.high_priority_usage
+    }
+}
+
+/// The key of a cached block: the id of the cache handle that read it, the SST id and the offset
+/// of the block in the SST.
+pub type CacheKey = (usize, usize, usize);
+
+/// A cache of blocks bounded by the total charge of the cached blocks.
+pub trait Cache: Send + Sync {
+    fn lookup(&self, key: &CacheKey) -> Option<CachedBlock>;
+
+    /// Inserts a block, evicting other blocks to make room for it, and returns the evicted blocks.
+    /// A block larger than the capacity is not inserted.
+    fn insert(&self, key: CacheKey, block: CachedBlock) -> Vec<(CacheKey, CachedBlock)>;
+
+    /// The total charge of the cached blocks.
+    fn usage(&self) -> u64;
+}
+
+/// A part of a sharded cache, which evicts its blocks independently of the other shards.
+trait CacheShard<V>: Send {
+    fn new(capacity: usize) -> Self;
+
+    fn lookup(&mut self, key: &CacheKey) -> Option<V>;
+
+    /// Inserts a value and returns the values evicted to make room for it.
+    fn insert(&mut self, key: CacheKey, value: V, charge: usize) -> Vec<(CacheKey, V)>;
+
+    fn erase(&mut self, key: &CacheKey);
+
+    fn usage(&self) -> usize;
+}
+
+/// Splits the keys into shards by their hash, so that reads of different blocks rarely contend on
+/// the same lock.
+struct ShardedCache<S> {
+    shards: Vec<Mutex<S>>,
+}
+
+impl<S: CacheShard<CachedBlock>> ShardedCache<S> {
+    fn new(capacity: u64, num_shard_bits: usize) -> Self {
+        let num_shards = 1 << num_shard_bits;
+        let shard_capacity = (capacity / num_shards as u64) as usize;
+        Self {
+            shards: (0..num_shards)
+                .map(|_| Mutex::new(S::new(shard_capacity)))
+                .collect(),
+        }
+    }
+
+    fn shard(&self, key: &CacheKey) -> &Mutex<S> {
+        let hash = BuildHasherDefault::<DefaultHasher>::default().hash_one(key);
+        &self.shards[hash as usize % self.shards.len()]
+    }
+}
+
+impl<S: CacheShard<CachedBlock>> Cache for ShardedCache<S> {
+    fn lookup(&self, key: &CacheKey) -> Option<CachedBlock> {
+        self.shard(key).lock().lookup(key)
+    }
+
+    fn insert(&self, key: CacheKey, block: CachedBlock) -> Vec<(CacheKey, CachedBlock)> {
+        let charge = block.charge();
+        self.shard(&key).lock().insert(key, block, charge)
+    }
+
+    fn usage(&self) -> u64 {
+        self.shards.iter().map(|x| x.lock().usage() as u64).sum()
+    }
+}
+
+fn build_pool(capacity: u64, options: &BlockCacheOptions) -> Box<dyn Cache> {
+    match options.eviction_policy {
+        EvictionPolicy::Lru => Box::new(ShardedCache::<LruShard<CachedBlock>>::new(
+            capacity,
+            options.num_shard_bits,
+        )),
+        EvictionPolicy::ClockPro => Box::new(ShardedCache::<ClockProShard>::new(
+            capacity,
+            options.num_shard_bits,
+        )),
+    }
+}
+
+/// The pools and the counters shared by all handles of a block cache.
+struct BlockCachePools {
+    options: BlockCacheOptions,
+    /// Data blocks.
+    low_priority: Box<dyn Cache>,
+    /// Index and filter partitions.
+    high_priority: Box<dyn Cache>,
+    /// Hits and misses of the data blocks, the index partitions and the filter partitions.
+    hits: [AtomicU64; 3],
+    misses: [AtomicU64; 3],
+    pinned_usage: AtomicU64,
+    secondary_hits: AtomicU64,
+    secondary_misses: AtomicU64,
+}
+
+/// Each handle gets its own id, so that the SSTs of the storage engines sharing a cache, which are
+/// all numbered from 1, do not collide.
+static NEXT_OWNER_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Caches the data blocks and the index and filter partitions of SSTs, weighted by their size in
+/// bytes. Index and filter partitions are kept in a separate high-priority pool.
+///
+/// A cache can be shared by several storage engines, each of which reads through its own handle
+/// created by [`BlockCache::share`].
+pub struct BlockCache {
+    pools: Arc<BlockCachePools>,
+    owner_id: usize,
+}
+
+impl Debug for BlockCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BlockCache")
+            .field("options", &self.pools.options)
+            .field("owner_id", &self.owner_id)
+            .finish()
+    }
+}
+
+impl BlockCache {
+    /// Creates a cache of `capacity` bytes with the default pools.
+    pub fn new(capacity: u64) -> Self {
+        Self::with_options(BlockCacheOptions {
+            capacity,
+            ..Default::default()
+        })
+    }
+
+    pub fn with_options(options: BlockCacheOptions) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&options.high_priority_ratio),
+            "high priority ratio must be within [0, 1]"
+        );
+        let high_priority_capacity = (options.capacity as f64 * options.high_priority_ratio) as u64;
+        let pools = BlockCachePools {
+            low_priority: build_pool(options.capacity - high_priority_capacity, &options),
+            high_priority: build_pool(high_priority_capacity, &options),
+            options,
+            hits: Default::default(),
+            misses: Default::default(),
+            pinned_usage: AtomicU64::new(0),
+            secondary_hits: AtomicU64::new(0),
+            secondary_misses: AtomicU64::new(0),
+        };
+        Self {
+            pools: Arc::new(pools),
+            owner_id: NEXT_OWNER_ID.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    /// Creates another handle to the same cache for another storage engine.
+    pub fn share(&self) -> Self {
+        Self {
+            pools: self.pools.clone(),
+            owner_id: NEXT_OWNER_ID.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    pub fn options(&self) -> &BlockCacheOptions {
+        &self.pools.options
+    }
+
+    /// Returns the block at `offset` of an SST, calling `read` to load it on a miss. The block read
+    /// on a miss is only inserted with `fill_cache`. Index and filter partitions are read from the
+    /// pinned blocks of the SST first, and pinned once read. On a miss, the secondary cache is
+    /// consulted before `read`, and the blocks evicted by an insertion are moved into it.
+    pub(crate) fn get_or_read(
+        &self,
+        sst_id: usize,
+        offset: usize,
+        high_priority: bool,
+        fill_cache: bool,
+        pinned: Option<&PinnedBlocks>,
+        read: impl FnOnce() -> Result<CachedBlock>,
+    ) -> Result<CachedBlock> {
+        let pinned = pinned.filter(|_| high_priority);
+        if let Some(block) = pinned.and_then(|x| x.get(offset)) {
+            self.record(&block, true);
+            return Ok(block);
+        }
+        let pool = if high_priority {
+            &self.pools.high_priority
+        } else {
+            &self.pools.low_priority
+        };
+        let key = (self.owner_id, sst_id, offset);
+        let block = match pool.lookup(&key) {
+            Some(block) => {
+                self.record(&block, true);
+                block
+            }
+            None => {
+                let secondary = self.pools.options.secondary_cache.as_ref();
+                let block = match secondary.map(|x| x.lookup(&key)).transpose()?.flatten() {
+                    Some(block) => {
+                        self.pools.secondary_hits.fetch_add(1, Ordering::Relaxed);
+                        if fill_cache {
+                            secondary.unwrap().erase(&key);
+                        }
+                        block
+                    }
+                    None => {
+                        if secondary.is_some() {
+                            self.pools.secondary_misses.fetch_add(1, Ordering::Relaxed);
+                        }
+                        read()?
+                    }
+                };
+                self.record(&block, false);
+                if fill_cache {
+                    let evicted = pool.insert(key, block.clone());
+                    if let Some(secondary) = secondary {
+                        for (key, block) in evicted {
+                            secondary.insert(key, &block)?;
+                        }
+                    }
+                }
+                block
+            }
+        };
+        if let Some(pinned) = pinned {
+            pinned.pin(offset, block.clone());
+        }
+        Ok(block)
+    }
+
+    fn record(&self, block: &CachedBlock, hit: bool) {
+        let counters = if hit {
+            &self.pools.hits
+        } else {
+            &self.pools.misses
+        };
+        counters[block.stats_idx()].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The statistics of the whole cache, including the reads of the other handles.
+    pub fn stats(&self) -> BlockCacheStats {
+        let pools = &self.pools;
+        let hits = |idx: usize| pools.hits[idx].load(Ordering::Relaxed);
+        let misses = |idx: usize| pools.misses[idx].load(Ordering::Relaxed);
+        BlockCacheStats {
+            data_hits: hits(0),
+            data_misses: misses(0),
+            index_hits: hits(1),
+            index_misses: misses(1),
+            filter_hits: hits(2),
+            filter_misses: misses(2),
+            low_priority_usage: pools.low_priority.usage(),
+            high_priority_usage: pools.high_priority.usage(),
+            pinned_usage: pools.pinned_usage.load(Ordering::Relaxed),
+            secondary_hits: pools.secondary_hits.load(Ordering::Relaxed),
+            secondary_misses: pools.secondary_misses.load(Ordering::Relaxed),
+            secondary_usage: pools
+                .options
+                .secondary_cache
+                .as_ref()
+                .map_or(0, |x| x.usage()),
+        }
+    }
+
+    /// Creates the set of pinned blocks of an SST, or `None` if the cache does not pin blocks.
+    pub(crate) fn pinned_blocks(&self) -> Option<PinnedBlocks> {
+        self.pools
+            .options
+            .pin_index_and_filter_blocks
+            .then(|| PinnedBlocks {
+                blocks: Mutex::new(HashMap::new()),
+                pools: self.pools.clone(),
+            })
+    }
+}
+
+/// The index and filter partitions pinned by an SST, keyed by their offset. They are released when
+/// the SST is dropped.
+pub(crate) struct PinnedBlocks {
+    blocks: Mutex<HashMap<usize, CachedBlock>>,
+    pools: Arc<BlockCachePools>,
+}
+
+impl PinnedBlocks {
+    pub(crate) fn get(&self, offset: usize) -> Option<CachedBlock> {
+        self.blocks.lock().get(&offset).cloned()
+    }
+
+    pub(crate) fn pin(&self, offset: usize, block: CachedBlock) {
+        let charge = block.charge() as u64;
+        if self.blocks.lock().insert(offset, block).is_none() {
+            self.pools.pinned_usage.fetch_add(charge, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Drop for PinnedBlocks {
+    fn drop(&mut self) {
+        let charge: usize = self
+            .blocks
+            .get_mut()
+            .values()
+            .map(CachedBlock::charge)
+            .sum();
+        self.pools
+            .pinned_usage
+            .fetch_sub(charge as u64, Ordering::Relaxed);
+    }
+}
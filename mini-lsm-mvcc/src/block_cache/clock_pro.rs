@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+
+use super::ring::Ring;
+use super::{CacheKey, CacheShard, CachedBlock};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Status {
+    /// A resident block that was accessed again while it was cold or in its test period.
+    Hot,
+    /// A resident block accessed only once recently.
+    Cold,
+    /// A block evicted while cold, whose key is kept for a test period. If it is inserted again
+    /// within the period, it becomes hot directly.
+    Test,
+}
+
+struct ClockEntry {
+    key: CacheKey,
+    /// `None` if the block is not resident.
+    block: Option<CachedBlock>,
+    charge: usize,
+    status: Status,
+    referenced: bool,
+}
+
+/// Evicts blocks with CLOCK-Pro, which keeps the blocks accessed repeatedly (hot) apart from
+/// those accessed once (cold), so that a large scan does not flush out the hot blocks.
+///
+/// All entries are kept in a clock, with new entries added right before the hot hand. The cold
+/// hand evicts cold blocks or promotes the referenced ones, the hot hand demotes the hot blocks not
+/// referenced since its last pass, and the test hand ends the test periods of evicted blocks. The
+/// share of cold blocks adapts: it grows when an evicted block comes back within its test period,
+/// and shrinks when a test period ends.
+pub(super) struct ClockProShard {
+    capacity: usize,
+    map: HashMap<CacheKey, usize>,
+    ring: Ring<ClockEntry>,
+    hand_hot: usize,
+    hand_cold: usize,
+    hand_test: usize,
+    hot_usage: usize,
+    cold_usage: usize,
+    /// The total charge of the blocks in their test periods.
+    test_usage: usize,
+    /// The target charge of the cold blocks, the rest of the capacity is for the hot blocks.
+    cold_target: usize,
+    /// The blocks evicted by the current insertion.
+    evicted: Vec<(CacheKey, CachedBlock)>,
+}
+
+impl ClockProShard {
+    fn add(&mut self, key: CacheKey, block: CachedBlock, charge: usize, status: Status) {
+        while self.hot_usage + self.cold_usage + charge > self.capacity {
+            if self.cold_usage == 0 {
+                self.run_hand_hot();
+            } else {
+                self.run_hand_cold();
+            }
+        }
+        let entry = ClockEntry {
+            key,
+            block: Some(block),
+            charge,
+            status,
+            referenced: false,
+        };
+        let was_empty = self.ring.is_empty();
+        let idx = self.ring.insert_before(self.hand_hot, entry);
+        self.map.insert(key, idx);
+        if was_empty {
+            (self.hand_hot, self.hand_cold, self.hand_test) = (idx, idx, idx);
+        } else if self.hand_cold == self.hand_hot {
+            self.hand_cold = idx;
+        }
+        match status {
+            Status::Hot => self.hot_usage += charge,
+            _ => self.cold_usage += charge,
+        }
+    }
+
+    /// Removes an entry, moving the hands on it back to the previous entry.
+    fn remove(&mut self, idx: usize) {
+        let prev = self.ring.prev(idx);
+        for hand in [&mut self.hand_hot, &mut self.hand_cold, &mut self.hand_test] {
+            if *hand == idx {
+                *hand = prev;
+            }
+        }
+        let entry = self.ring.remove(idx);
+        self.map.remove(&entry.key);
+        match entry.status {
+            Status::Hot => self.hot_usage -= entry.charge,
+            Status::Cold => self.cold_usage -= entry.charge,
+            Status::Test => self.test_usage -= entry.charge,
+        }
+    }
+
+    fn run_hand_cold(&mut self) {
+        let idx = self.hand_cold;
+        let entry = self.ring.get_mut(idx);
+        let mut ended_test = false;
+        if entry.status == Status::Cold {
+            let charge = entry.charge;
+            self.cold_usage -= charge;
+            if entry.referenced {
+                entry.status = Status::Hot;
+                entry.referenced = false;
+                self.hot_usage += charge;
+            } else {
+                entry.status = Status::Test;
+                let block = entry.block.take().unwrap();
+                self.evicted.push((entry.key, block));
+                self.test_usage += charge;
+                ended_test = true;
+            }
+        }
+        self.hand_cold = self.ring.next(idx);
+        if ended_test {
+            while self.test_usage > self.capacity {
+                self.run_hand_test();
+            }
+        }
+        while self.hot_usage > self.capacity - self.cold_target {
+            self.run_hand_hot();
+        }
+    }
+
+    fn run_hand_hot(&mut self) {
+        if self.hand_hot == self.hand_test && self.test_usage > 0 {
+            self.run_hand_test();
+        }
+        let idx = self.hand_hot;
+        let entry = self.ring.get_mut(idx);
+        if entry.status == Status::Hot {
+            if entry.referenced {
+                entry.referenced = false;
+            } else {
+                entry.status = Status::Cold;
+                self.hot_usage -= entry.charge;
+                self.cold_usage += entry.charge;
+            }
+        }
+        self.hand_hot = self.ring.next(idx);
+    }
+
+    fn run_hand_test(&mut self) {
+        if self.hand_test == self.hand_cold && self.cold_usage > 0 {
+            self.run_hand_cold();
+        }
+        let idx = self.hand_test;
+        if self.ring.get(idx).status == Status::Test {
+            self.cold_target = self.cold_target.saturating_sub(self.ring.get(idx).charge);
+            self.remove(idx);
+            if self.ring.is_empty() {
+                return;
+            }
+        }
+        self.hand_test = self.ring.next(self.hand_test);
+    }
+}
+
+impl CacheShard<CachedBlock> for ClockProShard {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            map: HashMap::new(),
+            ring: Ring::new(),
+            hand_hot: 0,
+            hand_cold: 0,
+            hand_test: 0,
+            hot_usage: 0,
+            cold_usage: 0,
+            test_usage: 0,
+            cold_target: capacity,
+            evicted: Vec::new(),
+        }
+    }
+
+    fn lookup(&mut self, key: &CacheKey) -> Option<CachedBlock> {
+        let entry = self.ring.get_mut(*self.map.get(key)?);
+        let block = entry.block.clone()?;
+        entry.referenced = true;
+        Some(block)
+    }
+
+    fn insert(
+        &mut self,
+        key: CacheKey,
+        block: CachedBlock,
+        charge: usize,
+    ) -> Vec<(CacheKey, CachedBlock)> {
+        if charge > self.capacity {
+            return Vec::new();
+        }
+        match self.map.get(&key).map(|&idx| (idx, self.ring.get(idx))) {
+            Some((idx, entry)) if entry.status == Status::Test => {
+                // Accessed again within the test period, so more cold blocks should be kept.
+                self.cold_target = (self.cold_target + entry.charge).min(self.capacity);
+                self.remove(idx);
+                self.add(key, block, charge, Status::Hot);
+            }
+            Some((idx, entry)) => {
+                // Inserted by a concurrent reader.
+                let status = entry.status;
+                self.remove(idx);
+                self.add(key, block, charge, status);
+            }
+            None => self.add(key, block, charge, Status::Cold),
+        }
+        std::mem::take(&mut self.evicted)
+    }
+
+    fn erase(&mut self, key: &CacheKey) {
+        if let Some(&idx) = self.map.get(key) {
+            self.remove(idx);
+        }
+    }
+
+    fn usage(&self) -> usize {
+        self.hot_usage + self.cold_usage
+    }
+}
@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+
+use super::ring::Ring;
+use super::{CacheKey, CacheShard};
+
+struct LruEntry<V> {
+    key: CacheKey,
+    value: V,
+    charge: usize,
+}
+
+/// Evicts the least recently used entries.
+pub(super) struct LruShard<V> {
+    capacity: usize,
+    usage: usize,
+    map: HashMap<CacheKey, usize>,
+    ring: Ring<LruEntry<V>>,
+    /// The most recently used entry. The least recently used one is right before it.
+    head: usize,
+}
+
+impl<V> LruShard<V> {
+    fn remove(&mut self, idx: usize) -> LruEntry<V> {
+        if self.head == idx {
+            self.head = self.ring.next(idx);
+        }
+        let entry = self.ring.remove(idx);
+        self.map.remove(&entry.key);
+        self.usage -= entry.charge;
+        entry
+    }
+}
+
+impl<V: Clone + Send> CacheShard<V> for LruShard<V> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            usage: 0,
+            map: HashMap::new(),
+            ring: Ring::new(),
+            head: 0,
+        }
+    }
+
+    fn lookup(&mut self, key: &CacheKey) -> Option<V> {
+        let idx = *self.map.get(key)?;
+        self.ring.move_before(idx, self.head);
+        self.head = idx;
+        Some(self.ring.get(idx).value.clone())
+    }
+
+    fn insert(&mut self, key: CacheKey, value: V, charge: usize) -> Vec<(CacheKey, V)> {
+        let mut evicted = Vec::new();
+        if charge > self.capacity {
+            return evicted;
+        }
+        self.erase(&key);
+        while self.usage + charge > self.capacity {
+            let entry = self.remove(self.ring.prev(self.head));
+            evicted.push((entry.key, entry.value));
+        }
+        let idx = self
+            .ring
+            .insert_before(self.head, LruEntry { key, value, charge });
+        self.map.insert(key, idx);
+        self.head = idx;
+        self.usage += charge;
+        evicted
+    }
+
+    fn erase(&mut self, key: &CacheKey) {
+        if let Some(&idx) = self.map.get(key) {
+            self.remove(idx);
+        }
+    }
+
+    fn usage(&self) -> usize {
+        self.usage
+    }
+}
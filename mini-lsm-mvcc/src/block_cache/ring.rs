@@ -0,0 +1,102 @@
+/// A circular doubly linked list stored in a vector. Nodes are addressed by their index, which
+/// stays the same until the node is removed.
+pub(super) struct Ring<T> {
+    nodes: Vec<Node<T>>,
+    /// Indexes of the removed nodes, which are reused by later insertions.
+    free: Vec<usize>,
+    len: usize,
+}
+
+struct Node<T> {
+    value: Option<T>,
+    prev: usize,
+    next: usize,
+}
+
+impl<T> Ring<T> {
+    pub(super) fn new() -> Self {
+        Self {
+            nodes: Vec::new(),
+            free: Vec::new(),
+            len: 0,
+        }
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Inserts a node before the node `at`, or as the only node if the ring is empty, and returns
+    /// its index.
+    pub(super) fn insert_before(&mut self, at: usize, value: T) -> usize {
+        let node = Node {
+            value: Some(value),
+            prev: 0,
+            next: 0,
+        };
+        let idx = match self.free.pop() {
+            Some(idx) => {
+                self.nodes[idx] = node;
+                idx
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+        if self.is_empty() {
+            self.nodes[idx].prev = idx;
+            self.nodes[idx].next = idx;
+        } else {
+            self.link_before(idx, at);
+        }
+        self.len += 1;
+        idx
+    }
+
+    /// Removes a node and returns its value.
+    pub(super) fn remove(&mut self, idx: usize) -> T {
+        self.unlink(idx);
+        self.free.push(idx);
+        self.len -= 1;
+        self.nodes[idx].value.take().unwrap()
+    }
+
+    /// Moves a node to the position before the node `at`.
+    pub(super) fn move_before(&mut self, idx: usize, at: usize) {
+        if idx != at {
+            self.unlink(idx);
+            self.link_before(idx, at);
+        }
+    }
+
+    pub(super) fn next(&self, idx: usize) -> usize {
+        self.nodes[idx].next
+    }
+
+    pub(super) fn prev(&self, idx: usize) -> usize {
+        self.nodes[idx].prev
+    }
+
+    pub(super) fn get(&self, idx: usize) -> &T {
+        self.nodes[idx].value.as_ref().unwrap()
+    }
+
+    pub(super) fn get_mut(&mut self, idx: usize) -> &mut T {
+        self.nodes[idx].value.as_mut().unwrap()
+    }
+
+    fn unlink(&mut self, idx: usize) {
+        let (prev, next) = (self.nodes[idx].prev, self.nodes[idx].next);
+        self.nodes[prev].next = next;
+        self.nodes[next].prev = prev;
+    }
+
+    fn link_before(&mut self, idx: usize, at: usize) {
+        let prev = self.nodes[at].prev;
+        self.nodes[idx].prev = prev;
+        self.nodes[idx].next = at;
+        self.nodes[prev].next = idx;
+        self.nodes[at].prev = idx;
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Debug;
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use bytes::{Buf, BufMut, Bytes};
+use parking_lot::Mutex;
+
+use crate::block::Block;
+use crate::table::bloom::Bloom;
+use crate::table::{BlockMeta, CompressionType};
+
+use super::lru::LruShard;
+use super::{CacheKey, CacheShard, CachedBlock};
+
+/// A slower and larger tier of the block cache, which keeps the blocks evicted from the primary
+/// cache and is consulted before reading them from the SSTs.
+pub trait SecondaryCache: Send + Sync + Debug {
+    fn lookup(&self, key: &CacheKey) -> Result<Option<CachedBlock>>;
+
+    /// Inserts a block evicted from the primary cache, evicting other blocks to make room for it.
+    fn insert(&self, key: CacheKey, block: &CachedBlock) -> Result<()>;
+
+    /// Removes a block, which is called once the block is back in the primary cache.
+    fn erase(&self, key: &CacheKey);
+
+    /// The total size in bytes of the stored blocks.
+    fn usage(&self) -> u64;
+}
+
+const KIND_DATA: u8 = 0;
+const KIND_INDEX: u8 = 1;
+const KIND_FILTER: u8 = 2;
+
+/// Encodes a block as its kind, the compression type and the compressed body.
+fn encode_block(block: &CachedBlock, compression: CompressionType) -> Bytes {
+    let (kind, body) = match block {
+        CachedBlock::Data(block) => (KIND_DATA, block.encode().to_vec()),
+        CachedBlock::Index(block_meta) => {
+            let mut buf = Vec::new();
+            BlockMeta::encode_block_meta(block_meta, 0, &mut buf);
+            (KIND_INDEX, buf)
+        }
+        CachedBlock::Filter(bloom) => {
+            let mut buf = Vec::new();
+            bloom.encode(&mut buf);
+            (KIND_FILTER, buf)
+        }
+    };
+    let (compression, body) = compression.compress(&body);
+    let mut buf = Vec::with_capacity(body.len() + 2);
+    buf.put_u8(kind);
+    buf.put_u8(compression.to_u8());
+    buf.put_slice(&body);
+    buf.into()
+}
+
+fn decode_block(mut buf: &[u8]) -> Result<CachedBlock> {
+    let kind = buf.get_u8();
+    let body = CompressionType::from_u8(buf.get_u8())?.decompress(buf)?;
+    Ok(match kind {
+        KIND_DATA => CachedBlock::Data(Arc::new(Block::decode(&body))),
+        KIND_INDEX => CachedBlock::Index(Arc::new(BlockMeta::decode_block_meta(&body)?.0)),
+        KIND_FILTER => CachedBlock::Filter(Arc::new(Bloom::decode(&body)?)),
+        _ => bail!("unknown block kind {}", kind),
+    })
+}
+
+/// Keeps the evicted blocks compressed in memory, evicting the least recently used ones once
+/// their compressed size exceeds the capacity.
+pub struct CompressedSecondaryCache {
+    compression: CompressionType,
+    blocks: Mutex<LruShard<Bytes>>,
+}
+
+impl Debug for CompressedSecondaryCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CompressedSecondaryCache")
+            .field("compression", &self.compression)
+            .field("usage", &self.usage())
+            .finish()
+    }
+}
+
+impl CompressedSecondaryCache {
+    pub fn new(capacity: u64, compression: CompressionType) -> Self {
+        Self {
+            compression,
+            blocks: Mutex::new(LruShard::new(capacity as usize)),
+        }
+    }
+}
+
+impl SecondaryCache for CompressedSecondaryCache {
+    fn lookup(&self, key: &CacheKey) -> Result<Option<CachedBlock>> {
+        let Some(buf) = self.blocks.lock().lookup(key) else {
+            return Ok(None);
+        };
+        decode_block(&buf).map(Some)
+    }
+
+    fn insert(&self, key: CacheKey, block: &CachedBlock) -> Result<()> {
+        let buf = encode_block(block, self.compression);
+        let charge = buf.len();
+        self.blocks.lock().insert(key, buf, charge);
+        Ok(())
+    }
+
+    fn erase(&self, key: &CacheKey) {
+        self.blocks.lock().erase(key);
+    }
+
+    fn usage(&self) -> u64 {
+        self.blocks.lock().usage() as u64
+    }
+}
+
+struct FileCacheIndex {
+    /// The offset and the length of each block in the file.
+    blocks: HashMap<CacheKey, (u64, u64)>,
+    /// The blocks in the order they were written, which is also the order of their offsets since
+    /// the last wrap-around. Erased blocks are left here until they are overwritten.
+    written: VecDeque<(CacheKey, u64, u64)>,
+    /// Where the next block is written.
+    head: u64,
+    usage: u64,
+}
+
+/// Keeps the evicted blocks compressed in a local file of a fixed size, which is written as a ring
+/// buffer: a new block overwrites the oldest blocks.
+pub struct FileSecondaryCache {
+    file: File,
+    capacity: u64,
+    compression: CompressionType,
+    index: Mutex<FileCacheIndex>,
+}
+
+impl Debug for FileSecondaryCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FileSecondaryCache")
+            .field("capacity", &self.capacity)
+            .field("compression", &self.compression)
+            .field("usage", &self.usage())
+            .finish()
+    }
+}
+
+impl FileSecondaryCache {
+    /// Creates the cache file at `path`, discarding the blocks cached by a previous run.
+    pub fn create(
+        path: impl AsRef<Path>,
+        capacity: u64,
+        compression: CompressionType,
+    ) -> Result<Self> {
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        Ok(Self {
+            file,
+            capacity,
+            compression,
+            index: Mutex::new(FileCacheIndex {
+                blocks: HashMap::new(),
+                written: VecDeque::new(),
+                head: 0,
+                usage: 0,
+            }),
+        })
+    }
+}
+
+impl SecondaryCache for FileSecondaryCache {
+    fn lookup(&self, key: &CacheKey) -> Result<Option<CachedBlock>> {
+        // Hold the lock while reading, so that the block is not overwritten in the meantime.
+        let index = self.index.lock();
+        let Some(&(offset, len)) = index.blocks.get(key) else {
+            return Ok(None);
+        };
+        let mut buf = vec![0; len as usize];
+        self.file.read_exact_at(&mut buf, offset)?;
+        decode_block(&buf).map(Some)
+    }
+
+    fn insert(&self, key: CacheKey, block: &CachedBlock) -> Result<()> {
+        let buf = encode_block(block, self.compression);
+        let len = buf.len() as u64;
+        if len > self.capacity {
+            return Ok(());
+        }
+        let mut index = self.index.lock();
+        if let Some((_, len)) = index.blocks.remove(&key) {
+            index.usage -= len;
+        }
+        if index.head + len > self.capacity {
+            // Wrap around, dropping the blocks after the head.
+            while let Some((key, offset, len)) = index.written.pop_front() {
+                if offset < index.head {
+                    index.written.push_front((key, offset, len));
+                    break;
+                }
+                index.drop_written(&key, offset, len);
+            }
+            index.head = 0;
+        }
+        // Drop the oldest blocks overlapping the new one.
+        let end = index.head + len;
+        while let Some(&(key, offset, len)) = index.written.front() {
+            if offset >= end || offset < index.head {
+                break;
+            }
+            index.written.pop_front();
+            index.drop_written(&key, offset, len);
+        }
+        let offset = index.head;
+        self.file.write_all_at(&buf, offset)?;
+        index.blocks.insert(key, (offset, len));
+        index.written.push_back((key, offset, len));
+        index.head = end;
+        index.usage += len;
+        Ok(())
+    }
+
+    fn erase(&self, key: &CacheKey) {
+        let mut index = self.index.lock();
+        if let Some((_, len)) = index.blocks.remove(key) {
+            index.usage -= len;
+        }
+    }
+
+    fn usage(&self) -> u64 {
+        self.index.lock().usage
+    }
+}
+
+impl FileCacheIndex {
+    /// Drops a block that is about to be overwritten, unless it has been erased or rewritten.
+    fn drop_written(&mut self, key: &CacheKey, offset: u64, len: u64) {
+        if self.blocks.get(key) == Some(&(offset, len)) {
+            self.blocks.remove(key);
+            self.usage -= len;
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{bail, ensure, Context, Result};
+use bytes::Bytes;
+use parking_lot::RwLock;
+
+use crate::block::BlockIterator;
+use crate::column_family::{
+    ColumnFamily, ColumnFamilyOptions, DEFAULT_COLUMN_FAMILY, DEFAULT_COLUMN_FAMILY_ID,
+};
+use crate::key::KeyVec;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions, LsmStorageState};
+use crate::manifest::Manifest;
+use crate::orphan_files::find_orphan_files;
+use crate::table::{FileObject, SsTable};
+use crate::wal::Wal;
+
+/// The result of checking a storage directory.
+#[derive(Debug, Clone, Default)]
+pub struct CheckReport {
+    /// The number of SSTs read.
+    pub num_sstables: usize,
+    /// The number of entries in the SSTs read.
+    pub num_entries: usize,
+    /// The number of WALs read.
+    pub num_wals: usize,
+    /// The SSTs and WALs referenced by the manifest which do not exist.
+    pub missing_files: Vec<PathBuf>,
+    /// The SSTs and WALs not referenced by the manifest, which are deleted when the storage is
+    /// opened.
+    pub orphan_files: Vec<PathBuf>,
+    /// The inconsistencies found in the files.
+    pub errors: Vec<String>,
+    /// The corrupted WAL records dropped by the next recovery, which are expected after a crash.
+    pub warnings: Vec<String>,
+}
+
+impl CheckReport {
+    /// Whether the storage can be opened without losing data. Orphan files and warnings are not
+    /// taken into account.
+    pub fn is_ok(&self) -> bool {
+        self.missing_files.is_empty() && self.errors.is_empty()
+    }
+}
+
+/// Checks a storage directory without opening the storage or changing any file. The manifest is
+/// replayed, and every SST it references is read: the checksums of all blocks, the key order
+/// within and across the SSTs of each sorted run, the filters and the max timestamps are
+/// verified. The WALs of the memtables are read if `enable_wal` is set.
+///
+/// The compaction options of the default column family and the WAL options must be the ones the
+/// storage is opened with.
+pub fn check_storage(path: impl AsRef<Path>, options: &LsmStorageOptions) -> Result<CheckReport> {
+    let path = path.as_ref();
+    if !Manifest::exists(path) {
+        bail!("no storage in {}", path.display());
+    }
+    let records = Manifest::read_records(path)?;
+    let mut column_families = BTreeMap::new();
+    column_families.insert(
+        DEFAULT_COLUMN_FAMILY_ID,
+        ColumnFamily::new(
+            DEFAULT_COLUMN_FAMILY_ID,
+            DEFAULT_COLUMN_FAMILY.to_string(),
+            &ColumnFamilyOptions {
+                compaction_options: options.compaction_options.clone(),
+            },
+            Arc::new(RwLock::new(Arc::new(LsmStorageState::create(
+                &options.compaction_options,
+            )))),
+        ),
+    );
+    let replayed = LsmStorageInner::replay_manifest(&mut column_families, records)
+        .context("failed to replay manifest")?;
+
+    let mut report = CheckReport::default();
+    let mut live_ssts = HashSet::new();
+    for cf in column_families.values() {
+        let state = cf.state.read().clone();
+        let mut sstables = BTreeMap::new();
+        for sst_id in state
+            .l0_sstables
+            .iter()
+            .chain(state.levels.iter().flat_map(|(_, files)| files))
+        {
+            if !live_ssts.insert(*sst_id) {
+                report.errors.push(format!(
+                    "column family {}: SST {} is referenced more than once",
+                    cf.name, sst_id
+                ));
+                continue;
+            }
+            let sst_path = LsmStorageInner::path_of_sst_static(path, *sst_id);
+            if !sst_path.exists() {
+                report.missing_files.push(sst_path);
+                continue;
+            }
+            match check_sstable(*sst_id, &sst_path) {
+                Ok((sst, num_entries)) => {
+                    report.num_sstables += 1;
+                    report.num_entries += num_entries;
+                    sstables.insert(*sst_id, sst);
+                }
+                Err(e) => report
+                    .errors
+                    .push(format!("{}: {:#}", sst_path.display(), e)),
+            }
+        }
+
+        // The SSTs of a level or a tier are a sorted run.
+        for (level, files) in &state.levels {
+            let mut ssts = files
+                .iter()
+                .filter_map(|id| sstables.get(id))
+                .collect::<Vec<_>>();
+            ssts.sort_by(|x, y| x.first_key().cmp(y.first_key()));
+            for pair in ssts.windows(2) {
+                if pair[0].last_key() > pair[1].first_key() {
+                    report.errors.push(format!(
+                        "column family {}: SSTs {} and {} overlap in level {}",
+                        cf.name,
+                        pair[0].sst_id(),
+                        pair[1].sst_id(),
+                        level
+                    ));
+                }
+            }
+        }
+    }
+
+    if options.enable_wal {
+        for memtable_id in &replayed.memtables {
+            let wal_path = LsmStorageInner::path_of_wal_static(path, *memtable_id);
+            if !wal_path.exists() {
+                report.missing_files.push(wal_path);
+                continue;
+            }
+            let result = Wal::read(
+                &wal_path,
+                *memtable_id,
+                options.wal.recovery_mode,
+                |column_family_id, _, _, _| {
+                    ensure!(
+                        column_families.contains_key(&column_family_id),
+                        "entry of unknown column family {}",
+                        column_family_id
+                    );
+                    Ok(())
+                },
+            );
+            match result {
+                Ok(dropped) => {
+                    report.num_wals += 1;
+                    if dropped {
+                        report.warnings.push(format!(
+                            "{}: records after a corruption are dropped",
+                            wal_path.display()
+                        ));
+                    }
+                }
+                Err(e) => report
+                    .errors
+                    .push(format!("{}: {:#}", wal_path.display(), e)),
+            }
+        }
+    }
+
+    let live_wals = replayed.memtables.iter().copied().collect();
+    let (orphan_ssts, orphan_wals) = find_orphan_files(path, &live_ssts, &live_wals)?;
+    report.orphan_files = orphan_ssts.into_iter().chain(orphan_wals).collect();
+    Ok(report)
+}
+
+/// Reads all blocks of an SST, and returns it with its number of entries.
+fn check_sstable(sst_id: usize, path: &Path) -> Result<(SsTable, usize)> {
+    // The properties, the range tombstones, the index and the filter are verified when the SST
+    // is opened, or when their partitions are read.
+    let sst = SsTable::open(sst_id, None, FileObject::open(path)?)?;
+    let block_meta = sst.read_block_meta()?;
+    if let Some(index) = &sst.partitioned_index {
+        for partition_idx in 0..index.partitions.len() {
+            sst.read_filter_partition(partition_idx)?;
+        }
+    }
+    ensure!(
+        block_meta.len() == sst.num_of_blocks(),
+        "index has {} blocks instead of {}",
+        block_meta.len(),
+        sst.num_of_blocks()
+    );
+
+    let mut num_entries = 0;
+    let mut max_ts = 0;
+    let mut last_key = KeyVec::new();
+    for (block_idx, meta) in block_meta.iter().enumerate() {
+        let block = sst
+            .read_block(block_idx)
+            .with_context(|| format!("failed to read block {}", block_idx))?;
+        let mut iter = BlockIterator::create_and_seek_to_first(block);
+        ensure!(iter.is_valid(), "block {} is empty", block_idx);
+        ensure!(
+            iter.key() == meta.first_key.as_key_slice(),
+            "first key of block {} does not match the index",
+            block_idx
+        );
+        while iter.is_valid() {
+            let key = iter.key();
+            ensure!(
+                num_entries == 0 || key > last_key.as_key_slice(),
+                "key {:?} at ts {} of block {} is out of order",
+                Bytes::copy_from_slice(key.key_ref()),
+                key.ts(),
+                block_idx
+            );
+            ensure!(
+                sst.may_contain_key(key.key_ref())?,
+                "filter does not contain key {:?}",
+                Bytes::copy_from_slice(key.key_ref())
+            );
+            max_ts = max_ts.max(key.ts());
+            last_key.set_from_slice(key);
+            num_entries += 1;
+            iter.next();
+        }
+        ensure!(
+            last_key.as_key_slice() == meta.last_key.as_key_slice(),
+            "last key of block {} does not match the index",
+            block_idx
+        );
+    }
+
+    for pair in sst.range_tombstones().windows(2) {
+        ensure!(
+            pair[0].start <= pair[1].start,
+            "range tombstones are out of order"
+        );
+    }
+    for tombstone in sst.range_tombstones() {
+        ensure!(
+            tombstone.start < tombstone.end,
+            "empty range tombstone {:?}",
+            tombstone.start
+        );
+        max_ts = max_ts.max(tombstone.ts);
+    }
+    ensure!(
+        max_ts == sst.max_ts(),
+        "max timestamp is {} instead of {}",
+        sst.max_ts(),
+        max_ts
+    );
+    Ok((sst, num_entries))
+}
@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+use std::ops::Bound;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+
+use crate::compact::{CompactionController, CompactionOptions, RunningCompactions};
+use crate::iterators::{Direction, StorageIterator};
+use crate::lsm_iterator::{FusedIterator, LsmIterator};
+use crate::lsm_storage::{
+    LsmStorageInner, LsmStorageState, MiniLsm, ScanOptions, WriteBatchRecord,
+};
+use crate::manifest::ManifestRecord;
+use crate::mem_table::MemTable;
+use crate::mvcc::txn::Transaction;
+use crate::mvcc::CommittedTxnData;
+
+/// The name of the column family that always exists. `MiniLsm::get`, `put`, `scan` and
+/// transactions operate on it.
+pub const DEFAULT_COLUMN_FAMILY: &str = "default";
+pub(crate) const DEFAULT_COLUMN_FAMILY_ID: usize = 0;
+
+/// Options of a column family. The options of the default column family come from
+/// `LsmStorageOptions`, and all other options are shared by all column families.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ColumnFamilyOptions {
+    pub compaction_options: CompactionOptions,
+}
+
+/// A named key space with its own memtables, levels and compaction. All column families share the
+/// block cache, the MVCC timestamps, the WAL and the manifest.
+///
+/// The memtables of all column families are frozen and flushed together, so that the memtables
+/// created at the same time (a generation) have the same id and log to the same WAL, which is
+/// deleted once the whole generation is flushed.
+pub(crate) struct ColumnFamily {
+    pub(crate) id: usize,
+    pub(crate) name: String,
+    pub(crate) options: ColumnFamilyOptions,
+    pub(crate) state: Arc<RwLock<Arc<LsmStorageState>>>,
+    pub(crate) compaction_controller: CompactionController,
+    pub(crate) running_compactions: Mutex<RunningCompactions>,
+}
+
+impl ColumnFamily {
+    pub(crate) fn new(
+        id: usize,
+        name: String,
+        options: &ColumnFamilyOptions,
+        state: Arc<RwLock<Arc<LsmStorageState>>>,
+    ) -> Self {
+        Self {
+            id,
+            name,
+            options: options.clone(),
+            compaction_controller: CompactionController::new(&options.compaction_options),
+            state,
+            running_compactions: Mutex::new(RunningCompactions::default()),
+        }
+    }
+
+    pub(crate) fn is_default(&self) -> bool {
+        self.id == DEFAULT_COLUMN_FAMILY_ID
+    }
+}
+
+/// An iterator over a column family. It keeps its read timestamp registered, so that the versions
+/// it reads are not garbage-collected while it is alive.
+pub struct ColumnFamilyIterator {
+    iter: FusedIterator<LsmIterator>,
+    _txn: Arc<Transaction>,
+}
+
+impl StorageIterator for ColumnFamilyIterator {
+    type KeyType<'a> = &'a [u8];
+
+    fn value(&self) -> &[u8] {
+        self.iter.value()
+    }
+
+    fn key(&self) -> &[u8] {
+        self.iter.key()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.iter.is_valid()
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.iter.next()
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        self.iter.prev()
+    }
+
+    fn seek_to_first(&mut self) -> Result<()> {
+        self.iter.seek_to_first()
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        self.iter.seek_to_last()
+    }
+
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        self.iter.seek(key)
+    }
+
+    fn seek_for_prev(&mut self, key: &[u8]) -> Result<()> {
+        self.iter.seek_for_prev(key)
+    }
+
+    fn num_active_iterators(&self) -> usize {
+        self.iter.num_active_iterators()
+    }
+}
+
+impl LsmStorageInner {
+    pub(crate) fn default_column_family(&self) -> Arc<ColumnFamily> {
+        self.column_families.read()[&DEFAULT_COLUMN_FAMILY_ID].clone()
+    }
+
+    pub(crate) fn column_family(&self, name: &str) -> Result<Arc<ColumnFamily>> {
+        let column_families = self.column_families.read();
+        match column_families.values().find(|cf| cf.name == name) {
+            Some(cf) => Ok(cf.clone()),
+            None => bail!("column family {} does not exist", name),
+        }
+    }
+
+    /// All column families, ordered by id. The default column family is the first one.
+    pub(crate) fn column_families(&self) -> Vec<Arc<ColumnFamily>> {
+        self.column_families.read().values().cloned().collect()
+    }
+
+    pub fn list_column_families(&self) -> Vec<String> {
+        self.column_families
+            .read()
+            .values()
+            .map(|cf| cf.name.clone())
+            .collect()
+    }
+
+    pub fn create_column_family(&self, name: &str, options: ColumnFamilyOptions) -> Result<()> {
+        let state_lock = self.state_lock.lock();
+        if self.column_family(name).is_ok() {
+            bail!("column family {} already exists", name);
+        }
+        let id = self.column_families.read().keys().max().unwrap() + 1;
+        // The new column family joins the current generation of memtables.
+        let memtable = {
+            let guard = self.state.read();
+            Arc::new(
+                MemTable::create_for_column_family(
+                    guard.memtable.id(),
+                    id,
+                    guard.memtable.memtable_type(),
+                    guard.memtable.wal().cloned(),
+                )
+                .with_write_buffer_manager(guard.memtable.write_buffer_manager()),
+            )
+        };
+        let mut state = LsmStorageState::create(&options.compaction_options);
+        state.memtable = memtable;
+        self.manifest().add_record(
+            &state_lock,
+            ManifestRecord::CreateColumnFamily(id, name.to_string(), options.clone()),
+        )?;
+        let cf = ColumnFamily::new(
+            id,
+            name.to_string(),
+            &options,
+            Arc::new(RwLock::new(Arc::new(state))),
+        );
+        self.column_families.write().insert(id, Arc::new(cf));
+        self.maybe_roll_manifest(&state_lock)?;
+        Ok(())
+    }
+
+    pub fn get_cf(self: &Arc<Self>, name: &str, key: &[u8]) -> Result<Option<Bytes>> {
+        let cf = self.column_family(name)?;
+        let txn = self.mvcc().new_txn(self.clone(), false);
+        self.get_with_ts_from(&cf.state, key, txn.read_ts, self.value_log.clone())
+    }
+
+    pub fn scan_cf(
+        self: &Arc<Self>,
+        name: &str,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<ColumnFamilyIterator> {
+        let cf = self.column_family(name)?;
+        let txn = self.mvcc().new_txn(self.clone(), false);
+        Ok(ColumnFamilyIterator {
+            iter: self.scan_with_ts_from(
+                &cf.state,
+                lower,
+                upper,
+                txn.read_ts,
+                Direction::Forward,
+                ScanOptions::default(),
+            )?,
+            _txn: txn,
+        })
+    }
+
+    /// Atomically writes a batch of records to several column families, with a single commit
+    /// timestamp. In serializable mode, the writes to the default column family conflict with the
+    /// concurrent transactions that read the same keys.
+    pub fn write_batch_cf<T: AsRef<[u8]>>(
+        &self,
+        batch: &[(&str, WriteBatchRecord<T>)],
+    ) -> Result<()> {
+        let mut column_families = HashMap::new();
+        for (name, _) in batch {
+            if !column_families.contains_key(name) {
+                column_families.insert(*name, self.column_family(name)?);
+            }
+        }
+        self.maybe_stall_write(batch.iter().map(|(_, record)| record.size()).sum());
+        let _commit_lock = self
+            .options
+            .serializable
+            .then(|| self.mvcc().commit_lock.lock());
+        let ts = self.group_commit(
+            batch
+                .iter()
+                .map(|(name, record)| (column_families[name].clone(), record))
+                .collect(),
+            false,
+        )?;
+        if self.options.serializable {
+            let key_hashes = batch
+                .iter()
+                .filter(|(name, record)| {
+                    column_families[name].is_default()
+                        && !matches!(record, WriteBatchRecord::DelRange(_, _))
+                })
+                .map(|(_, record)| farmhash::hash32(record.key()))
+                .collect();
+            self.mvcc().committed_txns.lock().insert(
+                ts,
+                CommittedTxnData {
+                    key_hashes,
+                    read_ts: ts - 1,
+                    commit_ts: ts,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    pub fn put_cf(&self, name: &str, key: &[u8], value: &[u8]) -> Result<()> {
+        self.write_batch_cf(&[(name, WriteBatchRecord::Put(key, value))])
+    }
+
+    pub fn delete_cf(&self, name: &str, key: &[u8]) -> Result<()> {
+        self.write_batch_cf(&[(name, WriteBatchRecord::Del(key))])
+    }
+}
+
+impl MiniLsm {
+    pub fn create_column_family(&self, name: &str, options: ColumnFamilyOptions) -> Result<()> {
+        self.inner.create_column_family(name, options)
+    }
+
+    pub fn list_column_families(&self) -> Vec<String> {
+        self.inner.list_column_families()
+    }
+
+    pub fn get_cf(&self, name: &str, key: &[u8]) -> Result<Option<Bytes>> {
+        self.inner.get_cf(name, key)
+    }
+
+    pub fn put_cf(&self, name: &str, key: &[u8], value: &[u8]) -> Result<()> {
+        self.inner.put_cf(name, key, value)
+    }
+
+    pub fn delete_cf(&self, name: &str, key: &[u8]) -> Result<()> {
+        self.inner.delete_cf(name, key)
+    }
+
+    pub fn write_batch_cf<T: AsRef<[u8]>>(
+        &self,
+        batch: &[(&str, WriteBatchRecord<T>)],
+    ) -> Result<()> {
+        self.inner.write_batch_cf(batch)
+    }
+
+    pub fn scan_cf(
+        &self,
+        name: &str,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<ColumnFamilyIterator> {
+        self.inner.scan_cf(name, lower, upper)
+    }
+}
@@ -2,11 +2,12 @@ mod leveled;
 mod simple_leveled;
 mod tiered;
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
 pub use leveled::{LeveledCompactionController, LeveledCompactionOptions, LeveledCompactionTask};
 use serde::{Deserialize, Serialize};
 pub use simple_leveled::{
@@ -14,16 +15,20 @@ pub use simple_leveled::{
 };
 pub use tiered::{TieredCompactionController, TieredCompactionOptions, TieredCompactionTask};
 
+use crate::column_family::ColumnFamily;
 use crate::iterators::concat_iterator::SstConcatIterator;
 use crate::iterators::merge_iterator::MergeIterator;
 use crate::iterators::two_merge_iterator::TwoMergeIterator;
-use crate::iterators::StorageIterator;
-use crate::key::KeySlice;
+use crate::iterators::{RecordKind, StorageIterator};
+use crate::key::{self, KeySlice};
 use crate::lsm_storage::{CompactionFilter, LsmStorageInner, LsmStorageState};
 use crate::manifest::ManifestRecord;
+use crate::range_tombstone::{RangeTombstone, RangeTombstones};
+use crate::rate_limiter::IoPriority;
 use crate::table::{SsTable, SsTableBuilder, SsTableIterator};
+use crate::value_log::ValuePointer;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum CompactionTask {
     Leveled(LeveledCompactionTask),
     Tiered(TieredCompactionTask),
@@ -43,6 +48,167 @@ impl CompactionTask {
             CompactionTask::Tiered(task) => task.bottom_tier_included,
         }
     }
+
+    /// A trivial move only relinks the input SSTs into the lower level without rewriting them.
+    pub fn is_trivial_move(&self) -> bool {
+        match self {
+            CompactionTask::ForceFullCompaction { .. } => false,
+            CompactionTask::Leveled(task) => task.is_trivial_move,
+            CompactionTask::Simple(task) => task.is_trivial_move,
+            CompactionTask::Tiered(task) => task.is_trivial_move,
+        }
+    }
+
+    /// Levels read or written by this task, where L0 is 0. Tiered compaction has no levels.
+    pub fn levels(&self) -> Vec<usize> {
+        match self {
+            CompactionTask::ForceFullCompaction { .. } => vec![0, 1],
+            CompactionTask::Leveled(LeveledCompactionTask {
+                upper_level,
+                lower_level,
+                ..
+            })
+            | CompactionTask::Simple(SimpleLeveledCompactionTask {
+                upper_level,
+                lower_level,
+                ..
+            }) => vec![upper_level.unwrap_or(0), *lower_level],
+            CompactionTask::Tiered(_) => Vec::new(),
+        }
+    }
+
+    /// All SSTs read by this task.
+    pub fn input_sst_ids(&self) -> Vec<usize> {
+        match self {
+            CompactionTask::ForceFullCompaction {
+                l0_sstables,
+                l1_sstables,
+            } => l0_sstables.iter().chain(l1_sstables).copied().collect(),
+            CompactionTask::Leveled(LeveledCompactionTask {
+                upper_level_sst_ids,
+                lower_level_sst_ids,
+                ..
+            })
+            | CompactionTask::Simple(SimpleLeveledCompactionTask {
+                upper_level_sst_ids,
+                lower_level_sst_ids,
+                ..
+            }) => upper_level_sst_ids
+                .iter()
+                .chain(lower_level_sst_ids)
+                .copied()
+                .collect(),
+            CompactionTask::Tiered(TieredCompactionTask { tiers, .. }) => tiers
+                .iter()
+                .flat_map(|(_, ssts)| ssts.iter().copied())
+                .collect(),
+        }
+    }
+}
+
+/// SSTs and levels that are read or written by compaction tasks that are currently running. A new
+/// task must not touch any of them.
+#[derive(Debug, Default)]
+pub struct RunningCompactions {
+    sst_ids: HashSet<usize>,
+    /// Levels of leveled and simple leveled compaction, where L0 is 0.
+    levels: HashSet<usize>,
+    num_tasks: usize,
+}
+
+impl RunningCompactions {
+    pub fn is_empty(&self) -> bool {
+        self.num_tasks == 0
+    }
+
+    pub fn num_tasks(&self) -> usize {
+        self.num_tasks
+    }
+
+    pub fn is_level_busy(&self, level: usize) -> bool {
+        self.levels.contains(&level)
+    }
+
+    pub fn is_sst_busy(&self, sst_id: usize) -> bool {
+        self.sst_ids.contains(&sst_id)
+    }
+
+    pub(crate) fn add(&mut self, task: &CompactionTask) {
+        for sst_id in task.input_sst_ids() {
+            assert!(
+                self.sst_ids.insert(sst_id),
+                "{}.sst is being compacted",
+                sst_id
+            );
+        }
+        for level in task.levels() {
+            assert!(self.levels.insert(level), "L{} is being compacted", level);
+        }
+        self.num_tasks += 1;
+    }
+
+    pub(crate) fn remove(&mut self, task: &CompactionTask) {
+        for sst_id in task.input_sst_ids() {
+            self.sst_ids.remove(&sst_id);
+        }
+        for level in task.levels() {
+            self.levels.remove(&level);
+        }
+        self.num_tasks -= 1;
+    }
+}
+
+/// Counts the value log record referenced by a dropped value as garbage.
+fn add_value_log_garbage(garbage: &mut HashMap<usize, u64>, value: &[u8]) {
+    if let Some(pointer) = ValuePointer::decode(value) {
+        *garbage.entry(pointer.segment_id).or_default() += pointer.len as u64;
+    }
+}
+
+/// Total size in bytes of the given SSTs.
+/// Adds the range tombstones clipped to the user key range `[lower, upper)` to the SST.
+fn add_range_tombstones(
+    builder: &mut SsTableBuilder,
+    range_tombstones: &[RangeTombstone],
+    lower: Option<&[u8]>,
+    upper: Option<&[u8]>,
+) {
+    for tombstone in range_tombstones {
+        if let Some(tombstone) = tombstone.clip(lower, upper) {
+            builder.add_range_tombstone(tombstone);
+        }
+    }
+}
+
+pub(crate) fn ssts_size(snapshot: &LsmStorageState, sst_ids: &[usize]) -> u64 {
+    sst_ids
+        .iter()
+        .map(|id| snapshot.sstables[id].table_size())
+        .sum()
+}
+
+/// Returns true if no two SSTs in `sst_ids` share any user key, so that they can form a sorted run
+/// as-is. SSTs without metadata in the snapshot are treated as overlapping.
+pub(crate) fn ssts_disjoint(snapshot: &LsmStorageState, sst_ids: &[usize]) -> bool {
+    let Some(mut ssts) = sst_ids
+        .iter()
+        .map(|id| snapshot.sstables.get(id))
+        .collect::<Option<Vec<_>>>()
+    else {
+        return false;
+    };
+    ssts.sort_by(|x, y| x.first_key().cmp(y.first_key()));
+    ssts.windows(2)
+        .all(|w| w[0].last_key().key_ref() < w[1].first_key().key_ref())
+}
+
+/// Sort SST ids by their first keys.
+pub(crate) fn sort_ssts_by_first_key(snapshot: &LsmStorageState, sst_ids: &mut [usize]) {
+    sst_ids.sort_by(|x, y| {
+        snapshot.sstables[x]
+            .first_key()
+            .cmp(snapshot.sstables[y].first_key())
+    });
 }
 
 pub(crate) enum CompactionController {
@@ -53,21 +219,52 @@ pub(crate) enum CompactionController {
 }
 
 impl CompactionController {
-    pub fn generate_compaction_task(&self, snapshot: &LsmStorageState) -> Option<CompactionTask> {
+    pub fn new(options: &CompactionOptions) -> Self {
+        match options {
+            CompactionOptions::Leveled(options) => {
+                CompactionController::Leveled(LeveledCompactionController::new(options.clone()))
+            }
+            CompactionOptions::Tiered(options) => {
+                CompactionController::Tiered(TieredCompactionController::new(options.clone()))
+            }
+            CompactionOptions::Simple(options) => CompactionController::Simple(
+                SimpleLeveledCompactionController::new(options.clone()),
+            ),
+            CompactionOptions::NoCompaction => CompactionController::NoCompaction,
+        }
+    }
+
+    /// Generates a compaction task that does not conflict with the running compactions.
+    pub fn generate_compaction_task_with_running(
+        &self,
+        snapshot: &LsmStorageState,
+        running: &RunningCompactions,
+    ) -> Option<CompactionTask> {
         match self {
             CompactionController::Leveled(ctrl) => ctrl
-                .generate_compaction_task(snapshot)
+                .generate_compaction_task_with_running(snapshot, running)
                 .map(CompactionTask::Leveled),
             CompactionController::Simple(ctrl) => ctrl
-                .generate_compaction_task(snapshot)
+                .generate_compaction_task_with_running(snapshot, running)
                 .map(CompactionTask::Simple),
             CompactionController::Tiered(ctrl) => ctrl
-                .generate_compaction_task(snapshot)
+                .generate_compaction_task_with_running(snapshot, running)
                 .map(CompactionTask::Tiered),
             CompactionController::NoCompaction => unreachable!(),
         }
     }
 
+    /// Estimates the number of bytes that compactions need to rewrite before the LSM tree reaches
+    /// its target shape. Write stalls are triggered when this grows too large.
+    pub fn estimate_pending_compaction_bytes(&self, snapshot: &LsmStorageState) -> u64 {
+        match self {
+            CompactionController::Leveled(ctrl) => ctrl.estimate_pending_compaction_bytes(snapshot),
+            CompactionController::Simple(ctrl) => ctrl.estimate_pending_compaction_bytes(snapshot),
+            CompactionController::Tiered(ctrl) => ctrl.estimate_pending_compaction_bytes(snapshot),
+            CompactionController::NoCompaction => 0,
+        }
+    }
+
     pub fn apply_compaction_result(
         &self,
         snapshot: &LsmStorageState,
@@ -89,6 +286,30 @@ impl CompactionController {
     }
 }
 
+/// Applies the result of a full compaction, which replaces all SSTs in L0 and L1 by its output.
+/// Returns the new state and the SSTs to remove, like `apply_compaction_result`.
+pub(crate) fn apply_force_full_compaction_result(
+    snapshot: &LsmStorageState,
+    l0_sstables: &[usize],
+    l1_sstables: &[usize],
+    output: &[usize],
+) -> (LsmStorageState, Vec<usize>) {
+    let mut snapshot = snapshot.clone();
+    let mut l0_sstables_map = l0_sstables.iter().copied().collect::<HashSet<_>>();
+    snapshot.l0_sstables = snapshot
+        .l0_sstables
+        .iter()
+        .filter(|x| !l0_sstables_map.remove(x))
+        .copied()
+        .collect::<Vec<_>>();
+    assert!(l0_sstables_map.is_empty());
+    assert_eq!(snapshot.levels[0].1, l1_sstables);
+    snapshot.levels[0].1 = output.to_vec();
+    let mut files_to_remove = l0_sstables.to_vec();
+    files_to_remove.extend(l1_sstables);
+    (snapshot, files_to_remove)
+}
+
 impl CompactionController {
     pub fn flush_to_l0(&self) -> bool {
         matches!(
@@ -98,7 +319,7 @@ impl CompactionController {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum CompactionOptions {
     /// Leveled compaction with partial compaction + dynamic level support (= RocksDB's Leveled
     /// Compaction)
@@ -112,20 +333,44 @@ pub enum CompactionOptions {
 }
 
 impl LsmStorageInner {
+    /// Writes the content of `iter` to new SSTs, stopping at the first user key `>= upper`.
+    ///
+    /// `range_tombstones` are the range tombstones of the input, clipped to the key range of the
+    /// compaction. The versions they delete are dropped once below the watermark, and they are
+    /// dropped themselves once below the watermark at the bottom level. The kept ones are split
+    /// across the new SSTs by key range.
     fn compact_generate_sst_from_iter(
         &self,
-        mut iter: impl for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>,
+        mut iter: impl for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>> + 'static,
+        upper: Option<&[u8]>,
         compact_to_bottom_level: bool,
+        range_tombstones: &[RangeTombstone],
     ) -> Result<Vec<Arc<SsTable>>> {
         let mut builder = None;
         let mut new_sst = Vec::new();
         let watermark = self.mvcc().watermark();
+        let deleting_tombstones = RangeTombstones::new(range_tombstones.iter().cloned(), watermark);
+        let mut kept_tombstones = Vec::new();
+        for tombstone in range_tombstones {
+            if !(compact_to_bottom_level && tombstone.ts <= watermark) {
+                kept_tombstones.push(tombstone.clone());
+            }
+        }
+        // The lower bound of the key range of the SST being built.
+        let mut sst_lower = None::<Bytes>;
         let mut last_key = Vec::<u8>::new();
         let mut first_key_below_watermark = false;
         let compaction_filters = self.compaction_filters.lock().clone();
+        // Bytes of value log records referenced by the dropped versions, by segment id.
+        let mut value_log_garbage = HashMap::new();
         'outer: while iter.is_valid() {
+            if let Some(upper) = upper {
+                if iter.key().key_ref() >= upper {
+                    break;
+                }
+            }
             if builder.is_none() {
-                builder = Some(SsTableBuilder::new(self.options.block_size));
+                builder = Some(self.new_sst_builder(IoPriority::Low));
             }
 
             let same_as_last_key = iter.key().key_ref() == last_key;
@@ -133,9 +378,43 @@ impl LsmStorageInner {
                 first_key_below_watermark = true;
             }
 
+            let builder_inner = builder.as_mut().unwrap();
+            if builder_inner.estimated_size() >= self.options.target_sst_size && !same_as_last_key {
+                let sst_id = self.next_sst_id();
+                let mut old_builder = builder.take().unwrap();
+                let sst_upper = iter.key().key_ref();
+                add_range_tombstones(
+                    &mut old_builder,
+                    &kept_tombstones,
+                    sst_lower.as_deref(),
+                    Some(sst_upper),
+                );
+                sst_lower = Some(Bytes::copy_from_slice(sst_upper));
+                let sst = Arc::new(old_builder.build(
+                    sst_id,
+                    Some(self.block_cache.clone()),
+                    self.path_of_sst(sst_id),
+                )?);
+                new_sst.push(sst);
+                builder = Some(self.new_sst_builder(IoPriority::Low));
+            }
+
+            if iter.key().ts() <= watermark
+                && deleting_tombstones.covers(iter.key().key_ref(), iter.key().ts())
+            {
+                // The older versions of the key are deleted by the same tombstone.
+                add_value_log_garbage(&mut value_log_garbage, iter.value());
+                last_key.clear();
+                last_key.extend(iter.key().key_ref());
+                iter.next()?;
+                first_key_below_watermark = false;
+                continue;
+            }
+
             if compact_to_bottom_level
                 && !same_as_last_key
                 && iter.key().ts() <= watermark
+                && iter.record_kind() == RecordKind::Value
                 && iter.value().is_empty()
             {
                 last_key.clear();
@@ -147,6 +426,7 @@ impl LsmStorageInner {
 
             if iter.key().ts() <= watermark {
                 if same_as_last_key && !first_key_below_watermark {
+                    add_value_log_garbage(&mut value_log_garbage, iter.value());
                     iter.next()?;
                     continue;
                 }
@@ -158,6 +438,7 @@ impl LsmStorageInner {
                         match filter {
                             CompactionFilter::Prefix(x) => {
                                 if iter.key().key_ref().starts_with(x) {
+                                    add_value_log_garbage(&mut value_log_garbage, iter.value());
                                     iter.next()?;
                                     continue 'outer;
                                 }
@@ -165,24 +446,25 @@ impl LsmStorageInner {
                         }
                     }
                 }
-            }
-
-            let builder_inner = builder.as_mut().unwrap();
 
-            if builder_inner.estimated_size() >= self.options.target_sst_size && !same_as_last_key {
-                let sst_id = self.next_sst_id();
-                let old_builder = builder.take().unwrap();
-                let sst = Arc::new(old_builder.build(
-                    sst_id,
-                    Some(self.block_cache.clone()),
-                    self.path_of_sst(sst_id),
-                )?);
-                new_sst.push(sst);
-                builder = Some(SsTableBuilder::new(self.options.block_size));
+                if iter.record_kind() == RecordKind::MergeOperand {
+                    // The older versions are dropped, so they are combined with the operands.
+                    if !same_as_last_key {
+                        last_key.clear();
+                        last_key.extend(iter.key().key_ref());
+                    }
+                    self.fold_merge_operands(
+                        &mut iter,
+                        builder.as_mut().unwrap(),
+                        compact_to_bottom_level,
+                        &deleting_tombstones,
+                    )?;
+                    continue;
+                }
             }
 
             let builder_inner = builder.as_mut().unwrap();
-            builder_inner.add(iter.key(), iter.value());
+            builder_inner.add_with_kind(iter.key(), iter.value(), iter.record_kind());
 
             if !same_as_last_key {
                 last_key.clear();
@@ -191,7 +473,12 @@ impl LsmStorageInner {
 
             iter.next()?;
         }
-        if let Some(builder) = builder {
+        let mut builder = match builder {
+            Some(builder) => builder,
+            None => self.new_sst_builder(IoPriority::Low),
+        };
+        add_range_tombstones(&mut builder, &kept_tombstones, sst_lower.as_deref(), None);
+        if !builder.is_empty() {
             let sst_id = self.next_sst_id(); // lock dropped here
             let sst = Arc::new(builder.build(
                 sst_id,
@@ -200,14 +487,227 @@ impl LsmStorageInner {
             )?);
             new_sst.push(sst);
         }
+        self.record_value_log_garbage(value_log_garbage)?;
         Ok(new_sst)
     }
 
-    fn compact(&self, task: &CompactionTask) -> Result<Vec<Arc<SsTable>>> {
+    /// Combines the merge operands at the iterator, which are the latest versions of the key below
+    /// the watermark, with the value they apply to, and adds the result to the SST. The iterator is
+    /// left at the first older version not combined.
+    fn fold_merge_operands<I>(
+        &self,
+        iter: &mut I,
+        builder: &mut SsTableBuilder,
+        compact_to_bottom_level: bool,
+        deleting_tombstones: &RangeTombstones,
+    ) -> Result<()>
+    where
+        I: for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>> + 'static,
+    {
+        let merge_operator = self.merge_operator()?.clone();
+        let key = iter.key().to_key_vec();
+        // Newest first.
+        let mut operands = Vec::new();
+        let mut operand_ts = Vec::new();
+        // Nothing is older than the input of a compaction to the bottom level.
+        let mut has_existing_value = compact_to_bottom_level;
+        let mut existing = None;
+        while iter.is_valid() && iter.key().key_ref() == key.key_ref() {
+            if deleting_tombstones.covers(key.key_ref(), iter.key().ts()) {
+                has_existing_value = true;
+                break;
+            }
+            if iter.record_kind() == RecordKind::Value {
+                has_existing_value = true;
+                if !iter.value().is_empty() {
+                    existing = Some(Bytes::copy_from_slice(iter.value()));
+                    iter.next()?;
+                }
+                break;
+            }
+            operands.push(Bytes::copy_from_slice(iter.value()));
+            operand_ts.push(iter.key().ts());
+            iter.next()?;
+        }
+        operands.reverse();
+        if has_existing_value {
+            let value = merge_operator.full_merge(key.key_ref(), existing.as_deref(), &operands);
+            if !(value.is_empty() && compact_to_bottom_level) {
+                builder.add(key.as_key_slice(), &value);
+            }
+        } else if let Some(operand) = merge_operator.partial_merge(key.key_ref(), &operands) {
+            builder.add_with_kind(key.as_key_slice(), &operand, RecordKind::MergeOperand);
+        } else {
+            for (operand, ts) in operands.iter().rev().zip(operand_ts) {
+                builder.add_with_kind(
+                    KeySlice::from_slice(key.key_ref(), ts),
+                    operand,
+                    RecordKind::MergeOperand,
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn compact(&self, cf: &ColumnFamily, task: &CompactionTask) -> Result<Vec<Arc<SsTable>>> {
         let snapshot = {
-            let state = self.state.read();
+            let state = cf.state.read();
             state.clone()
         };
+        if task.is_trivial_move() {
+            let mut sst_ids = task.input_sst_ids();
+            sort_ssts_by_first_key(&snapshot, &mut sst_ids);
+            return Ok(sst_ids
+                .iter()
+                .map(|id| snapshot.sstables[id].clone())
+                .collect());
+        }
+        let split_points = self.subcompaction_split_points(&snapshot, task)?;
+        if split_points.is_empty() {
+            return self.compact_range(&snapshot, task, None, None);
+        }
+
+        // Subcompaction `i` covers user keys in `[split_points[i - 1], split_points[i])`. All
+        // versions of a key fall into the same subcompaction.
+        let mut ranges = Vec::with_capacity(split_points.len() + 1);
+        ranges.push((None, Some(&split_points[0][..])));
+        for points in split_points.windows(2) {
+            ranges.push((Some(&points[0][..]), Some(&points[1][..])));
+        }
+        ranges.push((Some(&split_points[split_points.len() - 1][..]), None));
+        println!(
+            "running compaction with {} subcompactions, split at {:?}",
+            ranges.len(),
+            split_points
+        );
+
+        // The thread of the compaction runs a share of the key ranges itself, the others are
+        // spread over the threads reserved for subcompactions.
+        let num_threads = self.acquire_subcompaction_threads(ranges.len() - 1);
+        let snapshot = &snapshot;
+        let run_ranges = |ranges: &[_]| {
+            ranges
+                .iter()
+                .map(|&(lower, upper)| self.compact_range(snapshot, task, lower, upper))
+                .collect::<Vec<_>>()
+        };
+        let results = std::thread::scope(|scope| {
+            let mut chunks = ranges.chunks(ranges.len().div_ceil(num_threads + 1));
+            let first_chunk = chunks.next().unwrap();
+            let handles = chunks
+                .map(|chunk| scope.spawn(move || run_ranges(chunk)))
+                .collect::<Vec<_>>();
+            let mut results = run_ranges(first_chunk);
+            for handle in handles {
+                match handle.join() {
+                    Ok(chunk_results) => results.extend(chunk_results),
+                    Err(e) => results.push(Err(anyhow!("subcompaction panicked: {:?}", e))),
+                }
+            }
+            results
+        });
+        self.subcompaction_threads
+            .fetch_sub(num_threads, std::sync::atomic::Ordering::SeqCst);
+
+        let mut output = Vec::new();
+        let mut err = None;
+        for result in results {
+            match result {
+                Ok(ssts) => output.extend(ssts),
+                Err(e) => err = Some(e),
+            }
+        }
+        if let Some(err) = err {
+            // Do not leak the outputs of the succeeded subcompactions.
+            for sst in output {
+                std::fs::remove_file(self.path_of_sst(sst.sst_id())).ok();
+            }
+            return Err(err);
+        }
+        Ok(output)
+    }
+
+    /// Reserves up to `wanted` threads for subcompactions, so that all running compactions
+    /// together spawn fewer than `max_subcompactions` threads. Returns the number of reserved
+    /// threads, which may be zero.
+    fn acquire_subcompaction_threads(&self, wanted: usize) -> usize {
+        let limit = self.options.max_subcompactions.saturating_sub(1);
+        let mut acquired = 0;
+        self.subcompaction_threads
+            .fetch_update(
+                std::sync::atomic::Ordering::SeqCst,
+                std::sync::atomic::Ordering::SeqCst,
+                |used| {
+                    acquired = wanted.min(limit.saturating_sub(used));
+                    Some(used + acquired)
+                },
+            )
+            .unwrap();
+        acquired
+    }
+
+    /// Picks user keys that split the input of a compaction into key ranges of roughly equal number
+    /// of blocks. Returns an empty vector if the compaction should not be split.
+    fn subcompaction_split_points(
+        &self,
+        snapshot: &LsmStorageState,
+        task: &CompactionTask,
+    ) -> Result<Vec<Bytes>> {
+        let max_subcompactions = self.options.max_subcompactions;
+        if max_subcompactions <= 1 {
+            return Ok(Vec::new());
+        }
+        let mut block_keys = Vec::new();
+        for id in task.input_sst_ids() {
+            for meta in snapshot.sstables[&id].read_block_meta()? {
+                block_keys.push(meta.first_key.key_ref().to_vec());
+            }
+        }
+        block_keys.sort();
+        block_keys.dedup();
+        let num_subcompactions = max_subcompactions.min(block_keys.len());
+        Ok((1..num_subcompactions)
+            .map(|i| Bytes::copy_from_slice(&block_keys[i * block_keys.len() / num_subcompactions]))
+            .collect::<Vec<_>>())
+    }
+
+    /// Compacts the input of the task within the user key range `[lower, upper)`.
+    fn compact_range(
+        &self,
+        snapshot: &LsmStorageState,
+        task: &CompactionTask,
+        lower: Option<&[u8]>,
+        upper: Option<&[u8]>,
+    ) -> Result<Vec<Arc<SsTable>>> {
+        let sst_iter = |id: &usize| -> Result<Box<SsTableIterator>> {
+            let table = snapshot.sstables.get(id).unwrap().clone();
+            Ok(Box::new(match lower {
+                Some(key) => SsTableIterator::create_and_seek_to_key(
+                    table,
+                    KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
+                )?,
+                None => SsTableIterator::create_and_seek_to_first(table)?,
+            }))
+        };
+        let concat_iter = |ids: &[usize]| -> Result<SstConcatIterator> {
+            let mut ssts = Vec::with_capacity(ids.len());
+            for id in ids.iter() {
+                ssts.push(snapshot.sstables.get(id).unwrap().clone());
+            }
+            match lower {
+                Some(key) => SstConcatIterator::create_and_seek_to_key(
+                    ssts,
+                    KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
+                ),
+                None => SstConcatIterator::create_and_seek_to_first(ssts),
+            }
+        };
+        let range_tombstones = task
+            .input_sst_ids()
+            .iter()
+            .flat_map(|id| snapshot.sstables[id].range_tombstones())
+            .filter_map(|tombstone| tombstone.clip(lower, upper))
+            .collect::<Vec<_>>();
         match task {
             CompactionTask::ForceFullCompaction {
                 l0_sstables,
@@ -215,19 +715,18 @@ impl LsmStorageInner {
             } => {
                 let mut l0_iters = Vec::with_capacity(l0_sstables.len());
                 for id in l0_sstables.iter() {
-                    l0_iters.push(Box::new(SsTableIterator::create_and_seek_to_first(
-                        snapshot.sstables.get(id).unwrap().clone(),
-                    )?));
-                }
-                let mut l1_iters = Vec::with_capacity(l1_sstables.len());
-                for id in l1_sstables.iter() {
-                    l1_iters.push(snapshot.sstables.get(id).unwrap().clone());
+                    l0_iters.push(sst_iter(id)?);
                 }
                 let iter = TwoMergeIterator::create(
                     MergeIterator::create(l0_iters),
-                    SstConcatIterator::create_and_seek_to_first(l1_iters)?,
+                    concat_iter(l1_sstables)?,
                 )?;
-                self.compact_generate_sst_from_iter(iter, task.compact_to_bottom_level())
+                self.compact_generate_sst_from_iter(
+                    iter,
+                    upper,
+                    task.compact_to_bottom_level(),
+                    &range_tombstones,
+                )
             }
             CompactionTask::Simple(SimpleLeveledCompactionTask {
                 upper_level,
@@ -244,52 +743,40 @@ impl LsmStorageInner {
                 ..
             }) => match upper_level {
                 Some(_) => {
-                    let mut upper_ssts = Vec::with_capacity(upper_level_sst_ids.len());
-                    for id in upper_level_sst_ids.iter() {
-                        upper_ssts.push(snapshot.sstables.get(id).unwrap().clone());
-                    }
-                    let upper_iter = SstConcatIterator::create_and_seek_to_first(upper_ssts)?;
-                    let mut lower_ssts = Vec::with_capacity(upper_level_sst_ids.len());
-                    for id in lower_level_sst_ids.iter() {
-                        lower_ssts.push(snapshot.sstables.get(id).unwrap().clone());
-                    }
-                    let lower_iter = SstConcatIterator::create_and_seek_to_first(lower_ssts)?;
+                    let upper_iter = concat_iter(upper_level_sst_ids)?;
+                    let lower_iter = concat_iter(lower_level_sst_ids)?;
                     self.compact_generate_sst_from_iter(
                         TwoMergeIterator::create(upper_iter, lower_iter)?,
+                        upper,
                         task.compact_to_bottom_level(),
+                        &range_tombstones,
                     )
                 }
                 None => {
                     let mut upper_iters = Vec::with_capacity(upper_level_sst_ids.len());
                     for id in upper_level_sst_ids.iter() {
-                        upper_iters.push(Box::new(SsTableIterator::create_and_seek_to_first(
-                            snapshot.sstables.get(id).unwrap().clone(),
-                        )?));
+                        upper_iters.push(sst_iter(id)?);
                     }
                     let upper_iter = MergeIterator::create(upper_iters);
-                    let mut lower_ssts = Vec::with_capacity(upper_level_sst_ids.len());
-                    for id in lower_level_sst_ids.iter() {
-                        lower_ssts.push(snapshot.sstables.get(id).unwrap().clone());
-                    }
-                    let lower_iter = SstConcatIterator::create_and_seek_to_first(lower_ssts)?;
+                    let lower_iter = concat_iter(lower_level_sst_ids)?;
                     self.compact_generate_sst_from_iter(
                         TwoMergeIterator::create(upper_iter, lower_iter)?,
+                        upper,
                         task.compact_to_bottom_level(),
+                        &range_tombstones,
                     )
                 }
             },
             CompactionTask::Tiered(TieredCompactionTask { tiers, .. }) => {
                 let mut iters = Vec::with_capacity(tiers.len());
                 for (_, tier_sst_ids) in tiers {
-                    let mut ssts = Vec::with_capacity(tier_sst_ids.len());
-                    for id in tier_sst_ids.iter() {
-                        ssts.push(snapshot.sstables.get(id).unwrap().clone());
-                    }
-                    iters.push(Box::new(SstConcatIterator::create_and_seek_to_first(ssts)?));
+                    iters.push(Box::new(concat_iter(tier_sst_ids)?));
                 }
                 self.compact_generate_sst_from_iter(
                     MergeIterator::create(iters),
+                    upper,
                     task.compact_to_bottom_level(),
+                    &range_tombstones,
                 )
             }
         }
@@ -314,7 +801,7 @@ impl LsmStorageInner {
 
         println!("force full compaction: {:?}", compaction_task);
 
-        let sstables = self.compact(&compaction_task)?;
+        let sstables = self.compact(&self.default_column_family(), &compaction_task)?;
         let mut ids = Vec::with_capacity(sstables.len());
 
         {
@@ -329,22 +816,16 @@ impl LsmStorageInner {
                 let result = state.sstables.insert(new_sst.sst_id(), new_sst);
                 assert!(result.is_none());
             }
-            assert_eq!(l1_sstables, state.levels[0].1);
-            state.levels[0].1 = ids.clone();
-            let mut l0_sstables_map = l0_sstables.iter().copied().collect::<HashSet<_>>();
-            state.l0_sstables = state
-                .l0_sstables
-                .iter()
-                .filter(|x| !l0_sstables_map.remove(x))
-                .copied()
-                .collect::<Vec<_>>();
-            assert!(l0_sstables_map.is_empty());
+            let (state, _) =
+                apply_force_full_compaction_result(&state, &l0_sstables, &l1_sstables, &ids);
             *self.state.write() = Arc::new(state);
+            self.notify_write_stall();
             self.sync_dir()?;
             self.manifest.as_ref().unwrap().add_record(
                 &state_lock,
                 ManifestRecord::Compaction(compaction_task, ids.clone()),
             )?;
+            self.maybe_roll_manifest(&state_lock)?;
         }
         for sst in l0_sstables.iter().chain(l1_sstables.iter()) {
             std::fs::remove_file(self.path_of_sst(*sst))?;
@@ -355,45 +836,71 @@ impl LsmStorageInner {
         Ok(())
     }
 
-    fn trigger_compaction(&self) -> Result<()> {
+    /// Picks a compaction task in a column family that does not conflict with the running ones,
+    /// and marks its SSTs and levels as busy. The caller must call `finish_compaction_task` after
+    /// running the task.
+    fn schedule_compaction_task(&self, cf: &ColumnFamily) -> Option<CompactionTask> {
+        if let CompactionController::NoCompaction = cf.compaction_controller {
+            return None;
+        }
+        let mut running = cf.running_compactions.lock();
         let snapshot = {
-            let state = self.state.read();
+            let state = cf.state.read();
             state.clone()
         };
-        let task = self
+        let task = cf
             .compaction_controller
-            .generate_compaction_task(&snapshot);
-        let Some(task) = task else {
-            return Ok(());
-        };
-        self.dump_structure();
-        println!("running compaction task: {:?}", task);
-        let sstables = self.compact(&task)?;
+            .generate_compaction_task_with_running(&snapshot, &running)?;
+        running.add(&task);
+        Some(task)
+    }
+
+    fn finish_compaction_task(&self, cf: &ColumnFamily, task: &CompactionTask) {
+        cf.running_compactions.lock().remove(task);
+    }
+
+    fn run_compaction_task(&self, cf: &ColumnFamily, task: &CompactionTask) -> Result<()> {
+        if cf.is_default() {
+            self.dump_structure();
+            println!("running compaction task: {:?}", task);
+        } else {
+            println!(
+                "running compaction task in column family {}: {:?}",
+                cf.name, task
+            );
+        }
+        let sstables = self.compact(cf, task)?;
         let output = sstables.iter().map(|x| x.sst_id()).collect::<Vec<_>>();
         let ssts_to_remove = {
             let state_lock = self.state_lock.lock();
-            let mut snapshot = self.state.read().as_ref().clone();
+            let mut snapshot = cf.state.read().as_ref().clone();
             let mut new_sst_ids = Vec::new();
             for file_to_add in sstables {
                 new_sst_ids.push(file_to_add.sst_id());
                 let result = snapshot.sstables.insert(file_to_add.sst_id(), file_to_add);
-                assert!(result.is_none());
+                assert!(result.is_none() || task.is_trivial_move());
             }
-            let (mut snapshot, files_to_remove) = self
+            let (mut snapshot, files_to_remove) = cf
                 .compaction_controller
-                .apply_compaction_result(&snapshot, &task, &output);
+                .apply_compaction_result(&snapshot, task, &output);
             let mut ssts_to_remove = Vec::with_capacity(files_to_remove.len());
             for file_to_remove in &files_to_remove {
                 let result = snapshot.sstables.remove(file_to_remove);
                 assert!(result.is_some(), "cannot remove {}.sst", file_to_remove);
                 ssts_to_remove.push(result.unwrap());
             }
-            let mut state = self.state.write();
+            let mut state = cf.state.write();
             *state = Arc::new(snapshot);
             drop(state);
+            self.notify_write_stall();
             self.sync_dir()?;
-            self.manifest()
-                .add_record(&state_lock, ManifestRecord::Compaction(task, new_sst_ids))?;
+            let record = if cf.is_default() {
+                ManifestRecord::Compaction(task.clone(), new_sst_ids)
+            } else {
+                ManifestRecord::ColumnFamilyCompaction(cf.id, task.clone(), new_sst_ids)
+            };
+            self.manifest().add_record(&state_lock, record)?;
+            self.maybe_roll_manifest(&state_lock)?;
             ssts_to_remove
         };
         println!(
@@ -410,35 +917,59 @@ impl LsmStorageInner {
         Ok(())
     }
 
+    /// Spawns the compaction scheduler. It always runs, as column families with compaction
+    /// enabled can be created at any time.
     pub(crate) fn spawn_compaction_thread(
         self: &Arc<Self>,
         rx: crossbeam_channel::Receiver<()>,
     ) -> Result<Option<std::thread::JoinHandle<()>>> {
-        if let CompactionOptions::Leveled(_)
-        | CompactionOptions::Simple(_)
-        | CompactionOptions::Tiered(_) = self.options.compaction_options
-        {
-            let this = self.clone();
-            let handle = std::thread::spawn(move || {
-                let ticker = crossbeam_channel::tick(Duration::from_millis(50));
-                loop {
-                    crossbeam_channel::select! {
-                        recv(ticker) -> _ => if let Err(e) = this.trigger_compaction() {
-                            eprintln!("compaction failed: {}", e);
-                        },
-                        recv(rx) -> _ => return
+        let this = self.clone();
+        let handle = std::thread::spawn(move || {
+            let ticker = crossbeam_channel::tick(Duration::from_millis(50));
+            let mut workers: Vec<std::thread::JoinHandle<()>> = Vec::new();
+            loop {
+                crossbeam_channel::select! {
+                    recv(ticker) -> _ => {
+                        workers.retain(|worker| !worker.is_finished());
+                        'schedule: for cf in this.column_families() {
+                            while workers.len() < this.options.max_background_compactions {
+                                let Some(task) = this.schedule_compaction_task(&cf) else {
+                                    continue 'schedule;
+                                };
+                                let this = this.clone();
+                                let cf = cf.clone();
+                                workers.push(std::thread::spawn(move || {
+                                    if let Err(e) = this.run_compaction_task(&cf, &task) {
+                                        eprintln!("compaction failed: {}", e);
+                                    }
+                                    this.finish_compaction_task(&cf, &task);
+                                }));
+                            }
+                            break;
+                        }
+                    },
+                    recv(rx) -> _ => {
+                        for worker in workers {
+                            worker.join().ok();
+                        }
+                        return;
                     }
                 }
-            });
-            return Ok(Some(handle));
-        }
-        Ok(None)
+            }
+        });
+        Ok(Some(handle))
     }
 
     fn trigger_flush(&self) -> Result<()> {
         let res = {
             let state = self.state.read();
             state.imm_memtables.len() >= self.options.num_memtable_limit
+                || (!state.imm_memtables.is_empty()
+                    && self
+                        .options
+                        .write_buffer_manager
+                        .as_ref()
+                        .is_some_and(|x| x.should_flush()))
         };
         if res {
             self.force_flush_next_imm_memtable()?;
@@ -2,9 +2,10 @@ use std::collections::HashSet;
 
 use serde::{Deserialize, Serialize};
 
+use super::{ssts_disjoint, ssts_size, RunningCompactions};
 use crate::lsm_storage::LsmStorageState;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LeveledCompactionTask {
     // if upper_level is `None`, then it is L0 compaction
     pub upper_level: Option<usize>,
@@ -12,9 +13,13 @@ pub struct LeveledCompactionTask {
     pub lower_level: usize,
     pub lower_level_sst_ids: Vec<usize>,
     pub is_lower_level_bottom_level: bool,
+    /// The upper level SSTs do not overlap with each other or with the lower level, and can be
+    /// moved to the lower level without rewriting.
+    #[serde(default)]
+    pub is_trivial_move: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LeveledCompactionOptions {
     pub level_size_multiplier: usize,
     pub level0_file_num_compaction_trigger: usize,
@@ -61,11 +66,10 @@ impl LeveledCompactionController {
         overlap_ssts
     }
 
-    pub fn generate_compaction_task(
-        &self,
-        snapshot: &LsmStorageState,
-    ) -> Option<LeveledCompactionTask> {
-        // step 1: compute target level size
+    /// Computes the target size and the real size of each level (excluding L0) in bytes, and the
+    /// base level that L0 is compacted to.
+    fn compute_level_sizes(&self, snapshot: &LsmStorageState) -> (Vec<usize>, Vec<usize>, usize) {
+        // compute real level size
         let mut target_level_size = (0..self.options.max_levels).map(|_| 0).collect::<Vec<_>>(); // exclude level 0
         let mut real_level_size = Vec::with_capacity(self.options.max_levels);
         let mut base_level = self.options.max_levels;
@@ -93,20 +97,56 @@ impl LeveledCompactionController {
                 base_level = i + 1;
             }
         }
+        (target_level_size, real_level_size, base_level)
+    }
 
-        // Flush L0 SST is the top priority
+    /// Estimates the number of bytes that compactions need to rewrite to bring every level back
+    /// under its target size.
+    pub fn estimate_pending_compaction_bytes(&self, snapshot: &LsmStorageState) -> u64 {
+        let (target_level_size, real_level_size, _) = self.compute_level_sizes(snapshot);
+        let mut pending = 0;
         if snapshot.l0_sstables.len() >= self.options.level0_file_num_compaction_trigger {
+            pending += ssts_size(snapshot, &snapshot.l0_sstables);
+        }
+        for (real, target) in real_level_size.iter().zip(target_level_size.iter()) {
+            pending += real.saturating_sub(*target) as u64;
+        }
+        pending
+    }
+
+    pub fn generate_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+    ) -> Option<LeveledCompactionTask> {
+        self.generate_compaction_task_with_running(snapshot, &RunningCompactions::default())
+    }
+
+    /// Generates a compaction task whose upper and lower levels are not used by any running
+    /// compaction.
+    pub fn generate_compaction_task_with_running(
+        &self,
+        snapshot: &LsmStorageState,
+        running: &RunningCompactions,
+    ) -> Option<LeveledCompactionTask> {
+        let (target_level_size, real_level_size, base_level) = self.compute_level_sizes(snapshot);
+
+        // Flush L0 SST is the top priority
+        if snapshot.l0_sstables.len() >= self.options.level0_file_num_compaction_trigger
+            && !running.is_level_busy(0)
+            && !running.is_level_busy(base_level)
+        {
             println!("flush L0 SST to base level {}", base_level);
+            let lower_level_sst_ids =
+                self.find_overlapping_ssts(snapshot, &snapshot.l0_sstables, base_level);
+            let is_trivial_move =
+                lower_level_sst_ids.is_empty() && ssts_disjoint(snapshot, &snapshot.l0_sstables);
             return Some(LeveledCompactionTask {
                 upper_level: None,
                 upper_level_sst_ids: snapshot.l0_sstables.clone(),
                 lower_level: base_level,
-                lower_level_sst_ids: self.find_overlapping_ssts(
-                    snapshot,
-                    &snapshot.l0_sstables,
-                    base_level,
-                ),
+                lower_level_sst_ids,
                 is_lower_level_bottom_level: base_level == self.options.max_levels,
+                is_trivial_move,
             });
         }
 
@@ -119,7 +159,9 @@ impl LeveledCompactionController {
         }
         priorities.sort_by(|a, b| a.partial_cmp(b).unwrap().reverse());
 
-        let priority = priorities.first();
+        let priority = priorities
+            .iter()
+            .find(|(_, level)| !running.is_level_busy(*level) && !running.is_level_busy(level + 1));
         if let Some((_, level)) = priority {
             println!(
                 "target level sizes: {:?}, real level sizes: {:?}, base_level: {}",
@@ -140,15 +182,14 @@ impl LeveledCompactionController {
                 "compaction triggered by priority: {level} out of {:?}, select {selected_sst} for compaction",
                 priorities
             );
+            let lower_level_sst_ids =
+                self.find_overlapping_ssts(snapshot, &[selected_sst], level + 1);
             return Some(LeveledCompactionTask {
                 upper_level: Some(level),
                 upper_level_sst_ids: vec![selected_sst],
                 lower_level: level + 1,
-                lower_level_sst_ids: self.find_overlapping_ssts(
-                    snapshot,
-                    &[selected_sst],
-                    level + 1,
-                ),
+                is_trivial_move: lower_level_sst_ids.is_empty(),
+                lower_level_sst_ids,
                 is_lower_level_bottom_level: level + 1 == self.options.max_levels,
             });
         }
@@ -216,15 +257,23 @@ impl LeveledCompactionController {
             .collect::<Vec<_>>();
         assert!(lower_level_sst_ids_set.is_empty());
         new_lower_level_ssts.extend(output);
-        new_lower_level_ssts.sort_by(|x, y| {
-            snapshot
-                .sstables
-                .get(x)
-                .unwrap()
-                .first_key()
-                .cmp(snapshot.sstables.get(y).unwrap().first_key())
-        });
+        // SSTs are not opened yet when replaying the manifest; the levels are sorted after recovery.
+        if new_lower_level_ssts
+            .iter()
+            .all(|x| snapshot.sstables.contains_key(x))
+        {
+            new_lower_level_ssts.sort_by(|x, y| {
+                snapshot
+                    .sstables
+                    .get(x)
+                    .unwrap()
+                    .first_key()
+                    .cmp(snapshot.sstables.get(y).unwrap().first_key())
+            });
+        }
         snapshot.levels[task.lower_level - 1].1 = new_lower_level_ssts;
+        // SSTs that are moved to the lower level are still alive.
+        files_to_remove.retain(|x| !output.contains(x));
         (snapshot, files_to_remove)
     }
 }
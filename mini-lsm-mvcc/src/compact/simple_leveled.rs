@@ -2,16 +2,17 @@ use std::collections::HashSet;
 
 use serde::{Deserialize, Serialize};
 
+use super::{ssts_disjoint, ssts_size, RunningCompactions};
 use crate::lsm_storage::LsmStorageState;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SimpleLeveledCompactionOptions {
     pub size_ratio_percent: usize,
     pub level0_file_num_compaction_trigger: usize,
     pub max_levels: usize,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SimpleLeveledCompactionTask {
     // if upper_level is `None`, then it is L0 compaction
     pub upper_level: Option<usize>,
@@ -19,6 +20,10 @@ pub struct SimpleLeveledCompactionTask {
     pub lower_level: usize,
     pub lower_level_sst_ids: Vec<usize>,
     pub is_lower_level_bottom_level: bool,
+    /// No two SSTs of the upper and the lower level overlap, and the lower level can be formed by
+    /// relinking them without rewriting.
+    #[serde(default)]
+    pub is_trivial_move: bool,
 }
 
 pub struct SimpleLeveledCompactionController {
@@ -36,6 +41,16 @@ impl SimpleLeveledCompactionController {
     pub fn generate_compaction_task(
         &self,
         snapshot: &LsmStorageState,
+    ) -> Option<SimpleLeveledCompactionTask> {
+        self.generate_compaction_task_with_running(snapshot, &RunningCompactions::default())
+    }
+
+    /// Generates a compaction task whose upper and lower levels are not used by any running
+    /// compaction.
+    pub fn generate_compaction_task_with_running(
+        &self,
+        snapshot: &LsmStorageState,
+        running: &RunningCompactions,
     ) -> Option<SimpleLeveledCompactionTask> {
         let mut level_sizes = Vec::new();
         level_sizes.push(snapshot.l0_sstables.len());
@@ -51,28 +66,61 @@ impl SimpleLeveledCompactionController {
             }
 
             let lower_level = i + 1;
+            if running.is_level_busy(i) || running.is_level_busy(lower_level) {
+                continue;
+            }
             let size_ratio = level_sizes[lower_level] as f64 / level_sizes[i] as f64;
             if size_ratio < self.options.size_ratio_percent as f64 / 100.0 {
                 println!(
                     "compaction triggered at level {} and {} with size ratio {}",
                     i, lower_level, size_ratio
                 );
+                let upper_level_sst_ids = if i == 0 {
+                    snapshot.l0_sstables.clone()
+                } else {
+                    snapshot.levels[i - 1].1.clone()
+                };
+                let lower_level_sst_ids = snapshot.levels[lower_level - 1].1.clone();
+                let is_trivial_move = ssts_disjoint(
+                    snapshot,
+                    &[&upper_level_sst_ids[..], &lower_level_sst_ids[..]].concat(),
+                );
                 return Some(SimpleLeveledCompactionTask {
                     upper_level: if i == 0 { None } else { Some(i) },
-                    upper_level_sst_ids: if i == 0 {
-                        snapshot.l0_sstables.clone()
-                    } else {
-                        snapshot.levels[i - 1].1.clone()
-                    },
+                    upper_level_sst_ids,
                     lower_level,
-                    lower_level_sst_ids: snapshot.levels[lower_level - 1].1.clone(),
+                    lower_level_sst_ids,
                     is_lower_level_bottom_level: lower_level == self.options.max_levels,
+                    is_trivial_move,
                 });
             }
         }
         None
     }
 
+    /// Estimates the number of bytes to rewrite for all pairs of adjacent levels that violate the
+    /// size ratio.
+    pub fn estimate_pending_compaction_bytes(&self, snapshot: &LsmStorageState) -> u64 {
+        let mut pending = 0;
+        for i in 0..self.options.max_levels {
+            let upper_level_sst_ids = if i == 0 {
+                if snapshot.l0_sstables.len() < self.options.level0_file_num_compaction_trigger {
+                    continue;
+                }
+                &snapshot.l0_sstables
+            } else {
+                &snapshot.levels[i - 1].1
+            };
+            let lower_level_sst_ids = &snapshot.levels[i].1;
+            let size_ratio = lower_level_sst_ids.len() as f64 / upper_level_sst_ids.len() as f64;
+            if size_ratio < self.options.size_ratio_percent as f64 / 100.0 {
+                pending += ssts_size(snapshot, upper_level_sst_ids)
+                    + ssts_size(snapshot, lower_level_sst_ids);
+            }
+        }
+        pending
+    }
+
     /// Apply the compaction result.
     ///
     /// The compactor will call this function with the compaction task and the list of SST ids generated. This function applies the
@@ -119,6 +167,8 @@ impl SimpleLeveledCompactionController {
         );
         files_to_remove.extend(&snapshot.levels[task.lower_level - 1].1);
         snapshot.levels[task.lower_level - 1].1 = output.to_vec();
+        // SSTs that are moved to the lower level are still alive.
+        files_to_remove.retain(|x| !output.contains(x));
         (snapshot, files_to_remove)
     }
 }
@@ -2,15 +2,38 @@ use std::collections::HashMap;
 
 use serde::{Deserialize, Serialize};
 
+use super::{ssts_disjoint, ssts_size, RunningCompactions};
 use crate::lsm_storage::LsmStorageState;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TieredCompactionTask {
     pub tiers: Vec<(usize, Vec<usize>)>,
     pub bottom_tier_included: bool,
+    /// No two SSTs in the tiers overlap, and the merged tier can be formed by relinking them
+    /// without rewriting.
+    #[serde(default)]
+    pub is_trivial_move: bool,
 }
 
-#[derive(Debug, Clone)]
+impl TieredCompactionTask {
+    fn new(
+        snapshot: &LsmStorageState,
+        tiers: Vec<(usize, Vec<usize>)>,
+        bottom_tier_included: bool,
+    ) -> Self {
+        let sst_ids = tiers
+            .iter()
+            .flat_map(|(_, ssts)| ssts.iter().copied())
+            .collect::<Vec<_>>();
+        Self {
+            is_trivial_move: ssts_disjoint(snapshot, &sst_ids),
+            tiers,
+            bottom_tier_included,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TieredCompactionOptions {
     pub num_tiers: usize,
     pub max_size_amplification_percent: usize,
@@ -31,6 +54,31 @@ impl TieredCompactionController {
         &self,
         snapshot: &LsmStorageState,
     ) -> Option<TieredCompactionTask> {
+        self.generate_compaction_task_with_running(snapshot, &RunningCompactions::default())
+    }
+
+    /// Estimates the number of bytes to rewrite once there are enough tiers to trigger a
+    /// compaction, which is the size of all tiers above the bottom one.
+    pub fn estimate_pending_compaction_bytes(&self, snapshot: &LsmStorageState) -> u64 {
+        if snapshot.levels.len() < self.options.num_tiers {
+            return 0;
+        }
+        snapshot.levels[..snapshot.levels.len() - 1]
+            .iter()
+            .map(|(_, ssts)| ssts_size(snapshot, ssts))
+            .sum()
+    }
+
+    /// Tiered compaction may merge any tiers, including the ones produced by a running task, so
+    /// at most one tiered compaction runs at a time.
+    pub fn generate_compaction_task_with_running(
+        &self,
+        snapshot: &LsmStorageState,
+        running: &RunningCompactions,
+    ) -> Option<TieredCompactionTask> {
+        if !running.is_empty() {
+            return None;
+        }
         assert!(
             snapshot.l0_sstables.is_empty(),
             "should not add l0 ssts in tiered compaction"
@@ -50,10 +98,11 @@ impl TieredCompactionController {
                 "compaction triggered by space amplification ratio: {}",
                 space_amp_ratio
             );
-            return Some(TieredCompactionTask {
-                tiers: snapshot.levels.clone(),
-                bottom_tier_included: true,
-            });
+            return Some(TieredCompactionTask::new(
+                snapshot,
+                snapshot.levels.clone(),
+                true,
+            ));
         }
         let size_ratio_trigger = (100.0 + self.options.size_ratio as f64) / 100.0;
         // compaction triggered by size ratio
@@ -67,29 +116,31 @@ impl TieredCompactionController {
                     "compaction triggered by size ratio: {}",
                     current_size_ratio * 100.0
                 );
-                return Some(TieredCompactionTask {
-                    tiers: snapshot
+                return Some(TieredCompactionTask::new(
+                    snapshot,
+                    snapshot
                         .levels
                         .iter()
                         .take(id + 2)
                         .cloned()
                         .collect::<Vec<_>>(),
-                    bottom_tier_included: id + 2 >= snapshot.levels.len(),
-                });
+                    id + 2 >= snapshot.levels.len(),
+                ));
             }
         }
         // trying to reduce sorted runs without respecting size ratio
         let num_tiers_to_take = snapshot.levels.len() - self.options.num_tiers + 2;
         println!("compaction triggered by reducing sorted runs");
-        return Some(TieredCompactionTask {
-            tiers: snapshot
+        return Some(TieredCompactionTask::new(
+            snapshot,
+            snapshot
                 .levels
                 .iter()
                 .take(num_tiers_to_take)
                 .cloned()
                 .collect::<Vec<_>>(),
-            bottom_tier_included: snapshot.levels.len() >= num_tiers_to_take,
-        });
+            snapshot.levels.len() >= num_tiers_to_take,
+        ));
     }
 
     pub fn apply_compaction_result(
@@ -130,6 +181,8 @@ impl TieredCompactionController {
             unreachable!("some tiers not found??");
         }
         snapshot.levels = levels;
+        // SSTs that are moved to the new tier are still alive.
+        files_to_remove.retain(|x| !output.contains(x));
         (snapshot, files_to_remove)
     }
 }
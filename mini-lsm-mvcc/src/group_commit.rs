@@ -0,0 +1,173 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use parking_lot::Mutex;
+
+use crate::column_family::ColumnFamily;
+use crate::lsm_storage::{LsmStorageInner, WriteBatchRecord};
+
+/// The maximum size of the records committed by a leader at once, so that small writes queued
+/// behind large ones are not delayed for too long.
+const MAX_GROUP_SIZE: usize = 1 << 20;
+
+/// Counters of the writes committed by the write queue.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GroupCommitStats {
+    /// The number of committed writes.
+    pub writes: u64,
+    /// The number of groups the writes were committed in, each with a single WAL append.
+    pub groups: u64,
+    /// The number of WAL syncs, at most one per group.
+    pub syncs: u64,
+}
+
+/// A write queued until a leader commits it.
+struct PendingWrite {
+    records: Vec<(Arc<ColumnFamily>, WriteBatchRecord<Bytes>)>,
+    size: usize,
+    sync: bool,
+    /// The commit timestamp of the write, or the error of its group, once it is committed.
+    result: Mutex<Option<Result<u64>>>,
+}
+
+/// Batches concurrent writes, so that they are committed with a single WAL append and at most one
+/// WAL sync.
+///
+/// A writer queues its write and waits for the MVCC write lock. The writer that gets the lock
+/// becomes the leader of the queued writes: it commits them in queue order with consecutive
+/// timestamps, and its followers find their results once they get the lock in turn.
+#[derive(Default)]
+pub(crate) struct WriteQueue {
+    writes: Mutex<Vec<Arc<PendingWrite>>>,
+    num_writes: AtomicU64,
+    num_groups: AtomicU64,
+    num_syncs: AtomicU64,
+}
+
+impl WriteQueue {
+    /// Takes the writes at the front of the queue, up to `MAX_GROUP_SIZE` but at least one if the
+    /// queue is not empty.
+    fn take_group(&self) -> Vec<Arc<PendingWrite>> {
+        let mut writes = self.writes.lock();
+        if writes.is_empty() {
+            return Vec::new();
+        }
+        let mut size = 0;
+        let len = writes
+            .iter()
+            .take_while(|write| {
+                size += write.size;
+                size <= MAX_GROUP_SIZE
+            })
+            .count()
+            .max(1);
+        writes.drain(..len).collect()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn num_queued(&self) -> usize {
+        self.writes.lock().len()
+    }
+
+    pub(crate) fn stats(&self) -> GroupCommitStats {
+        GroupCommitStats {
+            writes: self.num_writes.load(Ordering::Relaxed),
+            groups: self.num_groups.load(Ordering::Relaxed),
+            syncs: self.num_syncs.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl LsmStorageInner {
+    /// Commits a batch of records through the write queue, and returns its commit timestamp. The
+    /// WAL is synced before returning if `sync` is set.
+    ///
+    /// The records are validated before they are queued, so that an invalid record panics in the
+    /// thread of its writer, not in the leader of its group.
+    pub(crate) fn group_commit<T: AsRef<[u8]>>(
+        &self,
+        records: Vec<(Arc<ColumnFamily>, &WriteBatchRecord<T>)>,
+        sync: bool,
+    ) -> Result<u64> {
+        for (_, record) in &records {
+            record.validate();
+        }
+        let records = records
+            .into_iter()
+            .map(|(cf, record)| {
+                let record = match record {
+                    WriteBatchRecord::Put(key, value) => WriteBatchRecord::Put(
+                        Bytes::copy_from_slice(key.as_ref()),
+                        Bytes::copy_from_slice(value.as_ref()),
+                    ),
+                    WriteBatchRecord::Del(key) => {
+                        WriteBatchRecord::Del(Bytes::copy_from_slice(key.as_ref()))
+                    }
+                    WriteBatchRecord::DelRange(lower, upper) => WriteBatchRecord::DelRange(
+                        Bytes::copy_from_slice(lower.as_ref()),
+                        Bytes::copy_from_slice(upper.as_ref()),
+                    ),
+                    WriteBatchRecord::Merge(key, operand) => WriteBatchRecord::Merge(
+                        Bytes::copy_from_slice(key.as_ref()),
+                        Bytes::copy_from_slice(operand.as_ref()),
+                    ),
+                };
+                (cf, record)
+            })
+            .collect::<Vec<_>>();
+        let write = Arc::new(PendingWrite {
+            size: records.iter().map(|(_, record)| record.size()).sum(),
+            records,
+            sync,
+            result: Mutex::new(None),
+        });
+        self.write_queue.writes.lock().push(write.clone());
+
+        let _lck = self.mvcc().write_lock.lock();
+        loop {
+            if let Some(result) = write.result.lock().take() {
+                return result;
+            }
+            // No leader has committed the write yet, so this writer leads the next group.
+            let group = self.write_queue.take_group();
+            if group.is_empty() {
+                // The write was taken by a leader which did not set its result, so it panicked.
+                return Err(anyhow!("group commit failed: the leader panicked"));
+            }
+            let sync = group.iter().any(|write| write.sync);
+            let batches = group
+                .iter()
+                .map(|write| {
+                    write
+                        .records
+                        .iter()
+                        .map(|(cf, record)| (cf.as_ref(), record))
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>();
+            let result = self.write_batches_with_lock(&batches, sync);
+            let queue = &self.write_queue;
+            queue
+                .num_writes
+                .fetch_add(group.len() as u64, Ordering::Relaxed);
+            queue.num_groups.fetch_add(1, Ordering::Relaxed);
+            if sync && self.options.enable_wal {
+                queue.num_syncs.fetch_add(1, Ordering::Relaxed);
+            }
+            match result {
+                Ok(first_ts) => {
+                    for (ts, write) in (first_ts..).zip(&group) {
+                        *write.result.lock() = Some(Ok(ts));
+                    }
+                }
+                Err(e) => {
+                    for write in &group {
+                        *write.result.lock() = Some(Err(anyhow!("group commit failed: {:#}", e)));
+                    }
+                }
+            }
+        }
+    }
+}
@@ -2,8 +2,23 @@ pub mod concat_iterator;
 pub mod merge_iterator;
 pub mod two_merge_iterator;
 
+/// The kind of a record in the LSM tree. A deletion is a value record with an empty value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordKind {
+    Value,
+    /// An operand to be combined with the older versions of the key by the merge operator.
+    MergeOperand,
+}
+
+/// The direction an iterator moves in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Forward,
+    Backward,
+}
+
 pub trait StorageIterator {
-    type KeyType<'a>: PartialEq + Eq + PartialOrd + Ord
+    type KeyType<'a>: PartialEq + Eq + PartialOrd + Ord + Copy
     where
         Self: 'a;
 
@@ -19,6 +34,39 @@ pub trait StorageIterator {
     /// Move to the next position.
     fn next(&mut self) -> anyhow::Result<()>;
 
+    /// Move to the previous position. Like `next`, it can only be called on a valid iterator, and
+    /// the iterator may change its direction at any valid position.
+    fn prev(&mut self) -> anyhow::Result<()> {
+        anyhow::bail!("backward iteration is not supported")
+    }
+
+    /// Move to the first position.
+    fn seek_to_first(&mut self) -> anyhow::Result<()> {
+        anyhow::bail!("seeking is not supported")
+    }
+
+    /// Move to the last position, so that the iterator moves backward from there.
+    fn seek_to_last(&mut self) -> anyhow::Result<()> {
+        anyhow::bail!("seeking is not supported")
+    }
+
+    /// Move to the first position with a key >= `key`, so that the iterator moves forward from
+    /// there.
+    fn seek(&mut self, _key: Self::KeyType<'_>) -> anyhow::Result<()> {
+        anyhow::bail!("seeking is not supported")
+    }
+
+    /// Move to the last position with a key <= `key`, so that the iterator moves backward from
+    /// there.
+    fn seek_for_prev(&mut self, _key: Self::KeyType<'_>) -> anyhow::Result<()> {
+        anyhow::bail!("seeking is not supported")
+    }
+
+    /// Get the kind of the current record.
+    fn record_kind(&self) -> RecordKind {
+        RecordKind::Value
+    }
+
     /// Number of underlying active iterators for this iterator.
     fn num_active_iterators(&self) -> usize {
         1
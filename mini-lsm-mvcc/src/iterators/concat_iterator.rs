@@ -7,7 +7,7 @@ use crate::{
     table::{SsTable, SsTableIterator},
 };
 
-use super::StorageIterator;
+use super::{RecordKind, StorageIterator};
 
 /// Concat multiple iterators ordered in key order and their key ranges do not overlap. We do not want to create the
 /// iterators when initializing this iterator to reduce the overhead of seeking.
@@ -15,6 +15,8 @@ pub struct SstConcatIterator {
     current: Option<SsTableIterator>,
     next_sst_idx: usize,
     sstables: Vec<Arc<SsTable>>,
+    /// Whether the blocks read by the iterator are added to the block cache.
+    fill_cache: bool,
 }
 
 impl SstConcatIterator {
@@ -24,55 +26,46 @@ impl SstConcatIterator {
         }
         if !sstables.is_empty() {
             for i in 0..(sstables.len() - 1) {
-                assert!(sstables[i].last_key() < sstables[i + 1].first_key());
+                // The range tombstones of adjacent SSTs may share a boundary key.
+                assert!(sstables[i].last_key() <= sstables[i + 1].first_key());
             }
         }
     }
 
-    pub fn create_and_seek_to_first(sstables: Vec<Arc<SsTable>>) -> Result<Self> {
+    /// Create a new iterator that is not valid until it seeks. Without `fill_cache`, the blocks
+    /// read by it are not added to the block cache.
+    pub fn new(sstables: Vec<Arc<SsTable>>, fill_cache: bool) -> Self {
         Self::check_sst_valid(&sstables);
-        if sstables.is_empty() {
-            return Ok(Self {
-                current: None,
-                next_sst_idx: 0,
-                sstables,
-            });
-        }
-        let mut iter = Self {
-            current: Some(SsTableIterator::create_and_seek_to_first(
-                sstables[0].clone(),
-            )?),
-            next_sst_idx: 1,
+        Self {
+            current: None,
+            next_sst_idx: sstables.len(),
             sstables,
-        };
-        iter.move_until_valid()?;
+            fill_cache,
+        }
+    }
+
+    pub fn create_and_seek_to_first(sstables: Vec<Arc<SsTable>>) -> Result<Self> {
+        let mut iter = Self::new(sstables, true);
+        iter.seek_to_first()?;
+        Ok(iter)
+    }
+
+    pub fn create_and_seek_to_last(sstables: Vec<Arc<SsTable>>) -> Result<Self> {
+        let mut iter = Self::new(sstables, true);
+        iter.seek_to_last()?;
         Ok(iter)
     }
 
     pub fn create_and_seek_to_key(sstables: Vec<Arc<SsTable>>, key: KeySlice) -> Result<Self> {
-        Self::check_sst_valid(&sstables);
-        let idx: usize = sstables
-            .partition_point(|table| table.first_key().as_key_slice() <= key)
-            .saturating_sub(1);
-        if idx >= sstables.len() {
-            return Ok(Self {
-                current: None,
-                next_sst_idx: sstables.len(),
-                sstables,
-            });
-        }
-        let mut iter = Self {
-            current: Some(SsTableIterator::create_and_seek_to_key(
-                sstables[idx].clone(),
-                key,
-            )?),
-            next_sst_idx: idx + 1,
-            sstables,
-        };
-        iter.move_until_valid()?;
+        let mut iter = Self::new(sstables, true);
+        iter.seek(key)?;
         Ok(iter)
     }
 
+    fn sst_iter(&self, idx: usize) -> SsTableIterator {
+        SsTableIterator::new(self.sstables[idx].clone(), self.fill_cache)
+    }
+
     fn move_until_valid(&mut self) -> Result<()> {
         while let Some(iter) = self.current.as_mut() {
             if iter.is_valid() {
@@ -81,14 +74,33 @@ impl SstConcatIterator {
             if self.next_sst_idx >= self.sstables.len() {
                 self.current = None;
             } else {
-                self.current = Some(SsTableIterator::create_and_seek_to_first(
-                    self.sstables[self.next_sst_idx].clone(),
-                )?);
+                let mut iter = self.sst_iter(self.next_sst_idx);
+                iter.seek_to_first()?;
+                self.current = Some(iter);
                 self.next_sst_idx += 1;
             }
         }
         Ok(())
     }
+
+    /// Moves to the last valid position at or before the current SST. `next_sst_idx` is the index
+    /// of the SST after the current one.
+    fn move_back_until_valid(&mut self) -> Result<()> {
+        while let Some(iter) = self.current.as_mut() {
+            if iter.is_valid() {
+                break;
+            }
+            if self.next_sst_idx <= 1 {
+                self.current = None;
+            } else {
+                self.next_sst_idx -= 1;
+                let mut iter = self.sst_iter(self.next_sst_idx - 1);
+                iter.seek_to_last()?;
+                self.current = Some(iter);
+            }
+        }
+        Ok(())
+    }
 }
 
 impl StorageIterator for SstConcatIterator {
@@ -102,6 +114,10 @@ impl StorageIterator for SstConcatIterator {
         self.current.as_ref().unwrap().value()
     }
 
+    fn record_kind(&self) -> RecordKind {
+        self.current.as_ref().unwrap().record_kind()
+    }
+
     fn is_valid(&self) -> bool {
         if let Some(current) = &self.current {
             assert!(current.is_valid());
@@ -117,6 +133,66 @@ impl StorageIterator for SstConcatIterator {
         Ok(())
     }
 
+    fn prev(&mut self) -> Result<()> {
+        self.current.as_mut().unwrap().prev()?;
+        self.move_back_until_valid()?;
+        Ok(())
+    }
+
+    fn seek_to_first(&mut self) -> Result<()> {
+        self.current = None;
+        self.next_sst_idx = 0;
+        if !self.sstables.is_empty() {
+            let mut iter = self.sst_iter(0);
+            iter.seek_to_first()?;
+            self.current = Some(iter);
+            self.next_sst_idx = 1;
+        }
+        self.move_until_valid()
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        self.current = None;
+        self.next_sst_idx = self.sstables.len();
+        if !self.sstables.is_empty() {
+            let mut iter = self.sst_iter(self.sstables.len() - 1);
+            iter.seek_to_last()?;
+            self.current = Some(iter);
+        }
+        self.move_back_until_valid()
+    }
+
+    fn seek(&mut self, key: KeySlice) -> Result<()> {
+        let idx = self
+            .sstables
+            .partition_point(|table| table.first_key().as_key_slice() <= key)
+            .saturating_sub(1);
+        self.current = None;
+        self.next_sst_idx = self.sstables.len();
+        if idx < self.sstables.len() {
+            let mut iter = self.sst_iter(idx);
+            iter.seek_to_key(key)?;
+            self.current = Some(iter);
+            self.next_sst_idx = idx + 1;
+        }
+        self.move_until_valid()
+    }
+
+    fn seek_for_prev(&mut self, key: KeySlice) -> Result<()> {
+        // The last SST that may contain a key <= `key`.
+        let idx = self
+            .sstables
+            .partition_point(|table| table.first_key().as_key_slice() <= key);
+        self.current = None;
+        self.next_sst_idx = idx;
+        if idx > 0 {
+            let mut iter = self.sst_iter(idx - 1);
+            iter.seek_for_prev(key)?;
+            self.current = Some(iter);
+        }
+        self.move_back_until_valid()
+    }
+
     fn num_active_iterators(&self) -> usize {
         1
     }
@@ -6,9 +6,9 @@ use anyhow::Result;
 
 use crate::key::KeySlice;
 
-use super::StorageIterator;
+use super::{Direction, RecordKind, StorageIterator};
 
-struct HeapWrapper<I: StorageIterator>(pub usize, pub Box<I>);
+struct HeapWrapper<I: StorageIterator>(pub usize, pub Box<I>, pub Direction);
 
 impl<I: StorageIterator> PartialEq for HeapWrapper<I> {
     fn eq(&self, other: &Self) -> bool {
@@ -21,12 +21,13 @@ impl<I: StorageIterator> Eq for HeapWrapper<I> {}
 impl<I: StorageIterator> PartialOrd for HeapWrapper<I> {
     #[allow(clippy::non_canonical_partial_ord_impl)]
     fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
-        match self.1.key().cmp(&other.1.key()) {
-            cmp::Ordering::Greater => Some(cmp::Ordering::Greater),
-            cmp::Ordering::Less => Some(cmp::Ordering::Less),
-            cmp::Ordering::Equal => self.0.partial_cmp(&other.0),
-        }
-        .map(|x| x.reverse())
+        // The heap pops the smallest key first, or the largest key when moving backward.
+        let ordering = match self.2 {
+            Direction::Forward => self.1.key().cmp(&other.1.key()).reverse(),
+            Direction::Backward => self.1.key().cmp(&other.1.key()),
+        };
+        // Prefer the iterator with the smaller index for the same key.
+        Some(ordering.then_with(|| other.0.cmp(&self.0)))
     }
 }
 
@@ -41,92 +42,156 @@ impl<I: StorageIterator> Ord for HeapWrapper<I> {
 pub struct MergeIterator<I: StorageIterator> {
     iters: BinaryHeap<HeapWrapper<I>>,
     current: Option<HeapWrapper<I>>,
+    /// The invalid iterators, which are kept for changing the direction.
+    exhausted: Vec<HeapWrapper<I>>,
+    direction: Direction,
 }
 
 impl<I: StorageIterator> MergeIterator<I> {
     pub fn create(iters: Vec<Box<I>>) -> Self {
-        if iters.is_empty() {
-            return Self {
-                iters: BinaryHeap::new(),
-                current: None,
-            };
-        }
-
-        let mut heap = BinaryHeap::new();
+        Self::create_with_direction(iters, Direction::Forward)
+    }
 
-        if iters.iter().all(|x| !x.is_valid()) {
-            // All invalid, select the last one as the current.
-            let mut iters = iters;
-            return Self {
-                iters: heap,
-                current: Some(HeapWrapper(0, iters.pop().unwrap())),
-            };
-        }
+    /// Merges the iterators positioned for moving in `direction`. When moving backward, the
+    /// iterators are at their last key, and the current key is the largest one.
+    pub fn create_with_direction(iters: Vec<Box<I>>, direction: Direction) -> Self {
+        let mut iter = Self {
+            iters: BinaryHeap::new(),
+            current: None,
+            exhausted: iters
+                .into_iter()
+                .enumerate()
+                .map(|(idx, iter)| HeapWrapper(idx, iter, direction))
+                .collect(),
+            direction,
+        };
+        iter.rebuild_heap();
+        iter
+    }
 
-        for (idx, iter) in iters.into_iter().enumerate() {
-            if iter.is_valid() {
-                heap.push(HeapWrapper(idx, iter));
+    /// Puts all valid iterators into the heap ordered by the current direction, and selects the
+    /// current one.
+    fn rebuild_heap(&mut self) {
+        let iters = std::mem::take(&mut self.iters)
+            .into_vec()
+            .into_iter()
+            .chain(self.current.take())
+            .chain(std::mem::take(&mut self.exhausted));
+        for mut iter in iters {
+            iter.2 = self.direction;
+            if iter.1.is_valid() {
+                self.iters.push(iter);
+            } else {
+                self.exhausted.push(iter);
             }
         }
-
-        let current = heap.pop().unwrap();
-        Self {
-            iters: heap,
-            current: Some(current),
-        }
+        self.current = self.iters.pop();
     }
 }
 
-impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIterator
-    for MergeIterator<I>
-{
-    type KeyType<'a> = KeySlice<'a>;
-
-    fn key(&self) -> KeySlice {
-        self.current.as_ref().unwrap().1.key()
-    }
-
-    fn value(&self) -> &[u8] {
-        self.current.as_ref().unwrap().1.value()
+impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> MergeIterator<I> {
+    /// Moves the iterators other than the current one to the other side of the current key, so
+    /// that the iterator can move in `direction`. An invalid iterator has passed all its keys, so
+    /// it starts over from the other end.
+    fn change_direction(&mut self, direction: Direction) -> Result<()> {
+        let current = self.current.as_mut().unwrap();
+        let key = current.1.key().to_key_vec();
+        current.2 = direction;
+        self.direction = direction;
+        let iters = std::mem::take(&mut self.iters)
+            .into_vec()
+            .into_iter()
+            .chain(std::mem::take(&mut self.exhausted));
+        for mut iter in iters {
+            iter.2 = direction;
+            match direction {
+                Direction::Forward => {
+                    if !iter.1.is_valid() {
+                        iter.1.seek_to_first()?;
+                    }
+                    while iter.1.is_valid() && iter.1.key() <= key.as_key_slice() {
+                        iter.1.next()?;
+                    }
+                }
+                Direction::Backward => {
+                    if !iter.1.is_valid() {
+                        iter.1.seek_to_last()?;
+                    }
+                    while iter.1.is_valid() && iter.1.key() >= key.as_key_slice() {
+                        iter.1.prev()?;
+                    }
+                }
+            }
+            if iter.1.is_valid() {
+                self.iters.push(iter);
+            } else {
+                self.exhausted.push(iter);
+            }
+        }
+        Ok(())
     }
 
-    fn is_valid(&self) -> bool {
-        self.current
-            .as_ref()
-            .map(|x| x.1.is_valid())
-            .unwrap_or(false)
+    /// Seeks all iterators to the first key, or to the last key when moving backward. With a
+    /// `key`, seeks them to the first key >= `key`, or to the last key <= `key`.
+    fn seek_all(&mut self, direction: Direction, key: Option<KeySlice>) -> Result<()> {
+        self.direction = direction;
+        let iters = std::mem::take(&mut self.iters).into_vec();
+        self.exhausted
+            .extend(iters.into_iter().chain(self.current.take()));
+        for iter in self.exhausted.iter_mut() {
+            match (direction, key) {
+                (Direction::Forward, None) => iter.1.seek_to_first()?,
+                (Direction::Backward, None) => iter.1.seek_to_last()?,
+                (Direction::Forward, Some(key)) => iter.1.seek(key)?,
+                (Direction::Backward, Some(key)) => iter.1.seek_for_prev(key)?,
+            }
+        }
+        self.rebuild_heap();
+        Ok(())
     }
 
-    fn next(&mut self) -> Result<()> {
+    /// Moves to the next key in the current direction.
+    fn step(&mut self) -> Result<()> {
+        let direction = self.direction;
         let current = self.current.as_mut().unwrap();
         // Pop the item out of the heap if they have the same value.
         while let Some(mut inner_iter) = self.iters.peek_mut() {
             debug_assert!(
-                inner_iter.1.key() >= current.1.key(),
+                match direction {
+                    Direction::Forward => inner_iter.1.key() >= current.1.key(),
+                    Direction::Backward => inner_iter.1.key() <= current.1.key(),
+                },
                 "heap invariant violated"
             );
             if inner_iter.1.key() == current.1.key() {
+                let result = match direction {
+                    Direction::Forward => inner_iter.1.next(),
+                    Direction::Backward => inner_iter.1.prev(),
+                };
                 // Case 1: an error occurred when calling `next`.
-                if let e @ Err(_) = inner_iter.1.next() {
+                if let e @ Err(_) = result {
                     PeekMut::pop(inner_iter);
                     return e;
                 }
 
                 // Case 2: iter is no longer valid.
                 if !inner_iter.1.is_valid() {
-                    PeekMut::pop(inner_iter);
+                    self.exhausted.push(PeekMut::pop(inner_iter));
                 }
             } else {
                 break;
             }
         }
 
-        current.1.next()?;
+        match direction {
+            Direction::Forward => current.1.next()?,
+            Direction::Backward => current.1.prev()?,
+        }
 
         // If the current iterator is invalid, pop it out of the heap and select the next one.
         if !current.1.is_valid() {
             if let Some(iter) = self.iters.pop() {
-                *current = iter;
+                self.exhausted.push(std::mem::replace(current, iter));
             }
             return Ok(());
         }
@@ -140,6 +205,61 @@ impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIt
 
         Ok(())
     }
+}
+
+impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIterator
+    for MergeIterator<I>
+{
+    type KeyType<'a> = KeySlice<'a>;
+
+    fn key(&self) -> KeySlice {
+        self.current.as_ref().unwrap().1.key()
+    }
+
+    fn value(&self) -> &[u8] {
+        self.current.as_ref().unwrap().1.value()
+    }
+
+    fn record_kind(&self) -> RecordKind {
+        self.current.as_ref().unwrap().1.record_kind()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.current
+            .as_ref()
+            .map(|x| x.1.is_valid())
+            .unwrap_or(false)
+    }
+
+    fn next(&mut self) -> Result<()> {
+        if self.direction != Direction::Forward {
+            self.change_direction(Direction::Forward)?;
+        }
+        self.step()
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        if self.direction != Direction::Backward {
+            self.change_direction(Direction::Backward)?;
+        }
+        self.step()
+    }
+
+    fn seek_to_first(&mut self) -> Result<()> {
+        self.seek_all(Direction::Forward, None)
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        self.seek_all(Direction::Backward, None)
+    }
+
+    fn seek(&mut self, key: KeySlice) -> Result<()> {
+        self.seek_all(Direction::Forward, Some(key))
+    }
+
+    fn seek_for_prev(&mut self, key: KeySlice) -> Result<()> {
+        self.seek_all(Direction::Backward, Some(key))
+    }
 
     fn num_active_iterators(&self) -> usize {
         self.iters
@@ -1,6 +1,6 @@
 use anyhow::Result;
 
-use super::StorageIterator;
+use super::{Direction, RecordKind, StorageIterator};
 
 /// Merges two iterators of different types into one. If the two iterators have the same key, only
 /// produce the key once and prefer the entry from A.
@@ -8,6 +8,7 @@ pub struct TwoMergeIterator<A: StorageIterator, B: StorageIterator> {
     a: A,
     b: B,
     choose_a: bool,
+    direction: Direction,
 }
 
 impl<
@@ -15,33 +16,115 @@ impl<
         B: 'static + for<'a> StorageIterator<KeyType<'a> = A::KeyType<'a>>,
     > TwoMergeIterator<A, B>
 {
-    fn choose_a(a: &A, b: &B) -> bool {
+    fn choose_a(a: &A, b: &B, direction: Direction) -> bool {
         if !a.is_valid() {
             return false;
         }
         if !b.is_valid() {
             return true;
         }
-        a.key() < b.key()
+        match direction {
+            Direction::Forward => a.key() < b.key(),
+            Direction::Backward => a.key() > b.key(),
+        }
     }
 
     fn skip_b(&mut self) -> Result<()> {
         if self.a.is_valid() && self.b.is_valid() && self.b.key() == self.a.key() {
-            self.b.next()?;
+            match self.direction {
+                Direction::Forward => self.b.next()?,
+                Direction::Backward => self.b.prev()?,
+            }
         }
         Ok(())
     }
 
     pub fn create(a: A, b: B) -> Result<Self> {
+        Self::create_with_direction(a, b, Direction::Forward)
+    }
+
+    /// Merges the iterators positioned for moving in `direction`. When moving backward, the
+    /// iterators are at their last key, and the current key is the larger one.
+    pub fn create_with_direction(a: A, b: B, direction: Direction) -> Result<Self> {
         let mut iter = Self {
             choose_a: false,
             a,
             b,
+            direction,
         };
         iter.skip_b()?;
-        iter.choose_a = Self::choose_a(&iter.a, &iter.b);
+        iter.choose_a = Self::choose_a(&iter.a, &iter.b, direction);
         Ok(iter)
     }
+
+    /// Moves the iterator that is not chosen to the other side of the current key, so that the
+    /// iterator can move in `direction`. An invalid iterator has passed all its keys, so it starts
+    /// over from the other end.
+    fn change_direction(&mut self, direction: Direction) -> Result<()> {
+        self.direction = direction;
+        match (self.choose_a, direction) {
+            (true, Direction::Forward) => {
+                if !self.b.is_valid() {
+                    self.b.seek_to_first()?;
+                }
+                while self.b.is_valid() && self.b.key() <= self.a.key() {
+                    self.b.next()?;
+                }
+            }
+            (true, Direction::Backward) => {
+                if !self.b.is_valid() {
+                    self.b.seek_to_last()?;
+                }
+                while self.b.is_valid() && self.b.key() >= self.a.key() {
+                    self.b.prev()?;
+                }
+            }
+            (false, Direction::Forward) => {
+                if !self.a.is_valid() {
+                    self.a.seek_to_first()?;
+                }
+                while self.a.is_valid() && self.a.key() <= self.b.key() {
+                    self.a.next()?;
+                }
+            }
+            (false, Direction::Backward) => {
+                if !self.a.is_valid() {
+                    self.a.seek_to_last()?;
+                }
+                while self.a.is_valid() && self.a.key() >= self.b.key() {
+                    self.a.prev()?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Seeks both iterators to the first key, or to the last key when moving backward. With a
+    /// `key`, seeks them to the first key >= `key`, or to the last key <= `key`.
+    fn seek_both(&mut self, direction: Direction, key: Option<A::KeyType<'_>>) -> Result<()> {
+        self.direction = direction;
+        match (direction, key) {
+            (Direction::Forward, None) => {
+                self.a.seek_to_first()?;
+                self.b.seek_to_first()?;
+            }
+            (Direction::Backward, None) => {
+                self.a.seek_to_last()?;
+                self.b.seek_to_last()?;
+            }
+            (Direction::Forward, Some(key)) => {
+                self.a.seek(key)?;
+                self.b.seek(key)?;
+            }
+            (Direction::Backward, Some(key)) => {
+                self.a.seek_for_prev(key)?;
+                self.b.seek_for_prev(key)?;
+            }
+        }
+        self.skip_b()?;
+        self.choose_a = Self::choose_a(&self.a, &self.b, direction);
+        Ok(())
+    }
 }
 
 impl<
@@ -69,6 +152,14 @@ impl<
         }
     }
 
+    fn record_kind(&self) -> RecordKind {
+        if self.choose_a {
+            self.a.record_kind()
+        } else {
+            self.b.record_kind()
+        }
+    }
+
     fn is_valid(&self) -> bool {
         if self.choose_a {
             self.a.is_valid()
@@ -78,16 +169,49 @@ impl<
     }
 
     fn next(&mut self) -> Result<()> {
+        if self.direction != Direction::Forward {
+            self.change_direction(Direction::Forward)?;
+        }
         if self.choose_a {
             self.a.next()?;
         } else {
             self.b.next()?;
         }
         self.skip_b()?;
-        self.choose_a = Self::choose_a(&self.a, &self.b);
+        self.choose_a = Self::choose_a(&self.a, &self.b, self.direction);
+        Ok(())
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        if self.direction != Direction::Backward {
+            self.change_direction(Direction::Backward)?;
+        }
+        if self.choose_a {
+            self.a.prev()?;
+        } else {
+            self.b.prev()?;
+        }
+        self.skip_b()?;
+        self.choose_a = Self::choose_a(&self.a, &self.b, self.direction);
         Ok(())
     }
 
+    fn seek_to_first(&mut self) -> Result<()> {
+        self.seek_both(Direction::Forward, None)
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        self.seek_both(Direction::Backward, None)
+    }
+
+    fn seek(&mut self, key: A::KeyType<'_>) -> Result<()> {
+        self.seek_both(Direction::Forward, Some(key))
+    }
+
+    fn seek_for_prev(&mut self, key: A::KeyType<'_>) -> Result<()> {
+        self.seek_both(Direction::Backward, Some(key))
+    }
+
     fn num_active_iterators(&self) -> usize {
         self.a.num_active_iterators() + self.b.num_active_iterators()
     }
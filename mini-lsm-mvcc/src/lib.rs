@@ -1,15 +1,27 @@
 pub mod block;
+pub mod block_cache;
+pub mod checker;
+pub mod column_family;
 pub mod compact;
 pub mod debug;
+pub mod group_commit;
 pub mod iterators;
 pub mod key;
 pub mod lsm_iterator;
 pub mod lsm_storage;
 pub mod manifest;
 pub mod mem_table;
+pub mod merge_operator;
 pub mod mvcc;
+pub mod orphan_files;
+pub mod prefix_extractor;
+pub mod range_tombstone;
+pub mod rate_limiter;
 pub mod table;
+pub mod value_log;
 pub mod wal;
+pub mod write_buffer_manager;
+pub mod write_stall;
 
 #[cfg(test)]
 mod tests;
@@ -1,4 +1,5 @@
 use std::ops::Bound;
+use std::sync::Arc;
 
 use anyhow::{bail, Result};
 use bytes::Bytes;
@@ -6,9 +7,13 @@ use bytes::Bytes;
 use crate::iterators::concat_iterator::SstConcatIterator;
 use crate::iterators::merge_iterator::MergeIterator;
 use crate::iterators::two_merge_iterator::TwoMergeIterator;
-use crate::iterators::StorageIterator;
+use crate::iterators::{Direction, RecordKind, StorageIterator};
+use crate::key::{KeySlice, TS_RANGE_BEGIN, TS_RANGE_END};
 use crate::mem_table::MemTableIterator;
+use crate::merge_operator::MergeOperator;
+use crate::range_tombstone::RangeTombstones;
 use crate::table::SsTableIterator;
+use crate::value_log::ValueLog;
 
 /// Represents the internal type for an LSM iterator. This type will be changed across the tutorial for multiple times.
 type LsmIteratorInner = TwoMergeIterator<
@@ -18,44 +23,133 @@ type LsmIteratorInner = TwoMergeIterator<
 
 pub struct LsmIterator {
     inner: LsmIteratorInner,
+    start_bound: Bound<Bytes>,
     end_bound: Bound<Bytes>,
     is_valid: bool,
     read_ts: u64,
     prev_key: Vec<u8>,
+    /// When moving forward, the inner iterator is at the records of the current key, or past the
+    /// ones combined into `buffered_value`. When moving backward, it is at the last record of the
+    /// previous key.
+    direction: Direction,
+    /// The range tombstones visible at `read_ts`.
+    range_tombstones: RangeTombstones,
+    merge_operator: Option<Arc<dyn MergeOperator>>,
+    /// The value of the current key if the inner iterator is not at it, because the value is
+    /// combined from merge operands, or because the iterator moves backward.
+    buffered_value: Option<Bytes>,
+    /// Resolves the values in the LSM tree to user values if the value log is enabled.
+    value_log: Option<Arc<ValueLog>>,
+    resolved_value: Bytes,
+}
+
+fn after_start_bound(key: &[u8], bound: Bound<&Bytes>) -> bool {
+    match bound {
+        Bound::Unbounded => true,
+        Bound::Included(start) => key >= start.as_ref(),
+        Bound::Excluded(start) => key > start.as_ref(),
+    }
+}
+
+fn before_end_bound(key: &[u8], bound: Bound<&Bytes>) -> bool {
+    match bound {
+        Bound::Unbounded => true,
+        Bound::Included(end) => key <= end.as_ref(),
+        Bound::Excluded(end) => key < end.as_ref(),
+    }
 }
 
 impl LsmIterator {
+    /// Creates an iterator over the keys in `bounds`. When moving backward, the inner iterator is
+    /// at the last record within the end bound.
     pub(crate) fn new(
         iter: LsmIteratorInner,
-        end_bound: Bound<Bytes>,
+        (start_bound, end_bound): (Bound<Bytes>, Bound<Bytes>),
         read_ts: u64,
+        range_tombstones: RangeTombstones,
+        merge_operator: Option<Arc<dyn MergeOperator>>,
+        value_log: Option<Arc<ValueLog>>,
+        direction: Direction,
     ) -> Result<Self> {
         let mut iter = Self {
             is_valid: iter.is_valid(),
             inner: iter,
+            start_bound,
             end_bound,
             read_ts,
             prev_key: Vec::new(),
+            direction,
+            range_tombstones,
+            merge_operator,
+            buffered_value: None,
+            value_log,
+            resolved_value: Bytes::new(),
         };
-        iter.move_to_key()?;
+        match direction {
+            Direction::Forward => {
+                // The first key may be past the end bound already.
+                iter.update_is_valid();
+                iter.move_to_key()?
+            }
+            Direction::Backward => iter.move_to_prev_key()?,
+        }
         Ok(iter)
     }
 
     fn next_inner(&mut self) -> Result<()> {
         self.inner.next()?;
+        self.update_is_valid();
+        Ok(())
+    }
+
+    /// Checks the key of the inner iterator against the bound it moves towards.
+    fn update_is_valid(&mut self) {
         if !self.inner.is_valid() {
             self.is_valid = false;
-            return Ok(());
+            return;
         }
-        match self.end_bound.as_ref() {
-            Bound::Unbounded => {}
-            Bound::Included(key) => self.is_valid = self.inner.key().key_ref() <= key.as_ref(),
-            Bound::Excluded(key) => self.is_valid = self.inner.key().key_ref() < key.as_ref(),
+        let key = self.inner.key().key_ref();
+        self.is_valid = match self.direction {
+            Direction::Forward => before_end_bound(key, self.end_bound.as_ref()),
+            Direction::Backward => after_start_bound(key, self.start_bound.as_ref()),
+        };
+    }
+
+    fn full_merge(&self, existing: Option<&[u8]>, operands: &[Bytes]) -> Result<Bytes> {
+        let Some(merge_operator) = &self.merge_operator else {
+            bail!("merge operator is not configured");
+        };
+        Ok(merge_operator.full_merge(&self.prev_key, existing, operands))
+    }
+
+    /// Combines the merge operands of the current key with the value they apply to, leaving the
+    /// inner iterator at the first record not combined.
+    fn merge_operands(&mut self) -> Result<()> {
+        let mut operands = Vec::new();
+        let mut existing = None;
+        while self.inner.is_valid() && self.inner.key().key_ref() == self.prev_key {
+            if self
+                .range_tombstones
+                .covers(&self.prev_key, self.inner.key().ts())
+            {
+                break;
+            }
+            if self.inner.record_kind() == RecordKind::Value {
+                if !self.inner.value().is_empty() {
+                    existing = Some(Bytes::copy_from_slice(self.inner.value()));
+                }
+                break;
+            }
+            operands.push(Bytes::copy_from_slice(self.inner.value()));
+            self.inner.next()?;
         }
+        operands.reverse();
+        self.buffered_value = Some(self.full_merge(existing.as_deref(), &operands)?);
         Ok(())
     }
 
     fn move_to_key(&mut self) -> Result<()> {
+        self.buffered_value = None;
         loop {
             while self.inner.is_valid() && self.inner.key().key_ref() == self.prev_key {
                 self.next_inner()?;
@@ -77,10 +171,128 @@ impl LsmIterator {
             if self.inner.key().key_ref() != self.prev_key {
                 continue;
             }
+            if self
+                .range_tombstones
+                .covers(self.inner.key().key_ref(), self.inner.key().ts())
+            {
+                continue;
+            }
+            if self.inner.record_kind() == RecordKind::MergeOperand {
+                self.merge_operands()?;
+                if !self.buffered_value.as_ref().unwrap().is_empty() {
+                    break;
+                }
+                // Deleted by the merge operator.
+                self.buffered_value = None;
+                self.update_is_valid();
+                continue;
+            }
             if !self.inner.value().is_empty() {
                 break;
             }
         }
+        self.resolve_value()
+    }
+
+    /// Moves backward to the previous key with a value, from the last record of the key.
+    fn move_to_prev_key(&mut self) -> Result<()> {
+        self.buffered_value = None;
+        loop {
+            self.update_is_valid();
+            if !self.is_valid {
+                break;
+            }
+            self.prev_key.clear();
+            self.prev_key.extend(self.inner.key().key_ref());
+            // The versions are visited from the oldest to the newest. Keeps the newest value
+            // visible at `read_ts`, and the merge operands newer than it.
+            let mut value = None;
+            let mut operands = Vec::new();
+            while self.inner.is_valid() && self.inner.key().key_ref() == self.prev_key {
+                let ts = self.inner.key().ts();
+                if ts <= self.read_ts {
+                    if self.range_tombstones.covers(&self.prev_key, ts) {
+                        value = None;
+                        operands.clear();
+                    } else if self.inner.record_kind() == RecordKind::MergeOperand {
+                        operands.push(Bytes::copy_from_slice(self.inner.value()));
+                    } else {
+                        value = (!self.inner.value().is_empty())
+                            .then(|| Bytes::copy_from_slice(self.inner.value()));
+                        operands.clear();
+                    }
+                }
+                self.inner.prev()?;
+            }
+            if !operands.is_empty() {
+                value = Some(self.full_merge(value.as_deref(), &operands)?);
+            }
+            if let Some(value) = value.filter(|value| !value.is_empty()) {
+                self.buffered_value = Some(value);
+                break;
+            }
+        }
+        self.resolve_value()
+    }
+
+    /// Moves to the first key >= `key` within the start bound, or to the first key within it.
+    fn seek_forward(&mut self, key: Option<&[u8]>) -> Result<()> {
+        self.direction = Direction::Forward;
+        let start = match self.start_bound.as_ref() {
+            Bound::Included(start) | Bound::Excluded(start) => Some(start.as_ref()),
+            Bound::Unbounded => None,
+        };
+        // The first version of the key sorts first.
+        match key.max(start) {
+            Some(key) => self.inner.seek(KeySlice::from_slice(key, TS_RANGE_BEGIN))?,
+            None => self.inner.seek_to_first()?,
+        }
+        while self.inner.is_valid()
+            && !after_start_bound(self.inner.key().key_ref(), self.start_bound.as_ref())
+        {
+            self.inner.next()?;
+        }
+        self.prev_key.clear();
+        self.update_is_valid();
+        self.move_to_key()
+    }
+
+    /// Moves to the last key <= `key` within the end bound, or to the last key within it.
+    fn seek_backward(&mut self, key: Option<&[u8]>) -> Result<()> {
+        self.direction = Direction::Backward;
+        let end = match self.end_bound.as_ref() {
+            Bound::Included(end) | Bound::Excluded(end) => Some(end.as_ref()),
+            Bound::Unbounded => None,
+        };
+        let key = match (key, end) {
+            (Some(key), Some(end)) => Some(key.min(end)),
+            (key, end) => key.or(end),
+        };
+        // The oldest version of the key sorts last.
+        match key {
+            Some(key) => self
+                .inner
+                .seek_for_prev(KeySlice::from_slice(key, TS_RANGE_END))?,
+            None => self.inner.seek_to_last()?,
+        }
+        while self.inner.is_valid()
+            && !before_end_bound(self.inner.key().key_ref(), self.end_bound.as_ref())
+        {
+            self.inner.prev()?;
+        }
+        self.move_to_prev_key()
+    }
+
+    fn resolve_value(&mut self) -> Result<()> {
+        if let Some(value_log) = &self.value_log {
+            if self.is_valid {
+                let value = match &self.buffered_value {
+                    Some(value) => value,
+                    None => self.inner.value(),
+                };
+                self.resolved_value = value_log.resolve(value)?;
+            }
+        }
         Ok(())
     }
 }
@@ -93,19 +305,69 @@ impl StorageIterator for LsmIterator {
     }
 
     fn key(&self) -> &[u8] {
-        self.inner.key().key_ref()
+        &self.prev_key
     }
 
     fn value(&self) -> &[u8] {
-        self.inner.value()
+        if self.value_log.is_some() {
+            &self.resolved_value
+        } else if let Some(buffered_value) = &self.buffered_value {
+            buffered_value
+        } else {
+            self.inner.value()
+        }
     }
 
     fn next(&mut self) -> Result<()> {
-        self.next_inner()?;
+        if self.direction == Direction::Backward {
+            // Moves the inner iterator to the records of the current key.
+            self.direction = Direction::Forward;
+            if !self.inner.is_valid() {
+                self.inner.seek_to_first()?;
+            }
+            while self.inner.is_valid() && self.inner.key().key_ref() < self.prev_key.as_slice() {
+                self.inner.next()?;
+            }
+        }
+        // The records of the current key are skipped by `move_to_key`, and may have been consumed
+        // by a merge already.
+        self.update_is_valid();
         self.move_to_key()?;
         Ok(())
     }
 
+    fn prev(&mut self) -> Result<()> {
+        if self.direction == Direction::Forward {
+            // Moves the inner iterator to the last record of the previous key.
+            self.direction = Direction::Backward;
+            if !self.inner.is_valid() {
+                self.inner.seek_to_last()?;
+            }
+            while self.inner.is_valid() && self.inner.key().key_ref() >= self.prev_key.as_slice() {
+                self.inner.prev()?;
+            }
+        }
+        self.move_to_prev_key()
+    }
+
+    fn seek_to_first(&mut self) -> Result<()> {
+        self.seek_forward(None)
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        self.seek_backward(None)
+    }
+
+    /// Moves to the first key >= `key` within the bounds, reading the same snapshot.
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        self.seek_forward(Some(key))
+    }
+
+    /// Moves to the last key <= `key` within the bounds, reading the same snapshot.
+    fn seek_for_prev(&mut self, key: &[u8]) -> Result<()> {
+        self.seek_backward(Some(key))
+    }
+
     fn num_active_iterators(&self) -> usize {
         self.inner.num_active_iterators()
     }
@@ -163,6 +425,63 @@ impl<I: StorageIterator> StorageIterator for FusedIterator<I> {
         Ok(())
     }
 
+    fn prev(&mut self) -> Result<()> {
+        if self.has_errored {
+            bail!("the iterator is tainted");
+        }
+        if self.iter.is_valid() {
+            if let Err(e) = self.iter.prev() {
+                self.has_errored = true;
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    fn seek_to_first(&mut self) -> Result<()> {
+        if self.has_errored {
+            bail!("the iterator is tainted");
+        }
+        if let Err(e) = self.iter.seek_to_first() {
+            self.has_errored = true;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        if self.has_errored {
+            bail!("the iterator is tainted");
+        }
+        if let Err(e) = self.iter.seek_to_last() {
+            self.has_errored = true;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn seek(&mut self, key: Self::KeyType<'_>) -> Result<()> {
+        if self.has_errored {
+            bail!("the iterator is tainted");
+        }
+        if let Err(e) = self.iter.seek(key) {
+            self.has_errored = true;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn seek_for_prev(&mut self, key: Self::KeyType<'_>) -> Result<()> {
+        if self.has_errored {
+            bail!("the iterator is tainted");
+        }
+        if let Err(e) = self.iter.seek_for_prev(key) {
+            self.has_errored = true;
+            return Err(e);
+        }
+        Ok(())
+    }
+
     fn num_active_iterators(&self) -> usize {
         self.iter.num_active_iterators()
     }
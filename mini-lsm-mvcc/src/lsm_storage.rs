@@ -1,32 +1,43 @@
-use std::collections::{BTreeSet, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs::File;
 use std::ops::Bound;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
 
-use anyhow::{Context, Result};
+use anyhow::{bail, ensure, Context, Result};
 use bytes::Bytes;
 use parking_lot::{Mutex, MutexGuard, RwLock};
 
-use crate::block::Block;
+pub use crate::block_cache::BlockCache;
+use crate::block_cache::{BlockCacheOptions, BlockCacheStats};
+use crate::column_family::{
+    ColumnFamily, ColumnFamilyOptions, DEFAULT_COLUMN_FAMILY, DEFAULT_COLUMN_FAMILY_ID,
+};
 use crate::compact::{
-    CompactionController, CompactionOptions, LeveledCompactionController, LeveledCompactionOptions,
-    SimpleLeveledCompactionController, SimpleLeveledCompactionOptions, TieredCompactionController,
+    apply_force_full_compaction_result, CompactionController, CompactionOptions, CompactionTask,
+    LeveledCompactionOptions, SimpleLeveledCompactionOptions,
 };
+use crate::group_commit::{GroupCommitStats, WriteQueue};
 use crate::iterators::concat_iterator::SstConcatIterator;
 use crate::iterators::merge_iterator::MergeIterator;
 use crate::iterators::two_merge_iterator::TwoMergeIterator;
-use crate::iterators::StorageIterator;
+use crate::iterators::{Direction, RecordKind, StorageIterator};
 use crate::key::{self, KeySlice};
 use crate::lsm_iterator::{FusedIterator, LsmIterator};
-use crate::manifest::{Manifest, ManifestRecord};
-use crate::mem_table::{map_bound, map_key_bound_plus_ts, MemTable};
+use crate::manifest::{ColumnFamilySnapshot, Manifest, ManifestRecord, ManifestSnapshot};
+use crate::mem_table::{map_bound, MemTable, MemTableType};
+use crate::merge_operator::MergeOperator;
 use crate::mvcc::txn::{Transaction, TxnIterator};
 use crate::mvcc::LsmMvccInner;
-use crate::table::{FileObject, SsTable, SsTableBuilder, SsTableIterator};
-
-pub type BlockCache = moka::sync::Cache<(usize, usize), Arc<Block>>;
+use crate::orphan_files::{OrphanFileAction, OrphanFileReport};
+use crate::prefix_extractor::PrefixExtractor;
+use crate::rate_limiter::{IoPriority, RateLimiter};
+use crate::table::{CompressionType, FileObject, SsTable, SsTableBuilder, SsTableIterator};
+use crate::value_log::{ValueLog, ValueLogOptions};
+use crate::wal::{Wal, WalBatch, WalOptions, WalRecoveryMode};
+use crate::write_buffer_manager::WriteBufferManager;
+use crate::write_stall::{WriteStallController, WriteStallOptions, WriteStallState};
 
 /// Represents the state of the storage engine.
 #[derive(Clone)]
@@ -47,11 +58,59 @@ pub struct LsmStorageState {
 pub enum WriteBatchRecord<T: AsRef<[u8]>> {
     Put(T, T),
     Del(T),
+    /// Deletes the keys in `[lower, upper)`.
+    DelRange(T, T),
+    /// Combines an operand with the existing value of the key.
+    Merge(T, T),
+}
+
+impl<T: AsRef<[u8]>> WriteBatchRecord<T> {
+    /// The key of the record, or the lower bound of a range deletion.
+    pub(crate) fn key(&self) -> &[u8] {
+        match self {
+            WriteBatchRecord::Put(key, _)
+            | WriteBatchRecord::Del(key)
+            | WriteBatchRecord::DelRange(key, _)
+            | WriteBatchRecord::Merge(key, _) => key.as_ref(),
+        }
+    }
+
+    /// Panics if the record writes an empty key, value or operand, or deletes an empty range.
+    pub(crate) fn validate(&self) {
+        match self {
+            WriteBatchRecord::Del(key) => {
+                assert!(!key.as_ref().is_empty(), "key cannot be empty");
+            }
+            WriteBatchRecord::Put(key, value) => {
+                assert!(!key.as_ref().is_empty(), "key cannot be empty");
+                assert!(!value.as_ref().is_empty(), "value cannot be empty");
+            }
+            WriteBatchRecord::Merge(key, operand) => {
+                assert!(!key.as_ref().is_empty(), "key cannot be empty");
+                assert!(!operand.as_ref().is_empty(), "operand cannot be empty");
+            }
+            WriteBatchRecord::DelRange(lower, upper) => {
+                assert!(
+                    lower.as_ref() < upper.as_ref(),
+                    "range deletion must not be empty"
+                );
+            }
+        }
+    }
+
+    pub(crate) fn size(&self) -> usize {
+        match self {
+            WriteBatchRecord::Put(key, value)
+            | WriteBatchRecord::DelRange(key, value)
+            | WriteBatchRecord::Merge(key, value) => key.as_ref().len() + value.as_ref().len(),
+            WriteBatchRecord::Del(key) => key.as_ref().len(),
+        }
+    }
 }
 
 impl LsmStorageState {
-    fn create(options: &LsmStorageOptions) -> Self {
-        let levels = match &options.compaction_options {
+    pub(crate) fn create(compaction_options: &CompactionOptions) -> Self {
+        let levels = match compaction_options {
             CompactionOptions::Leveled(LeveledCompactionOptions { max_levels, .. })
             | CompactionOptions::Simple(SimpleLeveledCompactionOptions { max_levels, .. }) => (1
                 ..=*max_levels)
@@ -80,7 +139,43 @@ pub struct LsmStorageOptions {
     pub num_memtable_limit: usize,
     pub compaction_options: CompactionOptions,
     pub enable_wal: bool,
+    // Recovery mode, preallocation and recycling of the WAL files
+    pub wal: WalOptions,
     pub serializable: bool,
+    // Compression codec for newly-written SST data blocks
+    pub compression: CompressionType,
+    // Maximum number of key ranges a compaction is split into, and of threads running the key
+    // ranges of all compactions at the same time
+    pub max_subcompactions: usize,
+    // Maximum number of compaction tasks running at the same time on disjoint levels
+    pub max_background_compactions: usize,
+    // Limits that slow down or stop writes when flushes and compactions fall behind
+    pub write_stall: WriteStallOptions,
+    // Limits the write throughput of flushes and compactions, can be shared by multiple engines
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    // Stores large values in a value log, cannot be changed once the storage is created
+    pub value_log: Option<ValueLogOptions>,
+    // Combines merge operands with existing values, cannot be used with the value log
+    pub merge_operator: Option<Arc<dyn MergeOperator>>,
+    // Extracts the key prefixes added to the SST bloom filters, so that prefix scans skip SSTs
+    pub prefix_extractor: Option<Arc<dyn PrefixExtractor>>,
+    // Splits the index and the filter of new SSTs into partitions of about this many bytes, which
+    // are loaded through the block cache when needed
+    pub index_partition_size: Option<usize>,
+    // Capacity and pools of the block cache, unless `shared_block_cache` is set
+    pub block_cache: BlockCacheOptions,
+    // A block cache shared with other engines, which replaces the one of `block_cache`
+    pub shared_block_cache: Option<Arc<BlockCache>>,
+    // The data structure of new memtables, which can be changed when the storage is reopened
+    pub memtable_type: MemTableType,
+    // Caps the memory of the memtables by freezing and flushing them, can be shared by multiple
+    // engines
+    pub write_buffer_manager: Option<Arc<WriteBufferManager>>,
+    // Size in bytes above which the manifest is rolled over into a new file starting with a
+    // snapshot of the state
+    pub max_manifest_size: u64,
+    // What to do with the SSTs and WALs not referenced by the state when the storage is opened
+    pub orphan_file_action: OrphanFileAction,
 }
 
 impl LsmStorageOptions {
@@ -90,8 +185,24 @@ impl LsmStorageOptions {
             target_sst_size: 2 << 20,
             compaction_options: CompactionOptions::NoCompaction,
             enable_wal: false,
+            wal: WalOptions::default(),
             num_memtable_limit: 50,
             serializable: false,
+            compression: CompressionType::None,
+            max_subcompactions: 1,
+            max_background_compactions: 1,
+            write_stall: WriteStallOptions::default(),
+            rate_limiter: None,
+            value_log: None,
+            merge_operator: None,
+            prefix_extractor: None,
+            index_partition_size: None,
+            block_cache: BlockCacheOptions::default(),
+            shared_block_cache: None,
+            memtable_type: MemTableType::SkipList,
+            write_buffer_manager: None,
+            max_manifest_size: 4 << 20,
+            orphan_file_action: OrphanFileAction::Delete,
         }
     }
 
@@ -101,8 +212,24 @@ impl LsmStorageOptions {
             target_sst_size: 2 << 20,
             compaction_options: CompactionOptions::NoCompaction,
             enable_wal: false,
+            wal: WalOptions::default(),
             num_memtable_limit: 2,
             serializable: false,
+            compression: CompressionType::None,
+            max_subcompactions: 1,
+            max_background_compactions: 1,
+            write_stall: WriteStallOptions::default(),
+            rate_limiter: None,
+            value_log: None,
+            merge_operator: None,
+            prefix_extractor: None,
+            index_partition_size: None,
+            block_cache: BlockCacheOptions::default(),
+            shared_block_cache: None,
+            memtable_type: MemTableType::SkipList,
+            write_buffer_manager: None,
+            max_manifest_size: 4 << 20,
+            orphan_file_action: OrphanFileAction::Delete,
         }
     }
 
@@ -112,13 +239,68 @@ impl LsmStorageOptions {
             target_sst_size: 1 << 20, // 1MB
             compaction_options,
             enable_wal: false,
+            wal: WalOptions::default(),
             num_memtable_limit: 2,
             serializable: false,
+            compression: CompressionType::None,
+            max_subcompactions: 1,
+            max_background_compactions: 1,
+            write_stall: WriteStallOptions::default(),
+            rate_limiter: None,
+            value_log: None,
+            merge_operator: None,
+            prefix_extractor: None,
+            index_partition_size: None,
+            block_cache: BlockCacheOptions::default(),
+            shared_block_cache: None,
+            memtable_type: MemTableType::SkipList,
+            write_buffer_manager: None,
+            max_manifest_size: 4 << 20,
+            orphan_file_action: OrphanFileAction::Delete,
+        }
+    }
+}
+
+/// The options of a read.
+#[derive(Debug, Clone)]
+pub struct ReadOptions {
+    // Add the blocks read from disk to the block cache, which large scans can turn off so that they
+    // do not evict the blocks of other reads
+    pub fill_cache: bool,
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        Self { fill_cache: true }
+    }
+}
+
+/// The options of a write.
+#[derive(Debug, Clone, Default)]
+pub struct WriteOptions {
+    // Sync the WAL before the write returns, together with the other writes committed in the same
+    // group
+    pub sync: bool,
+}
+
+/// How a scan reads the LSM tree besides its range and direction.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ScanOptions<'a> {
+    /// Skips the SSTs without any key of the prefix.
+    pub(crate) prefix: Option<&'a [u8]>,
+    pub(crate) fill_cache: bool,
+}
+
+impl Default for ScanOptions<'_> {
+    fn default() -> Self {
+        Self {
+            prefix: None,
+            fill_cache: true,
         }
     }
 }
 
-fn range_overlap(
+pub(crate) fn range_overlap(
     user_begin: Bound<&[u8]>,
     user_end: Bound<&[u8]>,
     table_begin: KeySlice,
@@ -149,23 +331,120 @@ fn key_within(user_key: &[u8], table_begin: KeySlice, table_end: KeySlice) -> bo
     table_begin.key_ref() <= user_key && user_key <= table_end.key_ref()
 }
 
+/// Maps a lower bound of user keys to the memtable keys, so that the bound includes or excludes all
+/// versions of the key.
+fn map_lower_bound(lower: Bound<&[u8]>) -> Bound<KeySlice<'_>> {
+    match lower {
+        Bound::Included(key) => Bound::Included(KeySlice::from_slice(key, key::TS_RANGE_BEGIN)),
+        Bound::Excluded(key) => Bound::Excluded(KeySlice::from_slice(key, key::TS_RANGE_END)),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+/// Maps an upper bound of user keys to the memtable keys, so that the bound includes or excludes
+/// all versions of the key.
+fn map_upper_bound(upper: Bound<&[u8]>) -> Bound<KeySlice<'_>> {
+    match upper {
+        Bound::Included(key) => Bound::Included(KeySlice::from_slice(key, key::TS_RANGE_END)),
+        Bound::Excluded(key) => Bound::Excluded(KeySlice::from_slice(key, key::TS_RANGE_BEGIN)),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+/// The key to seek to before moving back to the last record within `upper`.
+fn seek_key_for_upper_bound(upper: Bound<&[u8]>) -> Option<KeySlice<'_>> {
+    match upper {
+        Bound::Included(key) => Some(KeySlice::from_slice(key, key::TS_RANGE_END)),
+        Bound::Excluded(key) => Some(KeySlice::from_slice(key, key::TS_RANGE_BEGIN)),
+        Bound::Unbounded => None,
+    }
+}
+
+/// Positions an iterator over SSTs at the first record of a scan in the direction.
+fn seek_to_scan_start<I>(
+    iter: &mut I,
+    lower: Bound<&[u8]>,
+    upper: Bound<&[u8]>,
+    direction: Direction,
+) -> Result<()>
+where
+    I: for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>,
+{
+    match (direction, lower) {
+        (Direction::Forward, Bound::Included(key)) => {
+            iter.seek(KeySlice::from_slice(key, key::TS_RANGE_BEGIN))?;
+        }
+        (Direction::Forward, Bound::Excluded(key)) => {
+            iter.seek(KeySlice::from_slice(key, key::TS_RANGE_BEGIN))?;
+            while iter.is_valid() && iter.key().key_ref() == key {
+                iter.next()?;
+            }
+        }
+        (Direction::Forward, Bound::Unbounded) => iter.seek_to_first()?,
+        (Direction::Backward, _) => {
+            match seek_key_for_upper_bound(upper) {
+                Some(key) => iter.seek(key)?,
+                None => iter.seek_to_last()?,
+            }
+            // The iterator is at the seek key or past it, move back to the last record within
+            // `upper`.
+            if !iter.is_valid() {
+                iter.seek_to_last()?;
+            }
+            while iter.is_valid() {
+                let beyond_upper = match upper {
+                    Bound::Included(key) => iter.key().key_ref() > key,
+                    Bound::Excluded(key) => iter.key().key_ref() >= key,
+                    Bound::Unbounded => false,
+                };
+                if !beyond_upper {
+                    break;
+                }
+                iter.prev()?;
+            }
+        }
+    }
+    Ok(())
+}
+
 #[derive(Clone, Debug)]
 pub enum CompactionFilter {
     Prefix(Bytes),
 }
 
+/// The state recovered from the records of the manifest, besides the SSTs of the column families.
+pub(crate) struct ReplayedManifest {
+    /// The memtables which are not flushed yet.
+    pub(crate) memtables: BTreeSet<usize>,
+    /// The largest SST or memtable id in the records.
+    pub(crate) last_sst_id: usize,
+    /// Bytes of value log segments found to be garbage, by segment id.
+    pub(crate) value_log_garbage: Vec<(usize, u64)>,
+}
+
 /// The storage interface of the LSM tree.
 pub(crate) struct LsmStorageInner {
+    /// The state of the default column family.
     pub(crate) state: Arc<RwLock<Arc<LsmStorageState>>>,
     pub(crate) state_lock: Mutex<()>,
     path: PathBuf,
     pub(crate) block_cache: Arc<BlockCache>,
     next_sst_id: AtomicUsize,
+    /// The number of threads spawned for subcompactions by all running compactions.
+    pub(crate) subcompaction_threads: AtomicUsize,
     pub(crate) options: Arc<LsmStorageOptions>,
-    pub(crate) compaction_controller: CompactionController,
+    /// All column families by id, including the default one.
+    pub(crate) column_families: RwLock<BTreeMap<usize, Arc<ColumnFamily>>>,
+    pub(crate) write_stall: WriteStallController,
+    pub(crate) write_queue: WriteQueue,
+    /// The files of the WALs of flushed memtables, kept to be reused by new memtables.
+    recycled_wals: Mutex<Vec<PathBuf>>,
+    pub(crate) value_log: Option<Arc<ValueLog>>,
     pub(crate) manifest: Option<Manifest>,
     pub(crate) mvcc: Option<LsmMvccInner>,
     pub(crate) compaction_filters: Arc<Mutex<Vec<CompactionFilter>>>,
+    /// The orphan files found when the storage was opened.
+    pub(crate) orphan_files: OrphanFileReport,
 }
 
 /// A thin wrapper for `LsmStorageInner` and the user interface for MiniLSM.
@@ -179,10 +458,15 @@ pub struct MiniLsm {
     compaction_notifier: crossbeam_channel::Sender<()>,
     /// The handle for the compaction thread. (In week 2)
     compaction_thread: Mutex<Option<std::thread::JoinHandle<()>>>,
+    /// Notifies the value log GC thread to stop working.
+    value_log_gc_notifier: crossbeam_channel::Sender<()>,
+    /// The handle for the value log GC thread.
+    value_log_gc_thread: Mutex<Option<std::thread::JoinHandle<()>>>,
 }
 
 impl Drop for MiniLsm {
     fn drop(&mut self) {
+        self.value_log_gc_notifier.send(()).ok();
         self.compaction_notifier.send(()).ok();
         self.flush_notifier.send(()).ok();
     }
@@ -191,9 +475,16 @@ impl Drop for MiniLsm {
 impl MiniLsm {
     pub fn close(&self) -> Result<()> {
         self.inner.sync_dir()?;
+        self.value_log_gc_notifier.send(()).ok();
         self.compaction_notifier.send(()).ok();
         self.flush_notifier.send(()).ok();
 
+        let mut value_log_gc_thread = self.value_log_gc_thread.lock();
+        if let Some(value_log_gc_thread) = value_log_gc_thread.take() {
+            value_log_gc_thread
+                .join()
+                .map_err(|e| anyhow::anyhow!("{:?}", e))?;
+        }
         let mut compaction_thread = self.compaction_thread.lock();
         if let Some(compaction_thread) = compaction_thread.take() {
             compaction_thread
@@ -214,11 +505,14 @@ impl MiniLsm {
         }
 
         // create memtable and skip updating manifest
-        if !self.inner.state.read().memtable.is_empty() {
-            self.inner
-                .freeze_memtable_with_memtable(Arc::new(MemTable::create(
+        if !self.inner.memtables_empty() {
+            self.inner.freeze_memtable_with_memtable(Arc::new(
+                MemTable::create_with_type(
                     self.inner.next_sst_id(),
-                )))?;
+                    self.inner.options.memtable_type,
+                )
+                .with_write_buffer_manager(self.inner.options.write_buffer_manager.clone()),
+            ))?;
         }
 
         while {
@@ -240,12 +534,16 @@ impl MiniLsm {
         let compaction_thread = inner.spawn_compaction_thread(rx)?;
         let (tx2, rx) = crossbeam_channel::unbounded();
         let flush_thread = inner.spawn_flush_thread(rx)?;
+        let (tx3, rx) = crossbeam_channel::unbounded();
+        let value_log_gc_thread = inner.spawn_value_log_gc_thread(rx)?;
         Ok(Arc::new(Self {
             inner,
             flush_notifier: tx2,
             flush_thread: Mutex::new(flush_thread),
             compaction_notifier: tx1,
             compaction_thread: Mutex::new(compaction_thread),
+            value_log_gc_notifier: tx3,
+            value_log_gc_thread: Mutex::new(value_log_gc_thread),
         }))
     }
 
@@ -261,6 +559,18 @@ impl MiniLsm {
         self.inner.write_batch(batch)
     }
 
+    pub fn write_batch_with_options<T: AsRef<[u8]>>(
+        &self,
+        batch: &[WriteBatchRecord<T>],
+        write_options: &WriteOptions,
+    ) -> Result<()> {
+        self.inner.write_batch_with_options(batch, write_options)
+    }
+
+    pub fn group_commit_stats(&self) -> GroupCommitStats {
+        self.inner.write_queue.stats()
+    }
+
     pub fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
         self.inner.put(key, value)
     }
@@ -269,6 +579,10 @@ impl MiniLsm {
         self.inner.delete(key)
     }
 
+    pub fn delete_range(&self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        self.inner.delete_range(lower, upper)
+    }
+
     pub fn sync(&self) -> Result<()> {
         self.inner.sync()
     }
@@ -281,9 +595,22 @@ impl MiniLsm {
         self.inner.scan(lower, upper)
     }
 
+    pub fn scan_rev(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<TxnIterator> {
+        self.inner.scan_rev(lower, upper)
+    }
+
+    pub fn scan_with_options(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        read_options: &ReadOptions,
+    ) -> Result<TxnIterator> {
+        self.inner.scan_with_options(lower, upper, read_options)
+    }
+
     /// Only call this in test cases due to race conditions
     pub fn force_flush(&self) -> Result<()> {
-        if !self.inner.state.read().memtable.is_empty() {
+        if !self.inner.memtables_empty() {
             self.inner
                 .force_freeze_memtable(&self.inner.state_lock.lock())?;
         }
@@ -296,6 +623,25 @@ impl MiniLsm {
     pub fn force_full_compaction(&self) -> Result<()> {
         self.inner.force_full_compaction()
     }
+
+    pub fn write_stall_state(&self) -> WriteStallState {
+        self.inner.write_stall_state()
+    }
+
+    /// The statistics of the block cache, including the reads of the other engines sharing it.
+    pub fn block_cache_stats(&self) -> BlockCacheStats {
+        self.inner.block_cache.stats()
+    }
+
+    pub fn gc_value_log(&self) -> Result<Vec<usize>> {
+        self.inner.gc_value_log()
+    }
+
+    /// The SSTs and WALs which were not referenced by the state when the storage was opened, and
+    /// what was done with them.
+    pub fn orphan_files(&self) -> &OrphanFileReport {
+        &self.inner.orphan_files
+    }
 }
 
 impl LsmStorageInner {
@@ -312,138 +658,381 @@ impl LsmStorageInner {
         self.manifest.as_ref().unwrap()
     }
 
+    /// Applies the records of the manifest to the column families, which hold at least the default
+    /// one. The SSTs of the column families are not opened.
+    pub(crate) fn replay_manifest(
+        column_families: &mut BTreeMap<usize, ColumnFamily>,
+        records: Vec<ManifestRecord>,
+    ) -> Result<ReplayedManifest> {
+        let mut replayed = ReplayedManifest {
+            memtables: BTreeSet::new(),
+            last_sst_id: 0,
+            value_log_garbage: Vec::new(),
+        };
+        let flush = |cf: &ColumnFamily, sst_id: usize| {
+            let mut guard = cf.state.write();
+            let state = Arc::make_mut(&mut guard);
+            if cf.compaction_controller.flush_to_l0() {
+                state.l0_sstables.insert(0, sst_id);
+            } else {
+                state.levels.insert(0, (sst_id, vec![sst_id]));
+            }
+        };
+        let apply_compaction = |cf: &ColumnFamily, task: &CompactionTask, output: &[usize]| {
+            let mut guard = cf.state.write();
+            let (new_state, _) = match task {
+                CompactionTask::ForceFullCompaction {
+                    l0_sstables,
+                    l1_sstables,
+                } => apply_force_full_compaction_result(&guard, l0_sstables, l1_sstables, output),
+                _ => cf
+                    .compaction_controller
+                    .apply_compaction_result(&guard, task, output),
+            };
+            *guard = Arc::new(new_state);
+        };
+        fn get_cf(
+            column_families: &BTreeMap<usize, ColumnFamily>,
+            id: usize,
+        ) -> Result<&ColumnFamily> {
+            column_families
+                .get(&id)
+                .with_context(|| format!("column family {} does not exist", id))
+        }
+        for record in records {
+            match record {
+                ManifestRecord::Flush(sst_id) => {
+                    ensure!(
+                        replayed.memtables.remove(&sst_id),
+                        "flushed memtable {} does not exist",
+                        sst_id
+                    );
+                    flush(get_cf(column_families, DEFAULT_COLUMN_FAMILY_ID)?, sst_id);
+                    replayed.last_sst_id = replayed.last_sst_id.max(sst_id);
+                }
+                ManifestRecord::FlushColumnFamilies(memtable_id, ssts) => {
+                    ensure!(
+                        replayed.memtables.remove(&memtable_id),
+                        "flushed memtable {} does not exist",
+                        memtable_id
+                    );
+                    replayed.last_sst_id = replayed.last_sst_id.max(memtable_id);
+                    for (column_family_id, sst_id) in ssts {
+                        flush(get_cf(column_families, column_family_id)?, sst_id);
+                        replayed.last_sst_id = replayed.last_sst_id.max(sst_id);
+                    }
+                }
+                ManifestRecord::NewMemtable(x) => {
+                    replayed.last_sst_id = replayed.last_sst_id.max(x);
+                    replayed.memtables.insert(x);
+                }
+                ManifestRecord::Compaction(task, output) => {
+                    apply_compaction(
+                        get_cf(column_families, DEFAULT_COLUMN_FAMILY_ID)?,
+                        &task,
+                        &output,
+                    );
+                    replayed.last_sst_id = replayed
+                        .last_sst_id
+                        .max(output.iter().max().copied().unwrap_or_default());
+                }
+                ManifestRecord::ColumnFamilyCompaction(column_family_id, task, output) => {
+                    apply_compaction(get_cf(column_families, column_family_id)?, &task, &output);
+                    replayed.last_sst_id = replayed
+                        .last_sst_id
+                        .max(output.iter().max().copied().unwrap_or_default());
+                }
+                ManifestRecord::CreateColumnFamily(column_family_id, name, cf_options) => {
+                    let state = LsmStorageState::create(&cf_options.compaction_options);
+                    column_families.insert(
+                        column_family_id,
+                        ColumnFamily::new(
+                            column_family_id,
+                            name,
+                            &cf_options,
+                            Arc::new(RwLock::new(Arc::new(state))),
+                        ),
+                    );
+                }
+                ManifestRecord::Snapshot(snapshot) => {
+                    for cf_snapshot in snapshot.column_families {
+                        let cf = column_families.entry(cf_snapshot.id).or_insert_with(|| {
+                            ColumnFamily::new(
+                                cf_snapshot.id,
+                                cf_snapshot.name,
+                                &cf_snapshot.options,
+                                Arc::new(RwLock::new(Arc::new(LsmStorageState::create(
+                                    &cf_snapshot.options.compaction_options,
+                                )))),
+                            )
+                        });
+                        let mut guard = cf.state.write();
+                        let state = Arc::make_mut(&mut guard);
+                        state.l0_sstables = cf_snapshot.l0_sstables;
+                        state.levels = cf_snapshot.levels;
+                    }
+                    replayed.memtables = snapshot.memtables.into_iter().collect();
+                    replayed.last_sst_id = replayed.last_sst_id.max(snapshot.last_sst_id);
+                    replayed.value_log_garbage = snapshot.value_log_garbage;
+                }
+                ManifestRecord::ValueLogGarbage(garbage) => {
+                    replayed.value_log_garbage.extend(garbage);
+                }
+            }
+        }
+        Ok(replayed)
+    }
+
     /// Start the storage engine by either loading an existing directory or creating a new one if the directory does
     /// not exist.
     pub(crate) fn open(path: impl AsRef<Path>, options: LsmStorageOptions) -> Result<Self> {
-        let mut state = LsmStorageState::create(&options);
         let path = path.as_ref();
         let mut next_sst_id = 1;
-        let block_cache = Arc::new(BlockCache::new(1 << 20)); // 4GB block cache,
-        let manifest;
-
-        let compaction_controller = match &options.compaction_options {
-            CompactionOptions::Leveled(options) => {
-                CompactionController::Leveled(LeveledCompactionController::new(options.clone()))
-            }
-            CompactionOptions::Tiered(options) => {
-                CompactionController::Tiered(TieredCompactionController::new(options.clone()))
-            }
-            CompactionOptions::Simple(options) => CompactionController::Simple(
-                SimpleLeveledCompactionController::new(options.clone()),
+        let block_cache = Arc::new(match &options.shared_block_cache {
+            Some(block_cache) => block_cache.share(),
+            None => BlockCache::with_options(options.block_cache.clone()),
+        });
+        // The memtables of the manifest which are not flushed yet.
+        let mut memtables = BTreeSet::new();
+
+        let mut column_families = BTreeMap::new();
+        column_families.insert(
+            DEFAULT_COLUMN_FAMILY_ID,
+            ColumnFamily::new(
+                DEFAULT_COLUMN_FAMILY_ID,
+                DEFAULT_COLUMN_FAMILY.to_string(),
+                &ColumnFamilyOptions {
+                    compaction_options: options.compaction_options.clone(),
+                },
+                Arc::new(RwLock::new(Arc::new(LsmStorageState::create(
+                    &options.compaction_options,
+                )))),
             ),
-            CompactionOptions::NoCompaction => CompactionController::NoCompaction,
-        };
+        );
 
         if !path.exists() {
             std::fs::create_dir_all(path).context("failed to create DB dir")?;
         }
-        let manifest_path = path.join("MANIFEST");
+        let has_manifest = Manifest::exists(path);
         let mut last_commit_ts = 0;
-        if !manifest_path.exists() {
-            if options.enable_wal {
-                state.memtable = Arc::new(MemTable::create_with_wal(
-                    state.memtable.id(),
-                    Self::path_of_wal_static(path, state.memtable.id()),
-                )?);
+        if options.merge_operator.is_some() && options.value_log.is_some() {
+            bail!("merge operator cannot be used with the value log");
+        }
+        let has_value_log = !ValueLog::list_segments(path)?.is_empty();
+        let value_log = match &options.value_log {
+            Some(_) if has_manifest && !has_value_log => {
+                bail!("value log cannot be enabled on an existing storage")
             }
-            manifest = Manifest::create(&manifest_path).context("failed to create manifest")?;
-            manifest.add_record_when_init(ManifestRecord::NewMemtable(state.memtable.id()))?;
-        } else {
-            let (m, records) = Manifest::recover(&manifest_path)?;
-            let mut memtables = BTreeSet::new();
-            for record in records {
-                match record {
-                    ManifestRecord::Flush(sst_id) => {
-                        let res = memtables.remove(&sst_id);
-                        assert!(res, "memtable not exist?");
-                        if compaction_controller.flush_to_l0() {
-                            state.l0_sstables.insert(0, sst_id);
-                        } else {
-                            state.levels.insert(0, (sst_id, vec![sst_id]));
-                        }
-                        next_sst_id = next_sst_id.max(sst_id);
-                    }
-                    ManifestRecord::NewMemtable(x) => {
-                        next_sst_id = next_sst_id.max(x);
-                        memtables.insert(x);
-                    }
-                    ManifestRecord::Compaction(task, output) => {
-                        let (new_state, _) =
-                            compaction_controller.apply_compaction_result(&state, &task, &output);
-                        // TODO: apply remove again
-                        state = new_state;
-                        next_sst_id =
-                            next_sst_id.max(output.iter().max().copied().unwrap_or_default());
-                    }
+            Some(value_log_options) => {
+                Some(Arc::new(ValueLog::open(path, value_log_options.clone())?))
+            }
+            None if has_value_log => bail!("value log must be enabled for this storage"),
+            None => None,
+        };
+        let mut recovered_manifest = None;
+        if has_manifest {
+            let (m, records) = Manifest::recover(path)?;
+            let replayed = Self::replay_manifest(&mut column_families, records)?;
+            memtables = replayed.memtables;
+            next_sst_id = next_sst_id.max(replayed.last_sst_id);
+            if !replayed.value_log_garbage.is_empty() {
+                let value_log = value_log
+                    .as_ref()
+                    .context("value log garbage recorded without value log")?;
+                for (segment_id, bytes) in replayed.value_log_garbage {
+                    value_log.add_garbage(segment_id, bytes);
                 }
             }
 
             let mut sst_cnt = 0;
             // recover SSTs
-            for table_id in state
-                .l0_sstables
-                .iter()
-                .chain(state.levels.iter().flat_map(|(_, files)| files))
-            {
-                let table_id = *table_id;
-                let sst = SsTable::open(
-                    table_id,
-                    Some(block_cache.clone()),
-                    FileObject::open(&Self::path_of_sst_static(path, table_id))
-                        .context("failed to open SST")?,
-                )?;
-                last_commit_ts = last_commit_ts.max(sst.max_ts());
-                state.sstables.insert(table_id, Arc::new(sst));
-                sst_cnt += 1;
+            for cf in column_families.values() {
+                let mut guard = cf.state.write();
+                let state = Arc::make_mut(&mut guard);
+                for table_id in state
+                    .l0_sstables
+                    .iter()
+                    .chain(state.levels.iter().flat_map(|(_, files)| files))
+                {
+                    let table_id = *table_id;
+                    let sst = SsTable::open(
+                        table_id,
+                        Some(block_cache.clone()),
+                        FileObject::open(&Self::path_of_sst_static(path, table_id))
+                            .context("failed to open SST")?,
+                    )?;
+                    last_commit_ts = last_commit_ts.max(sst.max_ts());
+                    state.sstables.insert(table_id, Arc::new(sst));
+                    sst_cnt += 1;
+                }
+
+                if let CompactionController::Leveled(_) = &cf.compaction_controller {
+                    for (_, files) in &mut state.levels {
+                        files.sort_by(|x, y| {
+                            state.sstables[x]
+                                .first_key()
+                                .cmp(state.sstables[y].first_key())
+                        });
+                    }
+                }
             }
             println!("{} SSTs opened", sst_cnt);
 
             next_sst_id += 1;
 
             // recover memtables
+            let column_family_ids = column_families.keys().copied().collect::<Vec<_>>();
             if options.enable_wal {
                 let mut wal_cnt = 0;
+                let mut dropped = false;
                 for id in memtables.iter() {
-                    let memtable =
-                        MemTable::recover_from_wal(*id, Self::path_of_wal_static(path, *id))?;
-                    let max_ts = memtable
-                        .map
-                        .iter()
-                        .map(|x| x.key().ts())
+                    let wal_path = Self::path_of_wal_static(path, *id);
+                    if dropped && options.wal.recovery_mode == WalRecoveryMode::PointInTimeRecovery
+                    {
+                        // The WALs after a corruption are dropped by a point-in-time recovery.
+                        println!("dropping WAL {} after a corruption", id);
+                        let file = File::options().write(true).open(&wal_path)?;
+                        file.set_len(0)?;
+                        file.sync_all()?;
+                        continue;
+                    }
+                    let mut recovered;
+                    (recovered, dropped) = MemTable::recover_column_families_from_wal(
+                        *id,
+                        options.memtable_type,
+                        &wal_path,
+                        &column_family_ids,
+                        options.wal.recovery_mode,
+                    )?;
+                    if dropped {
+                        println!("dropped the records of WAL {} after a corruption", id);
+                    }
+                    let max_ts = recovered
+                        .values()
+                        .map(|memtable| memtable.max_ts())
                         .max()
                         .unwrap_or_default();
                     last_commit_ts = last_commit_ts.max(max_ts);
-                    if !memtable.is_empty() {
-                        state.imm_memtables.insert(0, Arc::new(memtable));
+                    if recovered.values().any(|memtable| !memtable.is_empty()) {
+                        for cf in column_families.values() {
+                            let memtable = recovered
+                                .remove(&cf.id)
+                                .unwrap()
+                                .with_write_buffer_manager(options.write_buffer_manager.clone());
+                            memtable.mark_immutable();
+                            let mut guard = cf.state.write();
+                            Arc::make_mut(&mut guard)
+                                .imm_memtables
+                                .insert(0, Arc::new(memtable));
+                        }
                         wal_cnt += 1;
                     }
                 }
                 println!("{} WALs recovered", wal_cnt);
-                state.memtable = Arc::new(MemTable::create_with_wal(
+            }
+            recovered_manifest = Some(m);
+        }
+
+        // Orphan files are collected before the WAL of the new memtable is created, as a WAL left
+        // by a crash may have its id. The ids of the files left by a dry run are not reused.
+        let orphan_files = Self::collect_orphan_files(
+            path,
+            &column_families,
+            &memtables,
+            options.orphan_file_action,
+        )?;
+        if let Some(max_file_id) = orphan_files.max_file_id() {
+            next_sst_id = next_sst_id.max(max_file_id + 1);
+        }
+
+        let manifest = if let Some(m) = recovered_manifest {
+            let default_memtable = if options.enable_wal {
+                MemTable::create_with_wal(
                     next_sst_id,
-                    Self::path_of_wal_static(path, next_sst_id),
-                )?);
+                    options.memtable_type,
+                    Wal::create(
+                        Self::path_of_wal_static(path, next_sst_id),
+                        next_sst_id,
+                        &options.wal,
+                    )?,
+                )
             } else {
-                state.memtable = Arc::new(MemTable::create(next_sst_id));
+                MemTable::create_with_type(next_sst_id, options.memtable_type)
+            };
+            let default_memtable = Arc::new(
+                default_memtable.with_write_buffer_manager(options.write_buffer_manager.clone()),
+            );
+            for cf in column_families.values() {
+                let memtable = if cf.is_default() {
+                    default_memtable.clone()
+                } else {
+                    Arc::new(
+                        MemTable::create_for_column_family(
+                            next_sst_id,
+                            cf.id,
+                            options.memtable_type,
+                            default_memtable.wal().cloned(),
+                        )
+                        .with_write_buffer_manager(options.write_buffer_manager.clone()),
+                    )
+                };
+                Arc::make_mut(&mut cf.state.write()).memtable = memtable;
             }
-            m.add_record_when_init(ManifestRecord::NewMemtable(state.memtable.id()))?;
+            m.add_record_when_init(ManifestRecord::NewMemtable(next_sst_id))?;
             next_sst_id += 1;
-            manifest = m;
+            m
+        } else {
+            let default_cf = &column_families[&DEFAULT_COLUMN_FAMILY_ID];
+            let mut guard = default_cf.state.write();
+            let state = Arc::make_mut(&mut guard);
+            // The first memtable has id 0, unless a dry run left files with that id behind.
+            let id = if orphan_files.is_empty() {
+                state.memtable.id()
+            } else {
+                next_sst_id += 1;
+                next_sst_id - 1
+            };
+            let memtable = if options.enable_wal {
+                MemTable::create_with_wal(
+                    id,
+                    options.memtable_type,
+                    Wal::create(Self::path_of_wal_static(path, id), id, &options.wal)?,
+                )
+            } else {
+                MemTable::create_with_type(id, options.memtable_type)
+            };
+            state.memtable =
+                Arc::new(memtable.with_write_buffer_manager(options.write_buffer_manager.clone()));
+            let manifest = Manifest::create(path).context("failed to create manifest")?;
+            manifest.add_record_when_init(ManifestRecord::NewMemtable(id))?;
+            manifest
         };
 
         let storage = Self {
-            state: Arc::new(RwLock::new(Arc::new(state))),
+            state: column_families[&DEFAULT_COLUMN_FAMILY_ID].state.clone(),
             state_lock: Mutex::new(()),
             path: path.to_path_buf(),
             block_cache,
             next_sst_id: AtomicUsize::new(next_sst_id),
-            compaction_controller,
+            subcompaction_threads: AtomicUsize::new(0),
+            column_families: RwLock::new(
+                column_families
+                    .into_iter()
+                    .map(|(id, cf)| (id, Arc::new(cf)))
+                    .collect(),
+            ),
+            write_stall: WriteStallController::default(),
+            write_queue: WriteQueue::default(),
+            recycled_wals: Mutex::new(Vec::new()),
+            value_log,
             manifest: Some(manifest),
             options: options.into(),
             mvcc: Some(LsmMvccInner::new(last_commit_ts)),
             compaction_filters: Arc::new(Mutex::new(Vec::new())),
+            orphan_files,
         };
         storage.sync_dir()?;
+        storage.maybe_roll_manifest(&storage.state_lock.lock())?;
 
         Ok(storage)
     }
@@ -454,6 +1043,9 @@ impl LsmStorageInner {
     }
 
     pub fn sync(&self) -> Result<()> {
+        if let Some(value_log) = &self.value_log {
+            value_log.sync()?;
+        }
         self.state.read().memtable.sync_wal()
     }
 
@@ -464,8 +1056,30 @@ impl LsmStorageInner {
     }
 
     pub(crate) fn get_with_ts(&self, key: &[u8], read_ts: u64) -> Result<Option<Bytes>> {
+        self.get_with_ts_from(&self.state, key, read_ts, self.value_log.clone())
+    }
+
+    /// Get the value of a key in a column family as stored in the LSM tree, without resolving
+    /// value log pointers.
+    pub(crate) fn get_raw_with_ts(
+        &self,
+        cf: &ColumnFamily,
+        key: &[u8],
+        read_ts: u64,
+    ) -> Result<Option<Bytes>> {
+        self.get_with_ts_from(&cf.state, key, read_ts, None)
+    }
+
+    /// Get a key from the state of a column family.
+    pub(crate) fn get_with_ts_from(
+        &self,
+        state: &RwLock<Arc<LsmStorageState>>,
+        key: &[u8],
+        read_ts: u64,
+        value_log: Option<Arc<ValueLog>>,
+    ) -> Result<Option<Bytes>> {
         let snapshot = {
-            let guard = self.state.read();
+            let guard = state.read();
             Arc::clone(&guard)
         }; // drop global lock here
 
@@ -484,26 +1098,26 @@ impl LsmStorageInner {
 
         let mut l0_iters = Vec::with_capacity(snapshot.l0_sstables.len());
 
-        let keep_table = |key: &[u8], table: &SsTable| {
+        // The range tombstones are taken from every SST containing the key, including those
+        // skipped by the bloom filter.
+        let mut tombstone_sets = Vec::new();
+        let mut keep_table = |key: &[u8], table: &SsTable| {
             if key_within(
                 key,
                 table.first_key().as_key_slice(),
                 table.last_key().as_key_slice(),
             ) {
-                if let Some(bloom) = &table.bloom {
-                    if bloom.may_contain(farmhash::fingerprint32(key)) {
-                        return true;
-                    }
-                } else {
-                    return true;
+                if !table.range_tombstone_set().is_empty() {
+                    tombstone_sets.push(table.range_tombstone_set().clone());
                 }
+                return table.may_contain_key(key);
             }
-            false
+            Ok(false)
         };
 
         for table in snapshot.l0_sstables.iter() {
             let table = snapshot.sstables[table].clone();
-            if keep_table(key, &table) {
+            if keep_table(key, &table)? {
                 l0_iters.push(Box::new(SsTableIterator::create_and_seek_to_key(
                     table,
                     KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
@@ -516,7 +1130,7 @@ impl LsmStorageInner {
             let mut level_ssts = Vec::with_capacity(snapshot.levels[0].1.len());
             for table in level_sst_ids {
                 let table = snapshot.sstables[table].clone();
-                if keep_table(key, &table) {
+                if keep_table(key, &table)? {
                     level_ssts.push(table);
                 }
             }
@@ -532,8 +1146,17 @@ impl LsmStorageInner {
                 TwoMergeIterator::create(memtable_iter, l0_iter)?,
                 MergeIterator::create(level_iters),
             )?,
-            Bound::Unbounded,
+            (Bound::Unbounded, Bound::Unbounded),
             read_ts,
+            snapshot.range_tombstones(
+                tombstone_sets,
+                Bound::Included(key),
+                Bound::Included(key),
+                read_ts,
+            ),
+            self.options.merge_operator.clone(),
+            value_log,
+            Direction::Forward,
         )?;
 
         if iter.is_valid() && iter.key() == key && !iter.value().is_empty() {
@@ -543,46 +1166,177 @@ impl LsmStorageInner {
     }
 
     pub fn write_batch_inner<T: AsRef<[u8]>>(&self, batch: &[WriteBatchRecord<T>]) -> Result<u64> {
-        let _lck = self.mvcc().write_lock.lock();
-        let ts = self.mvcc().latest_commit_ts() + 1;
-        for record in batch {
-            match record {
-                WriteBatchRecord::Del(key) => {
-                    let key = key.as_ref();
-                    assert!(!key.is_empty(), "key cannot be empty");
-                    let size;
-                    {
-                        let guard = self.state.read();
-                        guard.memtable.put(KeySlice::from_slice(key, ts), b"")?;
-                        size = guard.memtable.approximate_size();
+        self.write_batch_inner_with_options(batch, &WriteOptions::default())
+    }
+
+    pub(crate) fn write_batch_inner_with_options<T: AsRef<[u8]>>(
+        &self,
+        batch: &[WriteBatchRecord<T>],
+        write_options: &WriteOptions,
+    ) -> Result<u64> {
+        self.maybe_stall_write(batch.iter().map(WriteBatchRecord::size).sum());
+        let cf = self.default_column_family();
+        self.group_commit(
+            batch.iter().map(|record| (cf.clone(), record)).collect(),
+            write_options.sync,
+        )
+    }
+
+    /// Writes records to their column families with a new commit timestamp. The caller must hold
+    /// the MVCC write lock.
+    pub(crate) fn write_column_families_with_lock<'a, T: AsRef<[u8]> + 'a>(
+        &self,
+        batch: impl IntoIterator<Item = (&'a ColumnFamily, &'a WriteBatchRecord<T>)>,
+    ) -> Result<u64> {
+        self.write_batches_with_lock(&[batch.into_iter().collect()], false)
+    }
+
+    /// Writes batches of records to their column families with consecutive commit timestamps, in
+    /// order, returning the commit timestamp of the first batch. All records are appended to the
+    /// WAL at once, which is synced once if `sync` is set. The caller must hold the MVCC write
+    /// lock.
+    pub(crate) fn write_batches_with_lock<T: AsRef<[u8]>>(
+        &self,
+        batches: &[Vec<(&ColumnFamily, &WriteBatchRecord<T>)>],
+        sync: bool,
+    ) -> Result<u64> {
+        let first_ts = self.mvcc().latest_commit_ts() + 1;
+        // Write the values to the value log before logging them.
+        let mut values = Vec::new();
+        if let Some(value_log) = &self.value_log {
+            for (ts, batch) in (first_ts..).zip(batches) {
+                for (_, record) in batch {
+                    if let WriteBatchRecord::Put(key, value) = record {
+                        values.push(value_log.encode_value(key.as_ref(), ts, value.as_ref())?);
                     }
-                    self.try_freeze(size)?;
                 }
-                WriteBatchRecord::Put(key, value) => {
-                    let key = key.as_ref();
-                    let value = value.as_ref();
-                    assert!(!key.is_empty(), "key cannot be empty");
-                    assert!(!value.is_empty(), "value cannot be empty");
-                    let size;
-                    {
-                        let guard = self.state.read();
-                        guard.memtable.put(KeySlice::from_slice(key, ts), value)?;
-                        size = guard.memtable.approximate_size();
+            }
+        }
+        if sync {
+            if let Some(value_log) = &self.value_log {
+                value_log.sync()?;
+            }
+        }
+
+        // Hold the memtables of the column families, in the order of their ids, so that they are
+        // not frozen between the WAL append and the inserts.
+        let column_families = batches
+            .iter()
+            .flatten()
+            .map(|(cf, _)| (cf.id, *cf))
+            .collect::<BTreeMap<_, _>>();
+        let guards = loop {
+            let guards = column_families
+                .iter()
+                .map(|(id, cf)| (*id, cf.state.read()))
+                .collect::<BTreeMap<_, _>>();
+            // A freeze swaps the memtables of the column families one at a time, without the
+            // MVCC write lock. The memtables are read again until they all have the same id, so
+            // that they share the WAL the batches are logged to.
+            let mut ids = guards.values().map(|guard| guard.memtable.id());
+            let first_id = ids.next();
+            if ids.all(|id| Some(id) == first_id) {
+                break guards;
+            }
+            drop(guards);
+            std::thread::yield_now();
+        };
+        // The WAL is shared by the memtables of all column families.
+        if let Some(wal) = guards.values().next().and_then(|x| x.memtable.wal()) {
+            // Each batch is logged as a record, so that it is recovered entirely or not at all.
+            let mut wal_batches = Vec::with_capacity(batches.len());
+            let mut values = values.iter();
+            for (ts, batch) in (first_ts..).zip(batches) {
+                let mut wal_batch = WalBatch::new(ts);
+                for (cf, record) in batch {
+                    match record {
+                        WriteBatchRecord::Del(key) => wal_batch.put(cf.id, key.as_ref(), b""),
+                        WriteBatchRecord::Put(key, value) => {
+                            let value = match &self.value_log {
+                                Some(_) => &values.next().unwrap()[..],
+                                None => value.as_ref(),
+                            };
+                            wal_batch.put(cf.id, key.as_ref(), value)
+                        }
+                        WriteBatchRecord::Merge(key, operand) => {
+                            wal_batch.put_merge(cf.id, key.as_ref(), operand.as_ref())
+                        }
+                        WriteBatchRecord::DelRange(lower, upper) => {
+                            wal_batch.put_range_tombstone(cf.id, lower.as_ref(), upper.as_ref())
+                        }
+                    }
+                }
+                if !wal_batch.is_empty() {
+                    wal_batches.push(wal_batch);
+                }
+            }
+            wal.append(&wal_batches)?;
+            if sync {
+                wal.sync()?;
+            }
+        }
+
+        let mut values = values.iter();
+        for (ts, batch) in (first_ts..).zip(batches) {
+            for (cf, record) in batch {
+                let memtable = &guards[&cf.id].memtable;
+                match record {
+                    WriteBatchRecord::Del(key) => memtable.put_without_wal(
+                        KeySlice::from_slice(key.as_ref(), ts),
+                        b"",
+                        RecordKind::Value,
+                    ),
+                    WriteBatchRecord::Put(key, value) => {
+                        let value = match &self.value_log {
+                            Some(_) => &values.next().unwrap()[..],
+                            None => value.as_ref(),
+                        };
+                        memtable.put_without_wal(
+                            KeySlice::from_slice(key.as_ref(), ts),
+                            value,
+                            RecordKind::Value,
+                        )
                     }
-                    self.try_freeze(size)?;
+                    WriteBatchRecord::Merge(key, operand) => memtable.put_without_wal(
+                        KeySlice::from_slice(key.as_ref(), ts),
+                        operand.as_ref(),
+                        RecordKind::MergeOperand,
+                    ),
+                    WriteBatchRecord::DelRange(lower, upper) => memtable.delete_range_without_wal(
+                        KeySlice::from_slice(lower.as_ref(), ts),
+                        upper.as_ref(),
+                    ),
                 }
             }
         }
-        self.mvcc().update_commit_ts(ts);
-        Ok(ts)
+        let sizes = guards
+            .iter()
+            .map(|(id, guard)| (*id, guard.memtable.approximate_size()))
+            .collect::<Vec<_>>();
+        drop(guards);
+        let last_ts = first_ts + batches.len() as u64 - 1;
+        self.mvcc().update_commit_ts(last_ts);
+
+        for (id, size) in sizes {
+            self.try_freeze(column_families[&id], size)?;
+        }
+        Ok(first_ts)
     }
 
     pub fn write_batch<T: AsRef<[u8]>>(
         self: &Arc<Self>,
         batch: &[WriteBatchRecord<T>],
+    ) -> Result<()> {
+        self.write_batch_with_options(batch, &WriteOptions::default())
+    }
+
+    pub fn write_batch_with_options<T: AsRef<[u8]>>(
+        self: &Arc<Self>,
+        batch: &[WriteBatchRecord<T>],
+        write_options: &WriteOptions,
     ) -> Result<()> {
         if !self.options.serializable {
-            self.write_batch_inner(batch)?;
+            self.write_batch_inner_with_options(batch, write_options)?;
         } else {
             let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
             for record in batch {
@@ -593,9 +1347,19 @@ impl LsmStorageInner {
                     WriteBatchRecord::Put(key, value) => {
                         txn.put(key.as_ref(), value.as_ref());
                     }
+                    WriteBatchRecord::DelRange(_, _) => {
+                        bail!("range deletions are not supported in serializable write batches");
+                    }
+                    WriteBatchRecord::Merge(_, _) => {
+                        bail!("merges are not supported in serializable write batches");
+                    }
                 }
             }
             txn.commit()?;
+            // Transactions are committed one at a time, and synced separately.
+            if write_options.sync {
+                self.sync()?;
+            }
         }
         Ok(())
     }
@@ -624,12 +1388,31 @@ impl LsmStorageInner {
         Ok(())
     }
 
-    fn try_freeze(&self, estimated_size: usize) -> Result<()> {
-        if estimated_size >= self.options.target_sst_size {
+    /// Remove the keys in `[lower, upper)` from the storage by writing a range tombstone. The range
+    /// deletion is not validated against concurrent serializable transactions.
+    pub fn delete_range(&self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        if lower >= upper {
+            bail!("range deletion must not be empty");
+        }
+        self.write_batch_inner(&[WriteBatchRecord::DelRange(lower, upper)])?;
+        Ok(())
+    }
+
+    fn try_freeze(&self, cf: &ColumnFamily, estimated_size: usize) -> Result<()> {
+        // The write buffer manager frees memory by freezing the memtables of all column families.
+        let write_buffer_full = || {
+            self.options
+                .write_buffer_manager
+                .as_ref()
+                .is_some_and(|x| x.should_freeze())
+        };
+        if estimated_size >= self.options.target_sst_size || write_buffer_full() {
             let state_lock = self.state_lock.lock();
-            let guard = self.state.read();
+            let guard = cf.state.read();
             // the memtable could have already been frozen, check again to ensure we really need to freeze
-            if guard.memtable.approximate_size() >= self.options.target_sst_size {
+            if guard.memtable.approximate_size() >= self.options.target_sst_size
+                || (write_buffer_full() && !guard.memtable.is_empty())
+            {
                 drop(guard);
                 self.force_freeze_memtable(&state_lock)?;
             }
@@ -637,6 +1420,23 @@ impl LsmStorageInner {
         Ok(())
     }
 
+    /// Creates a builder for a new SST with the configured block size, compression, rate limiter,
+    /// prefix extractor and index partitioning.
+    pub(crate) fn new_sst_builder(&self, priority: IoPriority) -> SsTableBuilder {
+        let mut builder =
+            SsTableBuilder::new_with_compression(self.options.block_size, self.options.compression);
+        if let Some(rate_limiter) = &self.options.rate_limiter {
+            builder.set_rate_limiter(rate_limiter.clone(), priority);
+        }
+        if let Some(prefix_extractor) = &self.options.prefix_extractor {
+            builder.set_prefix_extractor(prefix_extractor.clone());
+        }
+        if let Some(partition_size) = self.options.index_partition_size {
+            builder.set_index_partition_size(partition_size);
+        }
+        builder
+    }
+
     pub(crate) fn path_of_sst_static(path: impl AsRef<Path>, id: usize) -> PathBuf {
         path.as_ref().join(format!("{:05}.sst", id))
     }
@@ -653,23 +1453,65 @@ impl LsmStorageInner {
         Self::path_of_wal_static(&self.path, id)
     }
 
+    /// Creates the WAL of a new memtable, reusing the file of a flushed WAL if there is one.
+    fn create_wal(&self, id: usize) -> Result<Wal> {
+        let path = self.path_of_wal(id);
+        match self.recycled_wals.lock().pop() {
+            Some(recycled) => Wal::reuse(recycled, path, id, &self.options.wal),
+            None => Wal::create(path, id, &self.options.wal),
+        }
+    }
+
     pub(super) fn sync_dir(&self) -> Result<()> {
         File::open(&self.path)?.sync_all()?;
         Ok(())
     }
 
+    /// Returns true if the memtables of all column families are empty.
+    pub(crate) fn memtables_empty(&self) -> bool {
+        self.column_families()
+            .iter()
+            .all(|cf| cf.state.read().memtable.is_empty())
+    }
+
+    /// Freezes the memtables of all column families. `memtable` becomes the memtable of the
+    /// default column family, and the other column families get new memtables with the same id,
+    /// sharing its WAL.
     fn freeze_memtable_with_memtable(&self, memtable: Arc<MemTable>) -> Result<()> {
-        let mut guard = self.state.write();
-        // Swap the current memtable with a new one.
-        let mut snapshot = guard.as_ref().clone();
-        let old_memtable = std::mem::replace(&mut snapshot.memtable, memtable);
-        // Add the memtable to the immutable memtables.
-        snapshot.imm_memtables.insert(0, old_memtable.clone());
-        // Update the snapshot.
-        *guard = Arc::new(snapshot);
-
-        drop(guard);
-        old_memtable.sync_wal()?;
+        let mut old_memtable = None;
+        for cf in self.column_families() {
+            let memtable = if cf.is_default() {
+                memtable.clone()
+            } else {
+                Arc::new(
+                    MemTable::create_for_column_family(
+                        memtable.id(),
+                        cf.id,
+                        memtable.memtable_type(),
+                        memtable.wal().cloned(),
+                    )
+                    .with_write_buffer_manager(memtable.write_buffer_manager()),
+                )
+            };
+            let mut guard = cf.state.write();
+            // Swap the current memtable with a new one.
+            let mut snapshot = guard.as_ref().clone();
+            let old = std::mem::replace(&mut snapshot.memtable, memtable);
+            // Add the memtable to the immutable memtables.
+            old.mark_immutable();
+            snapshot.imm_memtables.insert(0, old.clone());
+            // Update the snapshot.
+            *guard = Arc::new(snapshot);
+            if cf.is_default() {
+                old_memtable = Some(old);
+            }
+        }
+
+        if let Some(value_log) = &self.value_log {
+            value_log.sync()?;
+        }
+        // The WAL is shared by the memtables of all column families.
+        old_memtable.unwrap().sync_wal()?;
 
         Ok(())
     }
@@ -678,13 +1520,16 @@ impl LsmStorageInner {
     pub fn force_freeze_memtable(&self, state_lock_observer: &MutexGuard<'_, ()>) -> Result<()> {
         let memtable_id = self.next_sst_id();
         let memtable = if self.options.enable_wal {
-            Arc::new(MemTable::create_with_wal(
+            MemTable::create_with_wal(
                 memtable_id,
-                self.path_of_wal(memtable_id),
-            )?)
+                self.options.memtable_type,
+                self.create_wal(memtable_id)?,
+            )
         } else {
-            Arc::new(MemTable::create(memtable_id))
+            MemTable::create_with_type(memtable_id, self.options.memtable_type)
         };
+        let memtable =
+            Arc::new(memtable.with_write_buffer_manager(self.options.write_buffer_manager.clone()));
 
         self.freeze_memtable_with_memtable(memtable)?;
 
@@ -692,63 +1537,163 @@ impl LsmStorageInner {
             state_lock_observer,
             ManifestRecord::NewMemtable(memtable_id),
         )?;
+        self.maybe_roll_manifest(state_lock_observer)?;
         self.sync_dir()?;
 
         Ok(())
     }
 
-    /// Force flush the earliest-created immutable memtable to disk
+    /// A snapshot of the state recorded in the manifest.
+    fn manifest_snapshot(&self, _state_lock_observer: &MutexGuard<'_, ()>) -> ManifestSnapshot {
+        let column_families = self
+            .column_families()
+            .iter()
+            .map(|cf| {
+                let state = cf.state.read();
+                ColumnFamilySnapshot {
+                    id: cf.id,
+                    name: cf.name.clone(),
+                    options: cf.options.clone(),
+                    l0_sstables: state.l0_sstables.clone(),
+                    levels: state.levels.clone(),
+                }
+            })
+            .collect();
+        let memtables = {
+            let state = self.state.read();
+            state
+                .imm_memtables
+                .iter()
+                .rev()
+                .chain(std::iter::once(&state.memtable))
+                .map(|memtable| memtable.id())
+                .collect()
+        };
+        ManifestSnapshot {
+            column_families,
+            memtables,
+            last_sst_id: self.next_sst_id.load(std::sync::atomic::Ordering::SeqCst) - 1,
+            value_log_garbage: self
+                .value_log
+                .as_ref()
+                .map(|value_log| value_log.garbage())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Rolls the manifest over into a new file once it exceeds `max_manifest_size`.
+    pub(crate) fn maybe_roll_manifest(
+        &self,
+        state_lock_observer: &MutexGuard<'_, ()>,
+    ) -> Result<()> {
+        if self.manifest().size() <= self.options.max_manifest_size {
+            return Ok(());
+        }
+        let snapshot = self.manifest_snapshot(state_lock_observer);
+        self.manifest().roll_over(state_lock_observer, snapshot)?;
+        println!(
+            "manifest rolled over, {} bytes after the snapshot",
+            self.manifest().size()
+        );
+        Ok(())
+    }
+
+    /// Force flush the earliest-created immutable memtables of all column families to disk
     pub fn force_flush_next_imm_memtable(&self) -> Result<()> {
         let state_lock = self.state_lock.lock();
 
-        let flush_memtable;
-
-        {
+        // The caller checked for immutable memtables without the state lock, so another flush may
+        // have taken the last one in the meantime.
+        let memtable_id = {
             let guard = self.state.read();
-            flush_memtable = guard
-                .imm_memtables
-                .last()
-                .expect("no imm memtables!")
-                .clone();
-        }
-
-        let mut builder = SsTableBuilder::new(self.options.block_size);
-        flush_memtable.flush(&mut builder)?;
-        let sst_id = flush_memtable.id();
-        let sst = Arc::new(builder.build(
-            sst_id,
-            Some(self.block_cache.clone()),
-            self.path_of_sst(sst_id),
-        )?);
-
-        // Add the flushed L0 table to the list.
-        {
-            let mut guard = self.state.write();
+            let Some(memtable) = guard.imm_memtables.last() else {
+                return Ok(());
+            };
+            memtable.id()
+        };
+
+        // The value log records referenced by the SST must be durable before the SST.
+        if let Some(value_log) = &self.value_log {
+            value_log.sync()?;
+        }
+        let column_families = self.column_families();
+        let mut flushed = Vec::with_capacity(column_families.len());
+        for cf in &column_families {
+            let flush_memtable = {
+                let guard = cf.state.read();
+                match guard.imm_memtables.last() {
+                    Some(memtable) if memtable.id() == memtable_id => memtable.clone(),
+                    // The column family was created after this memtable.
+                    _ => continue,
+                }
+            };
+            if flush_memtable.is_empty() {
+                flushed.push((cf, None));
+                continue;
+            }
+            let mut builder = self.new_sst_builder(IoPriority::High);
+            flush_memtable.flush(&mut builder)?;
+            let sst_id = if cf.is_default() {
+                memtable_id
+            } else {
+                self.next_sst_id()
+            };
+            let sst = Arc::new(builder.build(
+                sst_id,
+                Some(self.block_cache.clone()),
+                self.path_of_sst(sst_id),
+            )?);
+            flushed.push((cf, Some(sst)));
+        }
+
+        // Add the flushed L0 tables to the lists. The default column family is updated last, as
+        // its immutable memtables tell which memtables are flushed in all column families.
+        for (cf, sst) in flushed.iter().rev() {
+            let mut guard = cf.state.write();
             let mut snapshot = guard.as_ref().clone();
             // Remove the memtable from the immutable memtables.
             let mem = snapshot.imm_memtables.pop().unwrap();
-            assert_eq!(mem.id(), sst_id);
-            // Add L0 table
-            if self.compaction_controller.flush_to_l0() {
-                // In leveled compaction or no compaction, simply flush to L0
-                snapshot.l0_sstables.insert(0, sst_id);
-            } else {
-                // In tiered compaction, create a new tier
-                snapshot.levels.insert(0, (sst_id, vec![sst_id]));
+            assert_eq!(mem.id(), memtable_id);
+            if let Some(sst) = sst {
+                let sst_id = sst.sst_id();
+                // Add L0 table
+                if cf.compaction_controller.flush_to_l0() {
+                    // In leveled compaction or no compaction, simply flush to L0
+                    snapshot.l0_sstables.insert(0, sst_id);
+                } else {
+                    // In tiered compaction, create a new tier
+                    snapshot.levels.insert(0, (sst_id, vec![sst_id]));
+                }
+                println!("flushed {}.sst with size={}", sst_id, sst.table_size());
+                snapshot.sstables.insert(sst_id, sst.clone());
             }
-            println!("flushed {}.sst with size={}", sst_id, sst.table_size());
-            snapshot.sstables.insert(sst_id, sst);
             // Update the snapshot.
             *guard = Arc::new(snapshot);
         }
+        self.notify_write_stall();
+
+        let record = match flushed.as_slice() {
+            [(cf, Some(_))] if cf.is_default() => ManifestRecord::Flush(memtable_id),
+            _ => ManifestRecord::FlushColumnFamilies(
+                memtable_id,
+                flushed
+                    .iter()
+                    .filter_map(|(cf, sst)| sst.as_ref().map(|sst| (cf.id, sst.sst_id())))
+                    .collect(),
+            ),
+        };
+        self.manifest().add_record(&state_lock, record)?;
+        self.maybe_roll_manifest(&state_lock)?;
 
         if self.options.enable_wal {
-            std::fs::remove_file(self.path_of_wal(sst_id))?;
+            let mut recycled_wals = self.recycled_wals.lock();
+            if recycled_wals.len() < self.options.wal.recycle_file_num {
+                recycled_wals.push(self.path_of_wal(memtable_id));
+            } else {
+                std::fs::remove_file(self.path_of_wal(memtable_id))?;
+            }
         }
 
-        self.manifest()
-            .add_record(&state_lock, ManifestRecord::Flush(sst_id))?;
-
         self.sync_dir()?;
 
         Ok(())
@@ -768,104 +1713,131 @@ impl LsmStorageInner {
         txn.scan(lower, upper)
     }
 
+    /// Create an iterator over a range of keys that starts from the last key, and moves backward
+    /// with `prev`.
+    pub fn scan_rev(
+        self: &Arc<Self>,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<TxnIterator> {
+        let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
+        txn.scan_rev(lower, upper)
+    }
+
+    /// Create an iterator over a range of keys with the read options.
+    pub fn scan_with_options(
+        self: &Arc<Self>,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        read_options: &ReadOptions,
+    ) -> Result<TxnIterator> {
+        let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
+        txn.scan_with_options(lower, upper, read_options)
+    }
+
     pub(crate) fn scan_with_ts(
         &self,
         lower: Bound<&[u8]>,
         upper: Bound<&[u8]>,
         read_ts: u64,
+        direction: Direction,
+        options: ScanOptions,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        self.scan_with_ts_from(&self.state, lower, upper, read_ts, direction, options)
+    }
+
+    /// Create an iterator over a range of keys in the state of a column family. When moving
+    /// backward, the iterator starts from the last key. With a prefix in the options, the SSTs
+    /// without any key of it are skipped.
+    pub(crate) fn scan_with_ts_from(
+        &self,
+        state: &RwLock<Arc<LsmStorageState>>,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        read_ts: u64,
+        direction: Direction,
+        options: ScanOptions,
     ) -> Result<FusedIterator<LsmIterator>> {
         let snapshot = {
-            let guard = self.state.read();
+            let guard = state.read();
             Arc::clone(&guard)
         }; // drop global lock here
 
         let mut memtable_iters = Vec::with_capacity(snapshot.imm_memtables.len() + 1);
-        memtable_iters.push(Box::new(snapshot.memtable.scan(
-            map_key_bound_plus_ts(lower, key::TS_RANGE_BEGIN),
-            map_key_bound_plus_ts(upper, key::TS_RANGE_END),
-        )));
-        for memtable in snapshot.imm_memtables.iter() {
-            memtable_iters.push(Box::new(memtable.scan(
-                map_key_bound_plus_ts(lower, key::TS_RANGE_BEGIN),
-                map_key_bound_plus_ts(upper, key::TS_RANGE_END),
-            )));
+        for memtable in std::iter::once(&snapshot.memtable).chain(snapshot.imm_memtables.iter()) {
+            let mut iter = memtable.scan(map_lower_bound(lower), map_upper_bound(upper));
+            if direction == Direction::Backward {
+                iter.seek_to_last()?;
+            }
+            memtable_iters.push(Box::new(iter));
         }
-        let memtable_iter = MergeIterator::create(memtable_iters);
+        let memtable_iter = MergeIterator::create_with_direction(memtable_iters, direction);
 
-        let mut table_iters = Vec::with_capacity(snapshot.l0_sstables.len());
-        for table_id in snapshot.l0_sstables.iter() {
-            let table = snapshot.sstables[table_id].clone();
-            if range_overlap(
+        let may_contain_prefix =
+            |table: &SsTable| match (&self.options.prefix_extractor, options.prefix) {
+                (Some(prefix_extractor), Some(prefix)) => {
+                    table.may_contain_prefix(prefix_extractor.as_ref(), prefix)
+                }
+                _ => Ok(true),
+            };
+
+        // The range tombstones are taken from every SST overlapping the range, including those
+        // skipped by the prefix bloom filter.
+        let mut tombstone_sets = Vec::new();
+        let mut overlaps = |table: &SsTable| {
+            let overlap = range_overlap(
                 lower,
                 upper,
                 table.first_key().as_key_slice(),
                 table.last_key().as_key_slice(),
-            ) {
-                let iter = match lower {
-                    Bound::Included(key) => SsTableIterator::create_and_seek_to_key(
-                        table,
-                        KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
-                    )?,
-                    Bound::Excluded(key) => {
-                        let mut iter = SsTableIterator::create_and_seek_to_key(
-                            table,
-                            KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
-                        )?;
-                        while iter.is_valid() && iter.key().key_ref() == key {
-                            iter.next()?;
-                        }
-                        iter
-                    }
-                    Bound::Unbounded => SsTableIterator::create_and_seek_to_first(table)?,
-                };
+            );
+            if overlap && !table.range_tombstone_set().is_empty() {
+                tombstone_sets.push(table.range_tombstone_set().clone());
+            }
+            overlap
+        };
 
+        let mut table_iters = Vec::with_capacity(snapshot.l0_sstables.len());
+        for table_id in snapshot.l0_sstables.iter() {
+            let table = snapshot.sstables[table_id].clone();
+            if overlaps(&table) && may_contain_prefix(&table)? {
+                let mut iter = SsTableIterator::new(table, options.fill_cache);
+                seek_to_scan_start(&mut iter, lower, upper, direction)?;
                 table_iters.push(Box::new(iter));
             }
         }
 
-        let l0_iter = MergeIterator::create(table_iters);
+        let l0_iter = MergeIterator::create_with_direction(table_iters, direction);
         let mut level_iters = Vec::with_capacity(snapshot.levels.len());
         for (_, level_sst_ids) in &snapshot.levels {
             let mut level_ssts = Vec::with_capacity(level_sst_ids.len());
             for table in level_sst_ids {
                 let table = snapshot.sstables[table].clone();
-                if range_overlap(
-                    lower,
-                    upper,
-                    table.first_key().as_key_slice(),
-                    table.last_key().as_key_slice(),
-                ) {
+                if overlaps(&table) && may_contain_prefix(&table)? {
                     level_ssts.push(table);
                 }
             }
 
-            let level_iter = match lower {
-                Bound::Included(key) => SstConcatIterator::create_and_seek_to_key(
-                    level_ssts,
-                    KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
-                )?,
-                Bound::Excluded(key) => {
-                    let mut iter = SstConcatIterator::create_and_seek_to_key(
-                        level_ssts,
-                        KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
-                    )?;
-                    while iter.is_valid() && iter.key().key_ref() == key {
-                        iter.next()?;
-                    }
-                    iter
-                }
-                Bound::Unbounded => SstConcatIterator::create_and_seek_to_first(level_ssts)?,
-            };
+            let mut level_iter = SstConcatIterator::new(level_ssts, options.fill_cache);
+            seek_to_scan_start(&mut level_iter, lower, upper, direction)?;
             level_iters.push(Box::new(level_iter));
         }
 
-        let iter = TwoMergeIterator::create(memtable_iter, l0_iter)?;
-        let iter = TwoMergeIterator::create(iter, MergeIterator::create(level_iters))?;
+        let iter = TwoMergeIterator::create_with_direction(memtable_iter, l0_iter, direction)?;
+        let iter = TwoMergeIterator::create_with_direction(
+            iter,
+            MergeIterator::create_with_direction(level_iters, direction),
+            direction,
+        )?;
 
         Ok(FusedIterator::new(LsmIterator::new(
             iter,
-            map_bound(upper),
+            (map_bound(lower), map_bound(upper)),
             read_ts,
+            snapshot.range_tombstones(tombstone_sets, lower, upper, read_ts),
+            self.options.merge_operator.clone(),
+            self.value_log.clone(),
+            direction,
         )?))
     }
 }
@@ -1,6 +1,6 @@
 use std::fs::{File, OpenOptions};
 use std::io::{Read, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use anyhow::{bail, Context, Result};
@@ -8,10 +8,55 @@ use bytes::{Buf, BufMut};
 use parking_lot::{Mutex, MutexGuard};
 use serde::{Deserialize, Serialize};
 
+use crate::column_family::ColumnFamilyOptions;
 use crate::compact::CompactionTask;
 
+/// The file holding the name of the current manifest file.
+const CURRENT: &str = "CURRENT";
+/// The manifest of the storages created before the manifest was rolled over, encoded in JSON.
+const LEGACY_MANIFEST: &str = "MANIFEST";
+
+struct ManifestFile {
+    file: File,
+    number: usize,
+    size: u64,
+}
+
+/// The log of the changes to the structure of the storage. Each record is encoded as:
+///
+/// ```text
+/// | len (u32) | record (bincode) | checksum (u32) |
+/// ```
+///
+/// The records are appended to a `MANIFEST-<number>` file named by the `CURRENT` file. The
+/// manifest is rolled over into a new file starting with a snapshot of the whole state, and
+/// `CURRENT` is replaced atomically to switch to it.
 pub struct Manifest {
-    file: Arc<Mutex<File>>,
+    dir: PathBuf,
+    file: Arc<Mutex<ManifestFile>>,
+}
+
+/// The SSTs of a column family in a manifest snapshot.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ColumnFamilySnapshot {
+    pub id: usize,
+    pub name: String,
+    pub options: ColumnFamilyOptions,
+    pub l0_sstables: Vec<usize>,
+    pub levels: Vec<(usize, Vec<usize>)>,
+}
+
+/// The state of the storage, which replaces all records before it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestSnapshot {
+    /// All column families, including the default one.
+    pub column_families: Vec<ColumnFamilySnapshot>,
+    /// The memtables which are not flushed yet, from earliest to latest.
+    pub memtables: Vec<usize>,
+    /// The largest SST or memtable id allocated so far.
+    pub last_sst_id: usize,
+    /// Bytes of value log segments found to be garbage, by segment id.
+    pub value_log_garbage: Vec<(usize, u64)>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -19,31 +64,174 @@ pub enum ManifestRecord {
     Flush(usize),
     NewMemtable(usize),
     Compaction(CompactionTask, Vec<usize>),
+    /// Bytes of value log segments found to be garbage, by segment id.
+    ValueLogGarbage(Vec<(usize, u64)>),
+    /// A column family is created with its id, name and options.
+    CreateColumnFamily(usize, String, ColumnFamilyOptions),
+    /// The memtables with the given id are flushed in all column families, with the new SST of
+    /// each non-empty memtable by column family id.
+    FlushColumnFamilies(usize, Vec<(usize, usize)>),
+    /// A compaction in a column family other than the default one.
+    ColumnFamilyCompaction(usize, CompactionTask, Vec<usize>),
+    /// The state of the storage at the start of a manifest file.
+    Snapshot(ManifestSnapshot),
 }
 
 impl Manifest {
-    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
-        Ok(Self {
-            file: Arc::new(Mutex::new(
-                OpenOptions::new()
-                    .read(true)
-                    .create_new(true)
-                    .write(true)
-                    .open(path)
-                    .context("failed to create manifest")?,
-            )),
+    fn path_of_manifest(dir: &Path, number: usize) -> PathBuf {
+        dir.join(format!("MANIFEST-{:06}", number))
+    }
+
+    /// Whether a storage with a manifest exists in the directory.
+    pub fn exists(dir: impl AsRef<Path>) -> bool {
+        let dir = dir.as_ref();
+        dir.join(CURRENT).exists() || dir.join(LEGACY_MANIFEST).exists()
+    }
+
+    /// Creates the manifest of a new storage.
+    pub fn create(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref();
+        let file = Self::create_file(dir, 1, &[])?;
+        Ok(Self::new(dir, file))
+    }
+
+    fn new(dir: &Path, file: ManifestFile) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            file: Arc::new(Mutex::new(file)),
+        }
+    }
+
+    /// Creates a manifest file starting with some records, and makes it the current one. A file
+    /// with the same number is left by a crash before `CURRENT` was switched to it, and is
+    /// overwritten.
+    fn create_file(dir: &Path, number: usize, records: &[ManifestRecord]) -> Result<ManifestFile> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(Self::path_of_manifest(dir, number))
+            .context("failed to create manifest")?;
+        let mut buf = Vec::new();
+        for record in records {
+            Self::encode_record(&mut buf, record)?;
+        }
+        file.write_all(&buf)?;
+        file.sync_all()?;
+        Self::set_current(dir, number)?;
+        Ok(ManifestFile {
+            file,
+            number,
+            size: buf.len() as u64,
         })
     }
 
-    pub fn recover(path: impl AsRef<Path>) -> Result<(Self, Vec<ManifestRecord>)> {
+    /// Replaces the `CURRENT` file atomically with one naming the manifest file.
+    fn set_current(dir: &Path, number: usize) -> Result<()> {
+        let tmp_path = dir.join(format!("{}.tmp", CURRENT));
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(format!("MANIFEST-{:06}\n", number).as_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(&tmp_path, dir.join(CURRENT))?;
+        File::open(dir)?.sync_all()?;
+        Ok(())
+    }
+
+    /// Opens the current manifest and reads its records. The manifest of a storage created before
+    /// the manifest was rolled over is converted to the current encoding.
+    pub fn recover(dir: impl AsRef<Path>) -> Result<(Self, Vec<ManifestRecord>)> {
+        let dir = dir.as_ref();
+        if !dir.join(CURRENT).exists() {
+            let legacy_path = dir.join(LEGACY_MANIFEST);
+            let buf = std::fs::read(&legacy_path).context("failed to recover manifest")?;
+            let records = Self::decode_legacy_records(&buf)?;
+            let file = Self::create_file(dir, 1, &records)?;
+            std::fs::remove_file(legacy_path)?;
+            return Ok((Self::new(dir, file), records));
+        }
+        let number = Self::current_number(dir)?;
+        Self::remove_stale_files(dir, number)?;
         let mut file = OpenOptions::new()
             .read(true)
             .append(true)
-            .open(path)
+            .open(Self::path_of_manifest(dir, number))
             .context("failed to recover manifest")?;
         let mut buf = Vec::new();
         file.read_to_end(&mut buf)?;
-        let mut buf_ptr = buf.as_slice();
+        let records = Self::decode_records(&buf)?;
+        let file = ManifestFile {
+            file,
+            number,
+            size: buf.len() as u64,
+        };
+        Ok((Self::new(dir, file), records))
+    }
+
+    /// Deletes the manifest files other than the current one, which are left by a crash during a
+    /// rollover or a conversion of the legacy manifest.
+    fn remove_stale_files(dir: &Path, number: usize) -> Result<()> {
+        let current_name = format!("MANIFEST-{:06}", number);
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            let Some(name) = path.file_name().and_then(|x| x.to_str()) else {
+                continue;
+            };
+            if (name == LEGACY_MANIFEST || name.starts_with("MANIFEST-")) && name != current_name {
+                std::fs::remove_file(&path)
+                    .with_context(|| format!("failed to delete {}", path.display()))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the records of the current manifest without changing any file.
+    pub fn read_records(dir: impl AsRef<Path>) -> Result<Vec<ManifestRecord>> {
+        let dir = dir.as_ref();
+        if !dir.join(CURRENT).exists() {
+            let buf =
+                std::fs::read(dir.join(LEGACY_MANIFEST)).context("failed to read manifest")?;
+            return Self::decode_legacy_records(&buf);
+        }
+        let buf = std::fs::read(Self::path_of_manifest(dir, Self::current_number(dir)?))
+            .context("failed to read manifest")?;
+        Self::decode_records(&buf)
+    }
+
+    /// The number of the manifest file named by the `CURRENT` file.
+    fn current_number(dir: &Path) -> Result<usize> {
+        let current = std::fs::read_to_string(dir.join(CURRENT))?;
+        current
+            .trim()
+            .strip_prefix("MANIFEST-")
+            .and_then(|x| x.parse::<usize>().ok())
+            .with_context(|| format!("invalid CURRENT file: {:?}", current))
+    }
+
+    fn decode_records(buf: &[u8]) -> Result<Vec<ManifestRecord>> {
+        let mut buf_ptr = buf;
+        let mut records = Vec::new();
+        while buf_ptr.has_remaining() {
+            if buf_ptr.remaining() < 4 {
+                bail!("truncated manifest record");
+            }
+            let len = buf_ptr.get_u32() as usize;
+            if buf_ptr.remaining() < len + 4 {
+                bail!("truncated manifest record");
+            }
+            let slice = &buf_ptr[..len];
+            buf_ptr.advance(len);
+            let checksum = buf_ptr.get_u32();
+            if checksum != crc32fast::hash(slice) {
+                bail!("checksum mismatched!");
+            }
+            records.push(bincode::deserialize::<ManifestRecord>(slice)?);
+        }
+        Ok(records)
+    }
+
+    fn decode_legacy_records(buf: &[u8]) -> Result<Vec<ManifestRecord>> {
+        let mut buf_ptr = buf;
         let mut records = Vec::new();
         while buf_ptr.has_remaining() {
             let len = buf_ptr.get_u64();
@@ -56,12 +244,38 @@ impl Manifest {
             }
             records.push(json);
         }
-        Ok((
-            Self {
-                file: Arc::new(Mutex::new(file)),
-            },
-            records,
-        ))
+        Ok(records)
+    }
+
+    fn encode_record(buf: &mut Vec<u8>, record: &ManifestRecord) -> Result<()> {
+        let encoded = bincode::serialize(record)?;
+        buf.put_u32(encoded.len() as u32);
+        buf.put_slice(&encoded);
+        buf.put_u32(crc32fast::hash(&encoded));
+        Ok(())
+    }
+
+    /// The size of the current manifest file.
+    pub fn size(&self) -> u64 {
+        self.file.lock().size
+    }
+
+    /// Starts a new manifest file with a snapshot of the state, switches to it and deletes the
+    /// previous file. The snapshot must include all records added so far.
+    pub fn roll_over(
+        &self,
+        _state_lock_observer: &MutexGuard<()>,
+        snapshot: ManifestSnapshot,
+    ) -> Result<()> {
+        let mut file = self.file.lock();
+        let old_number = file.number;
+        *file = Self::create_file(
+            &self.dir,
+            old_number + 1,
+            &[ManifestRecord::Snapshot(snapshot)],
+        )?;
+        std::fs::remove_file(Self::path_of_manifest(&self.dir, old_number))?;
+        Ok(())
     }
 
     pub fn add_record(
@@ -74,12 +288,11 @@ impl Manifest {
 
     pub fn add_record_when_init(&self, record: ManifestRecord) -> Result<()> {
         let mut file = self.file.lock();
-        let mut buf = serde_json::to_vec(&record)?;
-        let hash = crc32fast::hash(&buf);
-        file.write_all(&(buf.len() as u64).to_be_bytes())?;
-        buf.put_u32(hash);
-        file.write_all(&buf)?;
-        file.sync_all()?;
+        let mut buf = Vec::new();
+        Self::encode_record(&mut buf, &record)?;
+        file.file.write_all(&buf)?;
+        file.file.sync_all()?;
+        file.size += buf.len() as u64;
         Ok(())
     }
 }
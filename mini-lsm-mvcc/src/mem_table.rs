@@ -1,28 +1,165 @@
+mod arena_skip_list;
+mod hash_linked_list;
+
+use std::collections::HashMap;
 use std::ops::Bound;
 use std::path::Path;
-use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use bytes::Bytes;
-use crossbeam_skiplist::map::Entry;
 use crossbeam_skiplist::SkipMap;
 use ouroboros::self_referencing;
 
-use crate::iterators::StorageIterator;
+use crate::column_family::DEFAULT_COLUMN_FAMILY_ID;
+use crate::iterators::{Direction, RecordKind, StorageIterator};
 use crate::key::{KeyBytes, KeySlice, TS_DEFAULT};
+use crate::range_tombstone::RangeTombstone;
 use crate::table::SsTableBuilder;
-use crate::wal::Wal;
+use crate::wal::{Wal, WalRecordKind, WalRecoveryMode};
+use crate::write_buffer_manager::WriteBufferManager;
+
+pub use arena_skip_list::ArenaSkipList;
+pub use hash_linked_list::HashLinkedList;
+
+/// An entry of a memtable: the key with its timestamp, the kind of the record and the value.
+pub type MemTableEntry = (KeyBytes, RecordKind, Bytes);
+
+/// The data structure holding the entries of a memtable, ordered by key.
+pub trait MemTableRep: Send + Sync {
+    /// Inserts an entry, replacing the entry with the same key and timestamp.
+    fn insert(&self, key: KeySlice, kind: RecordKind, value: &[u8]);
+
+    fn get(&self, key: KeySlice) -> Option<(RecordKind, Bytes)>;
+
+    /// Returns the entries within the bounds, which can be iterated from both ends.
+    fn range(
+        &self,
+        bounds: (Bound<KeyBytes>, Bound<KeyBytes>),
+    ) -> Box<dyn DoubleEndedIterator<Item = MemTableEntry> + '_>;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The memory allocated for the entries, including the overhead of the data structure.
+    fn memory_usage(&self) -> usize;
+}
+
+/// The estimated bookkeeping of the memory allocator for each allocation.
+const ALLOCATION_OVERHEAD: usize = 16;
+
+/// The estimated memory of an entry of the crossbeam-skiplist besides its key and value: the node
+/// with its reference count and about two levels of links, and the separate allocations of the
+/// node, the key and the value.
+const SKIPLIST_ENTRY_OVERHEAD: usize = std::mem::size_of::<KeyBytes>()
+    + std::mem::size_of::<(RecordKind, Bytes)>()
+    + 3 * std::mem::size_of::<usize>()
+    + 3 * ALLOCATION_OVERHEAD;
+
+/// A crossbeam-skiplist, which estimates its memory from the size of the inserted entries.
+struct SkipList {
+    map: SkipMap<KeyBytes, (RecordKind, Bytes)>,
+    /// The memory of the inserted entries, including those replaced.
+    memory_usage: AtomicUsize,
+}
+
+impl MemTableRep for SkipList {
+    fn insert(&self, key: KeySlice, kind: RecordKind, value: &[u8]) {
+        self.map.insert(
+            key.to_key_vec().into_key_bytes(),
+            (kind, Bytes::copy_from_slice(value)),
+        );
+        // A replaced entry is only freed once no reader can see it.
+        self.memory_usage.fetch_add(
+            key.raw_len() + value.len() + SKIPLIST_ENTRY_OVERHEAD,
+            Ordering::Relaxed,
+        );
+    }
+
+    fn get(&self, key: KeySlice) -> Option<(RecordKind, Bytes)> {
+        let key_bytes = KeyBytes::from_bytes_with_ts(
+            Bytes::from_static(unsafe { std::mem::transmute::<&[u8], &[u8]>(key.key_ref()) }),
+            key.ts(),
+        );
+        self.map.get(&key_bytes).map(|e| e.value().clone())
+    }
+
+    fn range(
+        &self,
+        bounds: (Bound<KeyBytes>, Bound<KeyBytes>),
+    ) -> Box<dyn DoubleEndedIterator<Item = MemTableEntry> + '_> {
+        Box::new(
+            self.map
+                .range(bounds)
+                .map(|x| (x.key().clone(), x.value().0, x.value().1.clone())),
+        )
+    }
 
-/// A basic mem-table based on crossbeam-skiplist.
+    fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.memory_usage.load(Ordering::Relaxed)
+    }
+}
+
+/// The data structure of the memtables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MemTableType {
+    /// A lock-free skiplist, with each entry allocated separately.
+    #[default]
+    SkipList,
+    /// A skiplist with its entries allocated in an arena. Insertions are serialized.
+    ArenaSkipList,
+    /// Sorted linked lists in hash buckets by key, for workloads of point lookups. Scans are slow,
+    /// as they sort all entries.
+    HashLinkedList { bucket_count: usize },
+}
+
+impl MemTableType {
+    fn create_rep(self) -> Arc<dyn MemTableRep> {
+        match self {
+            MemTableType::SkipList => Arc::new(SkipList {
+                map: SkipMap::new(),
+                memory_usage: AtomicUsize::new(0),
+            }),
+            MemTableType::ArenaSkipList => Arc::new(ArenaSkipList::new()),
+            MemTableType::HashLinkedList { bucket_count } => {
+                Arc::new(HashLinkedList::new(bucket_count))
+            }
+        }
+    }
+}
+
+/// A mem-table holding its entries in one of the [`MemTableType`]s, and its range tombstones in a
+/// crossbeam-skiplist.
 ///
 /// An initial implementation of memtable is part of week 1, day 1. It will be incrementally implemented in other
 /// chapters of week 1 and week 2.
 pub struct MemTable {
-    pub(crate) map: Arc<SkipMap<KeyBytes, Bytes>>,
+    pub(crate) map: Arc<dyn MemTableRep>,
+    memtable_type: MemTableType,
+    /// Range tombstones, mapping the start key with the timestamp to the end key.
+    range_tombstones: Arc<SkipMap<KeyBytes, Bytes>>,
     wal: Option<Wal>,
     id: usize,
-    approximate_size: Arc<AtomicUsize>,
+    column_family_id: usize,
+    /// The size of the range tombstones.
+    range_tombstones_size: AtomicUsize,
+    write_buffer_manager: Option<Arc<WriteBufferManager>>,
+    /// The memory charged to the write buffer manager.
+    charged_size: AtomicUsize,
+    /// Whether the memtable is charged as an immutable memtable.
+    immutable: AtomicBool,
 }
 
 /// Create a bound of `Bytes` from a bound of `&[u8]`.
@@ -58,45 +195,153 @@ pub(crate) fn map_key_bound_plus_ts(bound: Bound<&[u8]>, ts: u64) -> Bound<KeySl
     }
 }
 
+/// Narrows `bounds` to the keys >= `key`, or to the keys <= `key` when moving backward.
+pub(crate) fn narrow_bounds<K: Ord + Clone>(
+    bounds: &(Bound<K>, Bound<K>),
+    key: K,
+    direction: Direction,
+) -> (Bound<K>, Bound<K>) {
+    match direction {
+        Direction::Forward => match &bounds.0 {
+            Bound::Included(x) | Bound::Excluded(x) if *x >= key => bounds.clone(),
+            _ => (Bound::Included(key), bounds.1.clone()),
+        },
+        Direction::Backward => match &bounds.1 {
+            Bound::Included(x) | Bound::Excluded(x) if *x <= key => bounds.clone(),
+            _ => (bounds.0.clone(), Bound::Included(key)),
+        },
+    }
+}
+
 impl MemTable {
     /// Create a new mem-table.
     pub fn create(id: usize) -> Self {
+        Self::create_with_type(id, MemTableType::default())
+    }
+
+    /// Create a new mem-table of the given type.
+    pub fn create_with_type(id: usize, memtable_type: MemTableType) -> Self {
         Self {
             id,
-            map: Arc::new(SkipMap::new()),
+            column_family_id: DEFAULT_COLUMN_FAMILY_ID,
+            map: memtable_type.create_rep(),
+            memtable_type,
+            range_tombstones: Arc::new(SkipMap::new()),
             wal: None,
-            approximate_size: Arc::new(AtomicUsize::new(0)),
+            range_tombstones_size: AtomicUsize::new(0),
+            write_buffer_manager: None,
+            charged_size: AtomicUsize::new(0),
+            immutable: AtomicBool::new(false),
         }
     }
 
     /// Create a new mem-table with WAL
-    pub fn create_with_wal(id: usize, path: impl AsRef<Path>) -> Result<Self> {
-        Ok(Self {
+    pub fn create_with_wal(id: usize, memtable_type: MemTableType, wal: Wal) -> Self {
+        Self::create_for_column_family(id, DEFAULT_COLUMN_FAMILY_ID, memtable_type, Some(wal))
+    }
+
+    /// Create a new mem-table of a column family, which logs to the WAL shared with the memtables
+    /// of the other column families created at the same time.
+    pub fn create_for_column_family(
+        id: usize,
+        column_family_id: usize,
+        memtable_type: MemTableType,
+        wal: Option<Wal>,
+    ) -> Self {
+        Self {
             id,
-            map: Arc::new(SkipMap::new()),
-            wal: Some(Wal::create(path.as_ref())?),
-            approximate_size: Arc::new(AtomicUsize::new(0)),
-        })
+            column_family_id,
+            map: memtable_type.create_rep(),
+            memtable_type,
+            range_tombstones: Arc::new(SkipMap::new()),
+            wal,
+            range_tombstones_size: AtomicUsize::new(0),
+            write_buffer_manager: None,
+            charged_size: AtomicUsize::new(0),
+            immutable: AtomicBool::new(false),
+        }
     }
 
     /// Create a memtable from WAL
-    pub fn recover_from_wal(id: usize, path: impl AsRef<Path>) -> Result<Self> {
-        let map = Arc::new(SkipMap::new());
-        Ok(Self {
+    pub fn recover_from_wal(
+        id: usize,
+        memtable_type: MemTableType,
+        path: impl AsRef<Path>,
+    ) -> Result<Self> {
+        let (mut memtables, _) = Self::recover_column_families_from_wal(
             id,
-            wal: Some(Wal::recover(path.as_ref(), &map)?),
-            map,
-            approximate_size: Arc::new(AtomicUsize::new(0)),
-        })
+            memtable_type,
+            path,
+            &[DEFAULT_COLUMN_FAMILY_ID],
+            WalRecoveryMode::default(),
+        )?;
+        Ok(memtables.remove(&DEFAULT_COLUMN_FAMILY_ID).unwrap())
+    }
+
+    /// Create the memtables of the column families from a shared WAL, indexed by column family id.
+    /// Also returns whether records were dropped after a corruption of the WAL.
+    pub fn recover_column_families_from_wal(
+        id: usize,
+        memtable_type: MemTableType,
+        path: impl AsRef<Path>,
+        column_family_ids: &[usize],
+        recovery_mode: WalRecoveryMode,
+    ) -> Result<(HashMap<usize, Self>, bool)> {
+        let maps = column_family_ids
+            .iter()
+            .map(|column_family_id| {
+                (
+                    *column_family_id,
+                    (memtable_type.create_rep(), Arc::new(SkipMap::new())),
+                )
+            })
+            .collect::<HashMap<_, _>>();
+        let (wal, dropped) = Wal::recover(
+            path.as_ref(),
+            id,
+            recovery_mode,
+            |column_family_id, kind, key, value| {
+                let Some((map, range_tombstones)) = maps.get(&column_family_id) else {
+                    bail!("unknown column family {}", column_family_id);
+                };
+                match kind {
+                    WalRecordKind::Value => {
+                        map.insert(key.as_key_slice(), RecordKind::Value, &value);
+                    }
+                    WalRecordKind::MergeOperand => {
+                        map.insert(key.as_key_slice(), RecordKind::MergeOperand, &value);
+                    }
+                    WalRecordKind::RangeTombstone => {
+                        range_tombstones.insert(key, value);
+                    }
+                }
+                Ok(())
+            },
+        )?;
+        let memtables = maps
+            .into_iter()
+            .map(|(column_family_id, (map, range_tombstones))| {
+                let memtable = Self {
+                    id,
+                    column_family_id,
+                    map,
+                    memtable_type,
+                    range_tombstones,
+                    wal: Some(wal.clone()),
+                    range_tombstones_size: AtomicUsize::new(0),
+                    write_buffer_manager: None,
+                    charged_size: AtomicUsize::new(0),
+                    immutable: AtomicBool::new(false),
+                };
+                (column_family_id, memtable)
+            })
+            .collect();
+        Ok((memtables, dropped))
     }
 
     /// Get a value by key. Should not be used in week 3.
     pub fn get(&self, key: KeySlice) -> Option<Bytes> {
-        let key_bytes = KeyBytes::from_bytes_with_ts(
-            Bytes::from_static(unsafe { std::mem::transmute(key.key_ref()) }),
-            key.ts(),
-        );
-        self.map.get(&key_bytes).map(|e| e.value().clone())
+        self.map.get(key).map(|(_, value)| value)
     }
 
     pub fn for_testing_put_slice(&self, key: &[u8], value: &[u8]) -> Result<()> {
@@ -123,19 +368,66 @@ impl MemTable {
     /// In week 1, day 1, simply put the key-value pair into the skipmap.
     /// In week 2, day 6, also flush the data to WAL.
     pub fn put(&self, key: KeySlice, value: &[u8]) -> Result<()> {
-        let estimated_size = key.raw_len() + value.len();
-        self.map.insert(
-            key.to_key_vec().into_key_bytes(),
-            Bytes::copy_from_slice(value),
-        );
-        self.approximate_size
-            .fetch_add(estimated_size, std::sync::atomic::Ordering::Relaxed);
+        self.put_record(key, value, RecordKind::Value)
+    }
+
+    /// Put a merge operand into the mem-table.
+    pub fn merge(&self, key: KeySlice, operand: &[u8]) -> Result<()> {
+        self.put_record(key, operand, RecordKind::MergeOperand)
+    }
+
+    fn put_record(&self, key: KeySlice, value: &[u8], kind: RecordKind) -> Result<()> {
+        self.put_without_wal(key, value, kind);
+        if let Some(ref wal) = self.wal {
+            match kind {
+                RecordKind::Value => wal.put(self.column_family_id, key, value)?,
+                RecordKind::MergeOperand => wal.put_merge(self.column_family_id, key, value)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Put a record into the mem-table without logging it, for records already appended to the
+    /// WAL by the caller.
+    pub(crate) fn put_without_wal(&self, key: KeySlice, value: &[u8], kind: RecordKind) {
+        self.map.insert(key, kind, value);
+        self.charge_write_buffer();
+    }
+
+    /// Put a range tombstone deleting the keys in `[start, end)` older than the timestamp of
+    /// `start` into the mem-table.
+    pub fn delete_range(&self, start: KeySlice, end: &[u8]) -> Result<()> {
+        self.delete_range_without_wal(start, end);
         if let Some(ref wal) = self.wal {
-            wal.put(key, value)?;
+            wal.put_range_tombstone(self.column_family_id, start, end)?;
         }
         Ok(())
     }
 
+    /// Put a range tombstone into the mem-table without logging it.
+    pub(crate) fn delete_range_without_wal(&self, start: KeySlice, end: &[u8]) {
+        let estimated_size = start.raw_len() + end.len();
+        self.range_tombstones.insert(
+            start.to_key_vec().into_key_bytes(),
+            Bytes::copy_from_slice(end),
+        );
+        self.range_tombstones_size
+            .fetch_add(estimated_size, Ordering::Relaxed);
+        self.charge_write_buffer();
+    }
+
+    /// Get all range tombstones in the mem-table.
+    pub fn range_tombstones(&self) -> Vec<RangeTombstone> {
+        self.range_tombstones
+            .iter()
+            .map(|entry| RangeTombstone {
+                start: entry.key().key_ref().to_vec().into(),
+                end: entry.value().clone(),
+                ts: entry.key().ts(),
+            })
+            .collect()
+    }
+
     pub fn sync_wal(&self) -> Result<()> {
         if let Some(ref wal) = self.wal {
             wal.sync()?;
@@ -148,8 +440,10 @@ impl MemTable {
         let (lower, upper) = (map_key_bound(lower), map_key_bound(upper));
         let mut iter = MemTableIteratorBuilder {
             map: self.map.clone(),
+            bounds: (lower.clone(), upper.clone()),
             iter_builder: |map| map.range((lower, upper)),
-            item: (KeyBytes::new(), Bytes::new()),
+            item: (KeyBytes::new(), RecordKind::Value, Bytes::new()),
+            direction: Direction::Forward,
         }
         .build();
         let entry = iter.with_iter_mut(|iter| MemTableIterator::entry_to_item(iter.next()));
@@ -159,8 +453,11 @@ impl MemTable {
 
     /// Flush the mem-table to SSTable. Implement in week 1 day 6.
     pub fn flush(&self, builder: &mut SsTableBuilder) -> Result<()> {
-        for entry in self.map.iter() {
-            builder.add(entry.key().as_key_slice(), &entry.value()[..]);
+        for (key, kind, value) in self.map.range((Bound::Unbounded, Bound::Unbounded)) {
+            builder.add_with_kind(key.as_key_slice(), &value[..], kind);
+        }
+        for tombstone in self.range_tombstones() {
+            builder.add_range_tombstone(tombstone);
         }
         Ok(())
     }
@@ -169,46 +466,119 @@ impl MemTable {
         self.id
     }
 
+    pub fn memtable_type(&self) -> MemTableType {
+        self.memtable_type
+    }
+
+    /// The largest timestamp of the entries, or 0 if there are none.
+    pub(crate) fn max_ts(&self) -> u64 {
+        self.map
+            .range((Bound::Unbounded, Bound::Unbounded))
+            .map(|(key, _, _)| key.ts())
+            .max()
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn wal(&self) -> Option<&Wal> {
+        self.wal.as_ref()
+    }
+
+    /// The memory allocated for the entries and the range tombstones.
     pub fn approximate_size(&self) -> usize {
-        self.approximate_size
-            .load(std::sync::atomic::Ordering::Relaxed)
+        self.map.memory_usage() + self.range_tombstones_size.load(Ordering::Relaxed)
+    }
+
+    /// Charges the memory of the memtable to a write buffer manager from now on, until it is
+    /// dropped.
+    pub fn with_write_buffer_manager(
+        mut self,
+        write_buffer_manager: Option<Arc<WriteBufferManager>>,
+    ) -> Self {
+        self.write_buffer_manager = write_buffer_manager;
+        self.charge_write_buffer();
+        self
+    }
+
+    pub(crate) fn write_buffer_manager(&self) -> Option<Arc<WriteBufferManager>> {
+        self.write_buffer_manager.clone()
+    }
+
+    /// Updates the memory charged to the write buffer manager after a write.
+    fn charge_write_buffer(&self) {
+        if let Some(write_buffer_manager) = &self.write_buffer_manager {
+            let size = self.approximate_size();
+            let old_size = self.charged_size.swap(size, Ordering::Relaxed);
+            write_buffer_manager.charge(old_size, size, !self.immutable.load(Ordering::Relaxed));
+        }
+    }
+
+    /// Charges the memtable as an immutable memtable once it is frozen. It must not be written
+    /// concurrently.
+    pub(crate) fn mark_immutable(&self) {
+        if !self.immutable.swap(true, Ordering::Relaxed) {
+            if let Some(write_buffer_manager) = &self.write_buffer_manager {
+                write_buffer_manager.mark_immutable(self.charged_size.load(Ordering::Relaxed));
+            }
+        }
     }
 
     /// Only use this function when closing the database
     pub fn is_empty(&self) -> bool {
-        self.map.is_empty()
+        self.map.is_empty() && self.range_tombstones.is_empty()
+    }
+}
+
+impl Drop for MemTable {
+    fn drop(&mut self) {
+        if let Some(write_buffer_manager) = &self.write_buffer_manager {
+            write_buffer_manager.charge(
+                *self.charged_size.get_mut(),
+                0,
+                !*self.immutable.get_mut(),
+            );
+        }
     }
 }
 
-type SkipMapRangeIter<'a> = crossbeam_skiplist::map::Range<
-    'a,
-    KeyBytes,
-    (Bound<KeyBytes>, Bound<KeyBytes>),
-    KeyBytes,
-    Bytes,
->;
+type MemTableRangeIter<'a> = Box<dyn DoubleEndedIterator<Item = MemTableEntry> + 'a>;
 
-/// An iterator over a range of `SkipMap`. This is a self-referential structure and please refer to week 1, day 2
+/// An iterator over a range of a memtable. This is a self-referential structure and please refer to week 1, day 2
 /// chapter for more information.
 ///
 /// This is part of week 1, day 2.
 #[self_referencing]
 pub struct MemTableIterator {
-    /// Stores a reference to the skipmap.
-    map: Arc<SkipMap<KeyBytes, Bytes>>,
-    /// Stores a skipmap iterator that refers to the lifetime of `MemTableIterator` itself.
+    /// Stores a reference to the entries of the memtable.
+    map: Arc<dyn MemTableRep>,
+    /// The range of the iterator.
+    bounds: (Bound<KeyBytes>, Bound<KeyBytes>),
+    /// Stores a range iterator that refers to the lifetime of `MemTableIterator` itself.
     #[borrows(map)]
     #[not_covariant]
-    iter: SkipMapRangeIter<'this>,
-    /// Stores the current key-value pair.
-    item: (KeyBytes, Bytes),
+    iter: MemTableRangeIter<'this>,
+    /// Stores the current key-value pair with its kind.
+    item: (KeyBytes, RecordKind, Bytes),
+    /// The range iterator yields from its front when moving forward, and from its back when moving
+    /// backward.
+    direction: Direction,
 }
 
 impl MemTableIterator {
-    fn entry_to_item(entry: Option<Entry<'_, KeyBytes, Bytes>>) -> (KeyBytes, Bytes) {
-        entry
-            .map(|x| (x.key().clone(), x.value().clone()))
-            .unwrap_or_else(|| (KeyBytes::new(), Bytes::new()))
+    fn entry_to_item(entry: Option<MemTableEntry>) -> MemTableEntry {
+        entry.unwrap_or_else(|| (KeyBytes::new(), RecordKind::Value, Bytes::new()))
+    }
+
+    /// Restarts the range iterator on `range`, and moves to its first entry in `direction`.
+    fn seek_range(&mut self, range: (Bound<KeyBytes>, Bound<KeyBytes>), direction: Direction) {
+        let entry = self.with_mut(|x| {
+            *x.iter = x.map.range(range);
+            *x.direction = direction;
+            MemTableIterator::entry_to_item(match direction {
+                Direction::Forward => x.iter.next(),
+                Direction::Backward => x.iter.next_back(),
+            })
+        });
+        self.with_mut(|x| *x.item = entry);
     }
 }
 
@@ -216,7 +586,11 @@ impl StorageIterator for MemTableIterator {
     type KeyType<'a> = KeySlice<'a>;
 
     fn value(&self) -> &[u8] {
-        &self.borrow_item().1[..]
+        &self.borrow_item().2[..]
+    }
+
+    fn record_kind(&self) -> RecordKind {
+        self.borrow_item().1
     }
 
     fn key(&self) -> KeySlice {
@@ -228,8 +602,54 @@ impl StorageIterator for MemTableIterator {
     }
 
     fn next(&mut self) -> Result<()> {
+        if *self.borrow_direction() != Direction::Forward {
+            let range = (
+                Bound::Excluded(self.borrow_item().0.clone()),
+                self.borrow_bounds().1.clone(),
+            );
+            self.seek_range(range, Direction::Forward);
+            return Ok(());
+        }
         let entry = self.with_iter_mut(|iter| MemTableIterator::entry_to_item(iter.next()));
         self.with_mut(|x| *x.item = entry);
         Ok(())
     }
+
+    fn prev(&mut self) -> Result<()> {
+        if *self.borrow_direction() != Direction::Backward {
+            let range = (
+                self.borrow_bounds().0.clone(),
+                Bound::Excluded(self.borrow_item().0.clone()),
+            );
+            self.seek_range(range, Direction::Backward);
+            return Ok(());
+        }
+        let entry = self.with_iter_mut(|iter| MemTableIterator::entry_to_item(iter.next_back()));
+        self.with_mut(|x| *x.item = entry);
+        Ok(())
+    }
+
+    fn seek_to_first(&mut self) -> Result<()> {
+        self.seek_range(self.borrow_bounds().clone(), Direction::Forward);
+        Ok(())
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        self.seek_range(self.borrow_bounds().clone(), Direction::Backward);
+        Ok(())
+    }
+
+    fn seek(&mut self, key: KeySlice) -> Result<()> {
+        let key = key.to_key_vec().into_key_bytes();
+        let range = narrow_bounds(self.borrow_bounds(), key, Direction::Forward);
+        self.seek_range(range, Direction::Forward);
+        Ok(())
+    }
+
+    fn seek_for_prev(&mut self, key: KeySlice) -> Result<()> {
+        let key = key.to_key_vec().into_key_bytes();
+        let range = narrow_bounds(self.borrow_bounds(), key, Direction::Backward);
+        self.seek_range(range, Direction::Backward);
+        Ok(())
+    }
 }
@@ -0,0 +1,402 @@
+use std::alloc::Layout;
+use std::ops::Bound;
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+use bytes::Bytes;
+use parking_lot::Mutex;
+
+use crate::iterators::RecordKind;
+use crate::key::{KeyBytes, KeySlice};
+
+use super::{MemTableEntry, MemTableRep};
+
+const MAX_HEIGHT: usize = 12;
+/// A node is one level higher than the previous one with a probability of 1/4.
+const BRANCHING: u32 = 4;
+/// The chunks grow with the arena from the minimum size to the maximum size, so that a small
+/// memtable does not allocate a large chunk.
+const MIN_CHUNK_SIZE: usize = 4 << 10;
+const MAX_CHUNK_SIZE: usize = 64 << 10;
+const ALIGN: usize = std::mem::align_of::<u64>();
+
+/// Allocates memory in large chunks, which are all freed at once when the arena is dropped.
+struct Arena {
+    chunks: Vec<(NonNull<u8>, Layout)>,
+    /// The position of the free space in the last chunk.
+    ptr: *mut u8,
+    remaining: usize,
+    /// The total size of the chunks.
+    allocated: usize,
+}
+
+impl Arena {
+    fn new() -> Self {
+        Self {
+            chunks: Vec::new(),
+            ptr: std::ptr::null_mut(),
+            remaining: 0,
+            allocated: 0,
+        }
+    }
+
+    /// Allocates `size` bytes aligned to 8 bytes.
+    fn alloc(&mut self, size: usize) -> NonNull<u8> {
+        let size = size.next_multiple_of(ALIGN);
+        if size > self.remaining {
+            // A large allocation gets its own chunk, so that the free space of the current chunk is
+            // not wasted.
+            let next_chunk_size = self.allocated.clamp(MIN_CHUNK_SIZE, MAX_CHUNK_SIZE);
+            let (chunk_size, dedicated) = if size > next_chunk_size / 4 {
+                (size, true)
+            } else {
+                (next_chunk_size, false)
+            };
+            let layout = Layout::from_size_align(chunk_size, ALIGN).unwrap();
+            let chunk = NonNull::new(unsafe { std::alloc::alloc(layout) })
+                .unwrap_or_else(|| std::alloc::handle_alloc_error(layout));
+            self.chunks.push((chunk, layout));
+            self.allocated += chunk_size;
+            if dedicated {
+                return chunk;
+            }
+            self.ptr = chunk.as_ptr();
+            self.remaining = chunk_size;
+        }
+        let ptr = self.ptr;
+        self.ptr = unsafe { ptr.add(size) };
+        self.remaining -= size;
+        unsafe { NonNull::new_unchecked(ptr) }
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        for (chunk, layout) in &self.chunks {
+            unsafe { std::alloc::dealloc(chunk.as_ptr(), *layout) };
+        }
+    }
+}
+
+/// The header of a node, which is followed in the arena by `height` next pointers, the key and
+/// the value.
+#[repr(C)]
+struct Node {
+    ts: u64,
+    key_len: u32,
+    value_len: u32,
+    kind: RecordKind,
+    height: u8,
+}
+
+/// A pointer to a node in the arena, which stays valid as long as the skiplist.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct NodePtr(NonNull<Node>);
+
+impl NodePtr {
+    fn header(&self) -> &Node {
+        unsafe { self.0.as_ref() }
+    }
+
+    fn tower(&self) -> &[AtomicPtr<Node>] {
+        unsafe {
+            std::slice::from_raw_parts(
+                self.0.as_ptr().add(1) as *const AtomicPtr<Node>,
+                self.header().height as usize,
+            )
+        }
+    }
+
+    fn next(&self, level: usize) -> Option<NodePtr> {
+        NonNull::new(self.tower()[level].load(Ordering::Acquire)).map(NodePtr)
+    }
+
+    fn set_next(&self, level: usize, next: Option<NodePtr>) {
+        let next = next.map_or(std::ptr::null_mut(), |x| x.0.as_ptr());
+        self.tower()[level].store(next, Ordering::Release);
+    }
+
+    fn key_ptr(&self) -> *const u8 {
+        unsafe {
+            (self.0.as_ptr().add(1) as *const AtomicPtr<Node>).add(self.header().height as usize)
+                as *const u8
+        }
+    }
+
+    fn key(&self) -> KeySlice<'_> {
+        let header = self.header();
+        let key = unsafe { std::slice::from_raw_parts(self.key_ptr(), header.key_len as usize) };
+        KeySlice::from_slice(key, header.ts)
+    }
+
+    fn value(&self) -> &[u8] {
+        let header = self.header();
+        unsafe {
+            std::slice::from_raw_parts(
+                self.key_ptr().add(header.key_len as usize),
+                header.value_len as usize,
+            )
+        }
+    }
+
+    fn to_entry(self) -> MemTableEntry {
+        let key = self.key();
+        (
+            KeyBytes::from_bytes_with_ts(Bytes::copy_from_slice(key.key_ref()), key.ts()),
+            self.header().kind,
+            Bytes::copy_from_slice(self.value()),
+        )
+    }
+}
+
+/// A skiplist whose nodes are allocated with their keys and values in an arena, which saves the
+/// separate allocations of each entry and their bookkeeping.
+///
+/// Insertions are serialized by the lock of the arena, while reads do not take any lock. Nodes are
+/// never freed before the skiplist is dropped, so a reader can keep following a node which is
+/// replaced concurrently.
+pub struct ArenaSkipList {
+    arena: Mutex<Arena>,
+    /// The sentinel node before the first entry, with the maximum height.
+    head: NodePtr,
+    len: AtomicUsize,
+}
+
+// The nodes are only modified through atomic pointers, and the arena only under its lock.
+unsafe impl Send for ArenaSkipList {}
+unsafe impl Sync for ArenaSkipList {}
+
+impl Default for ArenaSkipList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ArenaSkipList {
+    pub fn new() -> Self {
+        let mut arena = Arena::new();
+        let head = Self::alloc_node(
+            &mut arena,
+            KeySlice::from_slice(&[], 0),
+            RecordKind::Value,
+            &[],
+            MAX_HEIGHT,
+        );
+        Self {
+            arena: Mutex::new(arena),
+            head,
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    fn alloc_node(
+        arena: &mut Arena,
+        key: KeySlice,
+        kind: RecordKind,
+        value: &[u8],
+        height: usize,
+    ) -> NodePtr {
+        let size = std::mem::size_of::<Node>()
+            + height * std::mem::size_of::<AtomicPtr<Node>>()
+            + key.key_len()
+            + value.len();
+        let ptr = arena.alloc(size).cast::<Node>();
+        unsafe {
+            ptr.as_ptr().write(Node {
+                ts: key.ts(),
+                key_len: key.key_len() as u32,
+                value_len: value.len() as u32,
+                kind,
+                height: height as u8,
+            });
+            let tower = ptr.as_ptr().add(1) as *mut AtomicPtr<Node>;
+            for level in 0..height {
+                tower.add(level).write(AtomicPtr::new(std::ptr::null_mut()));
+            }
+            let key_ptr = tower.add(height) as *mut u8;
+            std::ptr::copy_nonoverlapping(key.key_ref().as_ptr(), key_ptr, key.key_len());
+            std::ptr::copy_nonoverlapping(value.as_ptr(), key_ptr.add(key.key_len()), value.len());
+        }
+        NodePtr(ptr)
+    }
+
+    fn random_height() -> usize {
+        let mut height = 1;
+        while height < MAX_HEIGHT && rand::random::<u32>().is_multiple_of(BRANCHING) {
+            height += 1;
+        }
+        height
+    }
+
+    /// Returns the last node before `key` at each level, which is the head if there is none.
+    fn find_predecessors(&self, key: KeySlice) -> [NodePtr; MAX_HEIGHT] {
+        let mut preds = [self.head; MAX_HEIGHT];
+        let mut node = self.head;
+        for level in (0..MAX_HEIGHT).rev() {
+            while let Some(next) = node.next(level) {
+                if next.key() >= key {
+                    break;
+                }
+                node = next;
+            }
+            preds[level] = node;
+        }
+        preds
+    }
+
+    /// Returns the first node at or after `key`.
+    fn seek(&self, key: KeySlice) -> Option<NodePtr> {
+        let mut node = self.head;
+        for level in (0..MAX_HEIGHT).rev() {
+            while let Some(next) = node.next(level) {
+                if next.key() >= key {
+                    break;
+                }
+                node = next;
+            }
+        }
+        node.next(0)
+    }
+
+    /// Returns the last node before `key`, or the last node if `key` is `None`.
+    fn seek_for_prev(&self, key: Option<KeySlice>) -> Option<NodePtr> {
+        let mut node = self.head;
+        for level in (0..MAX_HEIGHT).rev() {
+            while let Some(next) = node.next(level) {
+                if key.is_some_and(|key| next.key() >= key) {
+                    break;
+                }
+                node = next;
+            }
+        }
+        Some(node).filter(|x| *x != self.head)
+    }
+}
+
+impl MemTableRep for ArenaSkipList {
+    fn insert(&self, key: KeySlice, kind: RecordKind, value: &[u8]) {
+        let mut arena = self.arena.lock();
+        let preds = self.find_predecessors(key);
+        match preds[0].next(0).filter(|x| x.key() == key) {
+            Some(old) => {
+                // Link a new node of the same height in place of the old one.
+                let height = old.header().height as usize;
+                let node = Self::alloc_node(&mut arena, key, kind, value, height);
+                for level in 0..height {
+                    node.set_next(level, old.next(level));
+                }
+                for (level, pred) in preds.iter().enumerate().take(height) {
+                    pred.set_next(level, Some(node));
+                }
+            }
+            None => {
+                let height = Self::random_height();
+                let node = Self::alloc_node(&mut arena, key, kind, value, height);
+                for (level, pred) in preds.iter().enumerate().take(height) {
+                    node.set_next(level, pred.next(level));
+                }
+                // Link from the bottom, so that a node reachable at a level is reachable below.
+                for (level, pred) in preds.iter().enumerate().take(height) {
+                    pred.set_next(level, Some(node));
+                }
+                self.len.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn get(&self, key: KeySlice) -> Option<(RecordKind, Bytes)> {
+        let node = self.seek(key).filter(|x| x.key() == key)?;
+        Some((node.header().kind, Bytes::copy_from_slice(node.value())))
+    }
+
+    fn range(
+        &self,
+        bounds: (Bound<KeyBytes>, Bound<KeyBytes>),
+    ) -> Box<dyn DoubleEndedIterator<Item = MemTableEntry> + '_> {
+        let front = match &bounds.0 {
+            Bound::Included(x) => self.seek(x.as_key_slice()),
+            Bound::Excluded(x) => self.seek(x.as_key_slice()).and_then(|node| {
+                if node.key() == x.as_key_slice() {
+                    node.next(0)
+                } else {
+                    Some(node)
+                }
+            }),
+            Bound::Unbounded => self.head.next(0),
+        };
+        Box::new(ArenaRange {
+            list: self,
+            front,
+            bounds,
+        })
+    }
+
+    fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    /// The size of the chunks allocated by the arena, which includes the free space of the last
+    /// chunk.
+    fn memory_usage(&self) -> usize {
+        self.arena.lock().allocated
+    }
+}
+
+/// Iterates the nodes within the bounds. The bounds shrink as the entries are yielded from either
+/// end.
+struct ArenaRange<'a> {
+    list: &'a ArenaSkipList,
+    /// The next node from the front, which may be out of the upper bound.
+    front: Option<NodePtr>,
+    bounds: (Bound<KeyBytes>, Bound<KeyBytes>),
+}
+
+impl ArenaRange<'_> {
+    fn within_lower(&self, key: KeySlice) -> bool {
+        match &self.bounds.0 {
+            Bound::Included(x) => key >= x.as_key_slice(),
+            Bound::Excluded(x) => key > x.as_key_slice(),
+            Bound::Unbounded => true,
+        }
+    }
+
+    fn within_upper(&self, key: KeySlice) -> bool {
+        match &self.bounds.1 {
+            Bound::Included(x) => key <= x.as_key_slice(),
+            Bound::Excluded(x) => key < x.as_key_slice(),
+            Bound::Unbounded => true,
+        }
+    }
+}
+
+impl Iterator for ArenaRange<'_> {
+    type Item = MemTableEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.front.filter(|x| self.within_upper(x.key()))?;
+        self.front = node.next(0);
+        let entry = node.to_entry();
+        self.bounds.0 = Bound::Excluded(entry.0.clone());
+        Some(entry)
+    }
+}
+
+impl DoubleEndedIterator for ArenaRange<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let node = match &self.bounds.1 {
+            Bound::Included(x) => {
+                let key = x.as_key_slice();
+                match self.list.seek(key).filter(|x| x.key() == key) {
+                    Some(node) => Some(node),
+                    None => self.list.seek_for_prev(Some(key)),
+                }
+            }
+            Bound::Excluded(x) => self.list.seek_for_prev(Some(x.as_key_slice())),
+            Bound::Unbounded => self.list.seek_for_prev(None),
+        };
+        let node = node.filter(|x| self.within_lower(x.key()))?;
+        let entry = node.to_entry();
+        self.bounds.1 = Bound::Excluded(entry.0.clone());
+        Some(entry)
+    }
+}
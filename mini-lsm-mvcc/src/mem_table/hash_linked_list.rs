@@ -0,0 +1,161 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{BuildHasher, BuildHasherDefault};
+use std::ops::{Bound, RangeBounds};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use bytes::Bytes;
+use parking_lot::RwLock;
+
+use crate::iterators::RecordKind;
+use crate::key::{KeyBytes, KeySlice};
+
+use super::{MemTableEntry, MemTableRep, ALLOCATION_OVERHEAD};
+
+struct ListNode {
+    key: KeyBytes,
+    kind: RecordKind,
+    value: Bytes,
+    next: Option<Box<ListNode>>,
+}
+
+/// A hash table of sorted linked lists, with all versions of a key in the same bucket. Reading the
+/// versions of a key only locks and walks its bucket, while any other range has to collect and sort
+/// the entries of all buckets.
+pub struct HashLinkedList {
+    buckets: Vec<RwLock<Option<Box<ListNode>>>>,
+    len: AtomicUsize,
+    /// The memory of the nodes with their keys and values.
+    nodes_size: AtomicUsize,
+}
+
+impl HashLinkedList {
+    pub fn new(bucket_count: usize) -> Self {
+        assert!(bucket_count > 0, "bucket count must be positive");
+        Self {
+            buckets: (0..bucket_count).map(|_| RwLock::new(None)).collect(),
+            len: AtomicUsize::new(0),
+            nodes_size: AtomicUsize::new(0),
+        }
+    }
+
+    fn bucket(&self, key: &[u8]) -> &RwLock<Option<Box<ListNode>>> {
+        let hash = BuildHasherDefault::<DefaultHasher>::default().hash_one(key);
+        &self.buckets[hash as usize % self.buckets.len()]
+    }
+
+    /// Collects the entries of a bucket within the bounds.
+    fn collect_bucket(
+        bucket: &RwLock<Option<Box<ListNode>>>,
+        bounds: &(Bound<KeyBytes>, Bound<KeyBytes>),
+        entries: &mut Vec<MemTableEntry>,
+    ) {
+        let head = bucket.read();
+        let mut node = head.as_deref();
+        while let Some(x) = node {
+            if bounds.contains(&x.key) {
+                entries.push((x.key.clone(), x.kind, x.value.clone()));
+            }
+            node = x.next.as_deref();
+        }
+    }
+}
+
+impl MemTableRep for HashLinkedList {
+    fn insert(&self, key: KeySlice, kind: RecordKind, value: &[u8]) {
+        let mut head = self.bucket(key.key_ref()).write();
+        let mut cursor = &mut *head;
+        while cursor
+            .as_ref()
+            .is_some_and(|node| node.key.as_key_slice() < key)
+        {
+            cursor = &mut cursor.as_mut().unwrap().next;
+        }
+        let value = Bytes::copy_from_slice(value);
+        match cursor {
+            Some(node) if node.key.as_key_slice() == key => {
+                self.nodes_size.fetch_add(value.len(), Ordering::Relaxed);
+                self.nodes_size
+                    .fetch_sub(node.value.len(), Ordering::Relaxed);
+                node.kind = kind;
+                node.value = value;
+            }
+            _ => {
+                let value_len = value.len();
+                let next = cursor.take();
+                *cursor = Some(Box::new(ListNode {
+                    key: key.to_key_vec().into_key_bytes(),
+                    kind,
+                    value,
+                    next,
+                }));
+                self.len.fetch_add(1, Ordering::Relaxed);
+                // The node, the key and the value are allocated separately.
+                self.nodes_size.fetch_add(
+                    std::mem::size_of::<ListNode>()
+                        + key.raw_len()
+                        + value_len
+                        + 3 * ALLOCATION_OVERHEAD,
+                    Ordering::Relaxed,
+                );
+            }
+        }
+    }
+
+    fn get(&self, key: KeySlice) -> Option<(RecordKind, Bytes)> {
+        let head = self.bucket(key.key_ref()).read();
+        let mut node = head.as_deref();
+        while let Some(x) = node {
+            if x.key.as_key_slice() == key {
+                return Some((x.kind, x.value.clone()));
+            }
+            node = x.next.as_deref();
+        }
+        None
+    }
+
+    fn range(
+        &self,
+        bounds: (Bound<KeyBytes>, Bound<KeyBytes>),
+    ) -> Box<dyn DoubleEndedIterator<Item = MemTableEntry> + '_> {
+        let mut entries = Vec::new();
+        let single_key = match &bounds {
+            (
+                Bound::Included(lower) | Bound::Excluded(lower),
+                Bound::Included(upper) | Bound::Excluded(upper),
+            ) if lower.key_ref() == upper.key_ref() => Some(lower.key_ref()),
+            _ => None,
+        };
+        match single_key {
+            // The versions of a key are sorted within the bucket.
+            Some(key) => Self::collect_bucket(self.bucket(key), &bounds, &mut entries),
+            None => {
+                for bucket in &self.buckets {
+                    Self::collect_bucket(bucket, &bounds, &mut entries);
+                }
+                entries.sort_by(|x, y| x.0.cmp(&y.0));
+            }
+        }
+        Box::new(entries.into_iter())
+    }
+
+    fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.buckets.len() * std::mem::size_of::<RwLock<Option<Box<ListNode>>>>()
+            + self.nodes_size.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for HashLinkedList {
+    fn drop(&mut self) {
+        // Drop the lists iteratively, as dropping a long list recursively overflows the stack.
+        for bucket in &mut self.buckets {
+            let mut node = bucket.get_mut().take();
+            while let Some(mut x) = node {
+                node = x.next.take();
+            }
+        }
+    }
+}
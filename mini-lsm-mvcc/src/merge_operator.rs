@@ -0,0 +1,55 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use crate::column_family::DEFAULT_COLUMN_FAMILY;
+use crate::lsm_storage::{LsmStorageInner, MiniLsm, WriteBatchRecord};
+
+/// Combines the merge operands of a key with its existing value, so that read-modify-write
+/// updates like counters and appends do not need to read the key when writing. Operands are
+/// stored as they are written, and are combined when the key is read or compacted.
+pub trait MergeOperator: Send + Sync + Debug {
+    /// Applies the operands, ordered from the oldest to the newest, to the existing value of the
+    /// key, which is `None` if the key does not exist. An empty result deletes the key.
+    fn full_merge(&self, key: &[u8], existing: Option<&[u8]>, operands: &[Bytes]) -> Bytes;
+
+    /// Combines the operands, ordered from the oldest to the newest, into a single operand.
+    /// Compaction uses it when the existing value is not part of its input. Returns `None` if the
+    /// operands cannot be combined without the existing value, in which case they are kept as they
+    /// are.
+    fn partial_merge(&self, _key: &[u8], _operands: &[Bytes]) -> Option<Bytes> {
+        None
+    }
+}
+
+impl LsmStorageInner {
+    pub(crate) fn merge_operator(&self) -> Result<&Arc<dyn MergeOperator>> {
+        self.options
+            .merge_operator
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("merge operator is not configured"))
+    }
+
+    /// Writes a merge operand of the key, which is combined with the existing value by the merge
+    /// operator when the key is read.
+    pub fn merge(&self, key: &[u8], operand: &[u8]) -> Result<()> {
+        self.merge_cf(DEFAULT_COLUMN_FAMILY, key, operand)
+    }
+
+    pub fn merge_cf(&self, name: &str, key: &[u8], operand: &[u8]) -> Result<()> {
+        self.merge_operator()?;
+        self.write_batch_cf(&[(name, WriteBatchRecord::Merge(key, operand))])
+    }
+}
+
+impl MiniLsm {
+    pub fn merge(&self, key: &[u8], operand: &[u8]) -> Result<()> {
+        self.inner.merge(key, operand)
+    }
+
+    pub fn merge_cf(&self, name: &str, key: &[u8], operand: &[u8]) -> Result<()> {
+        self.inner.merge_cf(name, key, operand)
+    }
+}
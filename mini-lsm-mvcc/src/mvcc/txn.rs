@@ -14,11 +14,12 @@ use ouroboros::self_referencing;
 use parking_lot::Mutex;
 
 use crate::{
-    iterators::{two_merge_iterator::TwoMergeIterator, StorageIterator},
+    iterators::{two_merge_iterator::TwoMergeIterator, Direction, StorageIterator},
     lsm_iterator::{FusedIterator, LsmIterator},
-    lsm_storage::{LsmStorageInner, WriteBatchRecord},
-    mem_table::map_bound,
+    lsm_storage::{LsmStorageInner, ReadOptions, ScanOptions, WriteBatchRecord},
+    mem_table::{map_bound, narrow_bounds},
     mvcc::CommittedTxnData,
+    prefix_extractor::prefix_upper_bound,
 };
 
 pub struct Transaction {
@@ -51,24 +52,81 @@ impl Transaction {
     }
 
     pub fn scan(self: &Arc<Self>, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<TxnIterator> {
+        self.scan_with_direction(lower, upper, Direction::Forward, ScanOptions::default())
+    }
+
+    /// Create an iterator over a range of keys with the read options.
+    pub fn scan_with_options(
+        self: &Arc<Self>,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        read_options: &ReadOptions,
+    ) -> Result<TxnIterator> {
+        let options = ScanOptions {
+            fill_cache: read_options.fill_cache,
+            ..Default::default()
+        };
+        self.scan_with_direction(lower, upper, Direction::Forward, options)
+    }
+
+    /// Create an iterator over a range of keys that starts from the last key, and moves backward
+    /// with `prev`.
+    pub fn scan_rev(
+        self: &Arc<Self>,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<TxnIterator> {
+        self.scan_with_direction(lower, upper, Direction::Backward, ScanOptions::default())
+    }
+
+    /// Create an iterator over the keys with the prefix, skipping the SSTs without any key of it.
+    pub fn scan_prefix(self: &Arc<Self>, prefix: &[u8]) -> Result<TxnIterator> {
+        let upper = prefix_upper_bound(prefix);
+        let upper = upper.as_deref().map_or(Bound::Unbounded, Bound::Excluded);
+        self.scan_with_direction(
+            Bound::Included(prefix),
+            upper,
+            Direction::Forward,
+            ScanOptions {
+                prefix: Some(prefix),
+                ..Default::default()
+            },
+        )
+    }
+
+    fn scan_with_direction(
+        self: &Arc<Self>,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        direction: Direction,
+        options: ScanOptions,
+    ) -> Result<TxnIterator> {
         if self.committed.load(Ordering::SeqCst) {
             panic!("cannot operate on committed txn!");
         }
         let mut local_iter = TxnLocalIteratorBuilder {
             map: self.local_storage.clone(),
+            bounds: (map_bound(lower), map_bound(upper)),
             iter_builder: |map| map.range((map_bound(lower), map_bound(upper))),
             item: (Bytes::new(), Bytes::new()),
+            direction: Direction::Forward,
         }
         .build();
         let entry = local_iter.with_iter_mut(|iter| TxnLocalIterator::entry_to_item(iter.next()));
         local_iter.with_mut(|x| *x.item = entry);
+        if direction == Direction::Backward {
+            local_iter.seek_to_last()?;
+        }
 
         TxnIterator::create(
             self.clone(),
-            TwoMergeIterator::create(
+            TwoMergeIterator::create_with_direction(
                 local_iter,
-                self.inner.scan_with_ts(lower, upper, self.read_ts)?,
+                self.inner
+                    .scan_with_ts(lower, upper, self.read_ts, direction, options)?,
+                direction,
             )?,
+            direction,
         )
     }
 
@@ -179,12 +237,17 @@ type SkipMapRangeIter<'a> =
 pub struct TxnLocalIterator {
     /// Stores a reference to the skipmap.
     map: Arc<SkipMap<Bytes, Bytes>>,
+    /// The range of the iterator.
+    bounds: (Bound<Bytes>, Bound<Bytes>),
     /// Stores a skipmap iterator that refers to the lifetime of `MemTableIterator` itself.
     #[borrows(map)]
     #[not_covariant]
     iter: SkipMapRangeIter<'this>,
     /// Stores the current key-value pair.
     item: (Bytes, Bytes),
+    /// The skipmap iterator yields from its front when moving forward, and from its back when
+    /// moving backward.
+    direction: Direction,
 }
 
 impl TxnLocalIterator {
@@ -193,6 +256,19 @@ impl TxnLocalIterator {
             .map(|x| (x.key().clone(), x.value().clone()))
             .unwrap_or_else(|| (Bytes::new(), Bytes::new()))
     }
+
+    /// Restarts the skipmap iterator on `range`, and moves to its first entry in `direction`.
+    fn seek_range(&mut self, range: (Bound<Bytes>, Bound<Bytes>), direction: Direction) {
+        let entry = self.with_mut(|x| {
+            *x.iter = x.map.range(range);
+            *x.direction = direction;
+            TxnLocalIterator::entry_to_item(match direction {
+                Direction::Forward => x.iter.next(),
+                Direction::Backward => x.iter.next_back(),
+            })
+        });
+        self.with_mut(|x| *x.item = entry);
+    }
 }
 
 impl StorageIterator for TxnLocalIterator {
@@ -211,10 +287,56 @@ impl StorageIterator for TxnLocalIterator {
     }
 
     fn next(&mut self) -> Result<()> {
+        if *self.borrow_direction() != Direction::Forward {
+            let range = (
+                Bound::Excluded(self.borrow_item().0.clone()),
+                self.borrow_bounds().1.clone(),
+            );
+            self.seek_range(range, Direction::Forward);
+            return Ok(());
+        }
         let entry = self.with_iter_mut(|iter| TxnLocalIterator::entry_to_item(iter.next()));
         self.with_mut(|x| *x.item = entry);
         Ok(())
     }
+
+    fn prev(&mut self) -> Result<()> {
+        if *self.borrow_direction() != Direction::Backward {
+            let range = (
+                self.borrow_bounds().0.clone(),
+                Bound::Excluded(self.borrow_item().0.clone()),
+            );
+            self.seek_range(range, Direction::Backward);
+            return Ok(());
+        }
+        let entry = self.with_iter_mut(|iter| TxnLocalIterator::entry_to_item(iter.next_back()));
+        self.with_mut(|x| *x.item = entry);
+        Ok(())
+    }
+
+    fn seek_to_first(&mut self) -> Result<()> {
+        self.seek_range(self.borrow_bounds().clone(), Direction::Forward);
+        Ok(())
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        self.seek_range(self.borrow_bounds().clone(), Direction::Backward);
+        Ok(())
+    }
+
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        let key = Bytes::copy_from_slice(key);
+        let range = narrow_bounds(self.borrow_bounds(), key, Direction::Forward);
+        self.seek_range(range, Direction::Forward);
+        Ok(())
+    }
+
+    fn seek_for_prev(&mut self, key: &[u8]) -> Result<()> {
+        let key = Bytes::copy_from_slice(key);
+        let range = narrow_bounds(self.borrow_bounds(), key, Direction::Backward);
+        self.seek_range(range, Direction::Backward);
+        Ok(())
+    }
 }
 
 pub struct TxnIterator {
@@ -226,18 +348,22 @@ impl TxnIterator {
     pub fn create(
         txn: Arc<Transaction>,
         iter: TwoMergeIterator<TxnLocalIterator, FusedIterator<LsmIterator>>,
+        direction: Direction,
     ) -> Result<Self> {
         let mut iter = Self { txn, iter };
-        iter.skip_deletes()?;
+        iter.skip_deletes(direction)?;
         if iter.is_valid() {
             iter.add_to_read_set(iter.key());
         }
         Ok(iter)
     }
 
-    fn skip_deletes(&mut self) -> Result<()> {
+    fn skip_deletes(&mut self, direction: Direction) -> Result<()> {
         while self.iter.is_valid() && self.iter.value().is_empty() {
-            self.iter.next()?;
+            match direction {
+                Direction::Forward => self.iter.next()?,
+                Direction::Backward => self.iter.prev()?,
+            }
         }
         Ok(())
     }
@@ -268,7 +394,56 @@ impl StorageIterator for TxnIterator {
 
     fn next(&mut self) -> Result<()> {
         self.iter.next()?;
-        self.skip_deletes()?;
+        self.skip_deletes(Direction::Forward)?;
+        if self.is_valid() {
+            self.add_to_read_set(self.key());
+        }
+        Ok(())
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        self.iter.prev()?;
+        self.skip_deletes(Direction::Backward)?;
+        if self.is_valid() {
+            self.add_to_read_set(self.key());
+        }
+        Ok(())
+    }
+
+    fn seek_to_first(&mut self) -> Result<()> {
+        self.iter.seek_to_first()?;
+        self.skip_deletes(Direction::Forward)?;
+        if self.is_valid() {
+            self.add_to_read_set(self.key());
+        }
+        Ok(())
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        self.iter.seek_to_last()?;
+        self.skip_deletes(Direction::Backward)?;
+        if self.is_valid() {
+            self.add_to_read_set(self.key());
+        }
+        Ok(())
+    }
+
+    /// Repositions the iterator at the first key >= `key` within the scan bounds, reading the same
+    /// snapshot.
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        self.iter.seek(key)?;
+        self.skip_deletes(Direction::Forward)?;
+        if self.is_valid() {
+            self.add_to_read_set(self.key());
+        }
+        Ok(())
+    }
+
+    /// Repositions the iterator at the last key <= `key` within the scan bounds, reading the same
+    /// snapshot.
+    fn seek_for_prev(&mut self, key: &[u8]) -> Result<()> {
+        self.iter.seek_for_prev(key)?;
+        self.skip_deletes(Direction::Backward)?;
         if self.is_valid() {
             self.add_to_read_set(self.key());
         }
@@ -0,0 +1,148 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::column_family::ColumnFamily;
+use crate::lsm_storage::LsmStorageInner;
+
+/// The directory orphan files are moved to by `OrphanFileAction::Quarantine`.
+pub const QUARANTINE_DIR: &str = "quarantine";
+
+/// What to do with the SSTs and WALs that are not referenced by the recovered state when the
+/// storage is opened. They are left behind by a crash between writing an SST and recording it in
+/// the manifest, or between recording a compaction or a flush and deleting its inputs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OrphanFileAction {
+    #[default]
+    Delete,
+    /// Moves the files to the `quarantine` directory of the storage.
+    Quarantine,
+    /// Only reports the files.
+    DryRun,
+}
+
+/// The orphan files found when the storage was opened.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OrphanFileReport {
+    /// SSTs not referenced by any column family.
+    pub sst_files: Vec<PathBuf>,
+    /// WALs of memtables which are not in the manifest.
+    pub wal_files: Vec<PathBuf>,
+    /// The action applied to the files.
+    pub action: OrphanFileAction,
+}
+
+impl OrphanFileReport {
+    pub fn is_empty(&self) -> bool {
+        self.sst_files.is_empty() && self.wal_files.is_empty()
+    }
+
+    /// The largest id of the files.
+    pub(crate) fn max_file_id(&self) -> Option<usize> {
+        let sst_ids = self.sst_files.iter().map(|x| parse_file_id(x, "sst"));
+        let wal_ids = self.wal_files.iter().map(|x| parse_file_id(x, "wal"));
+        sst_ids.chain(wal_ids).flatten().max()
+    }
+}
+
+/// Returns the id of an SST or WAL file, or `None` if the file has another name.
+fn parse_file_id(path: &Path, extension: &str) -> Option<usize> {
+    if path.extension()? != extension {
+        return None;
+    }
+    path.file_stem()?.to_str()?.parse().ok()
+}
+
+/// Returns the SSTs and the WALs in `path` whose ids are not live, sorted by path.
+pub(crate) fn find_orphan_files(
+    path: &Path,
+    live_ssts: &HashSet<usize>,
+    live_wals: &HashSet<usize>,
+) -> Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+    let mut sst_files = Vec::new();
+    let mut wal_files = Vec::new();
+    for entry in std::fs::read_dir(path)? {
+        let file_path = entry?.path();
+        if let Some(id) = parse_file_id(&file_path, "sst") {
+            if !live_ssts.contains(&id) {
+                sst_files.push(file_path);
+            }
+        } else if let Some(id) = parse_file_id(&file_path, "wal") {
+            if !live_wals.contains(&id) {
+                wal_files.push(file_path);
+            }
+        }
+    }
+    sst_files.sort();
+    wal_files.sort();
+    Ok((sst_files, wal_files))
+}
+
+impl LsmStorageInner {
+    /// Finds the SSTs and WALs in `path` which are not referenced by the recovered column
+    /// families or by the memtables of the manifest, and applies `action` to them. Must be called
+    /// before the WAL of the new memtable is created, and before any background thread writes
+    /// files.
+    pub(crate) fn collect_orphan_files(
+        path: &Path,
+        column_families: &BTreeMap<usize, ColumnFamily>,
+        memtables: &BTreeSet<usize>,
+        action: OrphanFileAction,
+    ) -> Result<OrphanFileReport> {
+        let mut live_ssts = HashSet::new();
+        let live_wals = memtables.iter().copied().collect::<HashSet<_>>();
+        for cf in column_families.values() {
+            let state = cf.state.read();
+            live_ssts.extend(state.l0_sstables.iter().copied());
+            live_ssts.extend(state.levels.iter().flat_map(|(_, files)| files).copied());
+        }
+
+        let (sst_files, wal_files) = find_orphan_files(path, &live_ssts, &live_wals)?;
+        let report = OrphanFileReport {
+            sst_files,
+            wal_files,
+            action,
+        };
+        if report.is_empty() {
+            return Ok(report);
+        }
+
+        let files = report.sst_files.iter().chain(&report.wal_files);
+        match action {
+            OrphanFileAction::Delete => {
+                for file_path in files {
+                    std::fs::remove_file(file_path)
+                        .with_context(|| format!("failed to delete {}", file_path.display()))?;
+                }
+                println!(
+                    "{} orphan files deleted",
+                    report.sst_files.len() + report.wal_files.len()
+                );
+            }
+            OrphanFileAction::Quarantine => {
+                let quarantine_path = path.join(QUARANTINE_DIR);
+                std::fs::create_dir_all(&quarantine_path)?;
+                for file_path in files {
+                    std::fs::rename(
+                        file_path,
+                        quarantine_path.join(file_path.file_name().unwrap()),
+                    )
+                    .with_context(|| format!("failed to quarantine {}", file_path.display()))?;
+                }
+                println!(
+                    "{} orphan files moved to {}",
+                    report.sst_files.len() + report.wal_files.len(),
+                    quarantine_path.display()
+                );
+            }
+            OrphanFileAction::DryRun => {
+                println!(
+                    "{} orphan files found",
+                    report.sst_files.len() + report.wal_files.len()
+                );
+            }
+        }
+        Ok(report)
+    }
+}
@@ -0,0 +1,72 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use crate::lsm_storage::{LsmStorageInner, MiniLsm};
+use crate::mvcc::txn::TxnIterator;
+
+/// Extracts the prefix of a key. The hashes of the prefixes are added to the SST bloom filters, so
+/// that prefix scans can skip the SSTs without any key of the prefix.
+pub trait PrefixExtractor: Send + Sync + Debug {
+    /// The name recorded in the SSTs. The prefix bloom filter of an SST is only used when it is
+    /// built with an extractor of the same name.
+    fn name(&self) -> &str;
+
+    /// Returns the prefix of the key, or `None` if the key does not have one.
+    fn prefix<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]>;
+}
+
+/// Uses the first `len` bytes of a key as its prefix. Shorter keys do not have a prefix.
+#[derive(Debug)]
+pub struct FixedPrefixExtractor {
+    len: usize,
+    name: String,
+}
+
+impl FixedPrefixExtractor {
+    pub fn new(len: usize) -> Self {
+        Self {
+            len,
+            name: format!("fixed:{}", len),
+        }
+    }
+}
+
+impl PrefixExtractor for FixedPrefixExtractor {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn prefix<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]> {
+        key.get(..self.len)
+    }
+}
+
+/// Returns the smallest key that is larger than all keys with the prefix, or `None` if there is no
+/// such key.
+pub(crate) fn prefix_upper_bound(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut upper = prefix.to_vec();
+    while let Some(last) = upper.pop() {
+        if last != u8::MAX {
+            upper.push(last + 1);
+            return Some(upper);
+        }
+    }
+    None
+}
+
+impl LsmStorageInner {
+    /// Create an iterator over the keys with the prefix. If the prefix is a whole prefix of the
+    /// configured extractor, the SSTs without any key of the prefix are skipped.
+    pub fn scan_prefix(self: &Arc<Self>, prefix: &[u8]) -> Result<TxnIterator> {
+        let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
+        txn.scan_prefix(prefix)
+    }
+}
+
+impl MiniLsm {
+    pub fn scan_prefix(&self, prefix: &[u8]) -> Result<TxnIterator> {
+        self.inner.scan_prefix(prefix)
+    }
+}
@@ -0,0 +1,237 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use bytes::{Buf, BufMut, Bytes};
+
+use crate::key::{self, KeyBytes};
+use crate::lsm_storage::{range_overlap, LsmStorageState};
+
+/// Deletes all versions older than `ts` of the keys in `[start, end)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RangeTombstone {
+    pub start: Bytes,
+    pub end: Bytes,
+    pub ts: u64,
+}
+
+impl RangeTombstone {
+    pub fn new(start: &[u8], end: &[u8], ts: u64) -> Self {
+        Self {
+            start: Bytes::copy_from_slice(start),
+            end: Bytes::copy_from_slice(end),
+            ts,
+        }
+    }
+
+    /// The smallest key in the SST key order that the tombstone may cover.
+    pub(crate) fn first_key(&self) -> KeyBytes {
+        KeyBytes::from_bytes_with_ts(self.start.clone(), key::TS_RANGE_BEGIN)
+    }
+
+    /// A key in the SST key order that is larger than all keys the tombstone covers, and not
+    /// larger than any version of `end`.
+    pub(crate) fn last_key(&self) -> KeyBytes {
+        KeyBytes::from_bytes_with_ts(self.end.clone(), key::TS_RANGE_BEGIN)
+    }
+
+    /// Clips the tombstone to the user key range `[lower, upper)`. Returns `None` if nothing is
+    /// left.
+    pub(crate) fn clip(&self, lower: Option<&[u8]>, upper: Option<&[u8]>) -> Option<Self> {
+        let start = match lower {
+            Some(lower) if lower > self.start.as_ref() => Bytes::copy_from_slice(lower),
+            _ => self.start.clone(),
+        };
+        let end = match upper {
+            Some(upper) if upper < self.end.as_ref() => Bytes::copy_from_slice(upper),
+            _ => self.end.clone(),
+        };
+        (start < end).then_some(Self {
+            start,
+            end,
+            ts: self.ts,
+        })
+    }
+
+    /// Encodes the range tombstone meta block of an SST.
+    pub(crate) fn encode_block(tombstones: &[RangeTombstone], buf: &mut Vec<u8>) {
+        let original_len = buf.len();
+        buf.put_u32(tombstones.len() as u32);
+        for tombstone in tombstones {
+            buf.put_u16(tombstone.start.len() as u16);
+            buf.put_slice(&tombstone.start);
+            buf.put_u16(tombstone.end.len() as u16);
+            buf.put_slice(&tombstone.end);
+            buf.put_u64(tombstone.ts);
+        }
+        buf.put_u32(crc32fast::hash(&buf[original_len..]));
+    }
+
+    /// Decodes the range tombstone meta block of an SST.
+    pub(crate) fn decode_block(buf: &[u8]) -> Result<Vec<RangeTombstone>> {
+        if buf.len() < 8 {
+            bail!("range tombstone block too short");
+        }
+        let (content, mut checksum) = buf.split_at(buf.len() - 4);
+        if checksum.get_u32() != crc32fast::hash(content) {
+            bail!("range tombstone block checksum mismatched");
+        }
+        let mut buf = content;
+        let num = buf.get_u32() as usize;
+        let mut tombstones = Vec::with_capacity(num);
+        for _ in 0..num {
+            let start_len = buf.get_u16() as usize;
+            let start = buf.copy_to_bytes(start_len);
+            let end_len = buf.get_u16() as usize;
+            let end = buf.copy_to_bytes(end_len);
+            let ts = buf.get_u64();
+            tombstones.push(RangeTombstone { start, end, ts });
+        }
+        Ok(tombstones)
+    }
+}
+
+/// A part of the key space covered by the same range tombstones.
+#[derive(Debug)]
+struct Fragment {
+    start: Bytes,
+    end: Bytes,
+    /// The timestamps of the tombstones covering `[start, end)`, newest first.
+    timestamps: Vec<u64>,
+}
+
+/// The range tombstones of an SST or a memtable, split into non-overlapping fragments so that a
+/// key is checked with a binary search.
+#[derive(Debug, Default)]
+pub struct RangeTombstoneSet {
+    /// The tombstones, sorted by start key.
+    tombstones: Vec<RangeTombstone>,
+    /// The fragments, sorted by start key.
+    fragments: Vec<Fragment>,
+}
+
+impl RangeTombstoneSet {
+    pub fn new(mut tombstones: Vec<RangeTombstone>) -> Self {
+        tombstones.sort_by(|x, y| x.start.cmp(&y.start));
+        let mut boundaries = tombstones
+            .iter()
+            .flat_map(|tombstone| [tombstone.start.clone(), tombstone.end.clone()])
+            .collect::<Vec<_>>();
+        boundaries.sort();
+        boundaries.dedup();
+
+        let mut fragments = Vec::new();
+        let mut active = Vec::<&RangeTombstone>::new();
+        let mut next = 0;
+        for pair in boundaries.windows(2) {
+            let (start, end) = (&pair[0], &pair[1]);
+            while next < tombstones.len() && tombstones[next].start <= start {
+                active.push(&tombstones[next]);
+                next += 1;
+            }
+            active.retain(|tombstone| tombstone.end > start);
+            if active.is_empty() {
+                continue;
+            }
+            let mut timestamps = active
+                .iter()
+                .map(|tombstone| tombstone.ts)
+                .collect::<Vec<_>>();
+            timestamps.sort_by(|x, y| y.cmp(x));
+            timestamps.dedup();
+            fragments.push(Fragment {
+                start: start.clone(),
+                end: end.clone(),
+                timestamps,
+            });
+        }
+        Self {
+            tombstones,
+            fragments,
+        }
+    }
+
+    pub fn tombstones(&self) -> &[RangeTombstone] {
+        &self.tombstones
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tombstones.is_empty()
+    }
+
+    /// Returns true if the version of `key` at `ts` is deleted by a tombstone newer than it and
+    /// not newer than `read_ts`.
+    fn covers(&self, key: &[u8], ts: u64, read_ts: u64) -> bool {
+        let idx = self
+            .fragments
+            .partition_point(|fragment| fragment.start.as_ref() <= key);
+        let Some(fragment) = idx.checked_sub(1).map(|idx| &self.fragments[idx]) else {
+            return false;
+        };
+        if key >= fragment.end.as_ref() {
+            return false;
+        }
+        let visible = fragment.timestamps.partition_point(|x| *x > read_ts);
+        fragment.timestamps.get(visible).is_some_and(|x| *x > ts)
+    }
+}
+
+/// The range tombstones visible at a timestamp, which tell whether a version of a key is deleted.
+#[derive(Debug, Clone, Default)]
+pub struct RangeTombstones {
+    sets: Vec<Arc<RangeTombstoneSet>>,
+    read_ts: u64,
+}
+
+impl RangeTombstones {
+    /// Collects the tombstones written at or before `read_ts`.
+    pub fn new(tombstones: impl IntoIterator<Item = RangeTombstone>, read_ts: u64) -> Self {
+        let set = RangeTombstoneSet::new(tombstones.into_iter().collect());
+        Self {
+            sets: vec![Arc::new(set)],
+            read_ts,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sets.iter().all(|set| set.is_empty())
+    }
+
+    /// Returns true if the version of `key` at `ts` is deleted by a newer tombstone.
+    pub fn covers(&self, key: &[u8], ts: u64) -> bool {
+        self.sets
+            .iter()
+            .any(|set| set.covers(key, ts, self.read_ts))
+    }
+}
+
+impl LsmStorageState {
+    /// Combines the tombstone sets of the SSTs selected by a read with the range tombstones of
+    /// the memtables visible at `read_ts` which overlap the user key range. The tombstone sets of
+    /// the SSTs are shared, only those of the memtables are collected.
+    pub(crate) fn range_tombstones(
+        &self,
+        mut sets: Vec<Arc<RangeTombstoneSet>>,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        read_ts: u64,
+    ) -> RangeTombstones {
+        let memtable_tombstones = std::iter::once(&self.memtable)
+            .chain(self.imm_memtables.iter())
+            .flat_map(|memtable| memtable.range_tombstones())
+            .filter(|tombstone| {
+                tombstone.ts <= read_ts
+                    && range_overlap(
+                        lower,
+                        upper,
+                        tombstone.first_key().as_key_slice(),
+                        tombstone.last_key().as_key_slice(),
+                    )
+            })
+            .collect::<Vec<_>>();
+        if !memtable_tombstones.is_empty() {
+            sets.push(Arc::new(RangeTombstoneSet::new(memtable_tombstones)));
+        }
+        RangeTombstones { sets, read_ts }
+    }
+}
@@ -0,0 +1,183 @@
+use std::time::{Duration, Instant};
+
+use parking_lot::{Condvar, Mutex};
+
+/// The priority of an I/O request. Pending high-priority requests are granted before any
+/// low-priority request, so that flushes are not starved by compactions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoPriority {
+    /// Compaction writes.
+    Low,
+    /// Flush writes.
+    High,
+}
+
+impl IoPriority {
+    fn index(self) -> usize {
+        match self {
+            IoPriority::Low => 0,
+            IoPriority::High => 1,
+        }
+    }
+}
+
+/// Tokens are refilled at this interval.
+const REFILL_PERIOD: Duration = Duration::from_millis(100);
+/// In auto-tune mode, the rate is adjusted every this many refill periods.
+const TUNE_PERIODS: u64 = 10;
+/// In auto-tune mode, the rate never drops below `max_bytes_per_sec / AUTO_TUNE_MIN_RATE_DIVISOR`.
+const AUTO_TUNE_MIN_RATE_DIVISOR: u64 = 20;
+
+#[derive(Debug)]
+struct RateLimiterState {
+    bytes_per_sec: u64,
+    available_bytes: u64,
+    next_refill: Instant,
+    num_high_pri_waiting: usize,
+    /// For auto-tune: refill periods elapsed and periods in which a request had to wait, since the
+    /// last time the rate was tuned.
+    periods_since_tune: u64,
+    drained_periods: u64,
+    drained_in_current_period: bool,
+    total_bytes_through: [u64; 2],
+    total_requests: [u64; 2],
+}
+
+/// A token bucket that limits the write throughput of flushes and compactions. A single limiter
+/// can be shared by several storage engines on the same disk.
+///
+/// In auto-tune mode, the configured rate is the upper bound. The limiter lowers the rate when
+/// background writes do not use it up, and raises it back when writers have to wait most of the
+/// time, so that it only throttles bursts.
+#[derive(Debug)]
+pub struct RateLimiter {
+    max_bytes_per_sec: u64,
+    auto_tune: bool,
+    state: Mutex<RateLimiterState>,
+    cv: Condvar,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_sec: u64, auto_tune: bool) -> Self {
+        assert!(bytes_per_sec > 0, "rate limit must be positive");
+        Self {
+            max_bytes_per_sec: bytes_per_sec,
+            auto_tune,
+            state: Mutex::new(RateLimiterState {
+                bytes_per_sec,
+                available_bytes: 0,
+                next_refill: Instant::now(),
+                num_high_pri_waiting: 0,
+                periods_since_tune: 0,
+                drained_periods: 0,
+                drained_in_current_period: false,
+                total_bytes_through: [0; 2],
+                total_requests: [0; 2],
+            }),
+            cv: Condvar::new(),
+        }
+    }
+
+    /// The current rate in bytes per second. In auto-tune mode, it may be lower than the
+    /// configured rate.
+    pub fn bytes_per_sec(&self) -> u64 {
+        self.state.lock().bytes_per_sec
+    }
+
+    /// Changes the rate. In auto-tune mode, this changes the current rate, and the configured rate
+    /// remains the upper bound.
+    pub fn set_bytes_per_sec(&self, bytes_per_sec: u64) {
+        assert!(bytes_per_sec > 0, "rate limit must be positive");
+        self.state.lock().bytes_per_sec = if self.auto_tune {
+            bytes_per_sec.min(self.max_bytes_per_sec)
+        } else {
+            bytes_per_sec
+        };
+    }
+
+    /// The maximum number of bytes granted at once. Larger requests should be split into chunks
+    /// of this size.
+    pub fn single_burst_bytes(&self) -> usize {
+        Self::refill_bytes(self.bytes_per_sec()) as usize
+    }
+
+    pub fn total_bytes_through(&self, priority: IoPriority) -> u64 {
+        self.state.lock().total_bytes_through[priority.index()]
+    }
+
+    pub fn total_requests(&self, priority: IoPriority) -> u64 {
+        self.state.lock().total_requests[priority.index()]
+    }
+
+    fn refill_bytes(bytes_per_sec: u64) -> u64 {
+        (bytes_per_sec * REFILL_PERIOD.as_millis() as u64 / 1000).max(1)
+    }
+
+    /// Blocks until `bytes` bytes can be written. Requests larger than `single_burst_bytes` are
+    /// granted in several refill periods.
+    pub fn request(&self, bytes: usize, priority: IoPriority) {
+        let mut state = self.state.lock();
+        state.total_requests[priority.index()] += 1;
+        state.total_bytes_through[priority.index()] += bytes as u64;
+        let mut remaining = bytes as u64;
+        if priority == IoPriority::High {
+            state.num_high_pri_waiting += 1;
+        }
+        while remaining > 0 {
+            self.refill(&mut state);
+            let granted_to_me = priority == IoPriority::High || state.num_high_pri_waiting == 0;
+            if granted_to_me && state.available_bytes > 0 {
+                let granted = remaining.min(state.available_bytes);
+                state.available_bytes -= granted;
+                remaining -= granted;
+                continue;
+            }
+            state.drained_in_current_period = true;
+            let timeout = state
+                .next_refill
+                .saturating_duration_since(Instant::now())
+                .max(Duration::from_millis(1));
+            self.cv.wait_for(&mut state, timeout);
+        }
+        if priority == IoPriority::High {
+            state.num_high_pri_waiting -= 1;
+            self.cv.notify_all();
+        }
+    }
+
+    fn refill(&self, state: &mut RateLimiterState) {
+        let now = Instant::now();
+        if now < state.next_refill {
+            return;
+        }
+        let elapsed_periods =
+            (now - state.next_refill).as_millis() as u64 / REFILL_PERIOD.as_millis() as u64 + 1;
+        state.next_refill += REFILL_PERIOD * elapsed_periods as u32;
+        // Unused tokens are not accumulated over periods, so that the limiter never grants a burst
+        // larger than one period.
+        state.available_bytes = Self::refill_bytes(state.bytes_per_sec);
+        if state.drained_in_current_period {
+            state.drained_periods += 1;
+            state.drained_in_current_period = false;
+        }
+        state.periods_since_tune += elapsed_periods;
+        if self.auto_tune && state.periods_since_tune >= TUNE_PERIODS {
+            self.tune(state);
+        }
+        self.cv.notify_all();
+    }
+
+    fn tune(&self, state: &mut RateLimiterState) {
+        let drained_percent = state.drained_periods * 100 / state.periods_since_tune;
+        let min_bytes_per_sec = (self.max_bytes_per_sec / AUTO_TUNE_MIN_RATE_DIVISOR).max(1);
+        if drained_percent > 90 {
+            state.bytes_per_sec =
+                (state.bytes_per_sec + state.bytes_per_sec / 20 + 1).min(self.max_bytes_per_sec);
+        } else if drained_percent < 50 {
+            state.bytes_per_sec =
+                (state.bytes_per_sec - state.bytes_per_sec / 20).max(min_bytes_per_sec);
+        }
+        state.periods_since_tune = 0;
+        state.drained_periods = 0;
+    }
+}
@@ -1,21 +1,29 @@
 pub(crate) mod bloom;
 mod builder;
+mod compression;
 mod iterator;
+mod partition;
 
 use std::fs::File;
+use std::io::Write;
 use std::path::Path;
 use std::sync::Arc;
 
-use anyhow::{anyhow, bail, Result};
+use anyhow::{bail, Result};
 pub use builder::SsTableBuilder;
 use bytes::{Buf, BufMut};
+pub use compression::CompressionType;
 pub use iterator::SsTableIterator;
 
 use crate::block::Block;
+use crate::block_cache::{BlockCache, CachedBlock, PinnedBlocks};
 use crate::key::{KeyBytes, KeySlice};
-use crate::lsm_storage::BlockCache;
+use crate::prefix_extractor::{prefix_upper_bound, PrefixExtractor};
+use crate::range_tombstone::{RangeTombstone, RangeTombstoneSet};
+use crate::rate_limiter::{IoPriority, RateLimiter};
 
 use self::bloom::Bloom;
+use self::partition::PartitionedIndex;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct BlockMeta {
@@ -113,8 +121,26 @@ impl FileObject {
 
     /// Create a new file object (day 2) and write the file to the disk (day 4).
     pub fn create(path: &Path, data: Vec<u8>) -> Result<Self> {
-        std::fs::write(path, &data)?;
-        File::open(path)?.sync_all()?;
+        Self::create_with_rate_limiter(path, data, None)
+    }
+
+    /// Create a new file object, writing the file in chunks paced by the rate limiter.
+    pub fn create_with_rate_limiter(
+        path: &Path,
+        data: Vec<u8>,
+        rate_limiter: Option<&(Arc<RateLimiter>, IoPriority)>,
+    ) -> Result<Self> {
+        if let Some((rate_limiter, priority)) = rate_limiter {
+            let mut file = File::create(path)?;
+            for chunk in data.chunks(rate_limiter.single_burst_bytes()) {
+                rate_limiter.request(chunk.len(), *priority);
+                file.write_all(chunk)?;
+            }
+            file.sync_all()?;
+        } else {
+            std::fs::write(path, &data)?;
+            File::open(path)?.sync_all()?;
+        }
         Ok(FileObject(
             Some(File::options().read(true).write(false).open(path)?),
             data.len() as u64,
@@ -128,20 +154,71 @@ impl FileObject {
     }
 }
 
+/// The properties of an SST that decide how its index and filter are read.
+#[derive(Default)]
+struct TableProperties {
+    /// Whether the index and the filter are partitioned.
+    partitioned_index: bool,
+    /// The name of the prefix extractor whose prefixes are in the filter.
+    prefix_extractor: Option<String>,
+}
+
+impl TableProperties {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        let offset = buf.len();
+        buf.put_u8(self.partitioned_index as u8);
+        // An empty name means that the filter only has the hashes of whole keys.
+        let name = self.prefix_extractor.as_deref().unwrap_or_default();
+        buf.put_u16(name.len() as u16);
+        buf.put_slice(name.as_bytes());
+        let checksum = crc32fast::hash(&buf[offset..]);
+        buf.put_u32(checksum);
+    }
+
+    fn decode(buf: &[u8]) -> Result<Self> {
+        if buf.len() < 7 {
+            bail!("table properties block too short");
+        }
+        let (content, mut checksum) = buf.split_at(buf.len() - 4);
+        if checksum.get_u32() != crc32fast::hash(content) {
+            bail!("table properties block checksum mismatched");
+        }
+        let (mut header, name) = content.split_at(3);
+        let partitioned_index = header.get_u8() != 0;
+        if header.get_u16() as usize != name.len() {
+            bail!("invalid table properties block");
+        }
+        let name = String::from_utf8(name.to_vec())?;
+        Ok(Self {
+            partitioned_index,
+            prefix_extractor: (!name.is_empty()).then_some(name),
+        })
+    }
+}
+
 /// An SSTable.
 pub struct SsTable {
     /// The actual storage unit of SsTable, the format is as above.
     pub(crate) file: FileObject,
-    /// The meta blocks that hold info for data blocks.
+    /// The meta blocks that hold info for data blocks. Empty if the index is partitioned.
     pub(crate) block_meta: Vec<BlockMeta>,
-    /// The offset that indicates the start point of meta blocks in `file`.
+    /// The offset that indicates the start point of meta blocks in `file`, which is also the end of
+    /// the data blocks.
     pub(crate) block_meta_offset: usize,
+    /// The top-level index if the index and the filter are partitioned.
+    pub(crate) partitioned_index: Option<PartitionedIndex>,
     id: usize,
     block_cache: Option<Arc<BlockCache>>,
+    /// The index and filter partitions pinned by the SST, if the block cache pins them.
+    pinned_blocks: Option<PinnedBlocks>,
     first_key: KeyBytes,
     last_key: KeyBytes,
+    /// The filter of the whole SST. `None` if the filter is partitioned.
     pub(crate) bloom: Option<Bloom>,
     max_ts: u64,
+    range_tombstones: Arc<RangeTombstoneSet>,
+    /// The name of the prefix extractor whose prefixes are in the bloom filter.
+    prefix_extractor: Option<String>,
 }
 impl SsTable {
     #[cfg(test)]
@@ -152,24 +229,66 @@ impl SsTable {
     /// Open SSTable from a file.
     pub fn open(id: usize, block_cache: Option<Arc<BlockCache>>, file: FileObject) -> Result<Self> {
         let len = file.size();
+        let raw_properties_offset = file.read(len - 4, 4)?;
+        let properties_offset = (&raw_properties_offset[..]).get_u32() as u64;
+        let raw_properties = file.read(properties_offset, len - 4 - properties_offset)?;
+        let properties = TableProperties::decode(&raw_properties)?;
+        let len = properties_offset;
+        let raw_range_tombstone_offset = file.read(len - 4, 4)?;
+        let range_tombstone_offset = (&raw_range_tombstone_offset[..]).get_u32() as u64;
+        let raw_range_tombstones =
+            file.read(range_tombstone_offset, len - 4 - range_tombstone_offset)?;
+        let range_tombstones = RangeTombstone::decode_block(&raw_range_tombstones)?;
+        let len = range_tombstone_offset;
         let raw_bloom_offset = file.read(len - 4, 4)?;
         let bloom_offset = (&raw_bloom_offset[..]).get_u32() as u64;
+        let raw_meta_offset = file.read(bloom_offset - 4, 4)?;
+        let meta_offset = (&raw_meta_offset[..]).get_u32() as u64;
+        let raw_meta = file.read(meta_offset, bloom_offset - 4 - meta_offset)?;
+        if properties.partitioned_index {
+            // Only the top-level index is read, the partitions are read when they are needed.
+            let (index, max_ts) = PartitionedIndex::decode(&raw_meta)?;
+            let (first_key, last_key) = Self::key_range(
+                index.partitions.iter().map(|x| (&x.first_key, &x.last_key)),
+                &range_tombstones,
+            );
+            return Ok(Self {
+                file,
+                first_key,
+                last_key,
+                block_meta: Vec::new(),
+                block_meta_offset: index.partitions.last().map_or(0, |x| x.data_end),
+                partitioned_index: Some(index),
+                id,
+                pinned_blocks: block_cache.as_ref().and_then(|x| x.pinned_blocks()),
+                block_cache,
+                bloom: None,
+                max_ts,
+                range_tombstones: Arc::new(RangeTombstoneSet::new(range_tombstones)),
+                prefix_extractor: properties.prefix_extractor,
+            });
+        }
         let raw_bloom = file.read(bloom_offset, len - 4 - bloom_offset)?;
         let bloom_filter = Bloom::decode(&raw_bloom)?;
-        let raw_meta_offset = file.read(bloom_offset - 4, 4)?;
-        let block_meta_offset = (&raw_meta_offset[..]).get_u32() as u64;
-        let raw_meta = file.read(block_meta_offset, bloom_offset - 4 - block_meta_offset)?;
         let (block_meta, max_ts) = BlockMeta::decode_block_meta(&raw_meta[..])?;
+        let (first_key, last_key) = Self::key_range(
+            block_meta.iter().map(|x| (&x.first_key, &x.last_key)),
+            &range_tombstones,
+        );
         Ok(Self {
             file,
-            first_key: block_meta.first().unwrap().first_key.clone(),
-            last_key: block_meta.last().unwrap().last_key.clone(),
+            first_key,
+            last_key,
             block_meta,
-            block_meta_offset: block_meta_offset as usize,
+            block_meta_offset: meta_offset as usize,
+            partitioned_index: None,
             id,
+            pinned_blocks: block_cache.as_ref().and_then(|x| x.pinned_blocks()),
             block_cache,
             bloom: Some(bloom_filter),
             max_ts,
+            range_tombstones: Arc::new(RangeTombstoneSet::new(range_tombstones)),
+            prefix_extractor: properties.prefix_extractor,
         })
     }
 
@@ -184,56 +303,158 @@ impl SsTable {
             file: FileObject(None, file_size),
             block_meta: vec![],
             block_meta_offset: 0,
+            partitioned_index: None,
             id,
             block_cache: None,
+            pinned_blocks: None,
             first_key,
             last_key,
             bloom: None,
             max_ts: 0,
+            range_tombstones: Arc::default(),
+            prefix_extractor: None,
         }
     }
 
-    /// Read a block from the disk.
-    pub fn read_block(&self, block_idx: usize) -> Result<Arc<Block>> {
+    /// The key range of an SST covers both the data blocks and the range tombstones.
+    fn key_range<'a>(
+        blocks: impl Iterator<Item = (&'a KeyBytes, &'a KeyBytes)> + Clone,
+        range_tombstones: &[RangeTombstone],
+    ) -> (KeyBytes, KeyBytes) {
+        let first_key = blocks
+            .clone()
+            .map(|(first_key, _)| first_key.clone())
+            .chain(range_tombstones.iter().map(RangeTombstone::first_key))
+            .min()
+            .expect("SST without data blocks or range tombstones");
+        let last_key = blocks
+            .map(|(_, last_key)| last_key.clone())
+            .chain(range_tombstones.iter().map(RangeTombstone::last_key))
+            .max()
+            .unwrap();
+        (first_key, last_key)
+    }
+
+    /// Returns the start and end offsets of a data block.
+    fn block_location(&self, block_idx: usize) -> Result<(usize, usize)> {
+        if let Some(index) = &self.partitioned_index {
+            let partition_idx = index.partition_of_block(block_idx);
+            let partition = &index.partitions[partition_idx];
+            let block_meta = self.read_index_partition(partition_idx)?;
+            let idx = block_idx - partition.first_block_idx;
+            let offset_end = block_meta
+                .get(idx + 1)
+                .map_or(partition.data_end, |x| x.offset);
+            return Ok((block_meta[idx].offset, offset_end));
+        }
         let offset = self.block_meta[block_idx].offset;
         let offset_end = self
             .block_meta
             .get(block_idx + 1)
             .map_or(self.block_meta_offset, |x| x.offset);
-        let block_len = offset_end - offset - 4;
+        Ok((offset, offset_end))
+    }
+
+    /// Read a block from the disk.
+    pub fn read_block(&self, block_idx: usize) -> Result<Arc<Block>> {
+        let (offset, offset_end) = self.block_location(block_idx)?;
+        self.read_block_at(offset, offset_end)
+    }
+
+    fn read_block_at(&self, offset: usize, offset_end: usize) -> Result<Arc<Block>> {
+        let block_len = offset_end - offset - 5;
         let block_data_with_chksum: Vec<u8> = self
             .file
             .read(offset as u64, (offset_end - offset) as u64)?;
         let block_data = &block_data_with_chksum[..block_len];
-        let checksum = (&block_data_with_chksum[block_len..]).get_u32();
-        if checksum != crc32fast::hash(block_data) {
+        let checksum = (&block_data_with_chksum[block_len + 1..]).get_u32();
+        if checksum != crc32fast::hash(&block_data_with_chksum[..block_len + 1]) {
             bail!("block checksum mismatched");
         }
-        Ok(Arc::new(Block::decode(block_data)))
+        let compression = CompressionType::from_u8(block_data_with_chksum[block_len])?;
+        let block_data = compression.decompress(block_data)?;
+        Ok(Arc::new(Block::decode(&block_data)))
     }
 
-    /// Read a block from disk, with block cache.
-    pub fn read_block_cached(&self, block_idx: usize) -> Result<Arc<Block>> {
+    /// Reads a data block, an index partition or a filter partition at `offset` through the block
+    /// cache. Index and filter partitions are read with high priority. Without `fill_cache`, a block
+    /// not in the cache is not added to it.
+    fn read_cached(
+        &self,
+        offset: usize,
+        high_priority: bool,
+        fill_cache: bool,
+        read: impl FnOnce() -> Result<CachedBlock>,
+    ) -> Result<CachedBlock> {
         if let Some(ref block_cache) = self.block_cache {
-            let blk = block_cache
-                .try_get_with((self.id, block_idx), || self.read_block(block_idx))
-                .map_err(|e| anyhow!("{}", e))?;
-            Ok(blk)
+            block_cache.get_or_read(
+                self.id,
+                offset,
+                high_priority,
+                fill_cache,
+                self.pinned_blocks.as_ref(),
+                read,
+            )
         } else {
-            self.read_block(block_idx)
+            read()
+        }
+    }
+
+    /// Read a block from disk, with block cache.
+    pub fn read_block_cached(&self, block_idx: usize) -> Result<Arc<Block>> {
+        self.read_data_block(block_idx, true)
+    }
+
+    /// Read a block through the block cache, adding it to the cache only with `fill_cache`.
+    pub(crate) fn read_data_block(&self, block_idx: usize, fill_cache: bool) -> Result<Arc<Block>> {
+        let (offset, offset_end) = self.block_location(block_idx)?;
+        let block = self.read_cached(offset, false, fill_cache, || {
+            Ok(CachedBlock::Data(self.read_block_at(offset, offset_end)?))
+        })?;
+        match block {
+            CachedBlock::Data(block) => Ok(block),
+            _ => bail!("cached block is not a data block"),
         }
     }
 
+    /// Reads the meta of all data blocks, loading all index partitions if the index is
+    /// partitioned.
+    pub(crate) fn read_block_meta(&self) -> Result<Vec<BlockMeta>> {
+        let Some(index) = &self.partitioned_index else {
+            return Ok(self.block_meta.clone());
+        };
+        let mut block_meta = Vec::with_capacity(index.num_of_blocks);
+        for partition_idx in 0..index.partitions.len() {
+            block_meta.extend(self.read_index_partition(partition_idx)?.iter().cloned());
+        }
+        Ok(block_meta)
+    }
+
     /// Find the block that may contain `key`.
-    pub fn find_block_idx(&self, key: KeySlice) -> usize {
-        self.block_meta
+    pub fn find_block_idx(&self, key: KeySlice) -> Result<usize> {
+        let Some(index) = &self.partitioned_index else {
+            return Ok(self
+                .block_meta
+                .partition_point(|meta| meta.first_key.as_key_slice() <= key)
+                .saturating_sub(1));
+        };
+        let partition_idx = index
+            .partitions
+            .partition_point(|x| x.first_key.as_key_slice() <= key)
+            .saturating_sub(1);
+        let block_meta = self.read_index_partition(partition_idx)?;
+        let idx = block_meta
             .partition_point(|meta| meta.first_key.as_key_slice() <= key)
-            .saturating_sub(1)
+            .saturating_sub(1);
+        Ok(index.partitions[partition_idx].first_block_idx + idx)
     }
 
     /// Get number of data blocks.
     pub fn num_of_blocks(&self) -> usize {
-        self.block_meta.len()
+        match &self.partitioned_index {
+            Some(index) => index.num_of_blocks,
+            None => self.block_meta.len(),
+        }
     }
 
     pub fn first_key(&self) -> &KeyBytes {
@@ -255,4 +476,52 @@ impl SsTable {
     pub fn max_ts(&self) -> u64 {
         self.max_ts
     }
+
+    /// The range tombstones of the SST, sorted by start key.
+    pub fn range_tombstones(&self) -> &[RangeTombstone] {
+        self.range_tombstones.tombstones()
+    }
+
+    /// The range tombstones of the SST, split into fragments.
+    pub(crate) fn range_tombstone_set(&self) -> &Arc<RangeTombstoneSet> {
+        &self.range_tombstones
+    }
+
+    /// Returns false if the SST has no version of the key according to its filter.
+    pub fn may_contain_key(&self, key: &[u8]) -> Result<bool> {
+        let hash = farmhash::fingerprint32(key);
+        if self.partitioned_index.is_some() {
+            // The versions of the key may span multiple partitions.
+            let mut upper = key.to_vec();
+            upper.push(0);
+            return self.partitions_may_contain(key, Some(&upper), hash);
+        }
+        Ok(self
+            .bloom
+            .as_ref()
+            .is_none_or(|bloom| bloom.may_contain(hash)))
+    }
+
+    /// Returns false if the SST has no key with the prefix. The prefix filter is only checked when
+    /// the SST is built with the same extractor, and `prefix` is a whole prefix of it.
+    pub fn may_contain_prefix(
+        &self,
+        prefix_extractor: &dyn PrefixExtractor,
+        prefix: &[u8],
+    ) -> Result<bool> {
+        if self.prefix_extractor.as_deref() != Some(prefix_extractor.name())
+            || prefix_extractor.prefix(prefix) != Some(prefix)
+        {
+            return Ok(true);
+        }
+        let hash = farmhash::fingerprint32(prefix);
+        if self.partitioned_index.is_some() {
+            let upper = prefix_upper_bound(prefix);
+            return self.partitions_may_contain(prefix, upper.as_deref(), hash);
+        }
+        Ok(self
+            .bloom
+            .as_ref()
+            .is_none_or(|bloom| bloom.may_contain(hash)))
+    }
 }
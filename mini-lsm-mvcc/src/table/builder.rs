@@ -5,10 +5,15 @@ use anyhow::Result;
 use bytes::BufMut;
 
 use super::bloom::Bloom;
-use super::{BlockMeta, FileObject, SsTable};
+use super::partition::PartitionedIndex;
+use super::{BlockMeta, CompressionType, FileObject, SsTable, TableProperties};
 use crate::block::BlockBuilder;
+use crate::block_cache::BlockCache;
+use crate::iterators::RecordKind;
 use crate::key::{KeySlice, KeyVec};
-use crate::lsm_storage::BlockCache;
+use crate::prefix_extractor::PrefixExtractor;
+use crate::range_tombstone::{RangeTombstone, RangeTombstoneSet};
+use crate::rate_limiter::{IoPriority, RateLimiter};
 
 /// Builds an SSTable from key-value pairs.
 pub struct SsTableBuilder {
@@ -20,11 +25,24 @@ pub struct SsTableBuilder {
     block_size: usize,
     key_hashes: Vec<u32>,
     max_ts: u64,
+    range_tombstones: Vec<RangeTombstone>,
+    compression: CompressionType,
+    rate_limiter: Option<(Arc<RateLimiter>, IoPriority)>,
+    prefix_extractor: Option<Arc<dyn PrefixExtractor>>,
+    last_prefix_hash: Option<u32>,
+    /// The end of the key hashes of each data block.
+    block_hash_ends: Vec<usize>,
+    index_partition_size: Option<usize>,
 }
 
 impl SsTableBuilder {
     /// Create a builder based on target block size.
     pub fn new(block_size: usize) -> Self {
+        Self::new_with_compression(block_size, CompressionType::None)
+    }
+
+    /// Create a builder based on target block size, compressing each data block with the given codec.
+    pub fn new_with_compression(block_size: usize, compression: CompressionType) -> Self {
         Self {
             data: Vec::new(),
             meta: Vec::new(),
@@ -34,11 +52,40 @@ impl SsTableBuilder {
             builder: BlockBuilder::new(block_size),
             key_hashes: Vec::new(),
             max_ts: 0,
+            range_tombstones: Vec::new(),
+            compression,
+            rate_limiter: None,
+            prefix_extractor: None,
+            last_prefix_hash: None,
+            block_hash_ends: Vec::new(),
+            index_partition_size: None,
         }
     }
 
+    /// Paces the write of the SST file with the rate limiter.
+    pub fn set_rate_limiter(&mut self, rate_limiter: Arc<RateLimiter>, priority: IoPriority) {
+        self.rate_limiter = Some((rate_limiter, priority));
+    }
+
+    /// Adds the hashes of the key prefixes to the bloom filter, so that prefix scans can skip the
+    /// SSTable.
+    pub fn set_prefix_extractor(&mut self, prefix_extractor: Arc<dyn PrefixExtractor>) {
+        self.prefix_extractor = Some(prefix_extractor);
+    }
+
+    /// Splits the index and the filter into partitions of about `partition_size` bytes of index,
+    /// so that only the top-level index is kept in memory when the SSTable is opened.
+    pub fn set_index_partition_size(&mut self, partition_size: usize) {
+        self.index_partition_size = Some(partition_size);
+    }
+
     /// Adds a key-value pair to SSTable
     pub fn add(&mut self, key: KeySlice, value: &[u8]) {
+        self.add_with_kind(key, value, RecordKind::Value)
+    }
+
+    /// Adds a record of the given kind to SSTable
+    pub fn add_with_kind(&mut self, key: KeySlice, value: &[u8], kind: RecordKind) {
         if self.first_key.is_empty() {
             self.first_key.set_from_slice(key);
         }
@@ -46,10 +93,10 @@ impl SsTableBuilder {
         if key.ts() > self.max_ts {
             self.max_ts = key.ts();
         }
-        self.key_hashes.push(farmhash::fingerprint32(key.key_ref()));
 
-        if self.builder.add(key, value) {
+        if self.builder.add_with_kind(key, value, kind) {
             self.last_key.set_from_slice(key);
+            self.add_key_hashes(key.key_ref());
             return;
         }
 
@@ -57,9 +104,34 @@ impl SsTableBuilder {
         self.finish_block();
 
         // add the key-value pair to the next block
-        assert!(self.builder.add(key, value));
+        assert!(self.builder.add_with_kind(key, value, kind));
         self.first_key.set_from_slice(key);
         self.last_key.set_from_slice(key);
+        self.add_key_hashes(key.key_ref());
+    }
+
+    /// Adds the hashes of the key and its prefix to the filter of the current block.
+    fn add_key_hashes(&mut self, key: &[u8]) {
+        self.key_hashes.push(farmhash::fingerprint32(key));
+        if let Some(prefix) = self.prefix_extractor.as_ref().and_then(|x| x.prefix(key)) {
+            // The keys with the same prefix are added one after another.
+            let hash = farmhash::fingerprint32(prefix);
+            if self.last_prefix_hash != Some(hash) {
+                self.key_hashes.push(hash);
+                self.last_prefix_hash = Some(hash);
+            }
+        }
+    }
+
+    /// Adds a range tombstone to the range tombstone block of the SSTable.
+    pub fn add_range_tombstone(&mut self, tombstone: RangeTombstone) {
+        self.max_ts = self.max_ts.max(tombstone.ts);
+        self.range_tombstones.push(tombstone);
+    }
+
+    /// Returns true if neither a key-value pair nor a range tombstone is added.
+    pub fn is_empty(&self) -> bool {
+        self.meta.is_empty() && self.builder.is_empty() && self.range_tombstones.is_empty()
     }
 
     /// Get the estimated size of the SSTable.
@@ -70,13 +142,18 @@ impl SsTableBuilder {
     fn finish_block(&mut self) {
         let builder = std::mem::replace(&mut self.builder, BlockBuilder::new(self.block_size));
         let encoded_block = builder.build().encode();
+        let (compression, encoded_block) = self.compression.compress(&encoded_block);
+        let offset = self.data.len();
+        self.block_hash_ends.push(self.key_hashes.len());
         self.meta.push(BlockMeta {
-            offset: self.data.len(),
+            offset,
             first_key: std::mem::take(&mut self.first_key).into_key_bytes(),
             last_key: std::mem::take(&mut self.last_key).into_key_bytes(),
         });
-        let checksum = crc32fast::hash(&encoded_block);
         self.data.extend(encoded_block);
+        // The compression type is covered by the checksum.
+        self.data.put_u8(compression.to_u8());
+        let checksum = crc32fast::hash(&self.data[offset..]);
         self.data.put_u32(checksum);
     }
 
@@ -87,29 +164,80 @@ impl SsTableBuilder {
         block_cache: Option<Arc<BlockCache>>,
         path: impl AsRef<Path>,
     ) -> Result<SsTable> {
-        self.finish_block();
+        if !self.builder.is_empty() {
+            self.finish_block();
+        }
         let mut buf = self.data;
-        let meta_offset = buf.len();
-        BlockMeta::encode_block_meta(&self.meta, self.max_ts, &mut buf);
-        buf.put_u32(meta_offset as u32);
-        let bloom = Bloom::build_from_key_hashes(
-            &self.key_hashes,
-            Bloom::bloom_bits_per_key(self.key_hashes.len(), 0.01),
-        );
-        let bloom_offset = buf.len();
-        bloom.encode(&mut buf);
-        buf.put_u32(bloom_offset as u32);
-        let file = FileObject::create(path.as_ref(), buf)?;
+        let data_end = buf.len();
+        let (block_meta, partitioned_index, bloom) = match self.index_partition_size {
+            Some(partition_size) => {
+                let index = PartitionedIndex::build(
+                    &self.meta,
+                    &self.block_hash_ends,
+                    &self.key_hashes,
+                    self.max_ts,
+                    partition_size,
+                    &mut buf,
+                );
+                let meta_offset = buf.len();
+                index.encode(self.max_ts, &mut buf);
+                buf.put_u32(meta_offset as u32);
+                // The filter is partitioned along with the index.
+                let bloom_offset = buf.len();
+                buf.put_u32(bloom_offset as u32);
+                (Vec::new(), Some(index), None)
+            }
+            None => {
+                BlockMeta::encode_block_meta(&self.meta, self.max_ts, &mut buf);
+                buf.put_u32(data_end as u32);
+                let bloom = Bloom::build_from_key_hashes(
+                    &self.key_hashes,
+                    Bloom::bloom_bits_per_key(self.key_hashes.len(), 0.01),
+                );
+                let bloom_offset = buf.len();
+                bloom.encode(&mut buf);
+                buf.put_u32(bloom_offset as u32);
+                (self.meta, None, Some(bloom))
+            }
+        };
+        self.range_tombstones
+            .sort_by(|x, y| x.start.cmp(&y.start).then(y.ts.cmp(&x.ts)));
+        let range_tombstone_offset = buf.len();
+        RangeTombstone::encode_block(&self.range_tombstones, &mut buf);
+        buf.put_u32(range_tombstone_offset as u32);
+        let properties = TableProperties {
+            partitioned_index: partitioned_index.is_some(),
+            prefix_extractor: self.prefix_extractor.map(|x| x.name().to_string()),
+        };
+        let properties_offset = buf.len();
+        properties.encode(&mut buf);
+        buf.put_u32(properties_offset as u32);
+        let (first_key, last_key) = match &partitioned_index {
+            Some(index) => SsTable::key_range(
+                index.partitions.iter().map(|x| (&x.first_key, &x.last_key)),
+                &self.range_tombstones,
+            ),
+            None => SsTable::key_range(
+                block_meta.iter().map(|x| (&x.first_key, &x.last_key)),
+                &self.range_tombstones,
+            ),
+        };
+        let file =
+            FileObject::create_with_rate_limiter(path.as_ref(), buf, self.rate_limiter.as_ref())?;
         Ok(SsTable {
             id,
             file,
-            first_key: self.meta.first().unwrap().first_key.clone(),
-            last_key: self.meta.last().unwrap().last_key.clone(),
-            block_meta: self.meta,
-            block_meta_offset: meta_offset,
+            first_key,
+            last_key,
+            block_meta,
+            block_meta_offset: data_end,
+            partitioned_index,
+            pinned_blocks: block_cache.as_ref().and_then(|x| x.pinned_blocks()),
             block_cache,
-            bloom: Some(bloom),
+            bloom,
             max_ts: self.max_ts,
+            range_tombstones: Arc::new(RangeTombstoneSet::new(self.range_tombstones)),
+            prefix_extractor: properties.prefix_extractor,
         })
     }
 
@@ -0,0 +1,61 @@
+use anyhow::{bail, Result};
+
+/// The compression codec of a data block. The codec is recorded as a single byte after each
+/// block (before the checksum), so that SSTs written with different options can be read back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompressionType {
+    #[default]
+    None,
+    Lz4,
+    Zstd,
+    Snappy,
+}
+
+/// The zstd compression level used for data blocks.
+const ZSTD_LEVEL: i32 = 3;
+
+impl CompressionType {
+    pub fn to_u8(self) -> u8 {
+        match self {
+            CompressionType::None => 0,
+            CompressionType::Lz4 => 1,
+            CompressionType::Zstd => 2,
+            CompressionType::Snappy => 3,
+        }
+    }
+
+    pub fn from_u8(x: u8) -> Result<Self> {
+        Ok(match x {
+            0 => CompressionType::None,
+            1 => CompressionType::Lz4,
+            2 => CompressionType::Zstd,
+            3 => CompressionType::Snappy,
+            _ => bail!("unknown compression type {}", x),
+        })
+    }
+
+    /// Compress an encoded block. If the codec fails or does not make the block smaller, the block
+    /// is kept uncompressed, and the returned compression type is `None`.
+    pub fn compress(self, data: &[u8]) -> (CompressionType, Vec<u8>) {
+        let compressed = match self {
+            CompressionType::None => None,
+            CompressionType::Lz4 => Some(lz4_flex::compress_prepend_size(data)),
+            CompressionType::Zstd => zstd::bulk::compress(data, ZSTD_LEVEL).ok(),
+            CompressionType::Snappy => snap::raw::Encoder::new().compress_vec(data).ok(),
+        };
+        match compressed {
+            Some(compressed) if compressed.len() < data.len() => (self, compressed),
+            _ => (CompressionType::None, data.to_vec()),
+        }
+    }
+
+    /// Decompress a block written with this codec.
+    pub fn decompress(self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(match self {
+            CompressionType::None => data.to_vec(),
+            CompressionType::Lz4 => lz4_flex::decompress_size_prepended(data)?,
+            CompressionType::Zstd => zstd::stream::decode_all(data)?,
+            CompressionType::Snappy => snap::raw::Decoder::new().decompress_vec(data)?,
+        })
+    }
+}
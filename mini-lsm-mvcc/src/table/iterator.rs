@@ -3,8 +3,8 @@ use std::sync::Arc;
 use anyhow::Result;
 
 use super::SsTable;
-use crate::block::BlockIterator;
-use crate::iterators::StorageIterator;
+use crate::block::{Block, BlockIterator};
+use crate::iterators::{RecordKind, StorageIterator};
 use crate::key::KeySlice;
 
 /// An iterator over the contents of an SSTable.
@@ -12,65 +12,95 @@ pub struct SsTableIterator {
     table: Arc<SsTable>,
     blk_iter: BlockIterator,
     blk_idx: usize,
+    /// Whether the blocks read by the iterator are added to the block cache.
+    fill_cache: bool,
 }
 
 impl SsTableIterator {
-    fn seek_to_first_inner(table: &Arc<SsTable>) -> Result<(usize, BlockIterator)> {
-        Ok((
-            0,
-            BlockIterator::create_and_seek_to_first(table.read_block_cached(0)?),
-        ))
+    /// An SST that only holds range tombstones has no data blocks.
+    fn empty_block_iter() -> BlockIterator {
+        BlockIterator::create_and_seek_to_first(Arc::new(Block {
+            data: Vec::new(),
+            offsets: Vec::new(),
+        }))
+    }
+
+    /// Create a new iterator that is not valid until it seeks. Without `fill_cache`, the blocks
+    /// not in the block cache are read without being added to it, so that a large scan does not
+    /// evict the cached blocks.
+    pub fn new(table: Arc<SsTable>, fill_cache: bool) -> Self {
+        Self {
+            table,
+            blk_iter: Self::empty_block_iter(),
+            blk_idx: 0,
+            fill_cache,
+        }
+    }
+
+    fn read_block(&self, blk_idx: usize) -> Result<Arc<Block>> {
+        self.table.read_data_block(blk_idx, self.fill_cache)
     }
 
     /// Create a new iterator and seek to the first key-value pair.
     pub fn create_and_seek_to_first(table: Arc<SsTable>) -> Result<Self> {
-        let (blk_idx, blk_iter) = Self::seek_to_first_inner(&table)?;
-        let iter = Self {
-            blk_iter,
-            table,
-            blk_idx,
-        };
+        let mut iter = Self::new(table, true);
+        iter.seek_to_first()?;
         Ok(iter)
     }
 
     /// Seek to the first key-value pair.
     pub fn seek_to_first(&mut self) -> Result<()> {
-        let (blk_idx, blk_iter) = Self::seek_to_first_inner(&self.table)?;
-        self.blk_idx = blk_idx;
-        self.blk_iter = blk_iter;
+        self.blk_idx = 0;
+        self.blk_iter = if self.table.num_of_blocks() == 0 {
+            Self::empty_block_iter()
+        } else {
+            BlockIterator::create_and_seek_to_first(self.read_block(0)?)
+        };
         Ok(())
     }
 
-    fn seek_to_key_inner(table: &Arc<SsTable>, key: KeySlice) -> Result<(usize, BlockIterator)> {
-        let mut blk_idx = table.find_block_idx(key);
-        let mut blk_iter =
-            BlockIterator::create_and_seek_to_key(table.read_block_cached(blk_idx)?, key);
-        if !blk_iter.is_valid() {
-            blk_idx += 1;
-            if blk_idx < table.num_of_blocks() {
-                blk_iter =
-                    BlockIterator::create_and_seek_to_first(table.read_block_cached(blk_idx)?);
-            }
+    /// Create a new iterator and seek to the last key-value pair.
+    pub fn create_and_seek_to_last(table: Arc<SsTable>) -> Result<Self> {
+        let mut iter = Self::new(table, true);
+        iter.seek_to_last()?;
+        Ok(iter)
+    }
+
+    /// Seek to the last key-value pair.
+    pub fn seek_to_last(&mut self) -> Result<()> {
+        if self.table.num_of_blocks() == 0 {
+            self.blk_idx = 0;
+            self.blk_iter = Self::empty_block_iter();
+            return Ok(());
         }
-        Ok((blk_idx, blk_iter))
+        self.blk_idx = self.table.num_of_blocks() - 1;
+        self.blk_iter = BlockIterator::create_and_seek_to_last(self.read_block(self.blk_idx)?);
+        Ok(())
     }
 
     /// Create a new iterator and seek to the first key-value pair which >= `key`.
     pub fn create_and_seek_to_key(table: Arc<SsTable>, key: KeySlice) -> Result<Self> {
-        let (blk_idx, blk_iter) = Self::seek_to_key_inner(&table, key)?;
-        let iter = Self {
-            blk_iter,
-            table,
-            blk_idx,
-        };
+        let mut iter = Self::new(table, true);
+        iter.seek_to_key(key)?;
         Ok(iter)
     }
 
     /// Seek to the first key-value pair which >= `key`.
     pub fn seek_to_key(&mut self, key: KeySlice) -> Result<()> {
-        let (blk_idx, blk_iter) = Self::seek_to_key_inner(&self.table, key)?;
-        self.blk_iter = blk_iter;
-        self.blk_idx = blk_idx;
+        if self.table.num_of_blocks() == 0 {
+            self.blk_idx = 0;
+            self.blk_iter = Self::empty_block_iter();
+            return Ok(());
+        }
+        self.blk_idx = self.table.find_block_idx(key)?;
+        self.blk_iter = BlockIterator::create_and_seek_to_key(self.read_block(self.blk_idx)?, key);
+        if !self.blk_iter.is_valid() {
+            self.blk_idx += 1;
+            if self.blk_idx < self.table.num_of_blocks() {
+                self.blk_iter =
+                    BlockIterator::create_and_seek_to_first(self.read_block(self.blk_idx)?);
+            }
+        }
         Ok(())
     }
 }
@@ -90,16 +120,50 @@ impl StorageIterator for SsTableIterator {
         self.blk_iter.is_valid()
     }
 
+    fn record_kind(&self) -> RecordKind {
+        self.blk_iter.record_kind()
+    }
+
     fn next(&mut self) -> Result<()> {
         self.blk_iter.next();
         if !self.blk_iter.is_valid() {
             self.blk_idx += 1;
             if self.blk_idx < self.table.num_of_blocks() {
-                self.blk_iter = BlockIterator::create_and_seek_to_first(
-                    self.table.read_block_cached(self.blk_idx)?,
-                );
+                self.blk_iter =
+                    BlockIterator::create_and_seek_to_first(self.read_block(self.blk_idx)?);
             }
         }
         Ok(())
     }
+
+    fn prev(&mut self) -> Result<()> {
+        self.blk_iter.prev();
+        if !self.blk_iter.is_valid() && self.blk_idx > 0 {
+            self.blk_idx -= 1;
+            self.blk_iter = BlockIterator::create_and_seek_to_last(self.read_block(self.blk_idx)?);
+        }
+        Ok(())
+    }
+
+    fn seek_to_first(&mut self) -> Result<()> {
+        SsTableIterator::seek_to_first(self)
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        SsTableIterator::seek_to_last(self)
+    }
+
+    fn seek(&mut self, key: KeySlice) -> Result<()> {
+        self.seek_to_key(key)
+    }
+
+    fn seek_for_prev(&mut self, key: KeySlice) -> Result<()> {
+        self.seek_to_key(key)?;
+        if !self.is_valid() {
+            SsTableIterator::seek_to_last(self)?;
+        } else if self.key() > key {
+            self.prev()?;
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,226 @@
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use bytes::{Buf, BufMut};
+
+use super::bloom::Bloom;
+use super::{BlockMeta, SsTable};
+use crate::block_cache::CachedBlock;
+use crate::key::KeyBytes;
+
+/// Locates a partition of the index and the filter. A partition covers the data blocks from
+/// `first_block_idx` up to the first block of the next partition.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct IndexPartition {
+    pub(crate) first_key: KeyBytes,
+    pub(crate) last_key: KeyBytes,
+    pub(crate) first_block_idx: usize,
+    /// The end offset of the last data block in the partition.
+    pub(crate) data_end: usize,
+    pub(crate) index_offset: usize,
+    pub(crate) index_len: usize,
+    pub(crate) filter_offset: usize,
+    pub(crate) filter_len: usize,
+}
+
+/// The top-level index of a partitioned SST, which is the only part of the index and the filter
+/// kept in memory. The partitions are read through the block cache when they are needed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct PartitionedIndex {
+    pub(crate) partitions: Vec<IndexPartition>,
+    pub(crate) num_of_blocks: usize,
+}
+
+fn put_key(buf: &mut Vec<u8>, key: &KeyBytes) {
+    buf.put_u16(key.key_len() as u16);
+    buf.put_slice(key.key_ref());
+    buf.put_u64(key.ts());
+}
+
+fn get_key(buf: &mut &[u8]) -> KeyBytes {
+    let len = buf.get_u16() as usize;
+    let key = buf.copy_to_bytes(len);
+    KeyBytes::from_bytes_with_ts(key, buf.get_u64())
+}
+
+impl PartitionedIndex {
+    /// Splits the block metas into index partitions of about `partition_size` bytes, and appends
+    /// the index partitions and then their filter partitions to `buf`. The key hashes of block `i`
+    /// end at `block_hash_ends[i]`.
+    pub(crate) fn build(
+        block_meta: &[BlockMeta],
+        block_hash_ends: &[usize],
+        key_hashes: &[u32],
+        max_ts: u64,
+        partition_size: usize,
+        buf: &mut Vec<u8>,
+    ) -> Self {
+        let data_end = buf.len();
+        let mut groups = Vec::new();
+        let mut start = 0;
+        let mut size = 0;
+        for (idx, meta) in block_meta.iter().enumerate() {
+            size += 8 + meta.first_key.raw_len() + meta.last_key.raw_len();
+            if size >= partition_size || idx + 1 == block_meta.len() {
+                groups.push(start..idx + 1);
+                start = idx + 1;
+                size = 0;
+            }
+        }
+        let mut partitions = Vec::with_capacity(groups.len());
+        for group in &groups {
+            let index_offset = buf.len();
+            BlockMeta::encode_block_meta(&block_meta[group.clone()], max_ts, buf);
+            partitions.push(IndexPartition {
+                first_key: block_meta[group.start].first_key.clone(),
+                last_key: block_meta[group.end - 1].last_key.clone(),
+                first_block_idx: group.start,
+                data_end: block_meta.get(group.end).map_or(data_end, |x| x.offset),
+                index_offset,
+                index_len: buf.len() - index_offset,
+                filter_offset: 0,
+                filter_len: 0,
+            });
+        }
+        for (group, partition) in groups.iter().zip(partitions.iter_mut()) {
+            let hashes_start = group.start.checked_sub(1).map_or(0, |x| block_hash_ends[x]);
+            let hashes = &key_hashes[hashes_start..block_hash_ends[group.end - 1]];
+            let bloom =
+                Bloom::build_from_key_hashes(hashes, Bloom::bloom_bits_per_key(hashes.len(), 0.01));
+            partition.filter_offset = buf.len();
+            bloom.encode(buf);
+            partition.filter_len = buf.len() - partition.filter_offset;
+        }
+        Self {
+            partitions,
+            num_of_blocks: block_meta.len(),
+        }
+    }
+
+    /// Encodes the top-level index to a buffer.
+    pub(crate) fn encode(&self, max_ts: u64, buf: &mut Vec<u8>) {
+        let original_len = buf.len();
+        buf.put_u32(self.partitions.len() as u32);
+        for partition in &self.partitions {
+            put_key(buf, &partition.first_key);
+            put_key(buf, &partition.last_key);
+            buf.put_u32(partition.first_block_idx as u32);
+            buf.put_u32(partition.data_end as u32);
+            buf.put_u32(partition.index_offset as u32);
+            buf.put_u32(partition.index_len as u32);
+            buf.put_u32(partition.filter_offset as u32);
+            buf.put_u32(partition.filter_len as u32);
+        }
+        buf.put_u32(self.num_of_blocks as u32);
+        buf.put_u64(max_ts);
+        buf.put_u32(crc32fast::hash(&buf[original_len..]));
+    }
+
+    /// Decodes the top-level index from a buffer.
+    pub(crate) fn decode(buf: &[u8]) -> Result<(Self, u64)> {
+        if buf.len() < 20 {
+            bail!("top-level index too short");
+        }
+        let (content, mut checksum) = buf.split_at(buf.len() - 4);
+        if checksum.get_u32() != crc32fast::hash(content) {
+            bail!("top-level index checksum mismatched");
+        }
+        let mut buf = content;
+        let num = buf.get_u32() as usize;
+        let mut partitions = Vec::with_capacity(num);
+        for _ in 0..num {
+            partitions.push(IndexPartition {
+                first_key: get_key(&mut buf),
+                last_key: get_key(&mut buf),
+                first_block_idx: buf.get_u32() as usize,
+                data_end: buf.get_u32() as usize,
+                index_offset: buf.get_u32() as usize,
+                index_len: buf.get_u32() as usize,
+                filter_offset: buf.get_u32() as usize,
+                filter_len: buf.get_u32() as usize,
+            });
+        }
+        let num_of_blocks = buf.get_u32() as usize;
+        let max_ts = buf.get_u64();
+        Ok((
+            Self {
+                partitions,
+                num_of_blocks,
+            },
+            max_ts,
+        ))
+    }
+
+    /// Find the partition that covers the block.
+    pub(crate) fn partition_of_block(&self, block_idx: usize) -> usize {
+        self.partitions
+            .partition_point(|x| x.first_block_idx <= block_idx)
+            .saturating_sub(1)
+    }
+
+    /// The partitions that may contain the user keys in `[lower, upper)`, or in `[lower, ..)`
+    /// without an upper bound.
+    pub(crate) fn partitions_within<'a>(
+        &'a self,
+        lower: &'a [u8],
+        upper: Option<&'a [u8]>,
+    ) -> impl Iterator<Item = usize> + 'a {
+        self.partitions
+            .iter()
+            .enumerate()
+            .filter(move |(_, x)| {
+                x.last_key.key_ref() >= lower
+                    && upper.is_none_or(|upper| x.first_key.key_ref() < upper)
+            })
+            .map(|(idx, _)| idx)
+    }
+}
+
+impl SsTable {
+    /// Read an index partition, with block cache.
+    pub(crate) fn read_index_partition(&self, partition_idx: usize) -> Result<Arc<Vec<BlockMeta>>> {
+        let partition = &self.partitioned_index.as_ref().unwrap().partitions[partition_idx];
+        let block = self.read_cached(partition.index_offset, true, true, || {
+            let raw = self
+                .file
+                .read(partition.index_offset as u64, partition.index_len as u64)?;
+            let (block_meta, _) = BlockMeta::decode_block_meta(&raw)?;
+            Ok(CachedBlock::Index(Arc::new(block_meta)))
+        })?;
+        match block {
+            CachedBlock::Index(block_meta) => Ok(block_meta),
+            _ => bail!("cached block is not an index partition"),
+        }
+    }
+
+    /// Read a filter partition, with block cache.
+    pub(crate) fn read_filter_partition(&self, partition_idx: usize) -> Result<Arc<Bloom>> {
+        let partition = &self.partitioned_index.as_ref().unwrap().partitions[partition_idx];
+        let block = self.read_cached(partition.filter_offset, true, true, || {
+            let raw = self
+                .file
+                .read(partition.filter_offset as u64, partition.filter_len as u64)?;
+            Ok(CachedBlock::Filter(Arc::new(Bloom::decode(&raw)?)))
+        })?;
+        match block {
+            CachedBlock::Filter(bloom) => Ok(bloom),
+            _ => bail!("cached block is not a filter partition"),
+        }
+    }
+
+    /// Checks the filters of the partitions that may contain the user keys in `[lower, upper)`.
+    pub(crate) fn partitions_may_contain(
+        &self,
+        lower: &[u8],
+        upper: Option<&[u8]>,
+        hash: u32,
+    ) -> Result<bool> {
+        let index = self.partitioned_index.as_ref().unwrap();
+        for partition_idx in index.partitions_within(lower, upper) {
+            if self.read_filter_partition(partition_idx)?.may_contain(hash) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
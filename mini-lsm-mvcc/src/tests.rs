@@ -1,4 +1,25 @@
+mod block_cache;
+mod checker;
+mod column_family;
+mod compression;
+mod concurrent_compaction;
+mod group_commit;
 mod harness;
+mod helpers;
+mod iterator_seek;
+mod manifest;
+mod memtable_type;
+mod merge_operator;
+mod orphan_files;
+mod partitioned_index;
+mod prefix_bloom;
+mod range_tombstone;
+mod rate_limiter;
+mod reverse_iteration;
+mod subcompaction;
+mod trivial_move;
+mod value_log;
+mod wal_recovery;
 mod week1_day1;
 mod week1_day2;
 mod week1_day3;
@@ -19,3 +40,5 @@ mod week3_day4;
 mod week3_day5;
 mod week3_day6;
 mod week3_day7;
+mod write_buffer_manager;
+mod write_stall;
@@ -0,0 +1,412 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    block::Block,
+    block_cache::{
+        BlockCache, BlockCacheOptions, CachedBlock, CompressedSecondaryCache, EvictionPolicy,
+        FileSecondaryCache, SecondaryCache,
+    },
+    compact::CompactionOptions,
+    iterators::StorageIterator,
+    key::KeySlice,
+    lsm_storage::{LsmStorageOptions, MiniLsm, ReadOptions},
+    table::{CompressionType, FileObject, SsTable, SsTableBuilder, SsTableIterator},
+};
+
+use super::harness::generate_sst;
+use super::helpers::{key_of, value_of};
+
+fn scan_all(sst: &Arc<SsTable>) {
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+    while iter.is_valid() {
+        iter.next().unwrap();
+    }
+}
+
+/// Reads a data block of 100 bytes through the cache, and returns whether it was a hit.
+fn read_block(block_cache: &BlockCache, offset: usize) -> bool {
+    let hits = block_cache.stats().data_hits;
+    block_cache
+        .get_or_read(1, offset, false, true, None, || {
+            Ok(CachedBlock::Data(Arc::new(Block {
+                data: vec![0; 100],
+                offsets: Vec::new(),
+            })))
+        })
+        .unwrap();
+    block_cache.stats().data_hits > hits
+}
+
+fn build_partitioned_sst(
+    id: usize,
+    dir: &std::path::Path,
+    block_cache: Arc<BlockCache>,
+) -> SsTable {
+    let mut builder = SsTableBuilder::new(128);
+    builder.set_index_partition_size(128);
+    for i in 0..1000 {
+        builder.add(
+            KeySlice::for_testing_from_slice_no_ts(&key_of(i)),
+            &value_of(i),
+        );
+    }
+    builder
+        .build(id, Some(block_cache), dir.join(format!("{id}.sst")))
+        .unwrap()
+}
+
+#[test]
+fn test_block_cache_capacity_in_bytes() {
+    let dir = tempdir().unwrap();
+    let data = (0..1000).map(|i| (key_of(i), value_of(i))).collect();
+    let block_cache = Arc::new(BlockCache::new(1 << 20));
+    let sst = Arc::new(generate_sst(
+        1,
+        dir.path().join("1.sst"),
+        data,
+        Some(block_cache.clone()),
+    ));
+    let num_of_blocks = sst.num_of_blocks() as u64;
+
+    scan_all(&sst);
+    let stats = block_cache.stats();
+    assert_eq!(stats.data_misses, num_of_blocks);
+    assert_eq!(stats.data_hits, 0);
+    scan_all(&sst);
+    let stats = block_cache.stats();
+    assert_eq!(stats.data_misses, num_of_blocks);
+    assert_eq!(stats.data_hits, num_of_blocks);
+    // The cache is charged with the size of the blocks, not the number of entries.
+    assert!(stats.low_priority_usage > num_of_blocks * 64);
+    assert!(stats.low_priority_usage <= num_of_blocks * 128);
+    assert_eq!(stats.high_priority_usage, 0);
+
+    // A cache smaller than the SST keeps its usage within the capacity.
+    let small_cache = Arc::new(BlockCache::new(4096));
+    let file = FileObject::open(&dir.path().join("1.sst")).unwrap();
+    let sst = Arc::new(SsTable::open(1, Some(small_cache.clone()), file).unwrap());
+    for _ in 0..3 {
+        scan_all(&sst);
+    }
+    let stats = small_cache.stats();
+    assert!(stats.usage() <= 4096);
+    assert!(stats.data_misses > num_of_blocks);
+}
+
+#[test]
+fn test_block_cache_high_priority_pool() {
+    let dir = tempdir().unwrap();
+    // The data blocks do not fit into the cache, while the index and filter partitions do.
+    let block_cache = Arc::new(BlockCache::with_options(BlockCacheOptions {
+        capacity: 1 << 15,
+        high_priority_ratio: 0.75,
+        num_shard_bits: 0,
+        ..Default::default()
+    }));
+    let sst = Arc::new(build_partitioned_sst(1, dir.path(), block_cache.clone()));
+    scan_all(&sst);
+    for i in 0..1000 {
+        assert!(sst.may_contain_key(&key_of(i)).unwrap());
+    }
+    let stats = block_cache.stats();
+    let index_misses = stats.index_misses;
+    let filter_misses = stats.filter_misses;
+    assert!(index_misses > 0 && filter_misses > 0);
+    assert!(stats.high_priority_usage > 0);
+
+    // Scanning the data blocks again does not evict the partitions.
+    for _ in 0..5 {
+        scan_all(&sst);
+    }
+    for i in 0..1000 {
+        assert!(sst.may_contain_key(&key_of(i)).unwrap());
+    }
+    let stats = block_cache.stats();
+    assert_eq!(stats.index_misses, index_misses);
+    assert_eq!(stats.filter_misses, filter_misses);
+    assert!(stats.index_hits > 0 && stats.filter_hits > 0);
+    assert!(stats.data_misses > sst.num_of_blocks() as u64);
+}
+
+#[test]
+fn test_block_cache_pinned_partitions() {
+    let dir = tempdir().unwrap();
+    // The high-priority pool cannot hold any partition, but the pinned ones are read only once.
+    let block_cache = Arc::new(BlockCache::with_options(BlockCacheOptions {
+        capacity: 1 << 20,
+        high_priority_ratio: 0.0,
+        pin_index_and_filter_blocks: true,
+        ..Default::default()
+    }));
+    let sst = Arc::new(build_partitioned_sst(1, dir.path(), block_cache.clone()));
+    for _ in 0..3 {
+        scan_all(&sst);
+        for i in 0..1000 {
+            assert!(sst.may_contain_key(&key_of(i)).unwrap());
+        }
+    }
+    let stats = block_cache.stats();
+    let num_of_partitions = sst.partitioned_index.as_ref().unwrap().partitions.len() as u64;
+    assert_eq!(stats.index_misses, num_of_partitions);
+    assert_eq!(stats.filter_misses, num_of_partitions);
+    assert!(stats.pinned_usage > 0);
+    drop(sst);
+    assert_eq!(block_cache.stats().pinned_usage, 0);
+}
+
+#[test]
+fn test_shared_block_cache() {
+    let block_cache = Arc::new(BlockCache::new(1 << 20));
+    let dirs = [tempdir().unwrap(), tempdir().unwrap()];
+    let storages = dirs
+        .iter()
+        .enumerate()
+        .map(|(idx, dir)| {
+            let mut options =
+                LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+            options.shared_block_cache = Some(block_cache.clone());
+            let storage = MiniLsm::open(dir, options).unwrap();
+            // Both engines number their SSTs in the same way.
+            for i in 0..100 {
+                storage
+                    .put(&key_of(i), format!("{}_{}", idx, i).as_bytes())
+                    .unwrap();
+            }
+            storage.force_flush().unwrap();
+            storage
+        })
+        .collect::<Vec<_>>();
+
+    for _ in 0..2 {
+        for (idx, storage) in storages.iter().enumerate() {
+            for i in 0..100 {
+                assert_eq!(
+                    storage.get(&key_of(i)).unwrap(),
+                    Some(Bytes::from(format!("{}_{}", idx, i)))
+                );
+            }
+        }
+    }
+    let stats = storages[0].block_cache_stats();
+    assert_eq!(stats, storages[1].block_cache_stats());
+    assert_eq!(stats, block_cache.stats());
+    assert!(stats.data_misses >= 2);
+    assert!(stats.data_hits > 0);
+    for storage in storages {
+        storage.close().unwrap();
+    }
+}
+
+#[test]
+fn test_eviction_policies() {
+    for eviction_policy in [EvictionPolicy::Lru, EvictionPolicy::ClockPro] {
+        let block_cache = BlockCache::with_options(BlockCacheOptions {
+            capacity: 1000,
+            high_priority_ratio: 0.0,
+            eviction_policy,
+            num_shard_bits: 0,
+            ..Default::default()
+        });
+        for offset in 0..10 {
+            assert!(!read_block(&block_cache, offset));
+        }
+        for offset in 0..10 {
+            assert!(read_block(&block_cache, offset), "{:?}", eviction_policy);
+        }
+        for offset in 10..100 {
+            read_block(&block_cache, offset);
+            assert!(block_cache.stats().usage() <= 1000);
+        }
+        assert_eq!(block_cache.stats().usage(), 1000);
+        assert!(read_block(&block_cache, 99));
+    }
+}
+
+#[test]
+fn test_clock_pro_scan_resistance() {
+    let hot_hits = |eviction_policy| {
+        let block_cache = BlockCache::with_options(BlockCacheOptions {
+            capacity: 1000,
+            high_priority_ratio: 0.0,
+            eviction_policy,
+            num_shard_bits: 0,
+            ..Default::default()
+        });
+        // The hot blocks are read repeatedly between reads of other blocks.
+        for round in 0..5 {
+            for offset in 0..5 {
+                read_block(&block_cache, offset);
+            }
+            for offset in 0..10 {
+                read_block(&block_cache, 1000 + round * 10 + offset);
+            }
+        }
+        // A scan reads many blocks once.
+        for offset in 2000..2100 {
+            read_block(&block_cache, offset);
+        }
+        (0..5).filter(|x| read_block(&block_cache, *x)).count()
+    };
+    assert_eq!(hot_hits(EvictionPolicy::Lru), 0);
+    assert_eq!(hot_hits(EvictionPolicy::ClockPro), 5);
+}
+
+#[test]
+fn test_scan_without_filling_cache() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.block_size = 64;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for i in 0..200 {
+        storage.put(&key_of(i), &value_of(i)).unwrap();
+    }
+    storage.force_flush().unwrap();
+
+    let count = |read_options: &ReadOptions| {
+        let mut iter = storage
+            .scan_with_options(
+                std::ops::Bound::Unbounded,
+                std::ops::Bound::Unbounded,
+                read_options,
+            )
+            .unwrap();
+        let mut count = 0;
+        while iter.is_valid() {
+            count += 1;
+            iter.next().unwrap();
+        }
+        count
+    };
+    let no_fill = ReadOptions { fill_cache: false };
+    assert_eq!(count(&no_fill), 200);
+    let stats = storage.block_cache_stats();
+    let num_of_blocks = stats.data_misses;
+    assert!(num_of_blocks > 0);
+    assert_eq!(stats.low_priority_usage, 0);
+    assert_eq!(count(&no_fill), 200);
+    assert_eq!(storage.block_cache_stats().data_hits, 0);
+
+    // The blocks read by a normal scan are hit by the scans without filling the cache.
+    assert_eq!(count(&ReadOptions::default()), 200);
+    let stats = storage.block_cache_stats();
+    assert!(stats.low_priority_usage > 0);
+    assert_eq!(count(&no_fill), 200);
+    assert_eq!(storage.block_cache_stats().data_hits, stats.data_misses / 3);
+}
+
+fn check_secondary_cache(secondary_cache: Arc<dyn SecondaryCache>) {
+    let block_cache = BlockCache::with_options(BlockCacheOptions {
+        capacity: 1000,
+        high_priority_ratio: 0.0,
+        num_shard_bits: 0,
+        secondary_cache: Some(secondary_cache),
+        ..Default::default()
+    });
+    for offset in 0..20 {
+        assert!(!read_block(&block_cache, offset));
+    }
+    let stats = block_cache.stats();
+    assert_eq!(stats.secondary_misses, 20);
+    // The evicted blocks are kept compressed.
+    assert!(stats.secondary_usage > 0 && stats.secondary_usage < 1000);
+
+    // The evicted blocks are read from the secondary cache and moved back into the cache.
+    for offset in 0..10 {
+        assert!(!read_block(&block_cache, offset));
+    }
+    let stats = block_cache.stats();
+    assert_eq!(stats.secondary_hits, 10);
+    assert_eq!(stats.secondary_misses, 20);
+    assert_eq!(stats.data_misses, 30);
+    for offset in 0..10 {
+        assert!(read_block(&block_cache, offset));
+    }
+    for offset in 10..20 {
+        assert!(!read_block(&block_cache, offset));
+    }
+    assert_eq!(block_cache.stats().secondary_hits, 20);
+}
+
+#[test]
+fn test_compressed_secondary_cache() {
+    check_secondary_cache(Arc::new(CompressedSecondaryCache::new(
+        1 << 20,
+        CompressionType::Lz4,
+    )));
+}
+
+#[test]
+fn test_file_secondary_cache() {
+    let dir = tempdir().unwrap();
+    check_secondary_cache(Arc::new(
+        FileSecondaryCache::create(dir.path().join("cache"), 1 << 20, CompressionType::Lz4)
+            .unwrap(),
+    ));
+
+    // A small file keeps only the most recently evicted blocks.
+    let secondary_cache = Arc::new(
+        FileSecondaryCache::create(dir.path().join("small_cache"), 250, CompressionType::None)
+            .unwrap(),
+    );
+    let block_cache = BlockCache::with_options(BlockCacheOptions {
+        capacity: 1000,
+        high_priority_ratio: 0.0,
+        num_shard_bits: 0,
+        secondary_cache: Some(secondary_cache.clone()),
+        ..Default::default()
+    });
+    for offset in 0..100 {
+        read_block(&block_cache, offset);
+        // Each block takes 104 bytes in the file.
+        assert!(secondary_cache.usage() <= 208);
+    }
+    assert_eq!(secondary_cache.usage(), 208);
+    assert!(!read_block(&block_cache, 0));
+    assert_eq!(block_cache.stats().secondary_hits, 0);
+    // Block 0 evicts block 90 from the cache, which overwrites block 88 in the file.
+    assert!(!read_block(&block_cache, 89));
+    assert_eq!(block_cache.stats().secondary_hits, 1);
+}
+
+#[test]
+fn test_secondary_cache_with_sst() {
+    let dir = tempdir().unwrap();
+    for secondary_cache in [
+        Arc::new(CompressedSecondaryCache::new(
+            1 << 20,
+            CompressionType::Zstd,
+        )) as Arc<dyn SecondaryCache>,
+        Arc::new(
+            FileSecondaryCache::create(dir.path().join("cache"), 1 << 20, CompressionType::Snappy)
+                .unwrap(),
+        ),
+    ] {
+        // Neither the data blocks nor the index and filter partitions fit into the cache.
+        let block_cache = Arc::new(BlockCache::with_options(BlockCacheOptions {
+            capacity: 4096,
+            num_shard_bits: 0,
+            secondary_cache: Some(secondary_cache),
+            ..Default::default()
+        }));
+        let sst = Arc::new(build_partitioned_sst(1, dir.path(), block_cache.clone()));
+        for _ in 0..2 {
+            let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+            for i in 0..1000 {
+                assert_eq!(iter.key().key_ref(), key_of(i));
+                assert_eq!(iter.value(), value_of(i));
+                iter.next().unwrap();
+            }
+            assert!(!iter.is_valid());
+            for i in 0..1000 {
+                assert!(sst.may_contain_key(&key_of(i)).unwrap());
+            }
+        }
+        let stats = block_cache.stats();
+        assert!(stats.secondary_hits > 0);
+        assert!(stats.secondary_usage > 0);
+        assert!(stats.data_hits + stats.secondary_hits >= sst.num_of_blocks() as u64);
+    }
+}
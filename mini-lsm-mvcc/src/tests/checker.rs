@@ -0,0 +1,155 @@
+use std::{fs::File, os::unix::fs::FileExt, path::Path, sync::Arc};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    checker::check_storage,
+    column_family::ColumnFamilyOptions,
+    compact::CompactionOptions,
+    lsm_storage::{LsmStorageInner, LsmStorageOptions, MiniLsm, WriteBatchRecord},
+    merge_operator::MergeOperator,
+};
+
+use super::helpers::{flush_all, key_of, value_of};
+
+/// Appends to a comma-separated list.
+#[derive(Debug)]
+struct AppendMergeOperator;
+
+impl MergeOperator for AppendMergeOperator {
+    fn full_merge(&self, _key: &[u8], existing: Option<&[u8]>, operands: &[Bytes]) -> Bytes {
+        let mut items = existing.into_iter().map(|x| x.to_vec()).collect::<Vec<_>>();
+        items.extend(operands.iter().map(|x| x.to_vec()));
+        Bytes::from(items.join(&b","[..]))
+    }
+}
+
+fn options(index_partition_size: Option<usize>) -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    options.target_sst_size = 32 << 10;
+    options.num_memtable_limit = 1000;
+    options.index_partition_size = index_partition_size;
+    options.merge_operator = Some(Arc::new(AppendMergeOperator));
+    options
+}
+
+/// Creates a storage with SSTs in L0 and L1 and in two column families, with merge operands and
+/// range tombstones, and unflushed writes in the WAL.
+fn create_storage(dir: &Path, options: &LsmStorageOptions) {
+    let storage = MiniLsm::open(dir, options.clone()).unwrap();
+    storage
+        .create_column_family(
+            "meta",
+            ColumnFamilyOptions {
+                compaction_options: CompactionOptions::NoCompaction,
+            },
+        )
+        .unwrap();
+    for round in 0..6 {
+        for i in 0..2000 {
+            storage.put(&key_of(i), &value_of(i + round)).unwrap();
+        }
+        storage
+            .write_batch(&[WriteBatchRecord::Merge(key_of(round).as_ref(), b"merged")])
+            .unwrap();
+        storage
+            .delete_range(&key_of(round * 100), &key_of(round * 100 + 10))
+            .unwrap();
+        storage
+            .put_cf("meta", &key_of(round), &value_of(round))
+            .unwrap();
+        flush_all(&storage);
+    }
+    storage.force_full_compaction().unwrap();
+    for i in 0..2000 {
+        storage.put(&key_of(i), &value_of(i)).unwrap();
+    }
+    flush_all(&storage);
+    storage.put(b"unflushed", b"value").unwrap();
+    storage.close().unwrap();
+}
+
+#[test]
+fn test_check_storage() {
+    for index_partition_size in [None, Some(256)] {
+        let dir = tempdir().unwrap();
+        let options = options(index_partition_size);
+        create_storage(dir.path(), &options);
+        let report = check_storage(&dir, &options).unwrap();
+        assert!(report.is_ok(), "{:?}", report);
+        assert!(report.num_sstables > 2);
+        assert!(report.num_entries > 3000, "{:?}", report);
+        assert_eq!(report.num_wals, 1);
+        assert!(report.orphan_files.is_empty());
+        assert!(report.warnings.is_empty());
+
+        // The storage is not changed by the check.
+        let storage = MiniLsm::open(&dir, options).unwrap();
+        assert!(storage.orphan_files().is_empty());
+        assert_eq!(
+            storage.get(b"unflushed").unwrap(),
+            Some(Bytes::from("value"))
+        );
+        storage.close().unwrap();
+    }
+}
+
+#[test]
+fn test_check_corrupted_storage() {
+    let dir = tempdir().unwrap();
+    let options = options(None);
+    create_storage(dir.path(), &options);
+    let sst_ids = {
+        let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+        let ids = storage
+            .inner
+            .column_families()
+            .iter()
+            .flat_map(|cf| {
+                let state = cf.state.read();
+                let mut ids = state.l0_sstables.clone();
+                ids.extend(
+                    state
+                        .levels
+                        .iter()
+                        .flat_map(|(_, files)| files.iter().copied()),
+                );
+                ids
+            })
+            .collect::<Vec<_>>();
+        storage.close().unwrap();
+        ids
+    };
+    assert!(sst_ids.len() > 2);
+
+    // A corrupted data block, a missing SST, an orphan SST and a torn WAL record.
+    let corrupted_path = LsmStorageInner::path_of_sst_static(&dir, sst_ids[0]);
+    let file = File::options().write(true).open(&corrupted_path).unwrap();
+    file.write_all_at(b"corrupted", 10).unwrap();
+    drop(file);
+    let missing_path = LsmStorageInner::path_of_sst_static(&dir, sst_ids[1]);
+    let orphan_path = LsmStorageInner::path_of_sst_static(&dir, 10000);
+    std::fs::rename(&missing_path, &orphan_path).unwrap();
+    // The last record of the WAL with the unflushed write.
+    let wal_path = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|x| x == "wal"))
+        .max_by_key(|path| std::fs::metadata(path).unwrap().len())
+        .unwrap();
+    let file = File::options().write(true).open(&wal_path).unwrap();
+    file.write_all_at(b"!", file.metadata().unwrap().len() - 1)
+        .unwrap();
+    drop(file);
+
+    let report = check_storage(&dir, &options).unwrap();
+    assert!(!report.is_ok());
+    assert_eq!(report.missing_files, vec![missing_path]);
+    assert_eq!(report.orphan_files, vec![orphan_path]);
+    assert_eq!(report.errors.len(), 1, "{:?}", report.errors);
+    assert!(report.errors[0].contains("block checksum mismatched"));
+    assert_eq!(report.warnings.len(), 1, "{:?}", report.warnings);
+    assert!(report.warnings[0].contains(wal_path.to_str().unwrap()));
+}
@@ -0,0 +1,233 @@
+use std::{ops::Bound, time::Duration};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    column_family::{ColumnFamilyOptions, DEFAULT_COLUMN_FAMILY},
+    compact::{CompactionOptions, SimpleLeveledCompactionOptions},
+    lsm_storage::{LsmStorageOptions, MiniLsm, WriteBatchRecord},
+};
+
+use super::harness::check_lsm_iter_result_by_key;
+use super::helpers::key_of;
+
+fn simple_compaction_options() -> ColumnFamilyOptions {
+    ColumnFamilyOptions {
+        compaction_options: CompactionOptions::Simple(SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+        }),
+    }
+}
+
+fn no_compaction_options() -> ColumnFamilyOptions {
+    ColumnFamilyOptions {
+        compaction_options: CompactionOptions::NoCompaction,
+    }
+}
+
+#[test]
+fn test_column_family_read_write() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage
+        .create_column_family("meta", no_compaction_options())
+        .unwrap();
+    assert!(storage
+        .create_column_family("meta", no_compaction_options())
+        .is_err());
+    assert_eq!(
+        storage.list_column_families(),
+        vec![DEFAULT_COLUMN_FAMILY.to_string(), "meta".to_string()]
+    );
+
+    storage.put(b"a", b"default").unwrap();
+    storage.put_cf("meta", b"a", b"meta").unwrap();
+    storage.put_cf("meta", b"b", b"meta").unwrap();
+    storage.delete_cf("meta", b"b").unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("default")));
+    assert_eq!(
+        storage.get_cf(DEFAULT_COLUMN_FAMILY, b"a").unwrap(),
+        Some(Bytes::from("default"))
+    );
+    assert_eq!(
+        storage.get_cf("meta", b"a").unwrap(),
+        Some(Bytes::from("meta"))
+    );
+    assert_eq!(storage.get_cf("meta", b"b").unwrap(), None);
+    assert!(storage.get_cf("missing", b"a").is_err());
+    assert!(storage.put_cf("missing", b"a", b"1").is_err());
+
+    // A cross-family batch commits with a single timestamp.
+    let ts = storage.inner.mvcc().latest_commit_ts();
+    storage
+        .write_batch_cf(&[
+            (
+                DEFAULT_COLUMN_FAMILY,
+                WriteBatchRecord::Put(&b"c"[..], &b"1"[..]),
+            ),
+            ("meta", WriteBatchRecord::Put(&b"c"[..], &b"2"[..])),
+            ("meta", WriteBatchRecord::Del(&b"a"[..])),
+        ])
+        .unwrap();
+    assert_eq!(storage.inner.mvcc().latest_commit_ts(), ts + 1);
+    assert_eq!(storage.get(b"c").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(
+        storage.get_cf("meta", b"c").unwrap(),
+        Some(Bytes::from("2"))
+    );
+    assert_eq!(storage.get_cf("meta", b"a").unwrap(), None);
+    // A batch with an unknown column family is not applied at all.
+    assert!(storage
+        .write_batch_cf(&[
+            ("meta", WriteBatchRecord::Put(&b"d"[..], &b"1"[..])),
+            ("missing", WriteBatchRecord::Put(&b"d"[..], &b"1"[..])),
+        ])
+        .is_err());
+    assert_eq!(storage.get_cf("meta", b"d").unwrap(), None);
+
+    // The memtables of all column families are flushed together.
+    storage.force_flush().unwrap();
+    let meta = storage.inner.column_family("meta").unwrap();
+    assert_eq!(meta.state.read().l0_sstables.len(), 1);
+    assert_eq!(storage.inner.state.read().l0_sstables.len(), 1);
+    storage.put_cf("meta", b"e", b"3").unwrap();
+    check_lsm_iter_result_by_key(
+        &mut storage
+            .scan_cf("meta", Bound::Unbounded, Bound::Unbounded)
+            .unwrap(),
+        vec![
+            (Bytes::from("c"), Bytes::from("2")),
+            (Bytes::from("e"), Bytes::from("3")),
+        ],
+    );
+    check_lsm_iter_result_by_key(
+        &mut storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![
+            (Bytes::from("a"), Bytes::from("default")),
+            (Bytes::from("c"), Bytes::from("1")),
+        ],
+    );
+}
+
+#[test]
+fn test_column_family_recovery() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage
+        .create_column_family("meta", no_compaction_options())
+        .unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.put_cf("meta", b"a", b"2").unwrap();
+    storage.force_flush().unwrap();
+    // Only the new column family has data in this generation.
+    storage.put_cf("meta", b"b", b"3").unwrap();
+    storage.force_flush().unwrap();
+    storage
+        .create_column_family("logs", no_compaction_options())
+        .unwrap();
+    // Left in the WAL.
+    storage.put_cf("logs", b"a", b"4").unwrap();
+    storage.put(b"b", b"5").unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(
+        storage.list_column_families(),
+        vec![
+            DEFAULT_COLUMN_FAMILY.to_string(),
+            "meta".to_string(),
+            "logs".to_string()
+        ]
+    );
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from("5")));
+    assert_eq!(
+        storage.get_cf("meta", b"a").unwrap(),
+        Some(Bytes::from("2"))
+    );
+    assert_eq!(
+        storage.get_cf("meta", b"b").unwrap(),
+        Some(Bytes::from("3"))
+    );
+    assert_eq!(
+        storage.get_cf("logs", b"a").unwrap(),
+        Some(Bytes::from("4"))
+    );
+    assert_eq!(storage.get_cf("logs", b"b").unwrap(), None);
+    assert_eq!(
+        storage
+            .inner
+            .column_family("meta")
+            .unwrap()
+            .state
+            .read()
+            .l0_sstables
+            .len(),
+        2
+    );
+    // Timestamps are shared, so that new writes are newer than the recovered ones.
+    storage.put_cf("logs", b"a", b"6").unwrap();
+    assert_eq!(
+        storage.get_cf("logs", b"a").unwrap(),
+        Some(Bytes::from("6"))
+    );
+}
+
+#[test]
+fn test_column_family_compaction() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.target_sst_size = 16 << 10;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage
+        .create_column_family("data", simple_compaction_options())
+        .unwrap();
+    let mut expected = Vec::new();
+    for i in 0..2000 {
+        let value = Bytes::from(format!("value{:05}", i).repeat(10));
+        storage.put_cf("data", &key_of(i), &value).unwrap();
+        expected.push((key_of(i), value));
+    }
+    storage.put(b"a", b"1").unwrap();
+    let data = storage.inner.column_family("data").unwrap();
+    for _ in 0..100 {
+        storage.force_flush().unwrap();
+        let snapshot = data.state.read().clone();
+        if snapshot.imm_memtables.is_empty()
+            && snapshot.memtable.is_empty()
+            && snapshot.l0_sstables.len() < 2
+            && data.running_compactions.lock().is_empty()
+        {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    // The column family is compacted, and the default column family is not.
+    let snapshot = data.state.read().clone();
+    assert!(snapshot.levels.iter().any(|(_, ssts)| !ssts.is_empty()));
+    assert!(storage.inner.state.read().levels[0].1.is_empty());
+    check_lsm_iter_result_by_key(
+        &mut storage
+            .scan_cf("data", Bound::Unbounded, Bound::Unbounded)
+            .unwrap(),
+        expected.clone(),
+    );
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    check_lsm_iter_result_by_key(
+        &mut storage
+            .scan_cf("data", Bound::Unbounded, Bound::Unbounded)
+            .unwrap(),
+        expected,
+    );
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+}
@@ -0,0 +1,118 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    iterators::StorageIterator,
+    key::KeySlice,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    table::{CompressionType, FileObject, SsTable, SsTableBuilder, SsTableIterator},
+};
+
+use super::harness::check_iter_result_by_key_and_ts;
+
+fn generate_test_data() -> Vec<((Bytes, u64), Bytes)> {
+    (0..500)
+        .map(|id| {
+            (
+                (Bytes::from(format!("key{:05}", id)), 1),
+                Bytes::from(format!(
+                    "{{\"id\":{},\"payload\":\"{}\"}}",
+                    id,
+                    "x".repeat(64)
+                )),
+            )
+        })
+        .collect()
+}
+
+fn build_sst(dir: &std::path::Path, compression: CompressionType) -> Arc<SsTable> {
+    let data = generate_test_data();
+    let mut builder = SsTableBuilder::new_with_compression(4096, compression);
+    for ((key, ts), value) in &data {
+        builder.add(KeySlice::for_testing_from_slice_with_ts(key, *ts), value);
+    }
+    let path = dir.join(format!("{:?}.sst", compression));
+    builder.build_for_test(&path).unwrap();
+    Arc::new(SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap())
+}
+
+#[test]
+fn test_sst_compression_roundtrip() {
+    let dir = tempdir().unwrap();
+    let uncompressed = build_sst(dir.path(), CompressionType::None);
+    for compression in [
+        CompressionType::Lz4,
+        CompressionType::Zstd,
+        CompressionType::Snappy,
+    ] {
+        let sst = build_sst(dir.path(), compression);
+        assert!(
+            sst.table_size() < uncompressed.table_size(),
+            "{:?} does not reduce SST size",
+            compression
+        );
+        check_iter_result_by_key_and_ts(
+            &mut SsTableIterator::create_and_seek_to_first(sst).unwrap(),
+            generate_test_data(),
+        );
+    }
+}
+
+#[test]
+fn test_incompressible_block() {
+    let dir = tempdir().unwrap();
+    let mut builder = SsTableBuilder::new_with_compression(16, CompressionType::Zstd);
+    builder.add(KeySlice::for_testing_from_slice_with_ts(b"a", 1), b"b");
+    let path = dir.path().join("1.sst");
+    builder.build_for_test(&path).unwrap();
+    let sst = Arc::new(SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap());
+    let iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+    assert_eq!(iter.key().for_testing_key_ref(), b"a");
+    assert_eq!(iter.value(), b"b");
+}
+
+#[test]
+fn test_mixed_codec_storage() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let codecs = [
+        CompressionType::Lz4,
+        CompressionType::Zstd,
+        CompressionType::Snappy,
+        CompressionType::None,
+    ];
+    for (round, compression) in codecs.iter().enumerate() {
+        options.compression = *compression;
+        let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+        for i in 0..100 {
+            storage
+                .put(
+                    format!("key{:03}", i).as_bytes(),
+                    format!("value{:03}@{}", i, round).as_bytes(),
+                )
+                .unwrap();
+        }
+        storage.force_flush().unwrap();
+        storage.close().unwrap();
+    }
+
+    // Reopen with a different codec, compact all SSTs written with mixed codecs and read back.
+    options.compression = CompressionType::Zstd;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for i in 0..100 {
+        assert_eq!(
+            storage.get(format!("key{:03}", i).as_bytes()).unwrap(),
+            Some(Bytes::from(format!("value{:03}@3", i)))
+        );
+    }
+    storage.force_full_compaction().unwrap();
+    for i in 0..100 {
+        assert_eq!(
+            storage.get(format!("key{:03}", i).as_bytes()).unwrap(),
+            Some(Bytes::from(format!("value{:03}@3", i)))
+        );
+    }
+}
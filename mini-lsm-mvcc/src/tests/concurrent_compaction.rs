@@ -0,0 +1,139 @@
+use tempfile::tempdir;
+
+use crate::{
+    compact::{
+        CompactionOptions, CompactionTask, LeveledCompactionController, LeveledCompactionOptions,
+        RunningCompactions, SimpleLeveledCompactionController, SimpleLeveledCompactionOptions,
+        TieredCompactionController, TieredCompactionOptions,
+    },
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+};
+
+use super::{harness::compaction_bench, helpers::state_with_ssts};
+
+#[test]
+fn test_leveled_concurrent_tasks() {
+    const MB: u64 = 1024 * 1024;
+    let controller = LeveledCompactionController::new(LeveledCompactionOptions {
+        level_size_multiplier: 2,
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 4,
+        base_level_size_mb: 1,
+    });
+    let mut state = state_with_ssts(&[
+        (1, MB, 0, 999),
+        (2, MB, 0, 999),
+        (3, 2 * MB, 0, 999),
+        (4, 3 * MB, 0, 999),
+        (5, MB, 0, 999),
+        (6, 4 * MB, 0, 999),
+    ]);
+    state.l0_sstables = vec![2, 1];
+    state.levels = vec![(1, vec![]), (2, vec![3]), (3, vec![4, 5]), (4, vec![6])];
+
+    let mut running = RunningCompactions::default();
+    let first = controller
+        .generate_compaction_task_with_running(&state, &running)
+        .unwrap();
+    assert_eq!(first.upper_level, None);
+    assert_eq!(first.lower_level, 2);
+    running.add(&CompactionTask::Leveled(first.clone()));
+
+    // L0 and L2 are busy, so the next task must not touch them.
+    let second = controller
+        .generate_compaction_task_with_running(&state, &running)
+        .unwrap();
+    assert_eq!(second.upper_level, Some(3));
+    assert_eq!(second.lower_level, 4);
+    running.add(&CompactionTask::Leveled(second.clone()));
+    assert_eq!(running.num_tasks(), 2);
+    assert!(running.is_sst_busy(1) && running.is_sst_busy(6));
+
+    assert!(controller
+        .generate_compaction_task_with_running(&state, &running)
+        .is_none());
+
+    running.remove(&CompactionTask::Leveled(first));
+    let third = controller
+        .generate_compaction_task_with_running(&state, &running)
+        .unwrap();
+    assert_eq!(third.upper_level, None);
+    running.remove(&CompactionTask::Leveled(second));
+    assert!(running.is_empty());
+    assert!(!running.is_level_busy(0));
+}
+
+#[test]
+fn test_simple_concurrent_tasks() {
+    let controller = SimpleLeveledCompactionController::new(SimpleLeveledCompactionOptions {
+        size_ratio_percent: 200,
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 3,
+    });
+    let mut state = state_with_ssts(&[
+        (1, 1, 0, 999),
+        (2, 1, 0, 999),
+        (3, 1, 0, 999),
+        (4, 1, 0, 999),
+    ]);
+    state.l0_sstables = vec![2, 1];
+    state.levels = vec![(1, vec![]), (2, vec![3, 4]), (3, vec![])];
+
+    let mut running = RunningCompactions::default();
+    let first = controller
+        .generate_compaction_task_with_running(&state, &running)
+        .unwrap();
+    assert_eq!((first.upper_level, first.lower_level), (None, 1));
+    running.add(&CompactionTask::Simple(first));
+
+    let second = controller
+        .generate_compaction_task_with_running(&state, &running)
+        .unwrap();
+    assert_eq!((second.upper_level, second.lower_level), (Some(2), 3));
+    running.add(&CompactionTask::Simple(second));
+    assert!(controller
+        .generate_compaction_task_with_running(&state, &running)
+        .is_none());
+}
+
+#[test]
+fn test_tiered_single_task() {
+    let controller = TieredCompactionController::new(TieredCompactionOptions {
+        num_tiers: 2,
+        max_size_amplification_percent: 200,
+        size_ratio: 1,
+        min_merge_width: 2,
+    });
+    let mut state = state_with_ssts(&[(1, 1, 0, 999), (2, 1, 0, 999), (3, 1, 0, 999)]);
+    state.levels = vec![(3, vec![3]), (2, vec![2]), (1, vec![1])];
+    let mut running = RunningCompactions::default();
+    let task = controller
+        .generate_compaction_task_with_running(&state, &running)
+        .unwrap();
+    running.add(&CompactionTask::Tiered(task));
+    assert!(controller
+        .generate_compaction_task_with_running(&state, &running)
+        .is_none());
+}
+
+#[test]
+fn test_concurrent_compaction_integration() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Leveled(
+        LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            level_size_multiplier: 2,
+            base_level_size_mb: 1,
+            max_levels: 4,
+        },
+    ));
+    options.max_background_compactions = 2;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    compaction_bench(storage.clone());
+    assert!(storage
+        .inner
+        .default_column_family()
+        .running_compactions
+        .lock()
+        .is_empty());
+}
@@ -0,0 +1,185 @@
+use std::{collections::HashMap, ops::Bound};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    column_family::ColumnFamilyOptions,
+    compact::CompactionOptions,
+    iterators::StorageIterator,
+    lsm_storage::{LsmStorageOptions, MiniLsm, WriteBatchRecord, WriteOptions},
+};
+
+fn key_of(thread: usize, i: usize) -> Bytes {
+    Bytes::from(format!("key{}_{:05}", thread, i))
+}
+
+fn value_of(thread: usize, i: usize) -> Bytes {
+    Bytes::from(format!("value{}_{:05}", thread, i))
+}
+
+#[test]
+fn test_group_commit_sync_writes() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    let initial_ts = storage.inner.mvcc().latest_commit_ts();
+    let sync = WriteOptions { sync: true };
+    std::thread::scope(|scope| {
+        for thread in 0..8 {
+            let (storage, sync) = (&storage, &sync);
+            scope.spawn(move || {
+                for i in 0..100 {
+                    let batch = [
+                        WriteBatchRecord::Put(key_of(thread, i), value_of(thread, i)),
+                        WriteBatchRecord::Del(key_of(thread, i + 1000)),
+                    ];
+                    storage.write_batch_with_options(&batch, sync).unwrap();
+                }
+            });
+        }
+    });
+    // Each write gets its own commit timestamp, but the concurrent writes share WAL syncs.
+    let stats = storage.group_commit_stats();
+    assert_eq!(stats.writes, 800);
+    assert_eq!(stats.syncs, stats.groups);
+    assert_eq!(storage.inner.mvcc().latest_commit_ts(), initial_ts + 800);
+    drop(storage);
+
+    // The synced writes are recovered from the WAL without closing the storage.
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for thread in 0..8 {
+        for i in 0..100 {
+            assert_eq!(
+                storage.get(&key_of(thread, i)).unwrap(),
+                Some(value_of(thread, i))
+            );
+        }
+    }
+    storage.close().unwrap();
+}
+
+#[test]
+fn test_group_commit_queued_writes() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let sync = WriteOptions { sync: true };
+    std::thread::scope(|scope| {
+        // The writers queue up while the write lock is held, and are committed in one group.
+        let lck = storage.inner.mvcc().write_lock.lock();
+        let writers = (0..4)
+            .map(|thread| {
+                let (storage, sync) = (&storage, &sync);
+                scope.spawn(move || {
+                    let batch = [WriteBatchRecord::Put(
+                        key_of(thread, 0),
+                        value_of(thread, 0),
+                    )];
+                    storage.write_batch_with_options(&batch, sync).unwrap();
+                })
+            })
+            .collect::<Vec<_>>();
+        while storage.inner.write_queue.num_queued() < 4 {
+            std::thread::yield_now();
+        }
+        // An invalid write panics in its own thread without being queued.
+        let invalid = scope.spawn(|| {
+            storage
+                .write_batch(&[WriteBatchRecord::Put(&b""[..], &b"value"[..])])
+                .unwrap();
+        });
+        assert!(invalid.join().is_err());
+        drop(lck);
+        for writer in writers {
+            writer.join().unwrap();
+        }
+    });
+    let stats = storage.group_commit_stats();
+    assert_eq!(stats.writes, 4);
+    assert_eq!(stats.groups, 1);
+    assert_eq!(stats.syncs, 1);
+    for thread in 0..4 {
+        assert_eq!(
+            storage.get(&key_of(thread, 0)).unwrap(),
+            Some(value_of(thread, 0))
+        );
+    }
+    storage.close().unwrap();
+}
+
+#[test]
+fn test_group_commit_batches_are_atomic() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage
+        .create_column_family(
+            "meta",
+            ColumnFamilyOptions {
+                compaction_options: CompactionOptions::NoCompaction,
+            },
+        )
+        .unwrap();
+    std::thread::scope(|scope| {
+        for thread in 0..4 {
+            let storage = &storage;
+            scope.spawn(move || {
+                for i in 0..200 {
+                    // Both keys of a thread always have the same value.
+                    let value = value_of(thread, i);
+                    storage
+                        .write_batch(&[
+                            WriteBatchRecord::Put(key_of(thread, 0), value.clone()),
+                            WriteBatchRecord::Put(key_of(thread, 1), value.clone()),
+                        ])
+                        .unwrap();
+                    storage
+                        .write_batch_cf(&[
+                            (
+                                "default",
+                                WriteBatchRecord::Put(key_of(thread, 2), value.clone()),
+                            ),
+                            ("meta", WriteBatchRecord::Put(key_of(thread, 0), value)),
+                        ])
+                        .unwrap();
+                }
+            });
+        }
+        let storage = &storage;
+        scope.spawn(move || {
+            for _ in 0..50 {
+                let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+                let mut items = HashMap::new();
+                while iter.is_valid() {
+                    items.insert(
+                        Bytes::copy_from_slice(iter.key()),
+                        Bytes::copy_from_slice(iter.value()),
+                    );
+                    iter.next().unwrap();
+                }
+                for thread in 0..4 {
+                    assert_eq!(items.get(&key_of(thread, 0)), items.get(&key_of(thread, 1)));
+                }
+            }
+        });
+    });
+    let stats = storage.group_commit_stats();
+    assert_eq!(stats.writes, 1600);
+    assert_eq!(stats.syncs, 0);
+    for thread in 0..4 {
+        for i in 0..3 {
+            assert_eq!(
+                storage.get(&key_of(thread, i)).unwrap(),
+                Some(value_of(thread, 199))
+            );
+        }
+        assert_eq!(
+            storage.get_cf("meta", &key_of(thread, 0)).unwrap(),
+            Some(value_of(thread, 199))
+        );
+    }
+    storage.close().unwrap();
+}
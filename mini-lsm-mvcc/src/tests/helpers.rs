@@ -0,0 +1,52 @@
+use std::{collections::HashMap, sync::Arc};
+
+use bytes::Bytes;
+
+use crate::{
+    key::KeyBytes,
+    lsm_storage::{LsmStorageState, MiniLsm},
+    mem_table::MemTable,
+    table::SsTable,
+};
+
+/// Creates a state with metadata-only SSTs, each given as `(id, size, first key, last key)` with
+/// the keys as indexes, and no SST in any level.
+pub fn state_with_ssts(ssts: &[(usize, u64, usize, usize)]) -> LsmStorageState {
+    let key_of =
+        |i: usize| KeyBytes::for_testing_from_bytes_no_ts(Bytes::from(format!("key{:05}", i)));
+    let mut sstables = HashMap::new();
+    for (id, size, first, last) in ssts {
+        sstables.insert(
+            *id,
+            Arc::new(SsTable::create_meta_only(
+                *id,
+                *size,
+                key_of(*first),
+                key_of(*last),
+            )),
+        );
+    }
+    LsmStorageState {
+        memtable: Arc::new(MemTable::create(0)),
+        imm_memtables: Vec::new(),
+        l0_sstables: Vec::new(),
+        levels: Vec::new(),
+        sstables,
+    }
+}
+
+pub fn key_of(i: usize) -> Bytes {
+    Bytes::from(format!("key{:05}", i))
+}
+
+pub fn value_of(i: usize) -> Bytes {
+    Bytes::from(format!("value{:05}", i))
+}
+
+/// Flushes all memtables, so that the flush thread does not add L0 SSTs after a compaction.
+pub fn flush_all(storage: &MiniLsm) {
+    storage.force_flush().unwrap();
+    while !storage.inner.state.read().imm_memtables.is_empty() {
+        storage.force_flush().unwrap();
+    }
+}
@@ -0,0 +1,193 @@
+use std::{collections::BTreeMap, ops::Bound, sync::Arc};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    iterators::{concat_iterator::SstConcatIterator, StorageIterator},
+    key::KeySlice,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    mvcc::txn::TxnIterator,
+};
+
+use super::harness::generate_sst;
+use super::helpers::{flush_all, key_of};
+
+fn value_of(i: usize, version: usize) -> Bytes {
+    Bytes::from(format!("value{:05}_v{}", i, version))
+}
+
+fn current(iter: &TxnIterator) -> Option<(Bytes, Bytes)> {
+    iter.is_valid().then(|| {
+        (
+            Bytes::copy_from_slice(iter.key()),
+            Bytes::copy_from_slice(iter.value()),
+        )
+    })
+}
+
+/// Seeks to every key and to the keys between them, and checks the position and its neighbours
+/// against `model`.
+fn check_seek(
+    iter: &mut TxnIterator,
+    model: &BTreeMap<Bytes, Bytes>,
+    lower: Bound<&[u8]>,
+    upper: Bound<&[u8]>,
+) {
+    let entry = |(key, value): (&Bytes, &Bytes)| (key.clone(), value.clone());
+    for i in 0..110 {
+        for target in [key_of(i), Bytes::from(format!("key{:05}a", i))] {
+            let range = model.range::<[u8], _>((lower, upper));
+            let mut after = range.clone().filter(|(key, _)| *key >= &target).map(entry);
+            let mut before = range.filter(|(key, _)| *key <= &target).map(entry).rev();
+
+            iter.seek(&target).unwrap();
+            assert_eq!(current(iter), after.next(), "seek to {:?}", target);
+            if iter.is_valid() {
+                iter.next().unwrap();
+                assert_eq!(current(iter), after.next());
+            }
+
+            iter.seek_for_prev(&target).unwrap();
+            assert_eq!(
+                current(iter),
+                before.next(),
+                "seek_for_prev to {:?}",
+                target
+            );
+            if iter.is_valid() {
+                iter.prev().unwrap();
+                assert_eq!(current(iter), before.next());
+            }
+        }
+    }
+}
+
+#[test]
+fn test_sst_concat_seek() {
+    let dir = tempdir().unwrap();
+    let data = (0..100)
+        .step_by(2)
+        .map(|i| (key_of(i), value_of(i, 0)))
+        .collect::<Vec<_>>();
+    let ssts = data
+        .chunks(10)
+        .enumerate()
+        .map(|(id, chunk)| {
+            Arc::new(generate_sst(
+                id,
+                dir.path().join(format!("{id}.sst")),
+                chunk.to_vec(),
+                None,
+            ))
+        })
+        .collect::<Vec<_>>();
+    let mut iter = SstConcatIterator::create_and_seek_to_first(ssts).unwrap();
+    for i in 0..110 {
+        let key = key_of(i);
+        let key = KeySlice::for_testing_from_slice_no_ts(&key);
+        let after = data.iter().find(|(x, _)| x >= &key_of(i));
+        let before = data.iter().rev().find(|(x, _)| x <= &key_of(i));
+        iter.seek(key).unwrap();
+        assert_eq!(
+            iter.is_valid().then(|| iter.key().key_ref()),
+            after.map(|(x, _)| &x[..])
+        );
+        iter.seek_for_prev(key).unwrap();
+        assert_eq!(
+            iter.is_valid().then(|| iter.key().key_ref()),
+            before.map(|(x, _)| &x[..])
+        );
+    }
+}
+
+#[test]
+fn test_seek() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.block_size = 64;
+    options.target_sst_size = 1 << 10;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let mut model = BTreeMap::new();
+    for i in (0..100).step_by(2) {
+        storage.put(&key_of(i), &value_of(i, 0)).unwrap();
+        model.insert(key_of(i), value_of(i, 0));
+    }
+    flush_all(&storage);
+    storage.force_full_compaction().unwrap();
+    for i in (1..100).step_by(4) {
+        storage.put(&key_of(i), &value_of(i, 1)).unwrap();
+        model.insert(key_of(i), value_of(i, 1));
+    }
+    flush_all(&storage);
+    for i in (0..100).step_by(6) {
+        storage.delete(&key_of(i)).unwrap();
+        model.remove(&key_of(i));
+    }
+    for i in (3..100).step_by(8) {
+        storage.put(&key_of(i), &value_of(i, 2)).unwrap();
+        model.insert(key_of(i), value_of(i, 2));
+    }
+
+    let (key10, key90) = (key_of(10), key_of(90));
+    let bounds = [
+        (Bound::Unbounded, Bound::Unbounded),
+        (Bound::Excluded(&key10[..]), Bound::Included(&key90[..])),
+        (Bound::Included(&key10[..]), Bound::Excluded(&key90[..])),
+    ];
+    for (lower, upper) in bounds {
+        check_seek(
+            &mut storage.scan(lower, upper).unwrap(),
+            &model,
+            lower,
+            upper,
+        );
+        check_seek(
+            &mut storage.scan_rev(lower, upper).unwrap(),
+            &model,
+            lower,
+            upper,
+        );
+    }
+
+    // The writes of a transaction are merged with the LSM tree.
+    let txn = storage.new_txn().unwrap();
+    txn.put(&key_of(105), b"txn");
+    txn.put(&key_of(1), b"txn");
+    txn.delete(&key_of(2));
+    let mut txn_model = model.clone();
+    txn_model.insert(key_of(105), Bytes::from("txn"));
+    txn_model.insert(key_of(1), Bytes::from("txn"));
+    txn_model.remove(&key_of(2));
+    let mut iter = txn.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    check_seek(&mut iter, &txn_model, Bound::Unbounded, Bound::Unbounded);
+}
+
+#[test]
+fn test_seek_reads_same_snapshot() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.block_size = 64;
+    options.target_sst_size = 1 << 10;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let mut model = BTreeMap::new();
+    for i in 0..50 {
+        storage.put(&key_of(i), &value_of(i, 0)).unwrap();
+        model.insert(key_of(i), value_of(i, 0));
+    }
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    let txn = storage.new_txn().unwrap();
+    let mut txn_iter = txn.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+
+    // Newer writes and flushes are not visible to the existing iterators.
+    for i in (0..60).step_by(3) {
+        storage.put(&key_of(i), &value_of(i, 1)).unwrap();
+    }
+    for i in (0..50).step_by(5) {
+        storage.delete(&key_of(i)).unwrap();
+    }
+    flush_all(&storage);
+    check_seek(&mut iter, &model, Bound::Unbounded, Bound::Unbounded);
+    check_seek(&mut txn_iter, &model, Bound::Unbounded, Bound::Unbounded);
+}
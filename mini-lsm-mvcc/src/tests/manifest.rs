@@ -0,0 +1,234 @@
+use std::path::{Path, PathBuf};
+
+use bytes::{BufMut, Bytes};
+use tempfile::tempdir;
+
+use crate::{
+    column_family::ColumnFamilyOptions,
+    compact::CompactionOptions,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    manifest::Manifest,
+};
+
+use super::helpers::{key_of, value_of};
+
+fn manifest_files(path: &Path) -> Vec<PathBuf> {
+    let mut files = std::fs::read_dir(path)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.file_name()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .starts_with("MANIFEST")
+        })
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+fn manifest_path(path: &Path, number: usize) -> PathBuf {
+    path.join(format!("MANIFEST-{:06}", number))
+}
+
+/// The number of the manifest file named by `CURRENT`.
+fn current_number(path: &Path) -> usize {
+    let current = std::fs::read_to_string(path.join("CURRENT")).unwrap();
+    current
+        .trim()
+        .strip_prefix("MANIFEST-")
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+/// The name, L0 SSTs and levels of a column family.
+type ColumnFamilySsts = (String, Vec<usize>, Vec<(usize, Vec<usize>)>);
+
+fn sst_ids(storage: &MiniLsm) -> Vec<ColumnFamilySsts> {
+    storage
+        .inner
+        .column_families()
+        .iter()
+        .map(|cf| {
+            let state = cf.state.read();
+            (
+                cf.name.clone(),
+                state.l0_sstables.clone(),
+                state.levels.clone(),
+            )
+        })
+        .collect()
+}
+
+fn write_and_flush(storage: &MiniLsm, rounds: std::ops::Range<usize>) {
+    for round in rounds {
+        for i in 0..10 {
+            storage.put(&key_of(i), &value_of(i + round)).unwrap();
+            storage
+                .put_cf("meta", &key_of(i), &value_of(i + round + 1))
+                .unwrap();
+        }
+        storage.force_flush().unwrap();
+    }
+}
+
+fn check_data(storage: &MiniLsm, round: usize) {
+    for i in 0..10 {
+        assert_eq!(storage.get(&key_of(i)).unwrap(), Some(value_of(i + round)));
+        assert_eq!(
+            storage.get_cf("meta", &key_of(i)).unwrap(),
+            Some(value_of(i + round + 1))
+        );
+    }
+}
+
+#[test]
+fn test_manifest_rollover() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    options.max_manifest_size = 1 << 10;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage
+        .create_column_family(
+            "meta",
+            ColumnFamilyOptions {
+                compaction_options: CompactionOptions::NoCompaction,
+            },
+        )
+        .unwrap();
+    write_and_flush(&storage, 0..30);
+    storage.force_full_compaction().unwrap();
+    write_and_flush(&storage, 30..40);
+    storage.put(b"unflushed", b"value").unwrap();
+    storage.sync().unwrap();
+
+    // Only the current manifest file is kept, and it has been rolled over.
+    let files = manifest_files(dir.path());
+    assert_eq!(files.len(), 1);
+    let name = files[0].file_name().unwrap().to_str().unwrap().to_string();
+    assert_ne!(name, "MANIFEST-000001");
+    let current = std::fs::read_to_string(dir.path().join("CURRENT")).unwrap();
+    assert_eq!(current.trim(), name);
+    assert!(std::fs::metadata(&files[0]).unwrap().len() <= 1 << 10);
+    let ssts = sst_ids(&storage);
+    drop(storage);
+
+    // The state is recovered from the snapshot and the records after it.
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    assert_eq!(sst_ids(&storage), ssts);
+    check_data(&storage, 39);
+    assert_eq!(
+        storage.get(b"unflushed").unwrap(),
+        Some(Bytes::from("value"))
+    );
+    write_and_flush(&storage, 40..45);
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    check_data(&storage, 44);
+    storage.close().unwrap();
+}
+
+#[test]
+fn test_manifest_legacy_json() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage
+        .create_column_family(
+            "meta",
+            ColumnFamilyOptions {
+                compaction_options: CompactionOptions::NoCompaction,
+            },
+        )
+        .unwrap();
+    write_and_flush(&storage, 0..5);
+    storage.put(b"unflushed", b"value").unwrap();
+    storage.sync().unwrap();
+    let ssts = sst_ids(&storage);
+    drop(storage);
+
+    // Rewrite the manifest in the JSON encoding of the storages created before the rollover.
+    let (manifest, records) = Manifest::recover(dir.path()).unwrap();
+    drop(manifest);
+    let mut buf = Vec::new();
+    for record in &records {
+        let json = serde_json::to_vec(record).unwrap();
+        buf.put_u64(json.len() as u64);
+        buf.put_slice(&json);
+        buf.put_u32(crc32fast::hash(&json));
+    }
+    for path in manifest_files(dir.path()) {
+        std::fs::remove_file(path).unwrap();
+    }
+    std::fs::remove_file(dir.path().join("CURRENT")).unwrap();
+    std::fs::write(dir.path().join("MANIFEST"), buf).unwrap();
+    // A file left by a crash during a previous conversion.
+    std::fs::write(dir.path().join("MANIFEST-000001"), b"stale").unwrap();
+
+    // The legacy manifest is converted to the binary encoding when the storage is opened.
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    assert_eq!(sst_ids(&storage), ssts);
+    check_data(&storage, 4);
+    assert_eq!(
+        storage.get(b"unflushed").unwrap(),
+        Some(Bytes::from("value"))
+    );
+    let files = manifest_files(dir.path());
+    assert_eq!(files.len(), 1);
+    assert!(files[0].ends_with("MANIFEST-000001"));
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(sst_ids(&storage), ssts);
+    check_data(&storage, 4);
+    storage.close().unwrap();
+}
+
+#[test]
+fn test_manifest_stale_files() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.max_manifest_size = 1 << 10;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage
+        .create_column_family(
+            "meta",
+            ColumnFamilyOptions {
+                compaction_options: CompactionOptions::NoCompaction,
+            },
+        )
+        .unwrap();
+    write_and_flush(&storage, 0..5);
+    storage.close().unwrap();
+
+    // A crash after creating the next manifest file but before switching `CURRENT` to it, and a
+    // crash after switching `CURRENT` but before deleting the previous file.
+    let number = current_number(dir.path());
+    let next_path = manifest_path(dir.path(), number + 1);
+    std::fs::write(&next_path, b"stale").unwrap();
+    let previous_path = manifest_path(dir.path(), number - 1);
+    std::fs::copy(manifest_path(dir.path(), number), &previous_path).unwrap();
+
+    // The stale files are deleted, and the manifest keeps rolling over.
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    assert!(!next_path.exists() && !previous_path.exists());
+    check_data(&storage, 4);
+    write_and_flush(&storage, 5..30);
+    assert_eq!(manifest_files(dir.path()).len(), 1);
+    // The next rollover overwrites a stale file without reopening.
+    let number = current_number(dir.path());
+    std::fs::write(manifest_path(dir.path(), number + 1), b"stale").unwrap();
+    write_and_flush(&storage, 30..40);
+    assert!(current_number(dir.path()) > number);
+    storage.close().unwrap();
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    check_data(&storage, 39);
+    assert_eq!(manifest_files(dir.path()).len(), 1);
+    storage.close().unwrap();
+}
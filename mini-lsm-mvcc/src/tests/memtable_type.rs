@@ -0,0 +1,208 @@
+use std::{collections::BTreeMap, ops::Bound, sync::Arc};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    iterators::StorageIterator,
+    key::{KeyBytes, KeySlice},
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    mem_table::{MemTable, MemTableType},
+    table::{SsTableBuilder, SsTableIterator},
+};
+
+use super::helpers::key_of;
+
+const MEMTABLE_TYPES: [MemTableType; 3] = [
+    MemTableType::SkipList,
+    MemTableType::ArenaSkipList,
+    MemTableType::HashLinkedList { bucket_count: 16 },
+];
+
+fn value_of(i: usize, ts: u64) -> Bytes {
+    Bytes::from(format!("value{:05}@{}", i, ts))
+}
+
+/// Fills a memtable with several versions of each key in a shuffled order, overwriting some
+/// versions, and returns the expected entries.
+fn fill_memtable(memtable: &MemTable) -> BTreeMap<KeyBytes, Bytes> {
+    let mut model = BTreeMap::new();
+    for round in 0..2 {
+        for i in (0..300).map(|x| x * 7 % 300) {
+            for ts in 1..=(i % 3 + 1) as u64 {
+                let value = value_of(i + round, ts);
+                memtable
+                    .put(KeySlice::from_slice(&key_of(i), ts), &value)
+                    .unwrap();
+                model.insert(KeyBytes::from_bytes_with_ts(key_of(i), ts), value);
+            }
+        }
+    }
+    model
+}
+
+fn collect(
+    mut iter: impl for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>,
+    forward: bool,
+) -> Vec<(KeyBytes, Bytes)> {
+    let mut items = Vec::new();
+    while iter.is_valid() {
+        items.push((
+            iter.key().to_key_vec().into_key_bytes(),
+            Bytes::copy_from_slice(iter.value()),
+        ));
+        if forward {
+            iter.next().unwrap();
+        } else {
+            iter.prev().unwrap();
+        }
+    }
+    items
+}
+
+#[test]
+fn test_memtable_types() {
+    for memtable_type in MEMTABLE_TYPES {
+        let memtable = MemTable::create_with_type(0, memtable_type);
+        assert!(memtable.is_empty());
+        let model = fill_memtable(&memtable);
+        for (key, value) in &model {
+            assert_eq!(memtable.get(key.as_key_slice()).as_ref(), Some(value));
+        }
+        assert_eq!(memtable.get(KeySlice::from_slice(&key_of(0), 2)), None);
+
+        // Scans of all entries, of a range and of the versions of a key, in both directions.
+        let lower = KeyBytes::from_bytes_with_ts(key_of(10), 2);
+        let upper = KeyBytes::from_bytes_with_ts(key_of(200), 1);
+        let bounds = [
+            (Bound::Unbounded, Bound::Unbounded),
+            (Bound::Included(&lower), Bound::Excluded(&upper)),
+            (Bound::Excluded(&lower), Bound::Included(&upper)),
+            (
+                Bound::Included(&KeyBytes::from_bytes_with_ts(key_of(2), 3)),
+                Bound::Included(&KeyBytes::from_bytes_with_ts(key_of(2), 0)),
+            ),
+        ];
+        for (lower, upper) in bounds {
+            let expected = model
+                .range::<KeyBytes, _>((lower, upper))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect::<Vec<_>>();
+            assert!(!expected.is_empty());
+            let scan = || {
+                memtable.scan(
+                    lower.map(|x| x.as_key_slice()),
+                    upper.map(|x| x.as_key_slice()),
+                )
+            };
+            assert_eq!(collect(scan(), true), expected, "{:?}", memtable_type);
+            let mut iter = scan();
+            iter.seek_to_last().unwrap();
+            let mut expected_rev = expected.clone();
+            expected_rev.reverse();
+            assert_eq!(collect(iter, false), expected_rev, "{:?}", memtable_type);
+        }
+
+        // Changing the direction and seeking.
+        let mut iter = memtable.scan(Bound::Unbounded, Bound::Unbounded);
+        iter.seek(KeySlice::from_slice(&key_of(5), 1)).unwrap();
+        assert_eq!(iter.key(), KeySlice::from_slice(&key_of(5), 1));
+        iter.next().unwrap();
+        assert_eq!(iter.key(), KeySlice::from_slice(&key_of(6), 1));
+        iter.prev().unwrap();
+        iter.prev().unwrap();
+        assert_eq!(iter.key(), KeySlice::from_slice(&key_of(5), 2));
+        iter.seek_for_prev(KeySlice::from_slice(&key_of(8), 0))
+            .unwrap();
+        assert_eq!(iter.key(), KeySlice::from_slice(&key_of(8), 1));
+        assert_eq!(iter.value(), value_of(9, 1));
+
+        // The flushed SST has all entries in order.
+        let dir = tempdir().unwrap();
+        let mut builder = SsTableBuilder::new(128);
+        memtable.flush(&mut builder).unwrap();
+        let sst = Arc::new(builder.build_for_test(dir.path().join("1.sst")).unwrap());
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+        for (key, value) in &model {
+            assert_eq!(iter.key(), key.as_key_slice());
+            assert_eq!(iter.value(), &value[..]);
+            iter.next().unwrap();
+        }
+        assert!(!iter.is_valid());
+    }
+}
+
+#[test]
+fn test_memtable_types_concurrent() {
+    for memtable_type in MEMTABLE_TYPES {
+        let memtable = Arc::new(MemTable::create_with_type(0, memtable_type));
+        std::thread::scope(|scope| {
+            for thread in 0..4 {
+                let memtable = memtable.clone();
+                scope.spawn(move || {
+                    for i in (0..500).map(|x| x * 4 + thread) {
+                        memtable
+                            .put(KeySlice::from_slice(&key_of(i), 1), &value_of(i, 1))
+                            .unwrap();
+                    }
+                });
+            }
+            // Scans see a sorted prefix of the writes.
+            for _ in 0..10 {
+                let items = collect(memtable.scan(Bound::Unbounded, Bound::Unbounded), true);
+                assert!(items.windows(2).all(|x| x[0].0 < x[1].0));
+            }
+        });
+        let items = collect(memtable.scan(Bound::Unbounded, Bound::Unbounded), true);
+        assert_eq!(items.len(), 2000);
+        for (i, (key, value)) in items.into_iter().enumerate() {
+            assert_eq!(key.key_ref(), key_of(i));
+            assert_eq!(value, value_of(i, 1));
+        }
+    }
+}
+
+#[test]
+fn test_memtable_types_with_storage() {
+    for memtable_type in MEMTABLE_TYPES {
+        let dir = tempdir().unwrap();
+        let mut options =
+            LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+        options.enable_wal = true;
+        options.memtable_type = memtable_type;
+        let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+        for i in 0..100 {
+            storage.put(&key_of(i), &value_of(i, 1)).unwrap();
+        }
+        storage.force_flush().unwrap();
+        for i in 0..100 {
+            if i % 2 == 0 {
+                storage.put(&key_of(i), &value_of(i, 2)).unwrap();
+            } else {
+                storage.delete(&key_of(i)).unwrap();
+            }
+        }
+        let check = |storage: &MiniLsm| {
+            for i in 0..100 {
+                let expected = (i % 2 == 0).then(|| value_of(i, 2));
+                assert_eq!(storage.get(&key_of(i)).unwrap(), expected);
+            }
+            let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+            for i in (0..100).step_by(2) {
+                assert_eq!(iter.key(), &key_of(i)[..]);
+                assert_eq!(iter.value(), &value_of(i, 2)[..]);
+                iter.next().unwrap();
+            }
+            assert!(!iter.is_valid());
+        };
+        check(&storage);
+        storage.close().unwrap();
+
+        // The memtables are recovered from the WAL, whichever type they are recovered into.
+        options.memtable_type = MemTableType::ArenaSkipList;
+        let storage = MiniLsm::open(&dir, options).unwrap();
+        check(&storage);
+        storage.close().unwrap();
+    }
+}
@@ -0,0 +1,214 @@
+use std::{ops::Bound, sync::Arc};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    iterators::{RecordKind, StorageIterator},
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    merge_operator::MergeOperator,
+    table::SsTableIterator,
+    value_log::ValueLogOptions,
+};
+
+use super::harness::check_lsm_iter_result_by_key;
+
+/// Adds up decimal numbers.
+#[derive(Debug)]
+struct CounterMergeOperator;
+
+fn parse(value: &[u8]) -> i64 {
+    std::str::from_utf8(value).unwrap().parse().unwrap()
+}
+
+impl MergeOperator for CounterMergeOperator {
+    fn full_merge(&self, _key: &[u8], existing: Option<&[u8]>, operands: &[Bytes]) -> Bytes {
+        let sum = existing.map(parse).unwrap_or(0) + operands.iter().map(|x| parse(x)).sum::<i64>();
+        // A counter that drops to zero is deleted.
+        if sum == 0 {
+            Bytes::new()
+        } else {
+            Bytes::from(sum.to_string())
+        }
+    }
+}
+
+/// Appends to a comma-separated list.
+#[derive(Debug)]
+struct AppendMergeOperator;
+
+impl MergeOperator for AppendMergeOperator {
+    fn full_merge(&self, _key: &[u8], existing: Option<&[u8]>, operands: &[Bytes]) -> Bytes {
+        let mut items = existing.map(|x| vec![x]).unwrap_or_default();
+        items.extend(operands.iter().map(|x| &x[..]));
+        Bytes::from(items.join(&b","[..]))
+    }
+}
+
+fn options_with(merge_operator: Arc<dyn MergeOperator>) -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.merge_operator = Some(merge_operator);
+    options
+}
+
+fn versions_in_ssts(storage: &MiniLsm, key: &[u8]) -> Vec<(RecordKind, Bytes)> {
+    let snapshot = storage.inner.state.read().clone();
+    let mut versions = Vec::new();
+    for sst in snapshot.sstables.values() {
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+        while iter.is_valid() {
+            if iter.key().key_ref() == key {
+                versions.push((iter.record_kind(), Bytes::copy_from_slice(iter.value())));
+            }
+            iter.next().unwrap();
+        }
+    }
+    versions
+}
+
+#[test]
+fn test_merge_read_write() {
+    let dir = tempdir().unwrap();
+    let mut options = options_with(Arc::new(AppendMergeOperator));
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.merge(b"a", b"1").unwrap();
+    storage.merge(b"a", b"2").unwrap();
+    storage.put(b"b", b"x").unwrap();
+    storage.merge(b"b", b"y").unwrap();
+    storage.put(b"c", b"x").unwrap();
+    storage.delete(b"c").unwrap();
+    storage.merge(b"c", b"z").unwrap();
+    storage.put(b"d", b"d").unwrap();
+    let snapshot = storage.new_txn().unwrap();
+    storage.merge(b"a", b"3").unwrap();
+    storage.put(b"b", b"w").unwrap();
+
+    let check = |storage: &MiniLsm| {
+        assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1,2,3")));
+        assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from("w")));
+        assert_eq!(storage.get(b"c").unwrap(), Some(Bytes::from("z")));
+        check_lsm_iter_result_by_key(
+            &mut storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+            vec![
+                (Bytes::from("a"), Bytes::from("1,2,3")),
+                (Bytes::from("b"), Bytes::from("w")),
+                (Bytes::from("c"), Bytes::from("z")),
+                (Bytes::from("d"), Bytes::from("d")),
+            ],
+        );
+        check_lsm_iter_result_by_key(
+            &mut storage
+                .scan(Bound::Included(b"b"), Bound::Excluded(b"c"))
+                .unwrap(),
+            vec![(Bytes::from("b"), Bytes::from("w"))],
+        );
+    };
+    check(&storage);
+    // Operands newer than the read timestamp are not applied.
+    assert_eq!(snapshot.get(b"a").unwrap(), Some(Bytes::from("1,2")));
+    check_lsm_iter_result_by_key(
+        &mut snapshot.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![
+            (Bytes::from("a"), Bytes::from("1,2")),
+            (Bytes::from("b"), Bytes::from("x,y")),
+            (Bytes::from("c"), Bytes::from("z")),
+            (Bytes::from("d"), Bytes::from("d")),
+        ],
+    );
+
+    // Operands are recovered from the WAL, and are combined across memtables and SSTs.
+    storage.close().unwrap();
+    drop(snapshot);
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    check(&storage);
+    storage.force_flush().unwrap();
+    storage.merge(b"a", b"4").unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1,2,3,4")));
+
+    // A range deletion deletes the operands older than it.
+    storage.delete_range(b"a", b"b").unwrap();
+    storage.merge(b"a", b"5").unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("5")));
+}
+
+#[test]
+fn test_merge_deletes_key() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options_with(Arc::new(CounterMergeOperator))).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.merge(b"b", b"2").unwrap();
+    storage.merge(b"c", b"3").unwrap();
+    storage.merge(b"b", b"-2").unwrap();
+    assert_eq!(storage.get(b"b").unwrap(), None);
+    check_lsm_iter_result_by_key(
+        &mut storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![
+            (Bytes::from("a"), Bytes::from("1")),
+            (Bytes::from("c"), Bytes::from("3")),
+        ],
+    );
+    check_lsm_iter_result_by_key(
+        &mut storage
+            .scan(Bound::Included(b"b"), Bound::Included(b"b"))
+            .unwrap(),
+        vec![],
+    );
+}
+
+#[test]
+fn test_merge_compaction() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options_with(Arc::new(CounterMergeOperator))).unwrap();
+    storage.put(b"counter", b"100").unwrap();
+    storage.force_flush().unwrap();
+    for _ in 0..3 {
+        for _ in 0..10 {
+            storage.merge(b"counter", b"1").unwrap();
+        }
+        storage.force_flush().unwrap();
+    }
+    storage.force_full_compaction().unwrap();
+    // All versions are below the watermark, so they are combined into a single value.
+    assert_eq!(
+        versions_in_ssts(&storage, b"counter"),
+        vec![(RecordKind::Value, Bytes::from("130"))]
+    );
+    assert_eq!(storage.get(b"counter").unwrap(), Some(Bytes::from("130")));
+
+    // The operands visible to a transaction are not combined with the older ones.
+    let snapshot = storage.new_txn().unwrap();
+    storage.merge(b"counter", b"5").unwrap();
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+    assert_eq!(versions_in_ssts(&storage, b"counter").len(), 2);
+    assert_eq!(snapshot.get(b"counter").unwrap(), Some(Bytes::from("130")));
+    assert_eq!(storage.get(b"counter").unwrap(), Some(Bytes::from("135")));
+    drop(snapshot);
+
+    // A counter that drops to zero is deleted at the bottom level.
+    storage.merge(b"counter", b"-135").unwrap();
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+    assert!(versions_in_ssts(&storage, b"counter").is_empty());
+    assert_eq!(storage.get(b"counter").unwrap(), None);
+}
+
+#[test]
+fn test_merge_not_configured() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert!(storage.merge(b"a", b"1").is_err());
+
+    let dir = tempdir().unwrap();
+    let mut options = options_with(Arc::new(CounterMergeOperator));
+    options.value_log = Some(ValueLogOptions {
+        min_blob_size: 1024,
+        segment_size: 64 << 10,
+        gc_garbage_ratio: 0.5,
+    });
+    assert!(MiniLsm::open(&dir, options).is_err());
+}
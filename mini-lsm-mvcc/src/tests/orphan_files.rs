@@ -0,0 +1,193 @@
+use std::path::{Path, PathBuf};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    lsm_storage::{LsmStorageInner, LsmStorageOptions, MiniLsm},
+    orphan_files::{OrphanFileAction, QUARANTINE_DIR},
+};
+
+use super::helpers::{key_of, value_of};
+
+/// Leaves behind the files of a crash: a copy of a live SST as the output of an unrecorded
+/// compaction, and a WAL of an unrecorded memtable.
+fn create_orphan_files(dir: &Path, sst_id: usize) -> (PathBuf, PathBuf) {
+    let sst_path = LsmStorageInner::path_of_sst_static(dir, 100);
+    std::fs::copy(LsmStorageInner::path_of_sst_static(dir, sst_id), &sst_path).unwrap();
+    let wal_path = LsmStorageInner::path_of_wal_static(dir, 101);
+    std::fs::write(&wal_path, b"").unwrap();
+    (sst_path, wal_path)
+}
+
+#[test]
+fn test_orphan_files() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for round in 0..3 {
+        for i in 0..10 {
+            storage.put(&key_of(i), &value_of(i + round)).unwrap();
+        }
+        storage.force_flush().unwrap();
+    }
+    storage.put(b"unflushed", b"value").unwrap();
+    storage.sync().unwrap();
+    assert!(storage.orphan_files().is_empty());
+    let sst_id = storage.inner.state.read().l0_sstables[0];
+    drop(storage);
+
+    let (sst_path, wal_path) = create_orphan_files(dir.path(), sst_id);
+    let check = |storage: &MiniLsm| {
+        for i in 0..10 {
+            assert_eq!(storage.get(&key_of(i)).unwrap(), Some(value_of(i + 2)));
+        }
+        assert_eq!(
+            storage.get(b"unflushed").unwrap(),
+            Some(Bytes::from("value"))
+        );
+    };
+
+    // A dry run only reports the files.
+    options.orphan_file_action = OrphanFileAction::DryRun;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    let report = storage.orphan_files();
+    assert_eq!(report.sst_files, vec![sst_path.clone()]);
+    assert_eq!(report.wal_files, vec![wal_path.clone()]);
+    assert_eq!(report.action, OrphanFileAction::DryRun);
+    assert!(sst_path.exists() && wal_path.exists());
+    check(&storage);
+    drop(storage);
+
+    // The files are moved out of the way, and are not found again.
+    options.orphan_file_action = OrphanFileAction::Quarantine;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    assert_eq!(storage.orphan_files().sst_files, vec![sst_path.clone()]);
+    assert_eq!(storage.orphan_files().wal_files, vec![wal_path.clone()]);
+    assert!(!sst_path.exists() && !wal_path.exists());
+    let quarantine_path = dir.path().join(QUARANTINE_DIR);
+    assert!(quarantine_path.join(sst_path.file_name().unwrap()).exists());
+    assert!(quarantine_path.join(wal_path.file_name().unwrap()).exists());
+    check(&storage);
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    assert!(storage.orphan_files().is_empty());
+    drop(storage);
+
+    let (sst_path, wal_path) = create_orphan_files(dir.path(), sst_id);
+    options.orphan_file_action = OrphanFileAction::Delete;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(storage.orphan_files().sst_files, vec![sst_path.clone()]);
+    assert!(!sst_path.exists() && !wal_path.exists());
+    check(&storage);
+    storage.close().unwrap();
+}
+
+#[test]
+fn test_orphan_files_of_compaction() {
+    // The inputs of a recorded compaction, which were not deleted before a crash.
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for round in 0..3 {
+        for i in 0..10 {
+            storage.put(&key_of(i), &value_of(i + round)).unwrap();
+        }
+        storage.force_flush().unwrap();
+    }
+    let inputs = storage.inner.state.read().l0_sstables.clone();
+    let backups = inputs
+        .iter()
+        .map(|id| {
+            let path = LsmStorageInner::path_of_sst_static(&dir, *id);
+            (path.clone(), std::fs::read(path).unwrap())
+        })
+        .collect::<Vec<_>>();
+    storage.force_full_compaction().unwrap();
+    storage.close().unwrap();
+    for (path, data) in &backups {
+        std::fs::write(path, data).unwrap();
+    }
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let mut expected = backups
+        .into_iter()
+        .map(|(path, _)| path)
+        .collect::<Vec<_>>();
+    expected.sort();
+    assert_eq!(storage.orphan_files().sst_files, expected);
+    assert!(expected.iter().all(|path| !path.exists()));
+    for i in 0..10 {
+        assert_eq!(storage.get(&key_of(i)).unwrap(), Some(value_of(i + 2)));
+    }
+    storage.close().unwrap();
+}
+
+/// The largest id of the SSTs and WALs in the directory.
+fn max_file_id(dir: &Path) -> usize {
+    std::fs::read_dir(dir)
+        .unwrap()
+        .filter_map(|entry| {
+            let path = entry.unwrap().path();
+            match path.extension()?.to_str()? {
+                "sst" | "wal" => path.file_stem()?.to_str()?.parse::<usize>().ok(),
+                _ => None,
+            }
+        })
+        .max()
+        .unwrap()
+}
+
+#[test]
+fn test_orphan_files_with_next_ids() {
+    // The files of a crash after allocating the next ids: the WAL of a new memtable, and the SST
+    // of a flush or a compaction.
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for i in 0..10 {
+        storage.put(&key_of(i), &value_of(i)).unwrap();
+    }
+    storage.force_flush().unwrap();
+    storage.put(b"unflushed", b"value").unwrap();
+    let sst_id = storage.inner.state.read().l0_sstables[0];
+    storage.close().unwrap();
+
+    let next_id = max_file_id(dir.path()) + 1;
+    let wal_path = LsmStorageInner::path_of_wal_static(&dir, next_id);
+    std::fs::write(&wal_path, b"").unwrap();
+    let sst_path = LsmStorageInner::path_of_sst_static(&dir, next_id + 1);
+    std::fs::copy(LsmStorageInner::path_of_sst_static(&dir, sst_id), &sst_path).unwrap();
+    let sst_data = std::fs::read(&sst_path).unwrap();
+
+    // The ids of the files left by a dry run are not allocated again.
+    options.orphan_file_action = OrphanFileAction::DryRun;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    assert_eq!(storage.orphan_files().wal_files, vec![wal_path.clone()]);
+    assert_eq!(storage.orphan_files().sst_files, vec![sst_path.clone()]);
+    for i in 0..10 {
+        storage.put(&key_of(i), &value_of(i + 1)).unwrap();
+    }
+    storage.force_flush().unwrap();
+    storage.close().unwrap();
+    assert_eq!(std::fs::read(&wal_path).unwrap(), b"");
+    assert_eq!(std::fs::read(&sst_path).unwrap(), sst_data);
+
+    options.orphan_file_action = OrphanFileAction::Delete;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(storage.orphan_files().wal_files, vec![wal_path.clone()]);
+    assert_eq!(storage.orphan_files().sst_files, vec![sst_path.clone()]);
+    assert!(!wal_path.exists() && !sst_path.exists());
+    for i in 0..10 {
+        assert_eq!(storage.get(&key_of(i)).unwrap(), Some(value_of(i + 1)));
+    }
+    assert_eq!(
+        storage.get(b"unflushed").unwrap(),
+        Some(Bytes::from("value"))
+    );
+    storage.close().unwrap();
+}
@@ -0,0 +1,151 @@
+use std::{collections::BTreeMap, ops::Bound, sync::Arc};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    block_cache::BlockCache,
+    compact::CompactionOptions,
+    iterators::StorageIterator,
+    key::KeySlice,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    prefix_extractor::{FixedPrefixExtractor, PrefixExtractor},
+    table::{FileObject, SsTable, SsTableBuilder, SsTableIterator},
+};
+
+use super::helpers::key_of;
+
+fn value_of(i: usize, version: usize) -> Bytes {
+    Bytes::from(format!("value{:05}_v{}", i, version))
+}
+
+#[test]
+fn test_partitioned_sst() {
+    let dir = tempdir().unwrap();
+    let extractor: Arc<dyn PrefixExtractor> = Arc::new(FixedPrefixExtractor::new(6));
+    let mut builder = SsTableBuilder::new(128);
+    builder.set_index_partition_size(128);
+    builder.set_prefix_extractor(extractor.clone());
+    for i in (0..1000).step_by(2) {
+        builder.add(
+            KeySlice::for_testing_from_slice_no_ts(&key_of(i)),
+            &value_of(i, 0),
+        );
+    }
+    let path = dir.path().join("1.sst");
+    let built = builder.build_for_test(&path).unwrap();
+    let block_cache = Arc::new(BlockCache::new(1 << 20));
+    let sst = SsTable::open(1, Some(block_cache), FileObject::open(&path).unwrap()).unwrap();
+
+    // Only the top-level index is kept in memory.
+    let index = sst.partitioned_index.as_ref().unwrap();
+    assert!(index.partitions.len() > 1);
+    assert_eq!(Some(index), built.partitioned_index.as_ref());
+    assert!(sst.block_meta.is_empty());
+    assert!(sst.bloom.is_none());
+    assert_eq!(sst.num_of_blocks(), built.num_of_blocks());
+    assert_eq!(sst.read_block_meta().unwrap().len(), sst.num_of_blocks());
+    assert_eq!(sst.first_key().key_ref(), &key_of(0)[..]);
+    assert_eq!(sst.last_key().key_ref(), &key_of(998)[..]);
+
+    let sst = Arc::new(sst);
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+    for i in (0..1000).step_by(2) {
+        assert_eq!(iter.key().key_ref(), &key_of(i)[..]);
+        assert_eq!(iter.value(), &value_of(i, 0)[..]);
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+    for i in 0..1000 {
+        let iter = SsTableIterator::create_and_seek_to_key(
+            sst.clone(),
+            KeySlice::for_testing_from_slice_no_ts(&key_of(i)),
+        )
+        .unwrap();
+        let expected = i + i % 2;
+        if expected < 1000 {
+            assert_eq!(iter.key().key_ref(), &key_of(expected)[..]);
+        } else {
+            assert!(!iter.is_valid());
+        }
+    }
+
+    // The filter partitions have the keys and the prefixes of their blocks.
+    for i in (0..1000).step_by(2) {
+        assert!(sst.may_contain_key(&key_of(i)).unwrap());
+    }
+    let absent = (1..1000)
+        .step_by(2)
+        .filter(|i| !sst.may_contain_key(&key_of(*i)).unwrap())
+        .count();
+    assert!(absent > 450, "too many false positives: {}", 500 - absent);
+    assert!(!sst.may_contain_key(b"zzz").unwrap());
+    for prefix in [&b"key000"[..], b"key005", b"key009"] {
+        assert!(sst.may_contain_prefix(extractor.as_ref(), prefix).unwrap());
+    }
+    assert!(!sst
+        .may_contain_prefix(extractor.as_ref(), b"key010")
+        .unwrap());
+}
+
+#[test]
+fn test_partitioned_index_integration() {
+    let dir = tempdir().unwrap();
+    let options = |index_partition_size| {
+        let mut options =
+            LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+        options.block_size = 64;
+        options.target_sst_size = 1 << 12;
+        options.index_partition_size = index_partition_size;
+        options
+    };
+    let storage = MiniLsm::open(&dir, options(Some(256))).unwrap();
+    let mut model = BTreeMap::new();
+    for version in 0..3 {
+        for i in (0..600).step_by(version + 1) {
+            storage.put(&key_of(i), &value_of(i, version)).unwrap();
+            model.insert(key_of(i), value_of(i, version));
+        }
+        for i in (0..600).step_by(7 + version) {
+            storage.delete(&key_of(i)).unwrap();
+            model.remove(&key_of(i));
+        }
+        storage.force_flush().unwrap();
+        while !storage.inner.state.read().imm_memtables.is_empty() {
+            storage.force_flush().unwrap();
+        }
+        if version == 1 {
+            storage.force_full_compaction().unwrap();
+        }
+    }
+
+    let check = |storage: &MiniLsm| {
+        for i in 0..600 {
+            assert_eq!(
+                storage.get(&key_of(i)).unwrap(),
+                model.get(&key_of(i)).cloned()
+            );
+        }
+        let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+        for (key, value) in &model {
+            assert_eq!(iter.key(), &key[..]);
+            assert_eq!(iter.value(), &value[..]);
+            iter.next().unwrap();
+        }
+        assert!(!iter.is_valid());
+    };
+    check(&storage);
+    {
+        let state = storage.inner.state.read();
+        assert!(!state.sstables.is_empty());
+        for sst in state.sstables.values() {
+            assert!(sst.partitioned_index.is_some());
+        }
+    }
+    storage.close().unwrap();
+    drop(storage);
+
+    // The partitioned SSTs are read after reopening without partitioning.
+    let storage = MiniLsm::open(&dir, options(None)).unwrap();
+    check(&storage);
+}
@@ -0,0 +1,191 @@
+use std::{collections::BTreeMap, ops::Bound, sync::Arc};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    iterators::StorageIterator,
+    key::KeySlice,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    mvcc::txn::TxnIterator,
+    prefix_extractor::{prefix_upper_bound, FixedPrefixExtractor, PrefixExtractor},
+    table::{FileObject, SsTable, SsTableBuilder},
+};
+
+fn prefix_of(i: usize) -> Bytes {
+    Bytes::from(format!("p{:03}", i))
+}
+
+fn key_of(prefix: usize, i: usize) -> Bytes {
+    Bytes::from(format!("p{:03}_key{:03}", prefix, i))
+}
+
+fn collect(iter: &mut TxnIterator) -> Vec<(Bytes, Bytes)> {
+    let mut items = Vec::new();
+    while iter.is_valid() {
+        items.push((
+            Bytes::copy_from_slice(iter.key()),
+            Bytes::copy_from_slice(iter.value()),
+        ));
+        iter.next().unwrap();
+    }
+    items
+}
+
+fn expected(model: &BTreeMap<Bytes, Bytes>, prefix: &[u8]) -> Vec<(Bytes, Bytes)> {
+    model
+        .iter()
+        .filter(|(key, _)| key.starts_with(prefix))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect()
+}
+
+fn options_with(prefix_len: usize) -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.prefix_extractor = Some(Arc::new(FixedPrefixExtractor::new(prefix_len)));
+    options
+}
+
+#[test]
+fn test_prefix_upper_bound() {
+    assert_eq!(prefix_upper_bound(b"abc"), Some(b"abd".to_vec()));
+    assert_eq!(prefix_upper_bound(b"a\xff\xff"), Some(b"b".to_vec()));
+    assert_eq!(prefix_upper_bound(b"\xff\xff"), None);
+    assert_eq!(prefix_upper_bound(b""), None);
+    let extractor = FixedPrefixExtractor::new(4);
+    assert_eq!(extractor.prefix(b"p001_key"), Some(&b"p001"[..]));
+    assert_eq!(extractor.prefix(b"p00"), None);
+}
+
+#[test]
+fn test_sst_prefix_bloom() {
+    let dir = tempdir().unwrap();
+    let extractor: Arc<dyn PrefixExtractor> = Arc::new(FixedPrefixExtractor::new(4));
+    let mut builder = SsTableBuilder::new(128);
+    builder.set_prefix_extractor(extractor.clone());
+    for prefix in [1, 5, 9] {
+        for i in 0..10 {
+            builder.add(
+                KeySlice::for_testing_from_slice_no_ts(&key_of(prefix, i)),
+                b"value",
+            );
+        }
+    }
+    let path = dir.path().join("1.sst");
+    builder.build_for_test(&path).unwrap();
+    let file = FileObject::open(&path).unwrap();
+    let sst = SsTable::open_for_test(file).unwrap();
+    for prefix in [1, 5, 9] {
+        assert!(sst
+            .may_contain_prefix(extractor.as_ref(), &prefix_of(prefix))
+            .unwrap());
+    }
+    let absent = (10..100)
+        .filter(|x| {
+            !sst.may_contain_prefix(extractor.as_ref(), &prefix_of(*x))
+                .unwrap()
+        })
+        .count();
+    assert!(absent >= 85, "too many false positives: {}", 90 - absent);
+    // Not a whole prefix of the extractor.
+    assert!(sst.may_contain_prefix(extractor.as_ref(), b"p07").unwrap());
+    assert!(sst
+        .may_contain_prefix(extractor.as_ref(), b"p077_")
+        .unwrap());
+    // Built with another extractor.
+    assert!(sst
+        .may_contain_prefix(&FixedPrefixExtractor::new(3), b"p07")
+        .unwrap());
+}
+
+#[test]
+fn test_scan_prefix() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options_with(4)).unwrap();
+    let mut model = BTreeMap::new();
+    // Every SST spans a wide key range, but only has the keys of two prefixes.
+    for sst in 0..5 {
+        for prefix in [sst, sst + 10] {
+            for i in 0..10 {
+                let value = Bytes::from(format!("value{}_{}", sst, i));
+                storage.put(&key_of(prefix, i), &value).unwrap();
+                model.insert(key_of(prefix, i), value);
+            }
+        }
+        storage.force_flush().unwrap();
+    }
+    storage.delete(&key_of(12, 3)).unwrap();
+    model.remove(&key_of(12, 3));
+
+    for prefix in 0..20 {
+        let prefix = prefix_of(prefix);
+        assert_eq!(
+            collect(&mut storage.scan_prefix(&prefix).unwrap()),
+            expected(&model, &prefix),
+        );
+    }
+    // Shorter prefixes are not filtered.
+    assert_eq!(
+        collect(&mut storage.scan_prefix(b"p01").unwrap()),
+        expected(&model, b"p01"),
+    );
+    assert_eq!(
+        collect(&mut storage.scan_prefix(b"p002_key00").unwrap()),
+        expected(&model, b"p002_key00"),
+    );
+
+    // The SSTs without the prefix are skipped, while a range scan reads all of them.
+    let (lower, upper) = (prefix_of(7), prefix_of(8));
+    let range_iter = storage
+        .scan(Bound::Included(&lower), Bound::Excluded(&upper))
+        .unwrap();
+    assert!(!range_iter.is_valid());
+    assert!(range_iter.num_active_iterators() >= 5);
+    let prefix_iter = storage.scan_prefix(&prefix_of(7)).unwrap();
+    assert!(!prefix_iter.is_valid());
+    assert!(prefix_iter.num_active_iterators() < range_iter.num_active_iterators());
+
+    // The iterator stays within the prefix when moving and seeking.
+    let mut iter = storage.scan_prefix(&prefix_of(3)).unwrap();
+    iter.seek(&key_of(2, 5)).unwrap();
+    assert_eq!(iter.key(), &key_of(3, 0)[..]);
+    iter.seek_to_last().unwrap();
+    assert_eq!(iter.key(), &key_of(3, 9)[..]);
+    iter.next().unwrap();
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_scan_prefix_after_changing_extractor() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options_with(4)).unwrap();
+    let mut model = BTreeMap::new();
+    for prefix in 0..20 {
+        for i in 0..5 {
+            storage.put(&key_of(prefix, i), b"value").unwrap();
+            model.insert(key_of(prefix, i), Bytes::from("value"));
+        }
+        if prefix % 5 == 4 {
+            storage.force_flush().unwrap();
+        }
+    }
+    storage.close().unwrap();
+
+    // The SSTs built with the old extractor are not filtered by the new one.
+    let storage = MiniLsm::open(&dir, options_with(3)).unwrap();
+    for prefix in [&b"p00"[..], b"p01", b"p02"] {
+        assert_eq!(
+            collect(&mut storage.scan_prefix(prefix).unwrap()),
+            expected(&model, prefix),
+        );
+    }
+    let mut options = options_with(4);
+    options.prefix_extractor = None;
+    storage.close().unwrap();
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(
+        collect(&mut storage.scan_prefix(&prefix_of(13)).unwrap()),
+        expected(&model, &prefix_of(13)),
+    );
+}
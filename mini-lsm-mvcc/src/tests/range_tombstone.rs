@@ -0,0 +1,232 @@
+use std::{ops::Bound, sync::Arc};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    iterators::StorageIterator,
+    lsm_storage::{LsmStorageOptions, MiniLsm, WriteBatchRecord},
+    range_tombstone::{RangeTombstone, RangeTombstones},
+    table::{FileObject, SsTable, SsTableBuilder, SsTableIterator},
+};
+
+use super::harness::check_lsm_iter_result_by_key;
+use super::helpers::{flush_all, key_of, value_of};
+
+fn expected_except(range: std::ops::Range<usize>) -> Vec<(Bytes, Bytes)> {
+    (0..100)
+        .filter(|i| !range.contains(i))
+        .map(|i| (key_of(i), value_of(i)))
+        .collect()
+}
+
+fn num_keys_in_ssts(storage: &MiniLsm) -> usize {
+    let snapshot = storage.inner.state.read().clone();
+    let mut num_keys = 0;
+    for sst in snapshot.sstables.values() {
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+        while iter.is_valid() {
+            num_keys += 1;
+            iter.next().unwrap();
+        }
+    }
+    num_keys
+}
+
+#[test]
+fn test_range_tombstone_sst_encoding() {
+    let dir = tempdir().unwrap();
+    let mut builder = SsTableBuilder::new(128);
+    builder.add_range_tombstone(RangeTombstone::new(b"c", b"f", 5));
+    builder.add_range_tombstone(RangeTombstone::new(b"a", b"b", 3));
+    let path = dir.path().join("1.sst");
+    let sst = builder.build_for_test(&path).unwrap();
+    assert_eq!(sst.num_of_blocks(), 0);
+    assert_eq!(sst.first_key().key_ref(), b"a");
+    assert_eq!(sst.last_key().key_ref(), b"f");
+    assert_eq!(sst.max_ts(), 5);
+
+    let sst = Arc::new(SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap());
+    assert_eq!(
+        sst.range_tombstones(),
+        &[
+            RangeTombstone::new(b"a", b"b", 3),
+            RangeTombstone::new(b"c", b"f", 5)
+        ]
+    );
+    assert!(!SsTableIterator::create_and_seek_to_first(sst.clone())
+        .unwrap()
+        .is_valid());
+}
+
+#[test]
+fn test_range_tombstone_overlapping() {
+    let tombstones = RangeTombstones::new(
+        [
+            RangeTombstone::new(b"a", b"m", 3),
+            RangeTombstone::new(b"c", b"e", 7),
+            RangeTombstone::new(b"d", b"z", 5),
+            RangeTombstone::new(b"x", b"x", 9),
+        ],
+        6,
+    );
+    assert!(tombstones.covers(b"a", 2));
+    assert!(!tombstones.covers(b"a", 3));
+    // The tombstone at 7 is not visible at 6.
+    assert!(!tombstones.covers(b"c", 4));
+    assert!(tombstones.covers(b"d", 4));
+    assert!(!tombstones.covers(b"d", 5));
+    assert!(tombstones.covers(b"x", 4));
+    assert!(!tombstones.covers(b"z", 0));
+    assert!(!tombstones.covers(b"0", 0));
+}
+
+#[test]
+fn test_range_tombstone_read_write() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for i in 0..100 {
+        storage.put(&key_of(i), &value_of(i)).unwrap();
+    }
+    storage.force_flush().unwrap();
+    let snapshot = storage.new_txn().unwrap();
+    assert!(storage.delete_range(&key_of(20), &key_of(10)).is_err());
+    storage.delete_range(&key_of(10), &key_of(20)).unwrap();
+    // Only versions older than the tombstone are deleted.
+    storage.put(&key_of(15), &value_of(15)).unwrap();
+    let mut expected = expected_except(10..20);
+    expected.insert(10, (key_of(15), value_of(15)));
+
+    let check = |storage: &MiniLsm| {
+        assert_eq!(storage.get(&key_of(9)).unwrap(), Some(value_of(9)));
+        assert_eq!(storage.get(&key_of(10)).unwrap(), None);
+        assert_eq!(storage.get(&key_of(15)).unwrap(), Some(value_of(15)));
+        assert_eq!(storage.get(&key_of(19)).unwrap(), None);
+        assert_eq!(storage.get(&key_of(20)).unwrap(), Some(value_of(20)));
+        check_lsm_iter_result_by_key(
+            &mut storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+            expected.clone(),
+        );
+        check_lsm_iter_result_by_key(
+            &mut storage
+                .scan(Bound::Excluded(&key_of(9)), Bound::Included(&key_of(20)))
+                .unwrap(),
+            vec![(key_of(15), value_of(15)), (key_of(20), value_of(20))],
+        );
+    };
+    check(&storage);
+    // A transaction started before the range deletion still reads the deleted keys.
+    assert_eq!(snapshot.get(&key_of(10)).unwrap(), Some(value_of(10)));
+    check_lsm_iter_result_by_key(
+        &mut snapshot.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        expected_except(0..0),
+    );
+
+    // The tombstone is recovered from the WAL, and is flushed to an SST.
+    storage.close().unwrap();
+    drop(snapshot);
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    check(&storage);
+    storage.force_flush().unwrap();
+    check(&storage);
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    check(&storage);
+
+    // A range deletion can be part of a write batch.
+    storage
+        .write_batch(&[
+            WriteBatchRecord::DelRange(&key_of(0)[..], &key_of(5)[..]),
+            WriteBatchRecord::Put(&key_of(3)[..], &b"batch"[..]),
+        ])
+        .unwrap();
+    // Records in a batch share the commit timestamp, so the put is not deleted.
+    assert_eq!(storage.get(&key_of(3)).unwrap(), Some(Bytes::from("batch")));
+    assert_eq!(storage.get(&key_of(4)).unwrap(), None);
+}
+
+#[test]
+fn test_range_tombstone_compaction() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for i in 0..100 {
+        storage.put(&key_of(i), &value_of(i)).unwrap();
+    }
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+    let snapshot = storage.new_txn().unwrap();
+    storage.delete_range(&key_of(10), &key_of(40)).unwrap();
+    storage.force_flush().unwrap();
+
+    // The deleted versions are still visible to a transaction, so they are kept.
+    storage.force_full_compaction().unwrap();
+    assert_eq!(num_keys_in_ssts(&storage), 100);
+    assert_eq!(snapshot.get(&key_of(10)).unwrap(), Some(value_of(10)));
+    assert_eq!(storage.get(&key_of(10)).unwrap(), None);
+    let expected = expected_except(10..40);
+    check_lsm_iter_result_by_key(
+        &mut storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        expected.clone(),
+    );
+
+    // Both the deleted versions and the tombstone are dropped once below the watermark.
+    drop(snapshot);
+    storage.force_full_compaction().unwrap();
+    assert_eq!(num_keys_in_ssts(&storage), 70);
+    let state = storage.inner.state.read().clone();
+    assert!(state
+        .sstables
+        .values()
+        .all(|sst| sst.range_tombstones().is_empty()));
+    check_lsm_iter_result_by_key(
+        &mut storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        expected,
+    );
+}
+
+#[test]
+fn test_range_tombstone_split_across_ssts() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.block_size = 64;
+    options.target_sst_size = 1 << 10;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for i in 0..100 {
+        storage.put(&key_of(i), &value_of(i)).unwrap();
+    }
+    flush_all(&storage);
+    storage.force_full_compaction().unwrap();
+    // Keeps the tombstone in the output of the compaction.
+    let _snapshot = storage.new_txn().unwrap();
+    storage.delete_range(&key_of(10), &key_of(90)).unwrap();
+    flush_all(&storage);
+    storage.force_full_compaction().unwrap();
+
+    let state = storage.inner.state.read().clone();
+    let ssts_with_tombstones = state
+        .sstables
+        .values()
+        .filter(|sst| !sst.range_tombstones().is_empty())
+        .count();
+    assert!(ssts_with_tombstones > 1);
+    for sst in state.sstables.values() {
+        for tombstone in sst.range_tombstones() {
+            assert!(sst.first_key().key_ref() <= tombstone.start.as_ref());
+            assert!(tombstone.end.as_ref() <= sst.last_key().key_ref());
+        }
+    }
+    check_lsm_iter_result_by_key(
+        &mut storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        expected_except(10..90),
+    );
+    for i in 0..100 {
+        let expected = (!(10..90).contains(&i)).then(|| value_of(i));
+        assert_eq!(storage.get(&key_of(i)).unwrap(), expected);
+    }
+}
@@ -0,0 +1,93 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    rate_limiter::{IoPriority, RateLimiter},
+};
+
+#[test]
+fn test_rate_limiter_throughput() {
+    // 10KB per 100ms refill period
+    let limiter = RateLimiter::new(100 << 10, false);
+    assert_eq!(limiter.single_burst_bytes(), 10 << 10);
+    let start = Instant::now();
+    for _ in 0..5 {
+        limiter.request(10 << 10, IoPriority::Low);
+    }
+    assert!(start.elapsed() >= Duration::from_millis(350));
+    assert_eq!(limiter.total_bytes_through(IoPriority::Low), 50 << 10);
+    assert_eq!(limiter.total_requests(IoPriority::Low), 5);
+    assert_eq!(limiter.total_bytes_through(IoPriority::High), 0);
+}
+
+#[test]
+fn test_rate_limiter_priority() {
+    let limiter = Arc::new(RateLimiter::new(100 << 10, false));
+    let low = {
+        let limiter = limiter.clone();
+        std::thread::spawn(move || {
+            limiter.request(100 << 10, IoPriority::Low);
+            Instant::now()
+        })
+    };
+    std::thread::sleep(Duration::from_millis(150));
+    let high = {
+        let limiter = limiter.clone();
+        std::thread::spawn(move || {
+            limiter.request(20 << 10, IoPriority::High);
+            Instant::now()
+        })
+    };
+    let high_done = high.join().unwrap();
+    let low_done = low.join().unwrap();
+    assert!(high_done < low_done);
+}
+
+#[test]
+fn test_rate_limiter_auto_tune() {
+    let limiter = RateLimiter::new(1 << 20, true);
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_millis(1500) {
+        limiter.request(1, IoPriority::Low);
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    assert!(limiter.bytes_per_sec() < 1 << 20);
+    assert!(limiter.bytes_per_sec() >= (1 << 20) / 20);
+}
+
+#[test]
+fn test_rate_limited_flush_and_compaction() {
+    let dir = tempdir().unwrap();
+    let limiter = Arc::new(RateLimiter::new(16 << 20, false));
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.rate_limiter = Some(limiter.clone());
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for round in 0..3 {
+        for i in 0..1000 {
+            storage
+                .put(
+                    format!("key{:05}", i).as_bytes(),
+                    format!("value{}@{}", i, round).as_bytes(),
+                )
+                .unwrap();
+        }
+        storage.force_flush().unwrap();
+    }
+    assert!(limiter.total_bytes_through(IoPriority::High) > 0);
+    assert_eq!(limiter.total_bytes_through(IoPriority::Low), 0);
+    storage.force_full_compaction().unwrap();
+    assert!(limiter.total_bytes_through(IoPriority::Low) > 0);
+    for i in 0..1000 {
+        assert_eq!(
+            storage.get(format!("key{:05}", i).as_bytes()).unwrap(),
+            Some(Bytes::from(format!("value{}@2", i)))
+        );
+    }
+}
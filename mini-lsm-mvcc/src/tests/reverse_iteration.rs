@@ -0,0 +1,314 @@
+use std::{collections::BTreeMap, ops::Bound, sync::Arc};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    iterators::{concat_iterator::SstConcatIterator, StorageIterator},
+    key::KeySlice,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    merge_operator::MergeOperator,
+    mvcc::txn::TxnIterator,
+    table::SsTableIterator,
+};
+
+use super::harness::generate_sst;
+use super::helpers::{flush_all, key_of};
+
+fn value_of(i: usize, version: usize) -> Bytes {
+    Bytes::from(format!("value{:05}_v{}", i, version))
+}
+
+fn collect(iter: &mut TxnIterator) -> Vec<(Bytes, Bytes)> {
+    let mut items = Vec::new();
+    while iter.is_valid() {
+        items.push((
+            Bytes::copy_from_slice(iter.key()),
+            Bytes::copy_from_slice(iter.value()),
+        ));
+        iter.next().unwrap();
+    }
+    items
+}
+
+fn collect_rev(iter: &mut TxnIterator) -> Vec<(Bytes, Bytes)> {
+    let mut items = Vec::new();
+    while iter.is_valid() {
+        items.push((
+            Bytes::copy_from_slice(iter.key()),
+            Bytes::copy_from_slice(iter.value()),
+        ));
+        iter.prev().unwrap();
+    }
+    items
+}
+
+fn expected_rev(
+    model: &BTreeMap<Bytes, Bytes>,
+    lower: Bound<&[u8]>,
+    upper: Bound<&[u8]>,
+) -> Vec<(Bytes, Bytes)> {
+    model
+        .range::<[u8], _>((lower, upper))
+        .rev()
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect()
+}
+
+/// Moves the iterator back and forth, and checks it against `expected` at every step.
+fn check_walk(iter: &mut TxnIterator, expected: &[(Bytes, Bytes)], mut pos: usize) {
+    let mut seed = 17u64;
+    for _ in 0..300 {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key(), &expected[pos].0[..]);
+        assert_eq!(iter.value(), &expected[pos].1[..]);
+        seed = seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        match (seed >> 33) % 8 {
+            0 => {
+                iter.seek_to_first().unwrap();
+                pos = 0;
+            }
+            1 => {
+                iter.seek_to_last().unwrap();
+                pos = expected.len() - 1;
+            }
+            x if x % 2 == 0 && pos > 0 => {
+                iter.prev().unwrap();
+                pos -= 1;
+            }
+            _ if pos + 1 < expected.len() => {
+                iter.next().unwrap();
+                pos += 1;
+            }
+            _ => {
+                iter.prev().unwrap();
+                pos -= 1;
+            }
+        }
+    }
+    // Moves past both ends.
+    iter.seek_to_last().unwrap();
+    iter.next().unwrap();
+    assert!(!iter.is_valid());
+    iter.seek_to_first().unwrap();
+    iter.prev().unwrap();
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_sst_iterator_reverse() {
+    let dir = tempdir().unwrap();
+    let data = (0..100)
+        .map(|i| (key_of(i), value_of(i, 0)))
+        .collect::<Vec<_>>();
+    let sst1 = Arc::new(generate_sst(
+        1,
+        dir.path().join("1.sst"),
+        data[..50].to_vec(),
+        None,
+    ));
+    let sst2 = Arc::new(generate_sst(
+        2,
+        dir.path().join("2.sst"),
+        data[50..].to_vec(),
+        None,
+    ));
+    assert!(sst1.num_of_blocks() > 1);
+
+    let mut iter = SsTableIterator::create_and_seek_to_last(sst1.clone()).unwrap();
+    for (key, value) in data[..50].iter().rev() {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key().key_ref(), &key[..]);
+        assert_eq!(iter.value(), &value[..]);
+        iter.prev().unwrap();
+    }
+    assert!(!iter.is_valid());
+
+    let mut iter = SstConcatIterator::create_and_seek_to_last(vec![sst1, sst2.clone()]).unwrap();
+    for (key, _) in data.iter().rev() {
+        assert_eq!(iter.key().key_ref(), &key[..]);
+        iter.prev().unwrap();
+    }
+    assert!(!iter.is_valid());
+
+    // Changes the direction in the middle of the table.
+    let mut iter = SsTableIterator::create_and_seek_to_key(
+        sst2,
+        KeySlice::for_testing_from_slice_no_ts(&key_of(70)),
+    )
+    .unwrap();
+    iter.prev().unwrap();
+    assert_eq!(iter.key().key_ref(), &key_of(69)[..]);
+    iter.next().unwrap();
+    iter.next().unwrap();
+    assert_eq!(iter.key().key_ref(), &key_of(71)[..]);
+}
+
+#[test]
+fn test_scan_rev() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.block_size = 64;
+    options.target_sst_size = 1 << 10;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let mut model = BTreeMap::new();
+    for i in 0..100 {
+        storage.put(&key_of(i), &value_of(i, 0)).unwrap();
+        model.insert(key_of(i), value_of(i, 0));
+    }
+    flush_all(&storage);
+    storage.force_full_compaction().unwrap();
+    for i in (0..100).step_by(3) {
+        storage.put(&key_of(i), &value_of(i, 1)).unwrap();
+        model.insert(key_of(i), value_of(i, 1));
+    }
+    flush_all(&storage);
+    for i in (0..100).step_by(7) {
+        storage.delete(&key_of(i)).unwrap();
+        model.remove(&key_of(i));
+    }
+    let snapshot = storage.new_txn().unwrap();
+    let snapshot_model = model.clone();
+    for i in (0..100).step_by(5) {
+        storage.put(&key_of(i), &value_of(i, 2)).unwrap();
+        model.insert(key_of(i), value_of(i, 2));
+    }
+
+    let (key10, key50, key95) = (&key_of(10)[..], &key_of(50)[..], &key_of(95)[..]);
+    let bounds = [
+        (Bound::Unbounded, Bound::Unbounded),
+        (Bound::Included(key10), Bound::Included(key50)),
+        (Bound::Excluded(key10), Bound::Excluded(key50)),
+        (Bound::Included(key95), Bound::Unbounded),
+        (Bound::Unbounded, Bound::Excluded(key10)),
+        (Bound::Included(&b"zzz"[..]), Bound::Unbounded),
+    ];
+    for (lower, upper) in bounds {
+        assert_eq!(
+            collect_rev(&mut storage.scan_rev(lower, upper).unwrap()),
+            expected_rev(&model, lower, upper),
+        );
+        assert_eq!(
+            collect_rev(&mut snapshot.scan_rev(lower, upper).unwrap()),
+            expected_rev(&snapshot_model, lower, upper),
+        );
+    }
+
+    // The writes of a transaction are merged with the LSM tree.
+    let txn = storage.new_txn().unwrap();
+    txn.put(&key_of(200), b"txn");
+    txn.put(&key_of(1), b"txn");
+    txn.delete(&key_of(2));
+    model.insert(key_of(200), Bytes::from("txn"));
+    model.insert(key_of(1), Bytes::from("txn"));
+    model.remove(&key_of(2));
+    assert_eq!(
+        collect_rev(&mut txn.scan_rev(Bound::Unbounded, Bound::Unbounded).unwrap()),
+        expected_rev(&model, Bound::Unbounded, Bound::Unbounded),
+    );
+}
+
+#[test]
+fn test_bidirectional_iteration() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.block_size = 64;
+    options.target_sst_size = 1 << 10;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let mut model = BTreeMap::new();
+    for i in (0..100).step_by(2) {
+        storage.put(&key_of(i), &value_of(i, 0)).unwrap();
+        model.insert(key_of(i), value_of(i, 0));
+    }
+    flush_all(&storage);
+    storage.force_full_compaction().unwrap();
+    for i in (1..100).step_by(4) {
+        storage.put(&key_of(i), &value_of(i, 1)).unwrap();
+        model.insert(key_of(i), value_of(i, 1));
+    }
+    flush_all(&storage);
+    for i in (0..100).step_by(6) {
+        storage.delete(&key_of(i)).unwrap();
+        model.remove(&key_of(i));
+    }
+    for i in (3..100).step_by(8) {
+        storage.put(&key_of(i), &value_of(i, 2)).unwrap();
+        model.insert(key_of(i), value_of(i, 2));
+    }
+
+    let expected = expected_rev(&model, Bound::Unbounded, Bound::Unbounded)
+        .into_iter()
+        .rev()
+        .collect::<Vec<_>>();
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    check_walk(&mut iter, &expected, 0);
+    let mut iter = storage
+        .scan_rev(Bound::Unbounded, Bound::Unbounded)
+        .unwrap();
+    check_walk(&mut iter, &expected, expected.len() - 1);
+
+    let (lower, upper) = (key_of(10), key_of(90));
+    let (lower, upper) = (Bound::Excluded(&lower[..]), Bound::Included(&upper[..]));
+    let expected = expected_rev(&model, lower, upper)
+        .into_iter()
+        .rev()
+        .collect::<Vec<_>>();
+    let mut iter = storage.scan(lower, upper).unwrap();
+    check_walk(&mut iter, &expected, 0);
+    let mut iter = storage.scan_rev(lower, upper).unwrap();
+    check_walk(&mut iter, &expected, expected.len() - 1);
+}
+
+/// Appends to a comma-separated list.
+#[derive(Debug)]
+struct AppendMergeOperator;
+
+impl MergeOperator for AppendMergeOperator {
+    fn full_merge(&self, _key: &[u8], existing: Option<&[u8]>, operands: &[Bytes]) -> Bytes {
+        let mut items = existing.map(|x| vec![x]).unwrap_or_default();
+        items.extend(operands.iter().map(|x| &x[..]));
+        Bytes::from(items.join(&b","[..]))
+    }
+}
+
+#[test]
+fn test_reverse_merge_and_range_tombstones() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.merge_operator = Some(Arc::new(AppendMergeOperator));
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let check = |storage: &MiniLsm| {
+        let forward = collect(&mut storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap());
+        let mut backward = collect_rev(
+            &mut storage
+                .scan_rev(Bound::Unbounded, Bound::Unbounded)
+                .unwrap(),
+        );
+        backward.reverse();
+        assert_eq!(forward, backward);
+        forward
+    };
+    for i in 0..20 {
+        storage.put(&key_of(i), b"0").unwrap();
+    }
+    storage.force_flush().unwrap();
+    for i in (0..20).step_by(2) {
+        storage.merge(&key_of(i), b"1").unwrap();
+    }
+    storage.delete_range(&key_of(5), &key_of(10)).unwrap();
+    for i in (0..20).step_by(3) {
+        storage.merge(&key_of(i), b"2").unwrap();
+    }
+    let expected = check(&storage);
+    assert_eq!(expected.len(), 17);
+    assert_eq!(expected[0], (key_of(0), Bytes::from("0,1,2")));
+    assert_eq!(expected[5], (key_of(6), Bytes::from("2")));
+    assert_eq!(expected[6], (key_of(9), Bytes::from("2")));
+    storage.force_flush().unwrap();
+    assert_eq!(check(&storage), expected);
+    storage.force_full_compaction().unwrap();
+    assert_eq!(check(&storage), expected);
+}
@@ -0,0 +1,138 @@
+use std::ops::Bound;
+use std::sync::atomic::Ordering;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{CompactionOptions, LeveledCompactionOptions, TieredCompactionOptions},
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+};
+
+use super::harness::{check_lsm_iter_result_by_key, compaction_bench};
+use super::helpers::{flush_all, key_of};
+
+#[test]
+fn test_subcompaction_full_compaction() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.block_size = 256;
+    options.target_sst_size = 4096;
+    options.max_subcompactions = 4;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for round in 0..3 {
+        for i in 0..500 {
+            let key = format!("key{:05}", i);
+            if round == 2 && i % 3 == 0 {
+                storage.delete(key.as_bytes()).unwrap();
+            } else {
+                storage
+                    .put(key.as_bytes(), format!("value{}@{}", i, round).as_bytes())
+                    .unwrap();
+            }
+        }
+        storage.force_flush().unwrap();
+    }
+    // Flush all memtables, so that the flush thread does not add L0 SSTs after the compaction.
+    while !storage.inner.state.read().imm_memtables.is_empty() {
+        storage.force_flush().unwrap();
+    }
+    storage.force_full_compaction().unwrap();
+
+    let snapshot = storage.inner.state.read().clone();
+    assert!(snapshot.l0_sstables.is_empty());
+    let ssts = snapshot.levels[0]
+        .1
+        .iter()
+        .map(|id| snapshot.sstables[id].clone())
+        .collect::<Vec<_>>();
+    assert!(ssts.len() > 1);
+    for pair in ssts.windows(2) {
+        assert!(pair[0].last_key().key_ref() < pair[1].first_key().key_ref());
+    }
+
+    let mut expected = Vec::new();
+    for i in 0..500 {
+        let key = format!("key{:05}", i);
+        let value = storage.get(key.as_bytes()).unwrap();
+        if i % 3 == 0 {
+            assert_eq!(value, None);
+        } else {
+            let expected_value = Bytes::from(format!("value{}@2", i));
+            assert_eq!(value, Some(expected_value.clone()));
+            expected.push((Bytes::from(key), expected_value));
+        }
+    }
+    check_lsm_iter_result_by_key(
+        &mut storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        expected,
+    );
+}
+
+#[test]
+fn test_subcompaction_leveled() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Leveled(
+        LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            level_size_multiplier: 2,
+            base_level_size_mb: 1,
+            max_levels: 4,
+        },
+    ));
+    options.max_subcompactions = 4;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    compaction_bench(storage.clone());
+}
+
+#[test]
+fn test_subcompaction_tiered() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Tiered(
+        TieredCompactionOptions {
+            num_tiers: 3,
+            max_size_amplification_percent: 200,
+            size_ratio: 1,
+            min_merge_width: 2,
+        },
+    ));
+    options.max_subcompactions = 4;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    compaction_bench(storage.clone());
+}
+
+#[test]
+fn test_subcompaction_threads_bounded() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.block_size = 256;
+    options.target_sst_size = 4096;
+    options.max_subcompactions = 4;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for round in 0..2 {
+        for i in 0..500 {
+            storage
+                .put(&key_of(i), format!("value{}@{}", i, round).as_bytes())
+                .unwrap();
+        }
+        storage.force_flush().unwrap();
+    }
+    flush_all(&storage);
+
+    // First all subcompaction threads are taken by other compactions, so the key ranges run on
+    // the thread of the compaction, then one thread is left for them.
+    let threads = &storage.inner.subcompaction_threads;
+    threads.store(3, Ordering::SeqCst);
+    storage.force_full_compaction().unwrap();
+    assert_eq!(threads.load(Ordering::SeqCst), 3);
+    threads.store(2, Ordering::SeqCst);
+    storage.force_full_compaction().unwrap();
+    assert_eq!(threads.load(Ordering::SeqCst), 2);
+
+    for i in 0..500 {
+        assert_eq!(
+            storage.get(&key_of(i)).unwrap(),
+            Some(Bytes::from(format!("value{}@1", i)))
+        );
+    }
+}
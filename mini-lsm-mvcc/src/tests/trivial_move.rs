@@ -0,0 +1,122 @@
+use std::time::Duration;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{
+        CompactionOptions, LeveledCompactionController, LeveledCompactionOptions,
+        SimpleLeveledCompactionController, SimpleLeveledCompactionOptions,
+        TieredCompactionController, TieredCompactionOptions,
+    },
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+};
+
+use super::helpers::state_with_ssts;
+
+#[test]
+fn test_leveled_trivial_move_task() {
+    let controller = LeveledCompactionController::new(LeveledCompactionOptions {
+        level_size_multiplier: 2,
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 2,
+        base_level_size_mb: 1,
+    });
+    let mut state = state_with_ssts(&[(1, 1024, 0, 99), (2, 1024, 100, 199), (3, 1024, 300, 399)]);
+    state.l0_sstables = vec![2, 1];
+    state.levels = vec![(1, vec![]), (2, vec![3])];
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert!(task.is_trivial_move);
+    assert!(task.lower_level_sst_ids.is_empty());
+    let (state, removed) = controller.apply_compaction_result(&state, &task, &[1, 2]);
+    assert!(removed.is_empty());
+    assert!(state.l0_sstables.is_empty());
+
+    // overlapping L0 SSTs must be rewritten
+    let mut state = state_with_ssts(&[(1, 1024, 0, 150), (2, 1024, 100, 199), (3, 1024, 300, 399)]);
+    state.l0_sstables = vec![2, 1];
+    state.levels = vec![(1, vec![]), (2, vec![3])];
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert!(!task.is_trivial_move);
+}
+
+#[test]
+fn test_simple_trivial_move_task() {
+    let controller = SimpleLeveledCompactionController::new(SimpleLeveledCompactionOptions {
+        size_ratio_percent: 200,
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 2,
+    });
+    let mut state = state_with_ssts(&[(1, 1024, 0, 99), (2, 1024, 100, 199), (3, 1024, 300, 399)]);
+    state.l0_sstables = vec![2, 1];
+    state.levels = vec![(1, vec![3]), (2, vec![])];
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert!(task.is_trivial_move);
+    let (state, removed) = controller.apply_compaction_result(&state, &task, &[1, 2, 3]);
+    assert!(removed.is_empty());
+    assert_eq!(state.levels[0].1, vec![1, 2, 3]);
+}
+
+#[test]
+fn test_tiered_trivial_move_task() {
+    let controller = TieredCompactionController::new(TieredCompactionOptions {
+        num_tiers: 2,
+        max_size_amplification_percent: 200,
+        size_ratio: 1,
+        min_merge_width: 2,
+    });
+    let mut state = state_with_ssts(&[(1, 1024, 0, 99), (2, 1024, 100, 199), (3, 1024, 300, 399)]);
+    state.levels = vec![(3, vec![3]), (2, vec![2]), (1, vec![1])];
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert!(task.is_trivial_move);
+    let (state, removed) = controller.apply_compaction_result(&state, &task, &[1, 2, 3]);
+    assert!(removed.is_empty());
+    assert_eq!(state.levels, vec![(1, vec![1, 2, 3])]);
+}
+
+#[test]
+fn test_trivial_move_integration() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Leveled(
+        LeveledCompactionOptions {
+            level_size_multiplier: 2,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+            base_level_size_mb: 1,
+        },
+    ));
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    let mut flushed = Vec::new();
+    // Sequential inserts produce non-overlapping L0 SSTs.
+    for round in 0..4 {
+        for i in round * 100..(round + 1) * 100 {
+            storage
+                .put(format!("key{:05}", i).as_bytes(), b"value")
+                .unwrap();
+        }
+        storage.force_flush().unwrap();
+        flushed.push(*storage.inner.state.read().l0_sstables.first().unwrap());
+    }
+    std::thread::sleep(Duration::from_secs(1));
+    {
+        let snapshot = storage.inner.state.read();
+        let in_levels = snapshot
+            .levels
+            .iter()
+            .flat_map(|(_, ssts)| ssts.iter().copied())
+            .collect::<Vec<_>>();
+        for id in &flushed {
+            assert!(in_levels.contains(id), "{} was rewritten", id);
+        }
+    }
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for i in 0..400 {
+        assert_eq!(
+            storage.get(format!("key{:05}", i).as_bytes()).unwrap(),
+            Some(Bytes::from_static(b"value"))
+        );
+    }
+}
@@ -0,0 +1,227 @@
+use std::{ops::Bound, path::Path};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    value_log::{ValueLog, ValueLogOptions},
+};
+
+use super::harness::check_lsm_iter_result_by_key;
+use super::helpers::key_of;
+
+fn value_log_options() -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.value_log = Some(ValueLogOptions {
+        min_blob_size: 1024,
+        segment_size: 64 << 10,
+        gc_garbage_ratio: 0.5,
+    });
+    options
+}
+
+fn large_value(i: usize, round: usize) -> Bytes {
+    Bytes::from(format!("{:05}@{}", i, round).repeat(256))
+}
+
+fn segments(path: &Path) -> Vec<usize> {
+    ValueLog::list_segments(path).unwrap()
+}
+
+#[test]
+fn test_value_log_read_write() {
+    let dir = tempdir().unwrap();
+    let mut options = value_log_options();
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    let mut expected = Vec::new();
+    for i in 0..100 {
+        let value = if i % 2 == 0 {
+            large_value(i, 0)
+        } else {
+            Bytes::from(format!("small{}", i))
+        };
+        storage.put(&key_of(i), &value).unwrap();
+        expected.push((key_of(i), value));
+    }
+    storage.delete(&key_of(0)).unwrap();
+    expected.remove(0);
+    storage.force_flush().unwrap();
+    storage.put(&key_of(100), &large_value(100, 0)).unwrap();
+    expected.push((key_of(100), large_value(100, 0)));
+
+    let check = |storage: &MiniLsm| {
+        for (key, value) in &expected {
+            assert_eq!(storage.get(key).unwrap(), Some(value.clone()));
+        }
+        assert_eq!(storage.get(&key_of(0)).unwrap(), None);
+        check_lsm_iter_result_by_key(
+            &mut storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+            expected.clone(),
+        );
+    };
+    check(&storage);
+    // Values are not stored in the SSTs.
+    let snapshot = storage.inner.state.read().clone();
+    let sst_size = snapshot
+        .sstables
+        .values()
+        .map(|sst| sst.table_size())
+        .sum::<u64>();
+    assert!(sst_size < 50 * 1024);
+    assert!(segments(dir.path()).len() > 1);
+
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    check(&storage);
+}
+
+#[test]
+fn test_value_log_gc() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, value_log_options()).unwrap();
+    for i in 0..100 {
+        storage.put(&key_of(i), &large_value(i, 0)).unwrap();
+    }
+    storage.force_flush().unwrap();
+    let old_segments = segments(dir.path());
+    // Overwrite half of the keys, so that compaction finds the old values to be garbage.
+    for i in (0..100).step_by(2) {
+        storage.put(&key_of(i), &large_value(i, 1)).unwrap();
+    }
+    storage.force_flush().unwrap();
+    assert!(storage.gc_value_log().unwrap().is_empty());
+    storage.force_full_compaction().unwrap();
+
+    let collected = storage.gc_value_log().unwrap();
+    assert!(!collected.is_empty());
+    for segment_id in &collected {
+        assert!(old_segments.contains(segment_id));
+    }
+    // The relocated values are still in the memtable, so the segments are not deleted yet.
+    for segment_id in &collected {
+        assert!(segments(dir.path()).contains(segment_id));
+    }
+    storage.force_flush().unwrap();
+    storage.gc_value_log().unwrap();
+    for segment_id in &collected {
+        assert!(!segments(dir.path()).contains(segment_id));
+    }
+
+    let check = |storage: &MiniLsm| {
+        for i in 0..100 {
+            assert_eq!(
+                storage.get(&key_of(i)).unwrap(),
+                Some(large_value(i, if i % 2 == 0 { 1 } else { 0 }))
+            );
+        }
+    };
+    check(&storage);
+    storage.force_full_compaction().unwrap();
+    check(&storage);
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, value_log_options()).unwrap();
+    check(&storage);
+}
+
+#[test]
+fn test_value_log_gc_with_snapshot() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, value_log_options()).unwrap();
+    for i in 0..100 {
+        storage.put(&key_of(i), &large_value(i, 0)).unwrap();
+    }
+    storage.force_flush().unwrap();
+    let txn = storage.new_txn().unwrap();
+    for i in 0..100 {
+        storage.put(&key_of(i), &large_value(i, 1)).unwrap();
+    }
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+    // The old values are visible to the transaction, and are not garbage.
+    assert!(storage.gc_value_log().unwrap().is_empty());
+    drop(txn);
+
+    // Overwrite half of the keys, and relocate the other half while a transaction can still see
+    // their old locations.
+    for i in (0..100).step_by(2) {
+        storage.put(&key_of(i), &large_value(i, 2)).unwrap();
+    }
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+    let txn = storage.new_txn().unwrap();
+    let collected = storage.gc_value_log().unwrap();
+    assert!(!collected.is_empty());
+    storage.force_flush().unwrap();
+    storage.gc_value_log().unwrap();
+    // Segments with relocated values are kept for the transaction.
+    assert!(collected
+        .iter()
+        .any(|segment_id| segments(dir.path()).contains(segment_id)));
+    for i in 0..100 {
+        let round = if i % 2 == 0 { 2 } else { 1 };
+        assert_eq!(txn.get(&key_of(i)).unwrap(), Some(large_value(i, round)));
+    }
+    drop(txn);
+    storage.gc_value_log().unwrap();
+    for segment_id in &collected {
+        assert!(!segments(dir.path()).contains(segment_id));
+    }
+    for i in (1..100).step_by(2) {
+        assert_eq!(storage.get(&key_of(i)).unwrap(), Some(large_value(i, 1)));
+    }
+}
+
+#[test]
+fn test_value_log_gc_one_segment_per_pass() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, value_log_options()).unwrap();
+    for i in 0..200 {
+        storage.put(&key_of(i), &large_value(i, 0)).unwrap();
+    }
+    storage.force_flush().unwrap();
+    for i in (0..200).step_by(2) {
+        storage.put(&key_of(i), &large_value(i, 1)).unwrap();
+    }
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+
+    let mut collected = Vec::new();
+    while let Some(segment_id) = storage.inner.gc_value_log_once().unwrap() {
+        assert!(!collected.contains(&segment_id));
+        collected.push(segment_id);
+    }
+    assert!(collected.len() > 1);
+    storage.force_flush().unwrap();
+    storage.gc_value_log().unwrap();
+    for segment_id in &collected {
+        assert!(!segments(dir.path()).contains(segment_id));
+    }
+    for i in 0..200 {
+        assert_eq!(
+            storage.get(&key_of(i)).unwrap(),
+            Some(large_value(i, if i % 2 == 0 { 1 } else { 0 }))
+        );
+    }
+}
+
+#[test]
+fn test_value_log_option_mismatch() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.put(b"key", b"value").unwrap();
+    storage.close().unwrap();
+    drop(storage);
+    assert!(MiniLsm::open(&dir, value_log_options()).is_err());
+
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, value_log_options()).unwrap();
+    storage.close().unwrap();
+    drop(storage);
+    assert!(MiniLsm::open(&dir, options).is_err());
+}
@@ -0,0 +1,357 @@
+use std::{
+    fs::File,
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
+};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    key::KeySlice,
+    lsm_storage::{LsmStorageOptions, MiniLsm, WriteBatchRecord},
+    wal::{Wal, WalBatch, WalOptions, WalRecordKind, WalRecoveryMode},
+};
+
+use super::helpers::key_of;
+
+const MODES: [WalRecoveryMode; 3] = [
+    WalRecoveryMode::TolerateCorruptedTailRecords,
+    WalRecoveryMode::AbsoluteConsistency,
+    WalRecoveryMode::PointInTimeRecovery,
+];
+
+/// The values are large enough for the records to span several blocks, and some of them are
+/// larger than a block.
+fn value_of(i: usize) -> Bytes {
+    let len = if i % 10 == 9 { 40000 } else { 1000 + i };
+    Bytes::from(vec![b'a' + (i % 26) as u8; len])
+}
+
+/// Writes records to a new WAL, and returns the file offset after each record.
+fn write_wal(path: &Path, log_number: usize, options: &WalOptions, count: usize) -> Vec<u64> {
+    let wal = Wal::create(path, log_number, options).unwrap();
+    let mut offsets = Vec::new();
+    for i in 0..count {
+        wal.put(0, KeySlice::from_slice(&key_of(i), 1), &value_of(i))
+            .unwrap();
+        wal.sync().unwrap();
+        offsets.push(File::open(path).unwrap().metadata().unwrap().len());
+    }
+    offsets
+}
+
+/// Replays a WAL, and returns the indexes of the replayed records and whether records were dropped.
+fn replay_wal(
+    path: &Path,
+    log_number: usize,
+    recovery_mode: WalRecoveryMode,
+) -> anyhow::Result<(Vec<usize>, bool)> {
+    let mut replayed = Vec::new();
+    let (_, dropped) = Wal::recover(path, log_number, recovery_mode, |_, _, key, value| {
+        let i = replayed.len();
+        assert_eq!(key.key_ref(), key_of(i));
+        assert_eq!(value, value_of(i));
+        replayed.push(i);
+        Ok(())
+    })?;
+    Ok((replayed, dropped))
+}
+
+fn copy_wal(dir: &Path, path: &Path, name: &str) -> PathBuf {
+    let copy = dir.join(name);
+    std::fs::copy(path, &copy).unwrap();
+    copy
+}
+
+#[test]
+fn test_wal_torn_tail() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.wal");
+    let offsets = write_wal(&path, 1, &WalOptions::default(), 30);
+    assert!(offsets[29] > 4 * (32 << 10));
+    let (replayed, dropped) = replay_wal(&path, 1, WalRecoveryMode::AbsoluteConsistency).unwrap();
+    assert_eq!(replayed.len(), 30);
+    assert!(!dropped);
+
+    // The last record, which spans two blocks, is partially written.
+    let torn = (offsets[28] + offsets[29]) / 2;
+    File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_len(torn)
+        .unwrap();
+    for mode in MODES {
+        let path = copy_wal(dir.path(), &path, "2.wal");
+        match replay_wal(&path, 1, mode) {
+            Ok((replayed, dropped)) => {
+                assert_ne!(mode, WalRecoveryMode::AbsoluteConsistency);
+                assert_eq!(replayed.len(), 29);
+                assert!(dropped);
+                // The torn record is truncated, so that the WAL can be replayed again.
+                assert_eq!(
+                    File::open(&path).unwrap().metadata().unwrap().len(),
+                    offsets[28]
+                );
+                let (replayed, dropped) =
+                    replay_wal(&path, 1, WalRecoveryMode::AbsoluteConsistency).unwrap();
+                assert_eq!((replayed.len(), dropped), (29, false));
+            }
+            Err(_) => assert_eq!(mode, WalRecoveryMode::AbsoluteConsistency),
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn test_wal_corruption() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.wal");
+    let offsets = write_wal(&path, 1, &WalOptions::default(), 30);
+
+    // A corruption in the middle of the WAL is only tolerated by point-in-time recovery, which
+    // drops all records after it.
+    let file = File::options().write(true).open(&path).unwrap();
+    file.write_all_at(b"x", offsets[4] + 100).unwrap();
+    for mode in MODES {
+        let path = copy_wal(dir.path(), &path, "2.wal");
+        match replay_wal(&path, 1, mode) {
+            Ok((replayed, dropped)) => {
+                assert_eq!(mode, WalRecoveryMode::PointInTimeRecovery);
+                assert_eq!(replayed.len(), 5);
+                assert!(dropped);
+            }
+            Err(_) => assert_ne!(mode, WalRecoveryMode::PointInTimeRecovery),
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    // A corruption in the last block is a torn write.
+    let path = dir.path().join("3.wal");
+    let offsets = write_wal(&path, 1, &WalOptions::default(), 30);
+    let file = File::options().write(true).open(&path).unwrap();
+    file.write_all_at(b"x", offsets[29] - 10).unwrap();
+    let (replayed, dropped) =
+        replay_wal(&path, 1, WalRecoveryMode::TolerateCorruptedTailRecords).unwrap();
+    assert_eq!((replayed.len(), dropped), (29, true));
+}
+
+#[test]
+fn test_wal_preallocate_and_reuse() {
+    let dir = tempdir().unwrap();
+    let options = WalOptions {
+        preallocate_size: 1 << 20,
+        ..Default::default()
+    };
+    let path = dir.path().join("1.wal");
+    let offsets = write_wal(&path, 1, &options, 30);
+    // The size of the file does not change with the writes.
+    assert!(offsets.iter().all(|offset| *offset == 1 << 20));
+    for mode in MODES {
+        let (replayed, dropped) = replay_wal(&path, 1, mode).unwrap();
+        assert_eq!((replayed.len(), dropped), (30, false));
+    }
+
+    // A reused WAL ignores the records of its previous use.
+    let new_path = dir.path().join("2.wal");
+    let wal = Wal::reuse(&path, &new_path, 2, &options).unwrap();
+    for i in 0..5 {
+        wal.put(0, KeySlice::from_slice(&key_of(i), 1), &value_of(i))
+            .unwrap();
+    }
+    wal.sync().unwrap();
+    assert!(!path.exists());
+    for mode in MODES {
+        let (replayed, dropped) = replay_wal(&new_path, 2, mode).unwrap();
+        assert_eq!((replayed.len(), dropped), (5, false));
+    }
+}
+
+fn wal_files(dir: &Path) -> Vec<PathBuf> {
+    std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|x| x == "wal"))
+        .collect()
+}
+
+#[test]
+fn test_storage_wal_recycling() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    options.wal = WalOptions {
+        recovery_mode: WalRecoveryMode::AbsoluteConsistency,
+        preallocate_size: 64 << 10,
+        recycle_file_num: 2,
+    };
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for round in 0..6 {
+        for i in 0..20 {
+            storage.put(&key_of(i), &value_of(i + round)).unwrap();
+        }
+        storage.force_flush().unwrap();
+        // The current WAL and the recycled one, which is reused by the next memtable.
+        let wal_files = wal_files(dir.path());
+        assert_eq!(wal_files.len(), 2);
+        for path in wal_files {
+            assert!(File::open(path).unwrap().metadata().unwrap().len() >= 64 << 10);
+        }
+    }
+    for i in 0..10 {
+        storage.put(&key_of(i), &value_of(i)).unwrap();
+    }
+    storage.sync().unwrap();
+    // Crash without flushing the memtable.
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for i in 0..20 {
+        let expected = if i < 10 { i } else { i + 5 };
+        assert_eq!(storage.get(&key_of(i)).unwrap(), Some(value_of(expected)));
+    }
+    storage.close().unwrap();
+}
+
+#[test]
+fn test_storage_point_in_time_recovery() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    options.num_memtable_limit = 1000;
+    options.wal.recovery_mode = WalRecoveryMode::PointInTimeRecovery;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    // Two WALs of frozen memtables.
+    for round in 0..2 {
+        for i in 0..20 {
+            storage.put(&key_of(round * 20 + i), &value_of(i)).unwrap();
+        }
+        let state_lock = storage.inner.state_lock.lock();
+        storage.inner.force_freeze_memtable(&state_lock).unwrap();
+    }
+    storage.sync().unwrap();
+    let wal_ids = storage
+        .inner
+        .state
+        .read()
+        .imm_memtables
+        .iter()
+        .map(|memtable| memtable.id())
+        .rev()
+        .collect::<Vec<_>>();
+    drop(storage);
+
+    // Corrupt a record in the first block of the first WAL.
+    let path = dir.path().join(format!("{:05}.wal", wal_ids[0]));
+    let file = File::options().write(true).open(&path).unwrap();
+    file.write_all_at(b"x", 5000).unwrap();
+
+    // The records before the corruption are recovered, and all later ones are dropped, also after
+    // the storage is opened again.
+    for _ in 0..2 {
+        let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+        let recovered = (0..40)
+            .map(|i| storage.get(&key_of(i)).unwrap().is_some())
+            .collect::<Vec<_>>();
+        let count = recovered.iter().filter(|x| **x).count();
+        assert!(count > 0 && count < 10, "{:?}", recovered);
+        assert!(recovered[..count].iter().all(|x| *x));
+        drop(storage);
+    }
+}
+
+#[test]
+fn test_wal_batches() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.wal");
+    let wal = Wal::create(&path, 1, &WalOptions::default()).unwrap();
+    let mut small = WalBatch::new(5);
+    small.put(0, b"a", b"1");
+    small.put_merge(1, b"b", b"2");
+    small.put_range_tombstone(0, b"c", b"d");
+    small.put(1, b"e", b"");
+    // A batch spanning several blocks.
+    let mut large = WalBatch::new(6);
+    for i in 0..100 {
+        large.put(0, &key_of(i), &value_of(i));
+    }
+    wal.append(&[small, large]).unwrap();
+    wal.sync().unwrap();
+    let len = File::open(&path).unwrap().metadata().unwrap().len();
+
+    let replay = |path: &Path| {
+        let mut entries = Vec::new();
+        Wal::recover(
+            path,
+            1,
+            WalRecoveryMode::TolerateCorruptedTailRecords,
+            |column_family_id, kind, key, value| {
+                entries.push((column_family_id, kind, key, value));
+                Ok(())
+            },
+        )
+        .unwrap();
+        entries
+    };
+    let entries = replay(&path);
+    assert_eq!(entries.len(), 104);
+    assert_eq!(
+        entries[..4]
+            .iter()
+            .map(|(id, kind, key, value)| (*id, *kind, key.key_ref(), key.ts(), &value[..]))
+            .collect::<Vec<_>>(),
+        vec![
+            (0, WalRecordKind::Value, &b"a"[..], 5, &b"1"[..]),
+            (1, WalRecordKind::MergeOperand, b"b", 5, b"2"),
+            (0, WalRecordKind::RangeTombstone, b"c", 5, b"d"),
+            (1, WalRecordKind::Value, b"e", 5, b""),
+        ]
+    );
+    assert!(entries[4..].iter().all(|(_, _, key, _)| key.ts() == 6));
+
+    // A batch torn anywhere is dropped entirely.
+    for torn in [len - 10, len / 2, 100] {
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(torn)
+            .unwrap();
+        assert_eq!(replay(&path).len(), 4);
+    }
+}
+
+#[test]
+fn test_storage_torn_batch() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage
+        .write_batch(&[
+            WriteBatchRecord::Put(key_of(0), value_of(0)),
+            WriteBatchRecord::Put(key_of(1), value_of(1)),
+        ])
+        .unwrap();
+    let txn = storage.new_txn().unwrap();
+    for i in 2..60 {
+        txn.put(&key_of(i), &value_of(i));
+    }
+    txn.commit().unwrap();
+    storage.sync().unwrap();
+    let wal_id = storage.inner.state.read().memtable.id();
+    drop(storage);
+
+    // Tear the WAL in the middle of the transaction.
+    let path = dir.path().join(format!("{:05}.wal", wal_id));
+    let file = File::options().write(true).open(&path).unwrap();
+    file.set_len(file.metadata().unwrap().len() / 2).unwrap();
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for i in 0..60 {
+        let expected = (i < 2).then(|| value_of(i));
+        assert_eq!(storage.get(&key_of(i)).unwrap(), expected);
+    }
+    storage.close().unwrap();
+}
@@ -0,0 +1,139 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    key::KeySlice,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    mem_table::{MemTable, MemTableType},
+    write_buffer_manager::WriteBufferManager,
+};
+
+use super::helpers::{key_of, value_of};
+
+#[test]
+fn test_memtable_memory_usage() {
+    for memtable_type in [
+        MemTableType::SkipList,
+        MemTableType::ArenaSkipList,
+        MemTableType::HashLinkedList { bucket_count: 16 },
+    ] {
+        let memtable = MemTable::create_with_type(0, memtable_type);
+        let empty_size = memtable.approximate_size();
+        assert!(empty_size < 8 << 10, "{:?}", memtable_type);
+        let mut data_size = 0;
+        for i in 0..10000 {
+            let (key, value) = (key_of(i), value_of(i));
+            memtable.put(KeySlice::from_slice(&key, 1), &value).unwrap();
+            data_size += key.len() + 8 + value.len();
+        }
+        // The overhead of the entries is included.
+        let size = memtable.approximate_size();
+        assert!(size > data_size + 10000 * 16, "{:?}", memtable_type);
+        assert!(size < data_size * 10, "{:?}", memtable_type);
+    }
+
+    // The arena memtable reports the size of its chunks, which grow up to 64KB.
+    let memtable = MemTable::create_with_type(0, MemTableType::ArenaSkipList);
+    let mut sizes = Vec::new();
+    for i in 0..10000 {
+        memtable
+            .put(KeySlice::from_slice(&key_of(i), 1), &value_of(i))
+            .unwrap();
+        if sizes.last() != Some(&memtable.approximate_size()) {
+            sizes.push(memtable.approximate_size());
+        }
+    }
+    assert_eq!(&sizes[..4], &[4 << 10, 8 << 10, 16 << 10, 32 << 10]);
+    assert!(sizes.windows(2).skip(4).all(|x| x[1] - x[0] == 64 << 10));
+    // A value larger than a quarter of a chunk gets a chunk of its own.
+    let size = memtable.approximate_size();
+    memtable
+        .put(KeySlice::from_slice(b"large", 1), &[0; 100 << 10])
+        .unwrap();
+    assert!(memtable.approximate_size() - size > 100 << 10);
+    assert!(memtable.approximate_size() - size < 101 << 10);
+}
+
+#[test]
+fn test_write_buffer_manager_charges() {
+    let manager = Arc::new(WriteBufferManager::new(1 << 20));
+    let memtables = (0..2)
+        .map(|id| {
+            MemTable::create_with_type(id, MemTableType::ArenaSkipList)
+                .with_write_buffer_manager(Some(manager.clone()))
+        })
+        .collect::<Vec<_>>();
+    let total = |memtables: &[MemTable]| {
+        memtables
+            .iter()
+            .map(|x| x.approximate_size())
+            .sum::<usize>()
+    };
+    assert_eq!(manager.memory_usage(), total(&memtables));
+    for i in 0..10000 {
+        memtables[i % 2]
+            .put(KeySlice::from_slice(&key_of(i), 1), &value_of(i))
+            .unwrap();
+    }
+    memtables[0]
+        .delete_range(KeySlice::from_slice(b"a", 2), b"b")
+        .unwrap();
+    assert_eq!(manager.memory_usage(), total(&memtables));
+    assert_eq!(manager.mutable_memory_usage(), total(&memtables));
+    assert!(!manager.should_flush());
+
+    memtables[0].mark_immutable();
+    assert_eq!(manager.memory_usage(), total(&memtables));
+    assert_eq!(
+        manager.mutable_memory_usage(),
+        memtables[1].approximate_size()
+    );
+    drop(memtables);
+    assert_eq!(manager.memory_usage(), 0);
+    assert_eq!(manager.mutable_memory_usage(), 0);
+}
+
+#[test]
+fn test_write_buffer_manager_flush() {
+    // The memtables are frozen and flushed by the write buffer manager, long before they reach the
+    // target SST size or the limit of immutable memtables.
+    let manager = Arc::new(WriteBufferManager::new(256 << 10));
+    let dirs = [tempdir().unwrap(), tempdir().unwrap()];
+    let storages = dirs
+        .iter()
+        .map(|dir| {
+            let mut options =
+                LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+            options.target_sst_size = 64 << 20;
+            options.num_memtable_limit = 1000;
+            options.memtable_type = MemTableType::ArenaSkipList;
+            options.write_buffer_manager = Some(manager.clone());
+            MiniLsm::open(dir, options).unwrap()
+        })
+        .collect::<Vec<_>>();
+    for i in 0..20000 {
+        storages[i % 2].put(&key_of(i), &value_of(i)).unwrap();
+    }
+    // The flush thread catches up with the writes.
+    let start = Instant::now();
+    while manager.should_flush() {
+        assert!(start.elapsed() < Duration::from_secs(10));
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    for storage in &storages {
+        let state = storage.inner.state.read();
+        assert!(state.l0_sstables.len() > 1);
+        assert!(state.memtable.approximate_size() < 256 << 10);
+    }
+    for i in (0..20000).step_by(7) {
+        assert_eq!(storages[i % 2].get(&key_of(i)).unwrap(), Some(value_of(i)));
+    }
+    for storage in storages {
+        storage.close().unwrap();
+    }
+}
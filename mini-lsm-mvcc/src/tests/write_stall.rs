@@ -0,0 +1,135 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{CompactionOptions, LeveledCompactionOptions},
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    write_stall::{WriteStallCause, WriteStallCondition, WriteStallOptions, WriteStallState},
+};
+
+use super::harness::compaction_bench;
+
+fn flush_one(storage: &MiniLsm, key: &str) {
+    storage.put(key.as_bytes(), b"value").unwrap();
+    storage.force_flush().unwrap();
+}
+
+#[test]
+fn test_write_stall_l0_files() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.write_stall = WriteStallOptions {
+        slowdown_l0_files: 2,
+        stop_l0_files: 3,
+        ..Default::default()
+    };
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(
+        storage.write_stall_state(),
+        WriteStallState {
+            condition: WriteStallCondition::Normal,
+            cause: WriteStallCause::None,
+        }
+    );
+    flush_one(&storage, "a");
+    flush_one(&storage, "b");
+    assert_eq!(
+        storage.write_stall_state(),
+        WriteStallState {
+            condition: WriteStallCondition::Delayed,
+            cause: WriteStallCause::L0Files,
+        }
+    );
+    flush_one(&storage, "c");
+    assert_eq!(
+        storage.write_stall_state(),
+        WriteStallState {
+            condition: WriteStallCondition::Stopped,
+            cause: WriteStallCause::L0Files,
+        }
+    );
+
+    let done = Arc::new(AtomicBool::new(false));
+    let writer = {
+        let storage = storage.clone();
+        let done = done.clone();
+        std::thread::spawn(move || {
+            storage.put(b"d", b"value").unwrap();
+            done.store(true, Ordering::SeqCst);
+        })
+    };
+    std::thread::sleep(Duration::from_millis(300));
+    assert!(!done.load(Ordering::SeqCst), "write is not stopped");
+
+    storage.force_full_compaction().unwrap();
+    writer.join().unwrap();
+    assert_eq!(
+        storage.write_stall_state().condition,
+        WriteStallCondition::Normal
+    );
+    for key in ["a", "b", "c", "d"] {
+        assert_eq!(
+            storage.get(key.as_bytes()).unwrap(),
+            Some(Bytes::from_static(b"value"))
+        );
+    }
+}
+
+#[test]
+fn test_write_stall_delayed_write_rate() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.write_stall = WriteStallOptions {
+        slowdown_l0_files: 1,
+        delayed_write_rate: 1000,
+        ..Default::default()
+    };
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(
+        storage.write_stall_state().condition,
+        WriteStallCondition::Normal
+    );
+    storage.put(&[b'k'; 100], &[b'v'; 100]).unwrap();
+
+    storage.force_flush().unwrap();
+    assert_eq!(
+        storage.write_stall_state().condition,
+        WriteStallCondition::Delayed
+    );
+    let start = Instant::now();
+    storage.put(&[b'k'; 100], &[b'v'; 100]).unwrap();
+    // 200 bytes at 1000 bytes/s
+    assert!(start.elapsed() >= Duration::from_millis(200));
+}
+
+#[test]
+fn test_write_stall_leveled_bench() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Leveled(
+        LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            level_size_multiplier: 2,
+            base_level_size_mb: 1,
+            max_levels: 4,
+        },
+    ));
+    options.write_stall = WriteStallOptions {
+        slowdown_imm_memtables: 3,
+        stop_imm_memtables: 4,
+        slowdown_l0_files: 3,
+        stop_l0_files: 6,
+        soft_pending_compaction_bytes: 8 << 20,
+        hard_pending_compaction_bytes: 64 << 20,
+        ..Default::default()
+    };
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    compaction_bench(storage.clone());
+}
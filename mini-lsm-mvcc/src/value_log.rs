@@ -0,0 +1,574 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use bytes::{Buf, BufMut, Bytes};
+use parking_lot::{Mutex, RwLock};
+
+use crate::compact::CompactionOptions;
+use crate::lsm_storage::{LsmStorageInner, WriteBatchRecord};
+use crate::manifest::ManifestRecord;
+
+#[derive(Debug, Clone)]
+pub struct ValueLogOptions {
+    // Values of at least this size are stored in the value log instead of the LSM tree
+    pub min_blob_size: usize,
+    // A new segment is started once the current one reaches this size in bytes
+    pub segment_size: usize,
+    // Segments with at least this fraction of garbage are garbage-collected
+    pub gc_garbage_ratio: f64,
+}
+
+impl Default for ValueLogOptions {
+    fn default() -> Self {
+        Self {
+            min_blob_size: 4096,
+            segment_size: 64 << 20, // 64MB
+            gc_garbage_ratio: 0.5,
+        }
+    }
+}
+
+/// When the value log is enabled, every non-empty value in the LSM tree starts with one of these
+/// tags. Empty values are still delete tombstones.
+const VALUE_TAG_INLINE: u8 = 0;
+const VALUE_TAG_POINTER: u8 = 1;
+const POINTER_SIZE: usize = 1 + 8 + 8 + 4;
+
+/// The number of live records the garbage collector checks and relocates under one hold of the
+/// write lock.
+const GC_RELOCATE_BATCH_SIZE: usize = 64;
+
+/// The location of a record in the value log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValuePointer {
+    pub segment_id: usize,
+    pub offset: u64,
+    pub len: u32,
+}
+
+impl ValuePointer {
+    /// Encodes the pointer as a tagged value in the LSM tree.
+    pub fn encode(&self) -> Bytes {
+        let mut buf = Vec::with_capacity(POINTER_SIZE);
+        buf.put_u8(VALUE_TAG_POINTER);
+        buf.put_u64(self.segment_id as u64);
+        buf.put_u64(self.offset);
+        buf.put_u32(self.len);
+        buf.into()
+    }
+
+    /// Decodes a tagged value in the LSM tree. Returns `None` for inline values and tombstones.
+    pub fn decode(mut raw: &[u8]) -> Option<Self> {
+        if raw.len() != POINTER_SIZE || raw[0] != VALUE_TAG_POINTER {
+            return None;
+        }
+        raw.advance(1);
+        Some(Self {
+            segment_id: raw.get_u64() as usize,
+            offset: raw.get_u64(),
+            len: raw.get_u32(),
+        })
+    }
+}
+
+/// A record read back from a value log segment.
+pub struct ValueLogRecord {
+    pub pointer: ValuePointer,
+    pub key: Bytes,
+    pub ts: u64,
+    pub value: Bytes,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct SegmentStats {
+    size: u64,
+    garbage: u64,
+}
+
+struct ActiveSegment {
+    id: usize,
+    file: File,
+    offset: u64,
+    dirty: bool,
+}
+
+/// A garbage-collected segment, which is deleted once no reader can see the old versions of the
+/// relocated values and the relocated values are durable.
+struct PendingDelete {
+    segment_id: usize,
+    relocated_ts: u64,
+    memtable_id: usize,
+}
+
+/// An append-only log of large values (WiscKey). The LSM tree only stores pointers to the records,
+/// so that compactions do not rewrite the values.
+///
+/// The log is split into segments named `<id>.vlog` in the storage directory. A new segment is
+/// started every time the storage is opened and whenever the current segment is full. Each record
+/// is encoded as:
+///
+/// ```text
+/// | key_len (u16) | key | ts (u64) | value_len (u32) | value | checksum (u32) |
+/// ```
+pub struct ValueLog {
+    path: PathBuf,
+    options: ValueLogOptions,
+    active: Mutex<ActiveSegment>,
+    readers: RwLock<HashMap<usize, Arc<File>>>,
+    /// Size and garbage bytes of each segment, including the active segment. Garbage is an
+    /// estimate discovered by compactions, and is verified by the garbage collector.
+    stats: Mutex<BTreeMap<usize, SegmentStats>>,
+    pending_deletes: Mutex<Vec<PendingDelete>>,
+    /// Held by a garbage collection pass, so that a segment is not collected twice.
+    gc_lock: Mutex<()>,
+}
+
+impl ValueLog {
+    pub(crate) fn path_of_segment_static(path: impl AsRef<Path>, id: usize) -> PathBuf {
+        path.as_ref().join(format!("{:05}.vlog", id))
+    }
+
+    fn path_of_segment(&self, id: usize) -> PathBuf {
+        Self::path_of_segment_static(&self.path, id)
+    }
+
+    /// Lists the ids of all value log segments in the storage directory.
+    pub(crate) fn list_segments(path: impl AsRef<Path>) -> Result<Vec<usize>> {
+        let mut ids = Vec::new();
+        for entry in std::fs::read_dir(path)? {
+            let file_name = entry?.file_name();
+            let Some(id) = file_name
+                .to_str()
+                .and_then(|name| name.strip_suffix(".vlog"))
+            else {
+                continue;
+            };
+            ids.push(id.parse().context("invalid value log segment name")?);
+        }
+        ids.sort();
+        Ok(ids)
+    }
+
+    fn create_segment(path: &Path, id: usize) -> Result<File> {
+        OpenOptions::new()
+            .create_new(true)
+            .write(true)
+            .open(Self::path_of_segment_static(path, id))
+            .context("failed to create value log segment")
+    }
+
+    /// Opens the value log in `path`, and starts a new segment for writing.
+    pub fn open(path: impl AsRef<Path>, options: ValueLogOptions) -> Result<Self> {
+        let path = path.as_ref();
+        let mut stats = BTreeMap::new();
+        for id in Self::list_segments(path)? {
+            let size = std::fs::metadata(Self::path_of_segment_static(path, id))?.len();
+            stats.insert(id, SegmentStats { size, garbage: 0 });
+        }
+        let active_id = stats.keys().last().map_or(1, |id| id + 1);
+        stats.insert(active_id, SegmentStats::default());
+        let file = Self::create_segment(path, active_id)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            options,
+            active: Mutex::new(ActiveSegment {
+                id: active_id,
+                file,
+                offset: 0,
+                dirty: false,
+            }),
+            readers: RwLock::new(HashMap::new()),
+            stats: Mutex::new(stats),
+            pending_deletes: Mutex::new(Vec::new()),
+            gc_lock: Mutex::new(()),
+        })
+    }
+
+    /// Encodes a value to store in the LSM tree. Large values are appended to the log and replaced
+    /// by a pointer, and small values are stored inline.
+    pub fn encode_value(&self, key: &[u8], ts: u64, value: &[u8]) -> Result<Bytes> {
+        if value.is_empty() {
+            return Ok(Bytes::new());
+        }
+        if value.len() < self.options.min_blob_size {
+            let mut buf = Vec::with_capacity(value.len() + 1);
+            buf.put_u8(VALUE_TAG_INLINE);
+            buf.put_slice(value);
+            return Ok(buf.into());
+        }
+        Ok(self.append(key, ts, value)?.encode())
+    }
+
+    fn append(&self, key: &[u8], ts: u64, value: &[u8]) -> Result<ValuePointer> {
+        let mut buf = Vec::with_capacity(2 + key.len() + 8 + 4 + value.len() + 4);
+        buf.put_u16(key.len() as u16);
+        buf.put_slice(key);
+        buf.put_u64(ts);
+        buf.put_u32(value.len() as u32);
+        buf.put_slice(value);
+        buf.put_u32(crc32fast::hash(&buf));
+
+        let mut active = self.active.lock();
+        if active.offset > 0 && active.offset as usize >= self.options.segment_size {
+            self.rotate(&mut active)?;
+        }
+        active.file.write_all(&buf)?;
+        let pointer = ValuePointer {
+            segment_id: active.id,
+            offset: active.offset,
+            len: buf.len() as u32,
+        };
+        active.offset += buf.len() as u64;
+        active.dirty = true;
+        self.stats.lock().entry(active.id).or_default().size = active.offset;
+        Ok(pointer)
+    }
+
+    fn rotate(&self, active: &mut ActiveSegment) -> Result<()> {
+        active.file.sync_all()?;
+        let id = active.id + 1;
+        *active = ActiveSegment {
+            id,
+            file: Self::create_segment(&self.path, id)?,
+            offset: 0,
+            dirty: false,
+        };
+        self.stats.lock().insert(id, SegmentStats::default());
+        File::open(&self.path)?.sync_all()?;
+        Ok(())
+    }
+
+    /// Syncs the active segment. Must be called before the pointers to it are persisted.
+    pub fn sync(&self) -> Result<()> {
+        let mut active = self.active.lock();
+        if active.dirty {
+            active.file.sync_data()?;
+            active.dirty = false;
+        }
+        Ok(())
+    }
+
+    fn reader(&self, segment_id: usize) -> Result<Arc<File>> {
+        if let Some(file) = self.readers.read().get(&segment_id) {
+            return Ok(file.clone());
+        }
+        let file = Arc::new(
+            File::open(self.path_of_segment(segment_id))
+                .with_context(|| format!("value log segment {} not found", segment_id))?,
+        );
+        self.readers.write().insert(segment_id, file.clone());
+        Ok(file)
+    }
+
+    fn decode_record(pointer: ValuePointer, data: &[u8]) -> Result<ValueLogRecord> {
+        if data.len() < 4 {
+            bail!("value log record too short");
+        }
+        let (content, mut checksum) = data.split_at(data.len() - 4);
+        if checksum.get_u32() != crc32fast::hash(content) {
+            bail!("value log record checksum mismatched");
+        }
+        let mut content = content;
+        let key_len = content.get_u16() as usize;
+        let key = Bytes::copy_from_slice(&content[..key_len]);
+        content.advance(key_len);
+        let ts = content.get_u64();
+        let value_len = content.get_u32() as usize;
+        if content.len() != value_len {
+            bail!("value log record length mismatched");
+        }
+        Ok(ValueLogRecord {
+            pointer,
+            key,
+            ts,
+            value: Bytes::copy_from_slice(content),
+        })
+    }
+
+    pub fn read(&self, pointer: ValuePointer) -> Result<Bytes> {
+        let mut data = vec![0; pointer.len as usize];
+        self.reader(pointer.segment_id)?
+            .read_exact_at(&mut data, pointer.offset)?;
+        Ok(Self::decode_record(pointer, &data)?.value)
+    }
+
+    /// Resolves a tagged value in the LSM tree to the user value.
+    pub fn resolve(&self, raw: &[u8]) -> Result<Bytes> {
+        match raw.first() {
+            None => Ok(Bytes::new()),
+            Some(&VALUE_TAG_INLINE) => Ok(Bytes::copy_from_slice(&raw[1..])),
+            Some(&VALUE_TAG_POINTER) => self
+                .read(ValuePointer::decode(raw).ok_or_else(|| anyhow!("invalid value pointer"))?),
+            Some(tag) => bail!("unknown value tag {}", tag),
+        }
+    }
+
+    /// Reads all records of a segment.
+    pub fn read_segment(&self, segment_id: usize) -> Result<Vec<ValueLogRecord>> {
+        let data = std::fs::read(self.path_of_segment(segment_id))?;
+        let mut records = Vec::new();
+        let mut offset = 0;
+        // A record torn by a crash at the end of the segment was never referenced by the LSM tree.
+        while offset + 2 <= data.len() {
+            let mut header = &data[offset..];
+            let key_len = header.get_u16() as usize;
+            if header.len() < key_len + 8 + 4 {
+                break;
+            }
+            header.advance(key_len + 8);
+            let value_len = header.get_u32() as usize;
+            let len = 2 + key_len + 8 + 4 + value_len + 4;
+            if offset + len > data.len() {
+                break;
+            }
+            let pointer = ValuePointer {
+                segment_id,
+                offset: offset as u64,
+                len: len as u32,
+            };
+            records.push(Self::decode_record(pointer, &data[offset..offset + len])?);
+            offset += len;
+        }
+        Ok(records)
+    }
+
+    pub fn add_garbage(&self, segment_id: usize, bytes: u64) {
+        if let Some(stats) = self.stats.lock().get_mut(&segment_id) {
+            stats.garbage += bytes;
+        }
+    }
+
+    /// Returns the garbage bytes of each segment with some garbage.
+    pub fn garbage(&self) -> Vec<(usize, u64)> {
+        self.stats
+            .lock()
+            .iter()
+            .filter(|(_, stats)| stats.garbage > 0)
+            .map(|(id, stats)| (*id, stats.garbage))
+            .collect()
+    }
+
+    /// Returns the fraction of garbage in each segment.
+    pub fn garbage_ratios(&self) -> BTreeMap<usize, f64> {
+        self.stats
+            .lock()
+            .iter()
+            .map(|(id, stats)| {
+                let ratio = if stats.size == 0 {
+                    0.0
+                } else {
+                    stats.garbage as f64 / stats.size as f64
+                };
+                (*id, ratio.min(1.0))
+            })
+            .collect()
+    }
+
+    /// Segments that are not being written to, and have enough garbage to be collected.
+    fn gc_candidates(&self) -> Vec<usize> {
+        let active_id = self.active.lock().id;
+        let pending = self
+            .pending_deletes
+            .lock()
+            .iter()
+            .map(|x| x.segment_id)
+            .collect::<Vec<_>>();
+        self.garbage_ratios()
+            .into_iter()
+            .filter(|(id, ratio)| {
+                *id != active_id && !pending.contains(id) && *ratio >= self.options.gc_garbage_ratio
+            })
+            .map(|(id, _)| id)
+            .collect()
+    }
+
+    fn delete_segment(&self, segment_id: usize) -> Result<()> {
+        self.readers.write().remove(&segment_id);
+        self.stats.lock().remove(&segment_id);
+        std::fs::remove_file(self.path_of_segment(segment_id))?;
+        Ok(())
+    }
+}
+
+impl LsmStorageInner {
+    /// Records value log garbage discovered by a compaction. The garbage is persisted in the
+    /// manifest so that it survives restarts.
+    pub(crate) fn record_value_log_garbage(&self, garbage: HashMap<usize, u64>) -> Result<()> {
+        let Some(value_log) = &self.value_log else {
+            return Ok(());
+        };
+        if garbage.is_empty() {
+            return Ok(());
+        }
+        // The garbage is added under the state lock, so that it is either in a manifest snapshot
+        // or in a record after it, but not in both.
+        let state_lock = self.state_lock.lock();
+        for (segment_id, bytes) in &garbage {
+            value_log.add_garbage(*segment_id, *bytes);
+        }
+        self.manifest().add_record(
+            &state_lock,
+            ManifestRecord::ValueLogGarbage(garbage.into_iter().collect()),
+        )?;
+        self.maybe_roll_manifest(&state_lock)
+    }
+
+    /// Garbage-collects all value log segments with enough garbage: live values are written again
+    /// to the head of the log, and the segments are deleted once no reader can see their values.
+    /// Returns the ids of the collected segments.
+    pub fn gc_value_log(&self) -> Result<Vec<usize>> {
+        let Some(value_log) = &self.value_log else {
+            return Ok(Vec::new());
+        };
+        let _gc_lock = value_log.gc_lock.lock();
+        self.purge_value_log_segments()?;
+        let candidates = value_log.gc_candidates();
+        for segment_id in &candidates {
+            self.gc_value_log_segment(value_log, *segment_id)?;
+        }
+        self.purge_value_log_segments()?;
+        Ok(candidates)
+    }
+
+    /// Garbage-collects at most one value log segment, so that a pass of the GC thread is
+    /// bounded. Returns the id of the collected segment.
+    pub(crate) fn gc_value_log_once(&self) -> Result<Option<usize>> {
+        let Some(value_log) = &self.value_log else {
+            return Ok(None);
+        };
+        let _gc_lock = value_log.gc_lock.lock();
+        self.purge_value_log_segments()?;
+        let Some(segment_id) = value_log.gc_candidates().first().copied() else {
+            return Ok(None);
+        };
+        self.gc_value_log_segment(value_log, segment_id)?;
+        self.purge_value_log_segments()?;
+        Ok(Some(segment_id))
+    }
+
+    /// Spawns the value log GC thread, which collects one segment per tick. It is not started
+    /// when the value log is disabled, or when compactions do not run to discover garbage.
+    pub(crate) fn spawn_value_log_gc_thread(
+        self: &Arc<Self>,
+        rx: crossbeam_channel::Receiver<()>,
+    ) -> Result<Option<std::thread::JoinHandle<()>>> {
+        if self.value_log.is_none()
+            || matches!(
+                self.options.compaction_options,
+                CompactionOptions::NoCompaction
+            )
+        {
+            return Ok(None);
+        }
+        let this = self.clone();
+        let handle = std::thread::spawn(move || {
+            let ticker = crossbeam_channel::tick(Duration::from_millis(50));
+            loop {
+                crossbeam_channel::select! {
+                    recv(ticker) -> _ => if let Err(e) = this.gc_value_log_once() {
+                        eprintln!("value log gc failed: {}", e);
+                    },
+                    recv(rx) -> _ => return
+                }
+            }
+        });
+        Ok(Some(handle))
+    }
+
+    fn gc_value_log_segment(&self, value_log: &ValueLog, segment_id: usize) -> Result<()> {
+        let records = value_log.read_segment(segment_id)?;
+        let column_families = self.column_families();
+        // A record is live if it is the latest version of the key in any column family. Pointers
+        // are unique, so it cannot be live in more than one of them. A record which is not live
+        // cannot become live again, so the candidates are found without blocking writes.
+        let mut candidates = Vec::new();
+        let read_ts = self.mvcc().latest_commit_ts();
+        for record in &records {
+            let pointer = Some(record.pointer.encode());
+            for cf in &column_families {
+                if self.get_raw_with_ts(cf, &record.key, read_ts)? == pointer {
+                    candidates.push((cf.as_ref(), record, pointer));
+                    break;
+                }
+            }
+        }
+        // The candidates are checked again and relocated in batches, blocking writes so that the
+        // values checked to be the latest stay the latest, but only for a batch at a time.
+        let mut num_relocated = 0;
+        let mut relocated_ts = 0;
+        for batch in candidates.chunks(GC_RELOCATE_BATCH_SIZE) {
+            let _lck = self.mvcc().write_lock.lock();
+            let latest_ts = self.mvcc().latest_commit_ts();
+            let mut relocated = Vec::new();
+            for (cf, record, pointer) in batch {
+                if self.get_raw_with_ts(cf, &record.key, latest_ts)? == *pointer {
+                    relocated.push((*cf, WriteBatchRecord::Put(&record.key, &record.value)));
+                }
+            }
+            num_relocated += relocated.len();
+            relocated_ts = if relocated.is_empty() {
+                latest_ts
+            } else {
+                self.write_column_families_with_lock(
+                    relocated.iter().map(|(cf, record)| (*cf, record)),
+                )?
+            };
+        }
+        // All records are garbage after relocation, so that the segment is collected again if it
+        // is not deleted before a restart.
+        let segment_size = records
+            .iter()
+            .map(|record| record.pointer.len as u64)
+            .sum::<u64>();
+        self.record_value_log_garbage(HashMap::from([(segment_id, segment_size)]))?;
+        if self.options.enable_wal {
+            self.sync()?;
+        }
+        println!(
+            "value log segment {} collected, {} of {} records relocated",
+            segment_id,
+            num_relocated,
+            records.len()
+        );
+        value_log.pending_deletes.lock().push(PendingDelete {
+            segment_id,
+            relocated_ts,
+            memtable_id: self.state.read().memtable.id(),
+        });
+        Ok(())
+    }
+
+    /// Deletes the collected segments that are no longer visible to any reader.
+    fn purge_value_log_segments(&self) -> Result<()> {
+        let Some(value_log) = &self.value_log else {
+            return Ok(());
+        };
+        let watermark = self.mvcc().watermark();
+        let snapshot = {
+            let guard = self.state.read();
+            guard.clone()
+        };
+        let mut pending_deletes = value_log.pending_deletes.lock();
+        let mut result = Ok(());
+        pending_deletes.retain(|pending| {
+            let durable = self.options.enable_wal
+                || (snapshot.memtable.id() > pending.memtable_id
+                    && snapshot
+                        .imm_memtables
+                        .iter()
+                        .all(|memtable| memtable.id() > pending.memtable_id));
+            if watermark < pending.relocated_ts || !durable || result.is_err() {
+                return true;
+            }
+            result = value_log.delete_segment(pending.segment_id);
+            result.is_err()
+        });
+        result
+    }
+}
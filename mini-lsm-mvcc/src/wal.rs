@@ -1,94 +1,548 @@
 use std::fs::{File, OpenOptions};
 use std::hash::Hasher;
-use std::io::{BufWriter, Read, Write};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
 use std::path::Path;
 use std::sync::Arc;
 
 use anyhow::{bail, Context, Result};
 use bytes::{Buf, BufMut, Bytes};
-use crossbeam_skiplist::SkipMap;
 use parking_lot::Mutex;
 
 use crate::key::{KeyBytes, KeySlice};
 
+/// The size of the blocks the WAL is split into. Fragments of records never cross blocks, so that
+/// the reader can find the start of a fragment at the start of each block.
+const BLOCK_SIZE: usize = 32 << 10;
+/// | checksum (u32) | length (u16) | type (u8) | log_number (u32) |
+const HEADER_SIZE: usize = 11;
+
+/// The zeros of the padding at the end of a block and of a preallocated file.
+const ZERO_FRAGMENT: u8 = 0;
+const FULL_FRAGMENT: u8 = 1;
+const FIRST_FRAGMENT: u8 = 2;
+const MIDDLE_FRAGMENT: u8 = 3;
+const LAST_FRAGMENT: u8 = 4;
+
+/// The kind of a WAL record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalRecordKind {
+    Value,
+    RangeTombstone,
+    MergeOperand,
+}
+
+impl WalRecordKind {
+    fn to_u8(self) -> u8 {
+        match self {
+            WalRecordKind::Value => 0,
+            WalRecordKind::RangeTombstone => 1,
+            WalRecordKind::MergeOperand => 2,
+        }
+    }
+
+    fn from_u8(kind: u8) -> Result<Self> {
+        match kind {
+            0 => Ok(WalRecordKind::Value),
+            1 => Ok(WalRecordKind::RangeTombstone),
+            2 => Ok(WalRecordKind::MergeOperand),
+            _ => bail!("unknown WAL record kind {}", kind),
+        }
+    }
+}
+
+/// How the records after a corruption of the WAL are handled when it is replayed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WalRecoveryMode {
+    /// Drops the records torn by a crash at the end of the WAL, and fails on any other corruption.
+    /// The end of the WAL is torn if no record starts in the blocks after the corruption.
+    #[default]
+    TolerateCorruptedTailRecords,
+    /// Fails on any corruption, including torn records at the end of the WAL.
+    AbsoluteConsistency,
+    /// Drops all records from the first corruption on, including those of the later WALs, so that
+    /// the storage is recovered to a consistent point in time.
+    PointInTimeRecovery,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct WalOptions {
+    // How the corrupted records are handled when the WALs are replayed
+    pub recovery_mode: WalRecoveryMode,
+    // Extends new WAL files to this size when they are created, so that syncs do not have to
+    // update their size (0 disables preallocation)
+    pub preallocate_size: u64,
+    // Number of WAL files kept after their memtables are flushed, which are reused instead of
+    // creating new files
+    pub recycle_file_num: usize,
+}
+
+struct WalWriter {
+    file: BufWriter<File>,
+    /// The offset of the end of the file in the current block.
+    block_offset: usize,
+}
+
+/// A write-ahead log shared by the memtables of all column families created at the same time.
+/// Each record holds a whole write batch with its commit timestamp, so that a batch is either
+/// replayed entirely or not at all. A record is encoded as:
+///
+/// ```text
+/// | commit_ts (u64) | count (u32) | entry | ... | entry |
+/// ```
+///
+/// And each entry as:
+///
+/// ```text
+/// | column_family_id (u32) | kind (u8) | key_len (u16) | key | value_len (u16) | value |
+/// ```
+///
+/// A range tombstone is logged with its start key as the key and its end key as the value.
+///
+/// The file is split into blocks of `BLOCK_SIZE` bytes, and each record is split into fragments
+/// that fill the blocks. Each fragment has a header with a checksum, its length, whether it is
+/// the full record or its first, middle or last fragment, and the log number of the WAL. A block
+/// with less space left than a header is padded with zeros. Writes torn by a crash are detected
+/// with the checksums, and the records left by a previous use of a recycled file are ignored as
+/// they have another log number.
+#[derive(Clone)]
 pub struct Wal {
-    file: Arc<Mutex<BufWriter<File>>>,
+    writer: Arc<Mutex<WalWriter>>,
+    log_number: u32,
+}
+
+/// The result of reading the next fragment or record of a WAL.
+enum ReadResult<T> {
+    Item(T),
+    /// The end of the records of the WAL, at the end of the file, at zeros or at the records of a
+    /// previous use of the file.
+    End,
+    /// A corrupted or incomplete item, with the reason.
+    Corrupted(&'static str),
+}
+
+struct WalReader<'a> {
+    buf: &'a [u8],
+    offset: usize,
+    log_number: u32,
+}
+
+impl WalReader<'_> {
+    fn read_fragment(&mut self) -> ReadResult<(u8, &[u8])> {
+        let block_left = BLOCK_SIZE - self.offset % BLOCK_SIZE;
+        if block_left < HEADER_SIZE {
+            // Skip the padding.
+            self.offset += block_left;
+        }
+        let mut rbuf = &self.buf[self.offset.min(self.buf.len())..];
+        if rbuf.is_empty() {
+            return ReadResult::End;
+        }
+        if rbuf.len() < HEADER_SIZE {
+            if rbuf.iter().all(|x| *x == 0) {
+                return ReadResult::End;
+            }
+            return ReadResult::Corrupted("truncated fragment header");
+        }
+        let checksum = rbuf.get_u32();
+        let len = rbuf.get_u16() as usize;
+        let fragment_type = rbuf.get_u8();
+        let log_number = rbuf.get_u32();
+        if fragment_type == ZERO_FRAGMENT || log_number != self.log_number {
+            return ReadResult::End;
+        }
+        if fragment_type > LAST_FRAGMENT
+            || HEADER_SIZE + len > BLOCK_SIZE - self.offset % BLOCK_SIZE
+        {
+            return ReadResult::Corrupted("invalid fragment header");
+        }
+        if rbuf.len() < len {
+            return ReadResult::Corrupted("truncated fragment");
+        }
+        let payload = &rbuf[..len];
+        if fragment_checksum(fragment_type, log_number, payload) != checksum {
+            return ReadResult::Corrupted("checksum mismatch");
+        }
+        self.offset += HEADER_SIZE + len;
+        ReadResult::Item((fragment_type, payload))
+    }
+
+    fn read_record(&mut self) -> ReadResult<Vec<u8>> {
+        let mut record = Vec::new();
+        let mut in_record = false;
+        loop {
+            let (fragment_type, payload) = match self.read_fragment() {
+                ReadResult::Item(fragment) => fragment,
+                ReadResult::End if in_record => return ReadResult::Corrupted("incomplete record"),
+                ReadResult::End => return ReadResult::End,
+                ReadResult::Corrupted(reason) => return ReadResult::Corrupted(reason),
+            };
+            match (fragment_type, in_record) {
+                (FULL_FRAGMENT, false) => return ReadResult::Item(payload.to_vec()),
+                (FIRST_FRAGMENT, false) => {
+                    record.extend_from_slice(payload);
+                    in_record = true;
+                }
+                (MIDDLE_FRAGMENT, true) => record.extend_from_slice(payload),
+                (LAST_FRAGMENT, true) => {
+                    record.extend_from_slice(payload);
+                    return ReadResult::Item(record);
+                }
+                _ => return ReadResult::Corrupted("unexpected fragment"),
+            }
+        }
+    }
+
+    /// Whether a record starts in a block after `offset`, which tells a corruption in the middle of
+    /// the WAL from one at its end. The fragments of each block are read from its start, until
+    /// the first fragment of a record is found.
+    fn has_record_after(&self, offset: usize) -> bool {
+        let first_block = offset / BLOCK_SIZE + 1;
+        (first_block..)
+            .map(|block| block * BLOCK_SIZE)
+            .take_while(|block_offset| *block_offset < self.buf.len())
+            .any(|block_offset| {
+                let mut reader = WalReader {
+                    buf: self.buf,
+                    offset: block_offset,
+                    log_number: self.log_number,
+                };
+                while reader.offset < block_offset + BLOCK_SIZE {
+                    match reader.read_fragment() {
+                        ReadResult::Item((FULL_FRAGMENT | FIRST_FRAGMENT, _)) => return true,
+                        ReadResult::Item(_) => {}
+                        ReadResult::End | ReadResult::Corrupted(_) => return false,
+                    }
+                }
+                false
+            })
+    }
+}
+
+fn fragment_checksum(fragment_type: u8, log_number: u32, payload: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.write_u8(fragment_type);
+    hasher.write_u32(log_number);
+    hasher.write(payload);
+    hasher.finalize()
 }
 
 impl Wal {
-    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+    /// Creates a WAL in a new file. `log_number` tells its records from those of another WAL which
+    /// used the same file before.
+    pub fn create(path: impl AsRef<Path>, log_number: usize, options: &WalOptions) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .create_new(true)
+            .write(true)
+            .open(path)
+            .context("failed to create WAL")?;
+        Self::open_for_write(file, log_number, options, 0)
+    }
+
+    /// Creates a WAL in the file of a WAL which is no longer needed, overwriting its records from
+    /// the start of the file.
+    pub fn reuse(
+        old_path: impl AsRef<Path>,
+        path: impl AsRef<Path>,
+        log_number: usize,
+        options: &WalOptions,
+    ) -> Result<Self> {
+        std::fs::rename(old_path, path.as_ref()).context("failed to reuse WAL")?;
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .context("failed to reuse WAL")?;
+        Self::open_for_write(file, log_number, options, 0)
+    }
+
+    fn open_for_write(
+        mut file: File,
+        log_number: usize,
+        options: &WalOptions,
+        offset: u64,
+    ) -> Result<Self> {
+        if file.metadata()?.len() < options.preallocate_size {
+            file.set_len(options.preallocate_size)?;
+            file.sync_all()?;
+        }
+        file.seek(SeekFrom::Start(offset))?;
         Ok(Self {
-            file: Arc::new(Mutex::new(BufWriter::new(
-                OpenOptions::new()
-                    .read(true)
-                    .create_new(true)
-                    .write(true)
-                    .open(path)
-                    .context("failed to create WAL")?,
-            ))),
+            writer: Arc::new(Mutex::new(WalWriter {
+                file: BufWriter::new(file),
+                block_offset: offset as usize % BLOCK_SIZE,
+            })),
+            log_number: log_number as u32,
         })
     }
 
-    pub fn recover(path: impl AsRef<Path>, skiplist: &SkipMap<KeyBytes, Bytes>) -> Result<Self> {
+    /// Replays the WAL, passing each entry of the complete batches with its column family id to
+    /// `apply`. The corrupted records are handled according to `recovery_mode`. Returns the WAL, which appends records
+    /// after the replayed ones, and whether records were dropped after a corruption, in which case
+    /// the file is truncated after the replayed records.
+    pub fn recover(
+        path: impl AsRef<Path>,
+        log_number: usize,
+        recovery_mode: WalRecoveryMode,
+        apply: impl FnMut(usize, WalRecordKind, KeyBytes, Bytes) -> Result<()>,
+    ) -> Result<(Self, bool)> {
         let path = path.as_ref();
         let mut file = OpenOptions::new()
             .read(true)
-            .append(true)
+            .write(true)
             .open(path)
             .context("failed to recover from WAL")?;
         let mut buf = Vec::new();
         file.read_to_end(&mut buf)?;
-        let mut rbuf: &[u8] = buf.as_slice();
-        while rbuf.has_remaining() {
-            let mut hasher = crc32fast::Hasher::new();
-            let key_len = rbuf.get_u16() as usize;
-            hasher.write_u16(key_len as u16);
-            let key = Bytes::copy_from_slice(&rbuf[..key_len]);
-            hasher.write(&key);
-            rbuf.advance(key_len);
-            let ts = rbuf.get_u64();
-            hasher.write_u64(ts);
-            let value_len = rbuf.get_u16() as usize;
-            hasher.write_u16(value_len as u16);
-            let value = Bytes::copy_from_slice(&rbuf[..value_len]);
-            hasher.write(&value);
-            rbuf.advance(value_len);
-            let checksum = rbuf.get_u32();
-            if hasher.finalize() != checksum {
-                bail!("checksum mismatch");
+        let (end, dropped) = Self::replay(&buf, path, log_number, recovery_mode, apply)?;
+        if dropped {
+            file.set_len(end)?;
+            file.sync_all()?;
+        }
+        let wal = Self::open_for_write(file, log_number, &WalOptions::default(), end)?;
+        Ok((wal, dropped))
+    }
+
+    /// Reads the WAL without changing the file, passing each entry of the complete batches to
+    /// `apply` like `recover`. Returns whether records would be dropped after a corruption.
+    pub fn read(
+        path: impl AsRef<Path>,
+        log_number: usize,
+        recovery_mode: WalRecoveryMode,
+        apply: impl FnMut(usize, WalRecordKind, KeyBytes, Bytes) -> Result<()>,
+    ) -> Result<bool> {
+        let path = path.as_ref();
+        let buf = std::fs::read(path).context("failed to read WAL")?;
+        let (_, dropped) = Self::replay(&buf, path, log_number, recovery_mode, apply)?;
+        Ok(dropped)
+    }
+
+    /// Replays the records in `buf`, and returns the end of the replayed records and whether
+    /// records are dropped after a corruption.
+    fn replay(
+        buf: &[u8],
+        path: &Path,
+        log_number: usize,
+        recovery_mode: WalRecoveryMode,
+        mut apply: impl FnMut(usize, WalRecordKind, KeyBytes, Bytes) -> Result<()>,
+    ) -> Result<(u64, bool)> {
+        let mut reader = WalReader {
+            buf,
+            offset: 0,
+            log_number: log_number as u32,
+        };
+        let mut dropped = false;
+        loop {
+            let offset = reader.offset;
+            let record = match reader.read_record() {
+                ReadResult::Item(record) => record,
+                ReadResult::End => break,
+                ReadResult::Corrupted(reason) => {
+                    let tolerated = match recovery_mode {
+                        WalRecoveryMode::TolerateCorruptedTailRecords => {
+                            !reader.has_record_after(offset)
+                        }
+                        WalRecoveryMode::AbsoluteConsistency => false,
+                        WalRecoveryMode::PointInTimeRecovery => true,
+                    };
+                    if !tolerated {
+                        bail!(
+                            "corrupted WAL {} at offset {}: {}",
+                            path.display(),
+                            offset,
+                            reason
+                        );
+                    }
+                    reader.offset = offset;
+                    dropped = true;
+                    break;
+                }
+            };
+            let (ts, entries) = WalBatch::decode(&record)
+                .with_context(|| format!("malformed WAL batch at offset {}", offset))?;
+            for (column_family_id, kind, key, value) in entries {
+                apply(
+                    column_family_id,
+                    kind,
+                    KeyBytes::from_bytes_with_ts(key, ts),
+                    value,
+                )?;
             }
-            skiplist.insert(KeyBytes::from_bytes_with_ts(key, ts), value);
         }
-        Ok(Self {
-            file: Arc::new(Mutex::new(BufWriter::new(file))),
-        })
+        Ok((reader.offset as u64, dropped))
     }
 
-    pub fn put(&self, key: KeySlice, value: &[u8]) -> Result<()> {
-        let mut file = self.file.lock();
-        let mut buf: Vec<u8> =
-            Vec::with_capacity(key.raw_len() + value.len() + std::mem::size_of::<u16>());
-        let mut hasher = crc32fast::Hasher::new();
-        hasher.write_u16(key.key_len() as u16);
-        buf.put_u16(key.key_len() as u16);
-        hasher.write(key.key_ref());
-        buf.put_slice(key.key_ref());
-        hasher.write_u64(key.ts());
-        buf.put_u64(key.ts());
-        hasher.write_u16(value.len() as u16);
-        buf.put_u16(value.len() as u16);
-        buf.put_slice(value);
-        hasher.write(value);
-        // add checksum: week 2 day 7
-        buf.put_u32(hasher.finalize());
-        file.write_all(&buf)?;
+    pub fn put(&self, column_family_id: usize, key: KeySlice, value: &[u8]) -> Result<()> {
+        self.put_record(column_family_id, WalRecordKind::Value, key, value)
+    }
+
+    /// Logs a merge operand.
+    pub fn put_merge(&self, column_family_id: usize, key: KeySlice, operand: &[u8]) -> Result<()> {
+        self.put_record(column_family_id, WalRecordKind::MergeOperand, key, operand)
+    }
+
+    /// Logs a range tombstone deleting the keys in `[start, end)` older than the timestamp of
+    /// `start`.
+    pub fn put_range_tombstone(
+        &self,
+        column_family_id: usize,
+        start: KeySlice,
+        end: &[u8],
+    ) -> Result<()> {
+        self.put_record(column_family_id, WalRecordKind::RangeTombstone, start, end)
+    }
+
+    fn put_record(
+        &self,
+        column_family_id: usize,
+        kind: WalRecordKind,
+        key: KeySlice,
+        value: &[u8],
+    ) -> Result<()> {
+        let mut batch = WalBatch::new(key.ts());
+        batch.put_record(column_family_id, kind, key.key_ref(), value);
+        self.append(&[batch])
+    }
+
+    /// Appends batches with a single write, each as a record.
+    pub fn append(&self, batches: &[WalBatch]) -> Result<()> {
+        let mut writer = self.writer.lock();
+        let mut buf = Vec::new();
+        for batch in batches {
+            self.add_fragments(&mut buf, &mut writer.block_offset, &batch.buf);
+        }
+        writer.file.write_all(&buf)?;
         Ok(())
     }
 
+    /// Splits a record into fragments that fill the blocks, starting at `block_offset`.
+    fn add_fragments(&self, buf: &mut Vec<u8>, block_offset: &mut usize, record: &[u8]) {
+        let mut left = record;
+        let mut first = true;
+        loop {
+            let block_left = BLOCK_SIZE - *block_offset;
+            if block_left < HEADER_SIZE {
+                buf.resize(buf.len() + block_left, 0);
+                *block_offset = 0;
+                continue;
+            }
+            let len = left.len().min(block_left - HEADER_SIZE);
+            let last = len == left.len();
+            let fragment_type = match (first, last) {
+                (true, true) => FULL_FRAGMENT,
+                (true, false) => FIRST_FRAGMENT,
+                (false, false) => MIDDLE_FRAGMENT,
+                (false, true) => LAST_FRAGMENT,
+            };
+            let payload = &left[..len];
+            buf.put_u32(fragment_checksum(fragment_type, self.log_number, payload));
+            buf.put_u16(len as u16);
+            buf.put_u8(fragment_type);
+            buf.put_u32(self.log_number);
+            buf.put_slice(payload);
+            *block_offset += HEADER_SIZE + len;
+            left = &left[len..];
+            first = false;
+            if last {
+                return;
+            }
+        }
+    }
+
     pub fn sync(&self) -> Result<()> {
-        let mut file = self.file.lock();
-        file.flush()?;
-        file.get_mut().sync_all()?;
+        let mut writer = self.writer.lock();
+        writer.file.flush()?;
+        writer.file.get_mut().sync_data()?;
         Ok(())
     }
 }
+
+/// An entry of a write batch: the column family id, the kind, the key and the value.
+type WalEntry = (usize, WalRecordKind, Bytes, Bytes);
+
+/// A write batch encoded as a WAL record, with the commit timestamp of all its entries.
+pub struct WalBatch {
+    buf: Vec<u8>,
+    count: u32,
+}
+
+impl WalBatch {
+    pub fn new(commit_ts: u64) -> Self {
+        let mut buf = Vec::new();
+        buf.put_u64(commit_ts);
+        buf.put_u32(0);
+        Self { buf, count: 0 }
+    }
+
+    pub fn put(&mut self, column_family_id: usize, key: &[u8], value: &[u8]) {
+        self.put_record(column_family_id, WalRecordKind::Value, key, value)
+    }
+
+    pub fn put_merge(&mut self, column_family_id: usize, key: &[u8], operand: &[u8]) {
+        self.put_record(column_family_id, WalRecordKind::MergeOperand, key, operand)
+    }
+
+    pub fn put_range_tombstone(&mut self, column_family_id: usize, start: &[u8], end: &[u8]) {
+        self.put_record(column_family_id, WalRecordKind::RangeTombstone, start, end)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    fn put_record(
+        &mut self,
+        column_family_id: usize,
+        kind: WalRecordKind,
+        key: &[u8],
+        value: &[u8],
+    ) {
+        let buf = &mut self.buf;
+        buf.reserve(key.len() + value.len() + 9);
+        buf.put_u32(column_family_id as u32);
+        buf.put_u8(kind.to_u8());
+        buf.put_u16(key.len() as u16);
+        buf.put_slice(key);
+        buf.put_u16(value.len() as u16);
+        buf.put_slice(value);
+        self.count += 1;
+        self.buf[8..12].copy_from_slice(&self.count.to_be_bytes());
+    }
+
+    /// Decodes the commit timestamp and the entries of a batch record.
+    fn decode(record: &[u8]) -> Result<(u64, Vec<WalEntry>)> {
+        let mut rbuf = record;
+        if rbuf.remaining() < 12 {
+            bail!("truncated batch header");
+        }
+        let ts = rbuf.get_u64();
+        let count = rbuf.get_u32();
+        let mut entries = Vec::with_capacity(count as usize);
+        let get_slice = |rbuf: &mut &[u8]| {
+            if rbuf.remaining() < 2 {
+                bail!("truncated batch entry");
+            }
+            let len = rbuf.get_u16() as usize;
+            if rbuf.remaining() < len {
+                bail!("truncated batch entry");
+            }
+            let slice = Bytes::copy_from_slice(&rbuf[..len]);
+            rbuf.advance(len);
+            Ok(slice)
+        };
+        for _ in 0..count {
+            if rbuf.remaining() < 5 {
+                bail!("truncated batch entry");
+            }
+            let column_family_id = rbuf.get_u32() as usize;
+            let kind = WalRecordKind::from_u8(rbuf.get_u8())?;
+            let key = get_slice(&mut rbuf)?;
+            let value = get_slice(&mut rbuf)?;
+            entries.push((column_family_id, kind, key, value));
+        }
+        if rbuf.has_remaining() {
+            bail!("trailing bytes after batch entries");
+        }
+        Ok((ts, entries))
+    }
+}
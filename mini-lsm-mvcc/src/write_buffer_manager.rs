@@ -0,0 +1,69 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Tracks the memory allocated by the memtables, mutable and immutable, of one or more storage
+/// engines, so that their total stays around a budget. A single manager can be shared by several
+/// storage engines.
+///
+/// Once the budget is exceeded, the engines freeze their mutable memtables when these take a large
+/// share of the memory, and flush their immutable memtables.
+#[derive(Debug)]
+pub struct WriteBufferManager {
+    buffer_size: usize,
+    memory_usage: AtomicUsize,
+    mutable_memory_usage: AtomicUsize,
+}
+
+impl WriteBufferManager {
+    pub fn new(buffer_size: usize) -> Self {
+        Self {
+            buffer_size,
+            memory_usage: AtomicUsize::new(0),
+            mutable_memory_usage: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    /// The memory of all memtables charged to the manager.
+    pub fn memory_usage(&self) -> usize {
+        self.memory_usage.load(Ordering::Relaxed)
+    }
+
+    /// The memory of the mutable memtables charged to the manager.
+    pub fn mutable_memory_usage(&self) -> usize {
+        self.mutable_memory_usage.load(Ordering::Relaxed)
+    }
+
+    /// Whether the mutable memtables should be frozen: either they are about to use up the budget
+    /// by themselves, or the budget is exceeded and they hold at least half of it, so that freezing
+    /// them is worth it.
+    pub fn should_freeze(&self) -> bool {
+        let mutable_memory_usage = self.mutable_memory_usage();
+        mutable_memory_usage > self.buffer_size / 8 * 7
+            || (self.should_flush() && mutable_memory_usage >= self.buffer_size / 2)
+    }
+
+    /// Whether the immutable memtables should be flushed.
+    pub fn should_flush(&self) -> bool {
+        self.memory_usage() >= self.buffer_size
+    }
+
+    /// Changes the memory charged for a memtable from `old_size` to `new_size`.
+    pub(crate) fn charge(&self, old_size: usize, new_size: usize, mutable: bool) {
+        let counters = [&self.memory_usage, &self.mutable_memory_usage];
+        for counter in &counters[..if mutable { 2 } else { 1 }] {
+            if new_size >= old_size {
+                counter.fetch_add(new_size - old_size, Ordering::Relaxed);
+            } else {
+                counter.fetch_sub(old_size - new_size, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Moves the memory charged for a memtable out of the mutable memory once it is frozen.
+    pub(crate) fn mark_immutable(&self, size: usize) {
+        self.mutable_memory_usage.fetch_sub(size, Ordering::Relaxed);
+    }
+}
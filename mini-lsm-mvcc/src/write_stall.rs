@@ -0,0 +1,183 @@
+use std::time::{Duration, Instant};
+
+use parking_lot::{Condvar, Mutex};
+
+use crate::lsm_storage::LsmStorageInner;
+
+/// Thresholds that throttle writers when flushes and compactions cannot keep up. Writes are
+/// slowed down to `delayed_write_rate` once a soft limit is reached, and blocked once a hard limit
+/// is reached. All limits are disabled by default.
+///
+/// Note that writes blocked by a hard limit only resume after the background flush and compaction
+/// threads make progress, so the limits must be reachable by them (e.g., `stop_imm_memtables`
+/// should be larger than `num_memtable_limit`, and L0 limits should not be set without compaction).
+#[derive(Debug, Clone)]
+pub struct WriteStallOptions {
+    // Slow down writes when the number of immutable memtables reaches this limit
+    pub slowdown_imm_memtables: usize,
+    // Stop writes when the number of immutable memtables reaches this limit
+    pub stop_imm_memtables: usize,
+    // Slow down writes when the number of L0 SSTs (or tiers in tiered compaction) reaches this limit
+    pub slowdown_l0_files: usize,
+    // Stop writes when the number of L0 SSTs (or tiers in tiered compaction) reaches this limit
+    pub stop_l0_files: usize,
+    // Slow down writes when the estimated pending compaction bytes reach this limit
+    pub soft_pending_compaction_bytes: u64,
+    // Stop writes when the estimated pending compaction bytes reach this limit
+    pub hard_pending_compaction_bytes: u64,
+    // Write rate in bytes per second when writes are slowed down
+    pub delayed_write_rate: u64,
+}
+
+impl Default for WriteStallOptions {
+    fn default() -> Self {
+        Self {
+            slowdown_imm_memtables: usize::MAX,
+            stop_imm_memtables: usize::MAX,
+            slowdown_l0_files: usize::MAX,
+            stop_l0_files: usize::MAX,
+            soft_pending_compaction_bytes: u64::MAX,
+            hard_pending_compaction_bytes: u64::MAX,
+            delayed_write_rate: 16 << 20, // 16MB/s
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteStallCondition {
+    Normal,
+    Delayed,
+    Stopped,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteStallCause {
+    None,
+    ImmMemtables,
+    L0Files,
+    PendingCompactionBytes,
+}
+
+/// Whether writes are currently throttled, and which limit throttles them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WriteStallState {
+    pub condition: WriteStallCondition,
+    pub cause: WriteStallCause,
+}
+
+impl WriteStallState {
+    fn new(condition: WriteStallCondition, cause: WriteStallCause) -> Self {
+        Self { condition, cause }
+    }
+}
+
+/// Blocks writers under a hard limit until a flush or compaction changes the LSM state.
+#[derive(Default)]
+pub(crate) struct WriteStallController {
+    mutex: Mutex<()>,
+    cv: Condvar,
+}
+
+/// Blocked writers re-check the state at this interval in case no background work notifies them.
+const STALL_RECHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+impl LsmStorageInner {
+    /// Computes the write stall state from the current LSM state. Each limit applies to the column
+    /// family closest to it, and pending compaction bytes are summed over all column families.
+    pub fn write_stall_state(&self) -> WriteStallState {
+        let opts = &self.options.write_stall;
+        let estimate_pending_compaction_bytes = opts.soft_pending_compaction_bytes != u64::MAX
+            || opts.hard_pending_compaction_bytes != u64::MAX;
+        let mut num_imm_memtables = 0;
+        let mut num_l0_files = 0;
+        let mut pending_compaction_bytes = 0;
+        for cf in self.column_families() {
+            let snapshot = {
+                let guard = cf.state.read();
+                guard.clone()
+            };
+            num_imm_memtables = num_imm_memtables.max(snapshot.imm_memtables.len());
+            num_l0_files = num_l0_files.max(if cf.compaction_controller.flush_to_l0() {
+                snapshot.l0_sstables.len()
+            } else {
+                snapshot.levels.len()
+            });
+            if estimate_pending_compaction_bytes {
+                pending_compaction_bytes += cf
+                    .compaction_controller
+                    .estimate_pending_compaction_bytes(&snapshot);
+            }
+        }
+
+        let limits = [
+            (
+                WriteStallCause::ImmMemtables,
+                num_imm_memtables as u64,
+                opts.slowdown_imm_memtables as u64,
+                opts.stop_imm_memtables as u64,
+            ),
+            (
+                WriteStallCause::L0Files,
+                num_l0_files as u64,
+                opts.slowdown_l0_files as u64,
+                opts.stop_l0_files as u64,
+            ),
+            (
+                WriteStallCause::PendingCompactionBytes,
+                pending_compaction_bytes,
+                opts.soft_pending_compaction_bytes,
+                opts.hard_pending_compaction_bytes,
+            ),
+        ];
+        for (cause, value, _, stop) in limits {
+            if value >= stop {
+                return WriteStallState::new(WriteStallCondition::Stopped, cause);
+            }
+        }
+        for (cause, value, slowdown, _) in limits {
+            if value >= slowdown {
+                return WriteStallState::new(WriteStallCondition::Delayed, cause);
+            }
+        }
+        WriteStallState::new(WriteStallCondition::Normal, WriteStallCause::None)
+    }
+
+    /// Throttles a write of `bytes` bytes according to the write stall state. Blocks while writes
+    /// are stopped, and then sleeps for the time the write takes at the delayed write rate if
+    /// writes are slowed down.
+    pub(crate) fn maybe_stall_write(&self, bytes: usize) {
+        let mut state = self.write_stall_state();
+        if state.condition == WriteStallCondition::Normal {
+            return;
+        }
+        if state.condition == WriteStallCondition::Stopped {
+            let start = Instant::now();
+            let mut guard = self.write_stall.mutex.lock();
+            loop {
+                state = self.write_stall_state();
+                if state.condition != WriteStallCondition::Stopped {
+                    break;
+                }
+                self.write_stall
+                    .cv
+                    .wait_for(&mut guard, STALL_RECHECK_INTERVAL);
+            }
+            println!(
+                "write stopped for {:?} by {:?}",
+                start.elapsed(),
+                state.cause
+            );
+        }
+        if state.condition == WriteStallCondition::Delayed {
+            let rate = self.options.write_stall.delayed_write_rate.max(1);
+            std::thread::sleep(Duration::from_micros(bytes as u64 * 1_000_000 / rate));
+        }
+    }
+
+    /// Wakes up writers blocked by a hard limit. Called after the LSM state is changed by a flush
+    /// or a compaction.
+    pub(crate) fn notify_write_stall(&self) {
+        let _guard = self.write_stall.mutex.lock();
+        self.write_stall.cv.notify_all();
+    }
+}